@@ -0,0 +1,12 @@
+//! Arbitrary method definitions, which messages and `register_method` carry as text.
+
+#![no_main]
+
+use gateway_core::meta_parsing::MethodAndTypes;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(method_def) = std::str::from_utf8(data) {
+        let _ = MethodAndTypes::parse(method_def);
+    }
+});
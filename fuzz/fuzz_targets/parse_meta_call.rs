@@ -0,0 +1,22 @@
+//! Arbitrary messages, as `proxy` gets them from relayers.
+
+#![no_main]
+
+use gateway_core::meta_parsing::{near_erc712_domain, parse_meta_call, PreparedMethod};
+use libfuzzer_sys::fuzz_target;
+use primitive_types::U256;
+
+fuzz_target!(|data: &[u8]| {
+    let domain_separator = near_erc712_domain(U256::from(1));
+    // Method id 0 resolves to a registered method, so messages can take that path too.
+    let _ = parse_meta_call(
+        &domain_separator,
+        1,
+        b"gateway",
+        data.to_vec(),
+        |method_id| match method_id {
+            0 => PreparedMethod::parse("transfer(string receiver_id,uint128 amount)").ok(),
+            _ => None,
+        },
+    );
+});
@@ -0,0 +1,16 @@
+//! Arbitrary input of the proxy account's `call`, decoded before any host function reads it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(call) = proxy::decode_call(data) {
+        // Accepted inputs are exactly the fields with their lengths, nothing is read past them.
+        assert_eq!(
+            8 + 16 + 12 + call.receiver_id.len() + call.method_name.len() + call.args.len(),
+            data.len()
+        );
+        assert_eq!(call.amount.len(), 16);
+    }
+});
@@ -0,0 +1,189 @@
+//! Ethereum JSON-RPC adapter for the gateway, so Ethereum tooling can point at it with only
+//! the signing step changed. Supported methods:
+//!
+//! - `eth_sendRawTransaction` takes the hex encoded signed message and submits it to `proxy`,
+//!   or signed Ethereum transaction to `submit_eth_tx`, returning the NEAR transaction hash in hex.
+//! - `eth_getTransactionCount` returns the nonce the gateway expects from the address.
+//! - `eth_chainId` and `net_version` return the chain id transactions are signed for, the
+//!   gateway's own unless `--chain-id` overrides it.
+
+use std::process;
+
+use gateway_cli::rpc::{outcome_error, Relayer, Rpc};
+use gateway_cli::{fetch_nonce, TGAS};
+use gateway_core::meta_parsing::{gateway_chain_id, is_eth_transaction};
+use primitive_types::U256;
+use serde_json::{json, Value};
+use structopt::StructOpt;
+use tiny_http::{Header, Response, Server};
+
+/// Standard JSON-RPC error codes, and the one Ethereum nodes use for failed execution.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "eth-rpc",
+    about = "Serve a subset of Ethereum JSON-RPC on top of the Ethereum gateway"
+)]
+struct Opts {
+    /// Account id of the gateway contract.
+    #[structopt(long)]
+    gateway: String,
+    /// Chain id reported to wallets, the gateway's chain id of Ethereum transactions by default.
+    #[structopt(long)]
+    chain_id: Option<u64>,
+    /// NEAR JSON-RPC endpoint, e.g. https://rpc.testnet.near.org.
+    #[structopt(long)]
+    rpc: String,
+    /// NEAR account submitting the messages and paying for gas.
+    #[structopt(long)]
+    relayer: String,
+    /// Relayer's `ed25519:...` secret key, as in `~/.near-credentials`.
+    #[structopt(long)]
+    relayer_key: String,
+    #[structopt(long, default_value = "127.0.0.1:8545")]
+    listen: String,
+}
+
+struct Adapter {
+    opts: Opts,
+    rpc: Rpc,
+    relayer: Relayer,
+}
+
+type RpcResult = Result<Value, (i64, String)>;
+
+fn param<'a>(params: &'a Value, index: usize, name: &str) -> Result<&'a str, (i64, String)> {
+    params[index]
+        .as_str()
+        .ok_or_else(|| (INVALID_PARAMS, format!("Missing {}", name)))
+}
+
+fn error_response(id: &Value, code: i64, message: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+impl Adapter {
+    fn chain_id(&self) -> u64 {
+        self.opts
+            .chain_id
+            .unwrap_or_else(|| gateway_chain_id(self.opts.gateway.as_bytes()))
+    }
+
+    fn send_raw_transaction(&self, params: &Value) -> RpcResult {
+        let data = param(params, 0, "transaction data")?;
+        let data = hex::decode(data.trim_start_matches("0x"))
+            .map_err(|err| (INVALID_PARAMS, format!("Invalid transaction data: {}", err)))?;
+        let (method, args) = if is_eth_transaction(&data) {
+            ("submit_eth_tx", json!({ "rlp": base64::encode(data) }))
+        } else {
+            ("proxy", json!({ "message": base64::encode(data) }))
+        };
+        let outcome = self
+            .rpc
+            .function_call(
+                &self.relayer,
+                &self.opts.gateway,
+                method,
+                args,
+                300 * TGAS,
+                0,
+            )
+            .map_err(|err| (SERVER_ERROR, err))?;
+        if let Some(error) = outcome_error(&outcome) {
+            return Err((SERVER_ERROR, error));
+        }
+        let hash = bs58::decode(outcome["transaction"]["hash"].as_str().unwrap_or_default())
+            .into_vec()
+            .map_err(|err| (SERVER_ERROR, format!("Invalid transaction hash: {}", err)))?;
+        Ok(json!(format!("0x{}", hex::encode(hash))))
+    }
+
+    fn get_transaction_count(&self, params: &Value) -> RpcResult {
+        let address = param(params, 0, "address")?;
+        let nonce = fetch_nonce(&self.rpc, &self.opts.gateway, address, U256::zero())
+            .map_err(|err| (SERVER_ERROR, err))?;
+        Ok(json!(format!("{:#x}", nonce)))
+    }
+
+    fn dispatch(&self, method: &str, params: &Value) -> RpcResult {
+        match method {
+            "eth_sendRawTransaction" => self.send_raw_transaction(params),
+            "eth_getTransactionCount" => self.get_transaction_count(params),
+            "eth_chainId" => Ok(json!(format!("{:#x}", self.chain_id()))),
+            "net_version" => Ok(json!(self.chain_id().to_string())),
+            _ => Err((
+                METHOD_NOT_FOUND,
+                format!("Method {} is not supported", method),
+            )),
+        }
+    }
+
+    fn handle_call(&self, call: &Value) -> Value {
+        let result = match call["method"].as_str() {
+            Some(method) => self.dispatch(method, &call["params"]),
+            None => Err((INVALID_REQUEST, "Missing method".to_string())),
+        };
+        match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": call["id"], "result": result }),
+            Err((code, message)) => error_response(&call["id"], code, message),
+        }
+    }
+
+    fn handle(&self, body: &str) -> Value {
+        match serde_json::from_str::<Value>(body) {
+            Ok(Value::Array(calls)) => {
+                Value::Array(calls.iter().map(|call| self.handle_call(call)).collect())
+            }
+            Ok(call) => self.handle_call(&call),
+            Err(err) => error_response(&Value::Null, PARSE_ERROR, err.to_string()),
+        }
+    }
+}
+
+fn main() {
+    let opts = Opts::from_args();
+    let relayer = match Relayer::new(&opts.relayer, &opts.relayer_key) {
+        Ok(relayer) => relayer,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+    let server = match Server::http(&opts.listen) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("Error: failed to listen on {}: {}", opts.listen, err);
+            process::exit(1);
+        }
+    };
+    let adapter = Adapter {
+        rpc: Rpc::new(&opts.rpc),
+        relayer,
+        opts,
+    };
+
+    for mut request in server.incoming_requests() {
+        let mut body = String::new();
+        let response = match request.as_reader().read_to_string(&mut body) {
+            Ok(_) => adapter.handle(&body),
+            Err(err) => error_response(&Value::Null, PARSE_ERROR, err.to_string()),
+        };
+        let response = Response::from_data(response.to_string()).with_header(
+            "Content-Type: application/json"
+                .parse::<Header>()
+                .expect("Valid header"),
+        );
+        if let Err(err) = request.respond(response) {
+            eprintln!("Failed to respond: {}", err);
+        }
+    }
+}
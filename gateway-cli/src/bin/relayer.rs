@@ -0,0 +1,396 @@
+//! Relayer service: accepts signed messages over HTTP, checks them with the same code as the
+//! gateway, and submits `proxy` calls in nonce order of each sender, retrying failed ones.
+//! Senders whose next message tips more are submitted first.
+//!
+//! - `POST /messages` with `{"message": "<base64>"}` queues the message, returns its digest.
+//! - `GET /messages/<digest>` returns the message status.
+//! - `GET /status` returns the number of queued messages.
+//! - `GET /balances/<address>` returns the NEAR, ledger and registered token balances of the
+//!   account acting for the address.
+//!
+//! With `--meta-relayer` the relayer doesn't pay the gas itself: it signs NEP-366 delegate
+//! actions of the `proxy` calls and sends them to that relayer.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use borsh::BorshDeserialize;
+use gateway_cli::rpc::{outcome_error, relay_delegate_action, Relayer, Rpc};
+use gateway_cli::{fetch_balances, fetch_nonce, TGAS};
+use gateway_core::meta_parsing::{near_erc712_domain, recover_signer, PreparedMethod};
+use gateway_core::types::{
+    is_salted_nonce, make_nonce, nonce_key, DepositSource, MetaCallArgs, RecoveredSigner,
+};
+use primitive_types::U256;
+use serde::Serialize;
+use serde_json::{json, Value};
+use structopt::StructOpt;
+use tiny_http::{Header, Method, Response, Server};
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "relayer",
+    about = "Accept signed messages over HTTP and submit them to the Ethereum gateway"
+)]
+struct Opts {
+    /// Account id of the gateway contract.
+    #[structopt(long)]
+    gateway: String,
+    #[structopt(long, default_value = "1")]
+    chain_id: u64,
+    /// NEAR JSON-RPC endpoint, e.g. https://rpc.testnet.near.org.
+    #[structopt(long)]
+    rpc: String,
+    /// NEAR account submitting the messages, must be their fee address.
+    #[structopt(long)]
+    relayer: String,
+    /// Relayer's `ed25519:...` secret key, as in `~/.near-credentials`.
+    #[structopt(long)]
+    relayer_key: String,
+    /// Minimal fee in yoctoNEAR the messages must pay.
+    #[structopt(long, default_value = "0")]
+    min_fee: u128,
+    /// Attempts to submit a message before giving up on it.
+    #[structopt(long, default_value = "3")]
+    max_attempts: u32,
+    #[structopt(long, default_value = "127.0.0.1:3030")]
+    listen: String,
+    /// NEP-366 relayer submitting the `proxy` calls as signed delegate actions of the relayer,
+    /// e.g. http://localhost:3031.
+    #[structopt(long)]
+    meta_relayer: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Status {
+    /// Waiting for its turn, or for messages with lower nonces from the sender.
+    Queued {
+        attempts: u32,
+    },
+    Submitted {
+        /// Hash of the transaction, or the response of the NEP-366 relayer.
+        transaction: String,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+struct Pending {
+    digest: String,
+    message: String,
+    /// Tip the gateway pays the relayer once the message executes.
+    tip: U256,
+    attempts: u32,
+}
+
+#[derive(Default)]
+struct State {
+    /// Messages by sender address and nonce key, ordered by nonce.
+    queues: HashMap<(String, U256), BTreeMap<U256, Pending>>,
+    /// Status of every message by its digest.
+    statuses: HashMap<String, Status>,
+}
+
+struct Relay {
+    opts: Opts,
+    rpc: Rpc,
+    relayer: Relayer,
+    domain_separator: [u8; 32],
+    state: Mutex<State>,
+}
+
+impl Relay {
+    fn registered_method(&self, method_id: u32) -> Option<PreparedMethod> {
+        let method_def = self
+            .rpc
+            .view(
+                &self.opts.gateway,
+                "get_method_def",
+                json!({ "method_id": method_id }),
+            )
+            .ok()?;
+        PreparedMethod::parse(method_def.as_str()?).ok()
+    }
+
+    /// Verifies the message as the gateway would and queues it. Returns its digest and sender.
+    fn accept(&self, message: &str) -> Result<RecoveredSigner, String> {
+        let bytes = base64::decode(message).map_err(|err| format!("Invalid base64: {}", err))?;
+        let meta_tx = MetaCallArgs::try_from_slice(&bytes)
+            .map_err(|_| "ERR_META_TX_PARSE: ArgumentParseError".to_string())?;
+        if meta_tx.fee_address != self.relayer.account_id {
+            return Err(format!("Fee must be paid to {}", self.relayer.account_id));
+        }
+        if U256::from(meta_tx.fee_amount) < U256::from(self.opts.min_fee) {
+            return Err(format!("Fee must be at least {}", self.opts.min_fee));
+        }
+        if meta_tx.deposit_source == DepositSource::Relayer && meta_tx.value != [0; 32] {
+            return Err("Relayer doesn't attach deposits".to_string());
+        }
+        let nonce = U256::from(meta_tx.nonce);
+
+        let nonce_error = Mutex::new(None);
+        let signer = recover_signer(
+            &self.domain_separator,
+            self.opts.chain_id,
+            self.opts.gateway.as_bytes(),
+            bytes,
+            |method_id| self.registered_method(method_id),
+            |sender, nonce| {
+                // Salted nonces are checked by the gateway on submission.
+                if is_salted_nonce(nonce) {
+                    return nonce;
+                }
+                let address = format!("0x{}", hex::encode(sender));
+                fetch_nonce(&self.rpc, &self.opts.gateway, &address, nonce_key(nonce))
+                    .unwrap_or_else(|err| {
+                        *nonce_error.lock().unwrap() = Some(err);
+                        U256::zero()
+                    })
+            },
+        )
+        .map_err(|err| format!("ERR_META_TX_PARSE: {}", err))?;
+        if let Some(err) = nonce_error.into_inner().unwrap() {
+            return Err(err);
+        }
+        let expected_nonce = U256::from_dec_str(&signer.expected_nonce).unwrap_or_default();
+        if nonce < expected_nonce {
+            return Err(format!("Nonce {} is already used", nonce));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.statuses.contains_key(&signer.digest) {
+            return Err("Message is already queued".to_string());
+        }
+        state
+            .statuses
+            .insert(signer.digest.clone(), Status::Queued { attempts: 0 });
+        state
+            .queues
+            .entry((signer.address.clone(), nonce_key(nonce)))
+            .or_default()
+            .insert(
+                nonce,
+                Pending {
+                    digest: signer.digest.clone(),
+                    message: message.to_string(),
+                    tip: U256::from(meta_tx.tip),
+                    attempts: 0,
+                },
+            );
+        Ok(signer)
+    }
+
+    /// Submits the `proxy` call of the message, directly or through the NEP-366 relayer.
+    fn submit(&self, message: String) -> Result<String, String> {
+        let args = json!({ "message": message });
+        if let Some(url) = &self.opts.meta_relayer {
+            let signed = self.rpc.delegate_function_call(
+                &self.relayer,
+                &self.opts.gateway,
+                "proxy",
+                args,
+                300 * TGAS,
+                0,
+            )?;
+            return relay_delegate_action(url, &signed);
+        }
+        let outcome = self.rpc.function_call(
+            &self.relayer,
+            &self.opts.gateway,
+            "proxy",
+            args,
+            300 * TGAS,
+            0,
+        )?;
+        match outcome_error(&outcome) {
+            Some(error) => Err(error),
+            None => Ok(outcome["transaction"]["hash"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string()),
+        }
+    }
+
+    /// Submits the next message of each sender and nonce key whose nonce the gateway expects now.
+    /// Messages with salted nonces don't wait for each other, the next one is submitted.
+    /// Goes through the senders by the tip of their first queued message, highest first.
+    fn submit_ready(&self) {
+        let mut senders: Vec<((String, U256), U256)> = self
+            .state
+            .lock()
+            .unwrap()
+            .queues
+            .iter()
+            .map(|(sender, queue)| {
+                let tip = queue.values().next().map_or(U256::zero(), |p| p.tip);
+                (sender.clone(), tip)
+            })
+            .collect();
+        senders.sort_by_key(|(_, tip)| Reverse(*tip));
+        for (sender, _) in senders {
+            let salted_first = if is_salted_nonce(make_nonce(sender.1, 0)) {
+                let state = self.state.lock().unwrap();
+                state.queues[&sender].keys().next().cloned()
+            } else {
+                None
+            };
+            let expected_nonce = match salted_first {
+                Some(nonce) => nonce,
+                None => match fetch_nonce(&self.rpc, &self.opts.gateway, &sender.0, sender.1) {
+                    Ok(nonce) => nonce,
+                    Err(err) => {
+                        eprintln!("Failed to fetch nonce of {}: {}", sender.0, err);
+                        continue;
+                    }
+                },
+            };
+            let (digest, message) = {
+                let mut state = self.state.lock().unwrap();
+                let State { queues, statuses } = &mut *state;
+                let queue = queues.entry(sender.clone()).or_default();
+                // Nonces used by messages submitted elsewhere.
+                let stale: Vec<U256> = queue.range(..expected_nonce).map(|(n, _)| *n).collect();
+                for nonce in stale {
+                    let pending = queue.remove(&nonce).unwrap();
+                    statuses.insert(
+                        pending.digest,
+                        Status::Failed {
+                            error: format!("Nonce {} is already used", nonce),
+                        },
+                    );
+                }
+                match queue.get_mut(&expected_nonce) {
+                    Some(pending) => {
+                        pending.attempts += 1;
+                        (pending.digest.clone(), pending.message.clone())
+                    }
+                    None => continue,
+                }
+            };
+
+            let result = self.submit(message);
+
+            let mut state = self.state.lock().unwrap();
+            let State { queues, statuses } = &mut *state;
+            let queue = queues.entry(sender.clone()).or_default();
+            let status = match result {
+                Ok(transaction) => {
+                    queue.remove(&expected_nonce);
+                    Status::Submitted { transaction }
+                }
+                Err(error) => {
+                    let attempts = queue.get(&expected_nonce).map_or(0, |p| p.attempts);
+                    if attempts >= self.opts.max_attempts {
+                        queue.remove(&expected_nonce);
+                        Status::Failed { error }
+                    } else {
+                        eprintln!("Failed to submit {}: {}", digest, error);
+                        Status::Queued { attempts }
+                    }
+                }
+            };
+            statuses.insert(digest, status);
+            if queue.is_empty() {
+                queues.remove(&sender);
+            }
+        }
+    }
+}
+
+fn json_response(status: u16, body: Value) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_data(body.to_string())
+        .with_status_code(status)
+        .with_header(
+            "Content-Type: application/json"
+                .parse::<Header>()
+                .expect("Valid header"),
+        )
+}
+
+fn handle(relay: &Relay, request: &mut tiny_http::Request) -> (u16, Value) {
+    let url = request.url().to_string();
+    match (request.method(), url.as_str()) {
+        (Method::Post, "/messages") => {
+            let mut body = String::new();
+            if let Err(err) = request.as_reader().read_to_string(&mut body) {
+                return (400, json!({ "error": err.to_string() }));
+            }
+            let message = serde_json::from_str::<Value>(&body)
+                .ok()
+                .and_then(|body| body["message"].as_str().map(str::to_string));
+            match message.map(|message| relay.accept(&message)) {
+                Some(Ok(signer)) => (200, json!(signer)),
+                Some(Err(error)) => (400, json!({ "error": error })),
+                None => (
+                    400,
+                    json!({ "error": "Expected {\"message\": \"<base64>\"}" }),
+                ),
+            }
+        }
+        (Method::Get, "/status") => {
+            let state = relay.state.lock().unwrap();
+            let queued: usize = state.queues.values().map(|queue| queue.len()).sum();
+            let senders: HashSet<&String> = state.queues.keys().map(|(sender, _)| sender).collect();
+            (200, json!({ "queued": queued, "senders": senders.len() }))
+        }
+        (Method::Get, path) if path.starts_with("/messages/") => {
+            let digest = &path["/messages/".len()..];
+            match relay.state.lock().unwrap().statuses.get(digest) {
+                Some(status) => (200, json!(status)),
+                None => (404, json!({ "error": "Unknown message" })),
+            }
+        }
+        (Method::Get, path) if path.starts_with("/balances/") => {
+            let address = &path["/balances/".len()..];
+            match fetch_balances(&relay.rpc, &relay.opts.gateway, address) {
+                Ok(balances) => (200, balances),
+                Err(error) => (400, json!({ "error": error })),
+            }
+        }
+        _ => (404, json!({ "error": "Not found" })),
+    }
+}
+
+fn main() {
+    let opts = Opts::from_args();
+    let relayer = match Relayer::new(&opts.relayer, &opts.relayer_key) {
+        Ok(relayer) => relayer,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+    let server = match Server::http(&opts.listen) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("Error: failed to listen on {}: {}", opts.listen, err);
+            process::exit(1);
+        }
+    };
+    let relay = Arc::new(Relay {
+        rpc: Rpc::new(&opts.rpc),
+        relayer,
+        domain_separator: near_erc712_domain(U256::from(opts.chain_id)),
+        state: Mutex::new(State::default()),
+        opts,
+    });
+
+    let worker = relay.clone();
+    thread::spawn(move || loop {
+        worker.submit_ready();
+        thread::sleep(Duration::from_secs(1));
+    });
+
+    for mut request in server.incoming_requests() {
+        let (status, body) = handle(&relay, &mut request);
+        if let Err(err) = request.respond(json_response(status, body)) {
+            eprintln!("Failed to respond: {}", err);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Off-chain tools for the Ethereum gateway: `gateway-cli` to build and submit messages by hand
+//! and `relayer` to accept them over HTTP and submit in order.
+
+pub mod rpc;
+
+use gateway_core::types::keccak256;
+use primitive_types::U256;
+use serde_json::{json, Value};
+
+use crate::rpc::Rpc;
+
+pub const TGAS: u64 = 1_000_000_000_000;
+
+/// Nonce the gateway expects from the hex encoded address next, under the given nonce key.
+pub fn fetch_nonce(rpc: &Rpc, gateway: &str, address: &str, key: U256) -> Result<U256, String> {
+    let nonce = rpc.view(
+        gateway,
+        "get_nonce",
+        json!({ "address": address, "key": key.to_string() }),
+    )?;
+    U256::from_dec_str(nonce.as_str().unwrap_or_default())
+        .map_err(|_| format!("Invalid nonce {}", nonce))
+}
+
+/// ed25519 key of the NEAR implicit account derived from the Ethereum key, so the Ethereum
+/// secret alone recovers it. The implicit account id is the hex encoded public key.
+pub fn implicit_account_key(signing_key: &k256::ecdsa::SigningKey) -> ed25519_dalek::SigningKey {
+    let mut seed = b"near-implicit-account".to_vec();
+    seed.extend_from_slice(&signing_key.to_bytes());
+    let mut secret = [0u8; 32];
+    secret.copy_from_slice(&keccak256(&seed));
+    ed25519_dalek::SigningKey::from_bytes(&secret)
+}
+
+/// Balances of the account acting for the hex encoded address, in the shape of the gateway's
+/// `balances_of` plus the account's yoctoNEAR `near` balance, null if it doesn't exist yet.
+/// Read with views, so wallets get the address' portfolio without a transaction.
+pub fn fetch_balances(rpc: &Rpc, gateway: &str, address: &str) -> Result<Value, String> {
+    let account_id = rpc.view(gateway, "get_account_id", json!({ "address": address }))?;
+    let account_id = account_id.as_str().unwrap_or_default();
+    let near = rpc.view_account(account_id)?;
+    let ledger = rpc.view(gateway, "get_ledger_balance", json!({ "address": address }))?;
+    let tokens: Vec<String> = serde_json::from_value(rpc.view(gateway, "get_tokens", json!({}))?)
+        .map_err(|err| format!("Invalid tokens: {}", err))?;
+    let tokens: Vec<Value> = tokens
+        .into_iter()
+        .map(|token_id| {
+            let balance = rpc
+                .view(
+                    &token_id,
+                    "ft_balance_of",
+                    json!({ "account_id": account_id }),
+                )
+                .ok();
+            json!({ "token_id": token_id, "balance": balance })
+        })
+        .collect();
+    Ok(json!({
+        "account_id": account_id,
+        "near": near.map(|near| near.to_string()),
+        "ledger": ledger,
+        "tokens": tokens,
+    }))
+}
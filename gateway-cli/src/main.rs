@@ -0,0 +1,408 @@
+//! Command line tool to build, sign and submit meta transactions to the Ethereum gateway.
+//! Without `--rpc` it prints the message for submitting by other means, e.g.
+//! `near call <gateway> proxy '{"message": "<message>"}'`.
+
+use std::process;
+
+use gateway_cli::rpc::{relay_delegate_action, Relayer, Rpc};
+use gateway_cli::{fetch_balances, fetch_nonce, implicit_account_key, TGAS};
+use gateway_core::client::{signing_key_to_address, MetaCallBuilder};
+use gateway_core::meta_parsing::parse_near_amount;
+use gateway_core::types::{ArgsEncoding, DepositSource, MetaCallSignature, ValueUnit};
+use k256::ecdsa::SigningKey;
+use primitive_types::U256;
+use serde_json::json;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "gateway-cli",
+    about = "Build, sign and submit meta transactions to the Ethereum gateway"
+)]
+struct Opts {
+    /// Account id of the gateway contract.
+    #[structopt(long)]
+    gateway: String,
+    #[structopt(long, default_value = "1")]
+    chain_id: u64,
+    /// Hex encoded secp256k1 secret key to sign the message with.
+    #[structopt(long)]
+    key: Option<String>,
+    /// Hex encoded `r ‖ s ‖ v` signature of the message digest, made elsewhere.
+    /// The digest is printed when neither key nor signature are given.
+    #[structopt(long, conflicts_with = "key")]
+    signature: Option<String>,
+    /// Nonce of the message. Fetched from the gateway for the key's address when `--rpc` is given,
+    /// otherwise zero.
+    #[structopt(long)]
+    nonce: Option<String>,
+    /// Nonce key, messages under different keys don't wait for each other.
+    /// The fetched nonce is the one expected under this key.
+    #[structopt(long, default_value = "0")]
+    nonce_key: String,
+    /// Fee in yoctoNEAR for relaying the message.
+    #[structopt(long, default_value = "0")]
+    fee: u128,
+    #[structopt(long, default_value = "")]
+    fee_address: String,
+    /// Tip in yoctoNEAR, or NEAR like "0.1 NEAR", the proxy account pays whoever submits the
+    /// message once it executes.
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_amount))]
+    tip: u128,
+    /// Gas the called method gets, left to the gateway if not given.
+    #[structopt(long, default_value = "0")]
+    gas: u64,
+    /// Attach the message's value to the gateway call from the relayer, instead of spending it
+    /// from the proxy account.
+    #[structopt(long, requires = "relayer")]
+    relayer_deposit: bool,
+    /// `--gateway` is the signer's standalone proxy account: messages are signed for it and
+    /// submitted to its `execute` instead of the gateway's `proxy`.
+    #[structopt(long, conflicts_with = "relayer-deposit")]
+    standalone: bool,
+    /// NEAR JSON-RPC endpoint, e.g. https://rpc.testnet.near.org.
+    #[structopt(long)]
+    rpc: Option<String>,
+    /// NEAR account submitting the message and paying for gas.
+    #[structopt(long, requires_all = &["relayer-key", "rpc"])]
+    relayer: Option<String>,
+    /// Relayer's `ed25519:...` secret key, as in `~/.near-credentials`.
+    #[structopt(long)]
+    relayer_key: Option<String>,
+    /// Print the relayer's NEP-366 signed delegate action of the gateway call, base64 encoded,
+    /// instead of submitting it, for a NEP-366 relayer to pay the gas.
+    #[structopt(long, requires = "relayer")]
+    delegate: bool,
+    /// NEP-366 relayer to send the signed delegate action to, e.g. http://localhost:3030.
+    #[structopt(long, requires = "relayer")]
+    meta_relayer: Option<String>,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Create the signer's proxy account, funded with the attached deposit.
+    Create {
+        /// Deposit in yoctoNEAR or NEAR, covers the proxy account's storage.
+        #[structopt(long, default_value = "5 NEAR", parse(try_from_str = parse_amount))]
+        deposit: u128,
+        /// Proxy code template to deploy, see the gateway's `get_proxy_templates`.
+        #[structopt(long)]
+        template: Option<String>,
+    },
+    /// Create the signer's standalone proxy account, verifying its messages itself, funded with
+    /// the attached deposit.
+    CreateStandalone {
+        /// Deposit in yoctoNEAR or NEAR, covers the standalone proxy account's storage.
+        #[structopt(long, default_value = "5 NEAR", parse(try_from_str = parse_amount))]
+        deposit: u128,
+    },
+    /// Create the NEAR implicit account of the ed25519 key derived from `--key` instead of a
+    /// proxy account, funded with the attached deposit. Prints the account and its key.
+    CreateImplicit {
+        /// Deposit in yoctoNEAR or NEAR, covers the implicit account's storage.
+        #[structopt(long, default_value = "0.01 NEAR", parse(try_from_str = parse_amount))]
+        deposit: u128,
+    },
+    /// Transfer yoctoNEAR, or NEAR like "1.5 NEAR", from the proxy account.
+    Transfer {
+        receiver: String,
+        #[structopt(parse(try_from_str = parse_amount))]
+        amount: u128,
+    },
+    /// Call a contract from the proxy account.
+    Call {
+        contract: String,
+        /// Method definition, e.g. "test_call(uint64 x,string y)".
+        method_def: String,
+        /// Hex encoded arguments, RLP list unless `--abi` is set.
+        #[structopt(long, default_value = "")]
+        args: String,
+        #[structopt(long)]
+        abi: bool,
+        /// Id of the registered method definition, sent instead of the definition.
+        #[structopt(long)]
+        method_id: Option<u32>,
+        /// yoctoNEAR, or NEAR like "1.5 NEAR", attached to the call.
+        #[structopt(long, default_value = "0", parse(try_from_str = parse_amount))]
+        value: u128,
+    },
+    /// Transfer NEP-141 tokens from the proxy account to a contract with `ft_transfer_call`.
+    FtTransferCall {
+        token: String,
+        receiver_id: String,
+        amount: u128,
+        /// Message for the receiver's `ft_on_transfer`.
+        #[structopt(long, default_value = "")]
+        msg: String,
+    },
+    /// Transfer an NFT from the proxy account with `nft_transfer`, or `nft_transfer_call` with
+    /// `--msg`.
+    NftTransfer {
+        contract: String,
+        receiver_id: String,
+        token_id: String,
+        #[structopt(long)]
+        msg: Option<String>,
+    },
+    /// Approve an account to transfer the proxy account's NFT with `nft_approve`, calling its
+    /// `nft_on_approve` with `--msg`.
+    NftApprove {
+        contract: String,
+        token_id: String,
+        account_id: String,
+        #[structopt(long)]
+        msg: Option<String>,
+        /// Deposit in yoctoNEAR for the approval's storage.
+        #[structopt(long, default_value = "10000000000000000000000")]
+        deposit: u128,
+    },
+    /// Cancel outstanding messages signed with nonces up to `--nonce` under its key, or the one
+    /// with the salted `--nonce`, without executing anything.
+    Cancel,
+    /// Print the nonce the gateway expects from the address under `--nonce-key`, the key's
+    /// address if not given.
+    Nonce { address: Option<String> },
+    /// Print the NEAR, ledger and registered token balances of the account acting for the
+    /// address, the key's address if not given.
+    Balances { address: Option<String> },
+}
+
+fn parse_hex(name: &str, value: &str) -> Result<Vec<u8>, String> {
+    hex::decode(value.trim_start_matches("0x")).map_err(|err| format!("Invalid {}: {}", name, err))
+}
+
+/// yoctoNEAR of the amount given as is or in NEAR, like "1.5 NEAR".
+fn parse_amount(value: &str) -> Result<u128, String> {
+    parse_near_amount(value, ValueUnit::Yocto)
+        .map_err(|err| format!("Invalid amount {}: {}", value, err))
+}
+
+fn signing_key(opts: &Opts) -> Result<Option<SigningKey>, String> {
+    opts.key
+        .as_ref()
+        .map(|key| {
+            SigningKey::from_slice(&parse_hex("key", key)?)
+                .map_err(|_| "Invalid secp256k1 key".to_string())
+        })
+        .transpose()
+}
+
+fn run(opts: Opts) -> Result<(), String> {
+    let signing_key = signing_key(&opts)?;
+    let address = signing_key
+        .as_ref()
+        .map(|key| format!("0x{}", hex::encode(signing_key_to_address(key))));
+    let rpc = opts.rpc.as_ref().map(|url| Rpc::new(url));
+    let nonce_key = U256::from_dec_str(&opts.nonce_key)
+        .map_err(|_| format!("Invalid nonce key {}", opts.nonce_key))?;
+
+    if let Command::Nonce { address: given } = &opts.command {
+        let rpc = rpc.ok_or("Nonce requires --rpc")?;
+        let address = given
+            .clone()
+            .or(address)
+            .ok_or("Nonce requires an address or --key")?;
+        println!("{}", fetch_nonce(&rpc, &opts.gateway, &address, nonce_key)?);
+        return Ok(());
+    }
+    if let Command::Balances { address: given } = &opts.command {
+        let rpc = rpc.ok_or("Balances requires --rpc")?;
+        let address = given
+            .clone()
+            .or(address)
+            .ok_or("Balances requires an address or --key")?;
+        println!("{}", fetch_balances(&rpc, &opts.gateway, &address)?);
+        return Ok(());
+    }
+
+    let nonce = match (&opts.nonce, &rpc, &address) {
+        (Some(nonce), _, _) => {
+            U256::from_dec_str(nonce).map_err(|_| format!("Invalid nonce {}", nonce))?
+        }
+        (None, Some(rpc), Some(address)) => fetch_nonce(rpc, &opts.gateway, address, nonce_key)?,
+        _ => U256::zero(),
+    };
+    let builder = MetaCallBuilder::new(&opts.gateway, opts.chain_id)
+        .nonce(nonce)
+        .fee(opts.fee, &opts.fee_address)
+        .tip(opts.tip)
+        .gas(opts.gas);
+    let builder = if opts.relayer_deposit {
+        builder.deposit_source(DepositSource::Relayer)
+    } else {
+        builder
+    };
+    // Value the relayer attaches to the gateway call.
+    let relayer_deposit = |value: u128| if opts.relayer_deposit { value } else { 0 };
+    let (builder, gateway_method, deposit) = match &opts.command {
+        Command::Create { deposit, template } => {
+            let builder = match template {
+                Some(template) => builder.create(template),
+                None => builder.method("create()"),
+            };
+            (builder, "create", *deposit)
+        }
+        Command::CreateImplicit { deposit } => {
+            let key = implicit_account_key(
+                signing_key
+                    .as_ref()
+                    .ok_or("CreateImplicit requires --key")?,
+            );
+            let public_key = key.verifying_key().to_bytes();
+            eprintln!("Implicit account: {}", hex::encode(public_key));
+            eprintln!(
+                "Key: ed25519:{}",
+                bs58::encode(key.to_keypair_bytes()).into_string()
+            );
+            (
+                builder.create_implicit(&public_key),
+                "create_implicit",
+                *deposit,
+            )
+        }
+        Command::CreateStandalone { deposit } => (
+            builder
+                .receiver(&opts.gateway)
+                .method("create_standalone()"),
+            "create_standalone",
+            *deposit,
+        ),
+        Command::Cancel => (
+            builder.receiver(&opts.gateway).method("cancel()"),
+            "cancel",
+            0,
+        ),
+        Command::Transfer { receiver, amount } => (
+            builder.receiver(receiver).value(*amount),
+            "proxy",
+            relayer_deposit(*amount),
+        ),
+        Command::Call {
+            contract,
+            method_def,
+            args,
+            abi,
+            method_id,
+            value,
+        } => {
+            let args_encoding = if *abi {
+                ArgsEncoding::Abi
+            } else {
+                ArgsEncoding::Rlp
+            };
+            let builder = builder
+                .receiver(contract)
+                .value(*value)
+                .args(args_encoding, parse_hex("args", args)?);
+            let builder = match method_id {
+                Some(method_id) => builder.registered_method(*method_id, method_def),
+                None => builder.method(method_def),
+            };
+            (builder, "proxy", relayer_deposit(*value))
+        }
+        Command::FtTransferCall {
+            token,
+            receiver_id,
+            amount,
+            msg,
+        } => (
+            builder.ft_transfer_call(token, receiver_id, *amount, msg),
+            "proxy",
+            relayer_deposit(1),
+        ),
+        Command::NftTransfer {
+            contract,
+            receiver_id,
+            token_id,
+            msg,
+        } => (
+            builder.nft_transfer(contract, receiver_id, token_id, msg.as_deref()),
+            "proxy",
+            relayer_deposit(1),
+        ),
+        Command::NftApprove {
+            contract,
+            token_id,
+            account_id,
+            msg,
+            deposit,
+        } => (
+            builder.nft_approve(contract, token_id, account_id, msg.as_deref(), *deposit),
+            "proxy",
+            relayer_deposit(*deposit),
+        ),
+        Command::Nonce { .. } | Command::Balances { .. } => unreachable!(),
+    };
+    let gateway_method = match gateway_method {
+        "proxy" if opts.standalone => "execute",
+        gateway_method => gateway_method,
+    };
+
+    let message = match (&signing_key, &opts.signature) {
+        (Some(signing_key), _) => builder
+            .sign(signing_key)
+            .map_err(|err| format!("Invalid message: {}", err))?,
+        (None, Some(signature)) => {
+            let bytes = parse_hex("signature", signature)?;
+            if bytes.len() != 65 {
+                return Err("Signature must be 65 bytes".to_string());
+            }
+            let mut signature = [0u8; 64];
+            signature.copy_from_slice(&bytes[..64]);
+            builder.build(MetaCallSignature::Secp256k1 {
+                signature,
+                v: bytes[64],
+            })
+        }
+        (None, None) => {
+            let digest = builder
+                .digest()
+                .map_err(|err| format!("Invalid message: {}", err))?;
+            println!("0x{}", hex::encode(digest));
+            return Ok(());
+        }
+    };
+    let message = base64::encode(message);
+
+    match (&opts.relayer, &opts.relayer_key, &rpc) {
+        (Some(relayer), Some(relayer_key), Some(rpc)) => {
+            let relayer = Relayer::new(relayer, relayer_key)?;
+            if opts.delegate || opts.meta_relayer.is_some() {
+                let signed = rpc.delegate_function_call(
+                    &relayer,
+                    &opts.gateway,
+                    gateway_method,
+                    json!({ "message": message }),
+                    300 * TGAS,
+                    deposit,
+                )?;
+                match &opts.meta_relayer {
+                    Some(url) => println!("{}", relay_delegate_action(url, &signed)?),
+                    None => println!("{}", base64::encode(signed)),
+                }
+                return Ok(());
+            }
+            let outcome = rpc.function_call(
+                &relayer,
+                &opts.gateway,
+                gateway_method,
+                json!({ "message": message }),
+                300 * TGAS,
+                deposit,
+            )?;
+            println!("Transaction: {}", outcome["transaction"]["hash"]);
+            println!("Status: {}", outcome["status"]);
+        }
+        _ => println!("{}", message),
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run(Opts::from_args()) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,328 @@
+//! Minimal NEAR JSON-RPC client: view calls and function call transactions signed by the relayer,
+//! or NEP-366 delegate actions of them for another relayer to submit.
+
+use borsh::BorshSerialize;
+use ed25519_dalek::{Signer, SigningKey};
+use serde_json::{json, Value};
+use sha2::Digest;
+
+const ED25519_KEY_TYPE: u8 = 0;
+
+/// Prefix of signed NEP-366 delegate actions, `2^30 + 366`, so they can't be mistaken for
+/// transactions. See https://github.com/near/NEPs/blob/master/neps/nep-0366.md
+const DELEGATE_ACTION_PREFIX: u32 = (1 << 30) + 366;
+
+/// Blocks a delegate action stays valid for after the block it was signed at.
+const DELEGATE_ACTION_TTL: u64 = 120;
+
+#[derive(BorshSerialize)]
+struct PublicKey {
+    key_type: u8,
+    data: [u8; 32],
+}
+
+#[derive(BorshSerialize)]
+struct FunctionCallAction {
+    method_name: String,
+    args: Vec<u8>,
+    gas: u64,
+    deposit: u128,
+}
+
+/// Actions up to the function call, variant order matches `near_primitives::transaction::Action`.
+#[derive(BorshSerialize)]
+enum Action {
+    #[allow(dead_code)]
+    CreateAccount,
+    #[allow(dead_code)]
+    DeployContract {
+        code: Vec<u8>,
+    },
+    FunctionCall(FunctionCallAction),
+}
+
+#[derive(BorshSerialize)]
+struct Transaction {
+    signer_id: String,
+    public_key: PublicKey,
+    nonce: u64,
+    receiver_id: String,
+    block_hash: [u8; 32],
+    actions: Vec<Action>,
+}
+
+#[derive(BorshSerialize)]
+struct Signature {
+    key_type: u8,
+    data: [u8; 64],
+}
+
+#[derive(BorshSerialize)]
+struct SignedTransaction {
+    transaction: Transaction,
+    signature: Signature,
+}
+
+#[derive(BorshSerialize)]
+struct DelegateAction {
+    sender_id: String,
+    receiver_id: String,
+    actions: Vec<Action>,
+    nonce: u64,
+    max_block_height: u64,
+    public_key: PublicKey,
+}
+
+#[derive(BorshSerialize)]
+struct SignedDelegateAction {
+    delegate_action: DelegateAction,
+    signature: Signature,
+}
+
+/// Nonce and block of the relayer's access key, to sign the next transaction with.
+struct AccessKey {
+    nonce: u64,
+    block_hash: [u8; 32],
+    block_height: u64,
+}
+
+/// NEAR account that submits the transactions and pays for their gas.
+pub struct Relayer {
+    pub account_id: String,
+    signing_key: SigningKey,
+}
+
+impl Relayer {
+    /// Takes the key as stored in `~/.near-credentials`: `ed25519:` and base58 of the secret key
+    /// followed by the public key, or of the secret key alone.
+    pub fn new(account_id: &str, secret_key: &str) -> Result<Self, String> {
+        let encoded = secret_key
+            .strip_prefix("ed25519:")
+            .ok_or("Relayer key must be ed25519")?;
+        let bytes = bs58::decode(encoded)
+            .into_vec()
+            .map_err(|err| format!("Invalid relayer key: {}", err))?;
+        if bytes.len() != 32 && bytes.len() != 64 {
+            return Err("Invalid relayer key length".to_string());
+        }
+        let mut secret = [0u8; 32];
+        secret.copy_from_slice(&bytes[..32]);
+        Ok(Self {
+            account_id: account_id.to_string(),
+            signing_key: SigningKey::from_bytes(&secret),
+        })
+    }
+
+    fn public_key(&self) -> PublicKey {
+        PublicKey {
+            key_type: ED25519_KEY_TYPE,
+            data: self.signing_key.verifying_key().to_bytes(),
+        }
+    }
+
+    fn public_key_string(&self) -> String {
+        format!(
+            "ed25519:{}",
+            bs58::encode(self.signing_key.verifying_key().to_bytes()).into_string()
+        )
+    }
+}
+
+/// Error of the transaction outcome returned by `Rpc::function_call`, None if it succeeded.
+pub fn outcome_error(outcome: &Value) -> Option<String> {
+    match outcome["status"].get("Failure") {
+        Some(failure) => Some(failure.to_string()),
+        None if outcome["status"].get("SuccessValue").is_some() => None,
+        None => Some(format!("Unexpected outcome status {}", outcome["status"])),
+    }
+}
+
+pub struct Rpc {
+    url: String,
+}
+
+impl Rpc {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+        }
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let response: Value = ureq::post(&self.url)
+            .send_json(json!({
+                "jsonrpc": "2.0",
+                "id": "gateway-cli",
+                "method": method,
+                "params": params,
+            }))
+            .map_err(|err| format!("RPC request failed: {}", err))?
+            .into_json()
+            .map_err(|err| format!("Invalid RPC response: {}", err))?;
+        if let Some(error) = response.get("error") {
+            return Err(format!("RPC error: {}", error));
+        }
+        response
+            .get("result")
+            .cloned()
+            .ok_or_else(|| "RPC response without result".to_string())
+    }
+
+    /// Calls view method with JSON arguments and returns its JSON result.
+    pub fn view(&self, account_id: &str, method_name: &str, args: Value) -> Result<Value, String> {
+        let result = self.call(
+            "query",
+            json!({
+                "request_type": "call_function",
+                "finality": "final",
+                "account_id": account_id,
+                "method_name": method_name,
+                "args_base64": base64::encode(args.to_string()),
+            }),
+        )?;
+        if let Some(error) = result.get("error") {
+            return Err(format!("View call failed: {}", error));
+        }
+        let bytes: Vec<u8> = serde_json::from_value(result["result"].clone())
+            .map_err(|err| format!("Invalid view result: {}", err))?;
+        serde_json::from_slice(&bytes).map_err(|err| format!("Invalid view result: {}", err))
+    }
+
+    /// yoctoNEAR balance of the account, None if it doesn't exist.
+    pub fn view_account(&self, account_id: &str) -> Result<Option<u128>, String> {
+        let result = self.call(
+            "query",
+            json!({
+                "request_type": "view_account",
+                "finality": "final",
+                "account_id": account_id,
+            }),
+        );
+        match result {
+            Ok(account) => account["amount"]
+                .as_str()
+                .and_then(|amount| amount.parse().ok())
+                .map(Some)
+                .ok_or_else(|| format!("Invalid account {}", account)),
+            Err(error) if error.contains("UNKNOWN_ACCOUNT") => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn access_key(&self, relayer: &Relayer) -> Result<AccessKey, String> {
+        let access_key = self.call(
+            "query",
+            json!({
+                "request_type": "view_access_key",
+                "finality": "final",
+                "account_id": relayer.account_id,
+                "public_key": relayer.public_key_string(),
+            }),
+        )?;
+        let nonce = access_key["nonce"]
+            .as_u64()
+            .ok_or("Relayer key is not an access key of the account")?;
+        let block_hash = bs58::decode(access_key["block_hash"].as_str().unwrap_or_default())
+            .into_vec()
+            .map_err(|err| format!("Invalid block hash: {}", err))?;
+        if block_hash.len() != 32 {
+            return Err("Invalid block hash".to_string());
+        }
+        let mut result = AccessKey {
+            nonce,
+            block_hash: [0; 32],
+            block_height: access_key["block_height"].as_u64().unwrap_or_default(),
+        };
+        result.block_hash.copy_from_slice(&block_hash);
+        Ok(result)
+    }
+
+    /// Signs and sends function call transaction from the relayer, waiting for its outcome.
+    pub fn function_call(
+        &self,
+        relayer: &Relayer,
+        receiver_id: &str,
+        method_name: &str,
+        args: Value,
+        gas: u64,
+        deposit: u128,
+    ) -> Result<Value, String> {
+        let access_key = self.access_key(relayer)?;
+        let transaction = Transaction {
+            signer_id: relayer.account_id.clone(),
+            public_key: relayer.public_key(),
+            nonce: access_key.nonce + 1,
+            receiver_id: receiver_id.to_string(),
+            block_hash: access_key.block_hash,
+            actions: vec![Action::FunctionCall(FunctionCallAction {
+                method_name: method_name.to_string(),
+                args: args.to_string().into_bytes(),
+                gas,
+                deposit,
+            })],
+        };
+        let hash = sha2::Sha256::digest(transaction.try_to_vec().expect("Failed to serialize"));
+        let signed = SignedTransaction {
+            transaction,
+            signature: Signature {
+                key_type: ED25519_KEY_TYPE,
+                data: relayer.signing_key.sign(&hash).to_bytes(),
+            },
+        };
+        self.call(
+            "broadcast_tx_commit",
+            json!([base64::encode(
+                signed.try_to_vec().expect("Failed to serialize")
+            )]),
+        )
+    }
+
+    /// Borsh encoded NEP-366 signed delegate action of the function call from the relayer, for
+    /// another relayer to wrap in its transaction and pay the gas. Valid for
+    /// `DELEGATE_ACTION_TTL` blocks.
+    pub fn delegate_function_call(
+        &self,
+        relayer: &Relayer,
+        receiver_id: &str,
+        method_name: &str,
+        args: Value,
+        gas: u64,
+        deposit: u128,
+    ) -> Result<Vec<u8>, String> {
+        let access_key = self.access_key(relayer)?;
+        let delegate_action = DelegateAction {
+            sender_id: relayer.account_id.clone(),
+            receiver_id: receiver_id.to_string(),
+            actions: vec![Action::FunctionCall(FunctionCallAction {
+                method_name: method_name.to_string(),
+                args: args.to_string().into_bytes(),
+                gas,
+                deposit,
+            })],
+            nonce: access_key.nonce + 1,
+            max_block_height: access_key.block_height + DELEGATE_ACTION_TTL,
+            public_key: relayer.public_key(),
+        };
+        let mut signable = DELEGATE_ACTION_PREFIX.to_le_bytes().to_vec();
+        signable.extend(delegate_action.try_to_vec().expect("Failed to serialize"));
+        let hash = sha2::Sha256::digest(&signable);
+        let signed = SignedDelegateAction {
+            delegate_action,
+            signature: Signature {
+                key_type: ED25519_KEY_TYPE,
+                data: relayer.signing_key.sign(&hash).to_bytes(),
+            },
+        };
+        Ok(signed.try_to_vec().expect("Failed to serialize"))
+    }
+}
+
+/// Sends the borsh encoded signed delegate action to the `/relay` endpoint of a NEP-366 relayer
+/// at `url`, which takes it as a JSON array of bytes. Returns the relayer's response.
+pub fn relay_delegate_action(url: &str, signed_delegate_action: &[u8]) -> Result<String, String> {
+    ureq::post(&format!("{}/relay", url.trim_end_matches('/')))
+        .send_json(json!(signed_delegate_action))
+        .map_err(|err| format!("Relay request failed: {}", err))?
+        .into_string()
+        .map_err(|err| format!("Invalid relay response: {}", err))
+}
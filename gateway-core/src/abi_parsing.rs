@@ -0,0 +1,230 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::meta_parsing::{ParsingError, ParsingResult};
+
+/// Parameter of a function in Solidity JSON ABI.
+#[derive(Deserialize)]
+struct AbiParam {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(rename = "internalType", default)]
+    internal_type: Option<String>,
+    #[serde(default)]
+    components: Vec<AbiParam>,
+}
+
+/// Function fragment of Solidity JSON ABI.
+#[derive(Deserialize)]
+struct AbiFunction {
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+    name: String,
+    inputs: Vec<AbiParam>,
+}
+
+/// Convert method definition given as Solidity JSON ABI fragment or ethers human readable ABI
+/// into the method_def format, e.g.
+/// `{"type":"function","name":"adopt","inputs":[{"name":"petId","type":"uint256"}]}` or
+/// `function adopt(uint256 petId) external` -> "adopt(uint256 petId)".
+/// Method definitions already in method_def format are returned as is.
+pub(crate) fn normalize_method_def(text: &str) -> ParsingResult<Cow<'_, str>> {
+    if text.starts_with('{') {
+        Ok(Cow::Owned(json_abi_to_method_def(text)?))
+    } else if let Some(signature) = text.strip_prefix("function ") {
+        Ok(Cow::Owned(human_readable_to_method_def(signature)?))
+    } else {
+        Ok(Cow::Borrowed(text))
+    }
+}
+
+fn json_abi_to_method_def(text: &str) -> ParsingResult<String> {
+    let function: AbiFunction =
+        serde_json::from_str(text).map_err(|_| ParsingError::InvalidMetaTransactionMethodName)?;
+    if matches!(&function.kind, Some(kind) if kind != "function") {
+        return Err(ParsingError::InvalidMetaTransactionMethodName);
+    }
+    let mut structs = BTreeMap::new();
+    let mut result = function.name;
+    result.push_str(&json_abi_params(&function.inputs, &mut structs)?);
+    for definition in structs.values() {
+        result.push_str(definition);
+    }
+    Ok(result)
+}
+
+/// Format parameters as "(type name,...)", collecting definitions of the structs they use.
+fn json_abi_params(
+    params: &[AbiParam],
+    structs: &mut BTreeMap<String, String>,
+) -> ParsingResult<String> {
+    let mut result = "(".to_string();
+    for (i, param) in params.iter().enumerate() {
+        if i > 0 {
+            result.push(',');
+        }
+        result.push_str(&json_abi_param_type(param, structs)?);
+        result.push(' ');
+        result.push_str(&param.name);
+    }
+    result.push(')');
+    Ok(result)
+}
+
+/// Type of the parameter in method_def format.
+/// Tuples are replaced with the struct name from `internalType`, e.g. "struct Market.Order[]" -> "Order[]".
+fn json_abi_param_type(
+    param: &AbiParam,
+    structs: &mut BTreeMap<String, String>,
+) -> ParsingResult<String> {
+    let array_suffix = match param.kind.strip_prefix("tuple") {
+        Some(array_suffix) => array_suffix,
+        None => return Ok(param.kind.clone()),
+    };
+    let internal_type = param
+        .internal_type
+        .as_deref()
+        .and_then(|t| t.strip_prefix("struct "))
+        .ok_or(ParsingError::InvalidMetaTransactionMethodName)?;
+    let name = internal_type
+        .strip_suffix(array_suffix)
+        .ok_or(ParsingError::InvalidMetaTransactionMethodName)?
+        .rsplit('.')
+        .next()
+        .unwrap_or_default();
+    let definition = name.to_string() + &json_abi_params(&param.components, structs)?;
+    match structs.get(name) {
+        Some(existing) if existing != &definition => {
+            return Err(ParsingError::InvalidMetaTransactionMethodName)
+        }
+        _ => {
+            structs.insert(name.to_string(), definition);
+        }
+    }
+    Ok(name.to_string() + array_suffix)
+}
+
+/// Convert ethers human readable signature (without "function " prefix) into method_def,
+/// e.g. "transfer(address to, uint amount) external returns (bool)" -> "transfer(address to,uint256 amount)".
+/// Tuples are not supported, as they don't carry struct names required by EIP-712.
+fn human_readable_to_method_def(signature: &str) -> ParsingResult<String> {
+    let signature = signature.trim_start();
+    let args_start = signature
+        .find('(')
+        .ok_or(ParsingError::InvalidMetaTransactionMethodName)?;
+    let args_end = signature
+        .find(')')
+        .ok_or(ParsingError::InvalidMetaTransactionMethodName)?;
+    if args_end < args_start {
+        return Err(ParsingError::InvalidMetaTransactionMethodName);
+    }
+    let mut result = signature[..args_start].trim_end().to_string();
+    result.push('(');
+    let args = signature[args_start + 1..args_end].trim();
+    if !args.is_empty() {
+        for (i, arg) in args.split(',').enumerate() {
+            let words: Vec<&str> = arg
+                .split_whitespace()
+                .filter(|word| !matches!(*word, "memory" | "calldata" | "storage"))
+                .collect();
+            let (arg_type, arg_name) = match words.as_slice() {
+                [arg_type, arg_name] if !arg_type.starts_with("tuple") => (*arg_type, *arg_name),
+                _ => return Err(ParsingError::InvalidMetaTransactionMethodName),
+            };
+            if i > 0 {
+                result.push(',');
+            }
+            result.push_str(&canonical_type(arg_type));
+            result.push(' ');
+            result.push_str(arg_name);
+        }
+    }
+    result.push(')');
+    Ok(result)
+}
+
+/// Expand Solidity aliases, e.g. "uint[]" -> "uint256[]".
+fn canonical_type(arg_type: &str) -> String {
+    let base_end = arg_type.find('[').unwrap_or(arg_type.len());
+    let (base, array_suffix) = arg_type.split_at(base_end);
+    match base {
+        "uint" | "int" => format!("{}256{}", base, array_suffix),
+        _ => arg_type.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_method_def;
+    use crate::meta_parsing::MethodAndTypes;
+
+    #[test]
+    fn test_json_abi() {
+        let abi = r#"{
+            "type": "function",
+            "name": "adopt",
+            "stateMutability": "nonpayable",
+            "inputs": [
+                {"name": "petId", "type": "uint256", "internalType": "uint256"},
+                {
+                    "name": "pets",
+                    "type": "tuple[]",
+                    "internalType": "struct Shelter.PetObj[]",
+                    "components": [
+                        {"name": "name", "type": "string", "internalType": "string"},
+                        {
+                            "name": "owner",
+                            "type": "tuple",
+                            "internalType": "struct Owner",
+                            "components": [{"name": "wallet", "type": "address"}]
+                        }
+                    ]
+                }
+            ],
+            "outputs": []
+        }"#;
+        assert_eq!(
+            normalize_method_def(abi).unwrap(),
+            "adopt(uint256 petId,PetObj[] pets)Owner(address wallet)PetObj(string name,Owner owner)"
+        );
+        let methods = MethodAndTypes::parse(abi).unwrap();
+        assert_eq!(methods.method.name, "adopt");
+        assert_eq!(methods.types.len(), 2);
+
+        // Tuples must name their struct.
+        normalize_method_def(
+            r#"{"name": "f", "inputs": [{"name": "a", "type": "tuple", "components": []}]}"#,
+        )
+        .unwrap_err();
+        // Only functions can be called.
+        normalize_method_def(r#"{"type": "event", "name": "E", "inputs": []}"#).unwrap_err();
+        normalize_method_def("{not json").unwrap_err();
+    }
+
+    #[test]
+    fn test_human_readable_abi() {
+        assert_eq!(
+            normalize_method_def(
+                "function transfer(address to, uint amount, bytes calldata data) external returns (bool)"
+            )
+            .unwrap(),
+            "transfer(address to,uint256 amount,bytes data)"
+        );
+        assert_eq!(normalize_method_def("function ping()").unwrap(), "ping()");
+        assert_eq!(
+            normalize_method_def("function f(int[2] values)").unwrap(),
+            "f(int256[2] values)"
+        );
+        // Arguments must be named.
+        normalize_method_def("function transfer(address, uint256)").unwrap_err();
+        normalize_method_def("function f(tuple(string a) t)").unwrap_err();
+        // method_def format is passed through.
+        assert_eq!(
+            normalize_method_def("adopt(uint256 petId)").unwrap(),
+            "adopt(uint256 petId)"
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use ethabi::Address;
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use k256::elliptic_curve::scalar::IsHigh;
+use primitive_types::H256;
+use sha2::{Digest, Sha256};
+
+use crate::ecrecover::EcRecoverError;
+
+const MESSAGE_PREFIX: &[u8] = b"\x18Bitcoin Signed Message:\n";
+
+/// Kind of the address signing key belongs to, given by the BIP-137 header byte.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AddressKind {
+    /// P2PKH of the uncompressed key.
+    Uncompressed,
+    /// P2PKH or P2WPKH of the compressed key, both commit to the same key hash.
+    Compressed,
+    /// P2WPKH nested in P2SH.
+    SegwitInP2sh,
+}
+
+fn hash160(data: &[u8]) -> [u8; 20] {
+    let mut result = [0u8; 20];
+    result.copy_from_slice(&ripemd::Ripemd160::digest(Sha256::digest(data)));
+    result
+}
+
+/// Double SHA-256 of the message with the "Bitcoin Signed Message" prefix, as signed by wallets.
+fn signed_message_hash(message: &[u8]) -> [u8; 32] {
+    let mut data = MESSAGE_PREFIX.to_vec();
+    // Bitcoin's CompactSize length.
+    match message.len() {
+        len if len < 0xfd => data.push(len as u8),
+        len if len <= 0xffff => {
+            data.push(0xfd);
+            data.extend_from_slice(&(len as u16).to_le_bytes());
+        }
+        len => {
+            data.push(0xfe);
+            data.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+    }
+    data.extend_from_slice(message);
+    let mut result = [0u8; 32];
+    result.copy_from_slice(&Sha256::digest(Sha256::digest(&data)));
+    result
+}
+
+/// Recovers the key from BIP-137 signature `header ‖ r ‖ s` and returns the 20-byte hash
+/// the signer's Bitcoin address encodes.
+fn recover_address(
+    message_hash: &[u8; 32],
+    signature: &[u8; 65],
+) -> Result<Address, EcRecoverError> {
+    let (kind, bit) = match signature[0] {
+        27..=30 => (AddressKind::Uncompressed, signature[0] - 27),
+        31..=34 => (AddressKind::Compressed, signature[0] - 31),
+        35..=38 => (AddressKind::SegwitInP2sh, signature[0] - 35),
+        39..=42 => (AddressKind::Compressed, signature[0] - 39),
+        _ => return Err(EcRecoverError::InvalidRecoveryId),
+    };
+    let recovery_id = RecoveryId::from_byte(bit).ok_or(EcRecoverError::InvalidRecoveryId)?;
+    let signature =
+        Signature::from_slice(&signature[1..]).map_err(|_| EcRecoverError::InvalidScalar)?;
+    // Bitcoin Core only produces low `s` signatures.
+    if bool::from(signature.s().is_high()) {
+        return Err(EcRecoverError::HighS);
+    }
+    let public_key = VerifyingKey::recover_from_prehash(message_hash, &signature, recovery_id)
+        .map_err(|_| EcRecoverError::RecoveryFailed)?;
+    let key_hash = match kind {
+        AddressKind::Uncompressed => hash160(public_key.to_encoded_point(false).as_bytes()),
+        AddressKind::Compressed => hash160(public_key.to_encoded_point(true).as_bytes()),
+        AddressKind::SegwitInP2sh => {
+            // Redeem script is `OP_0 <20-byte key hash>`.
+            let mut script = vec![0x00, 0x14];
+            script.extend_from_slice(&hash160(public_key.to_encoded_point(true).as_bytes()));
+            hash160(&script)
+        }
+    };
+    Ok(Address::from(key_hash))
+}
+
+/// Verifies BIP-137 signed message, which Bitcoin wallets produce with "Sign Message",
+/// and returns the hash from the signer's address, which names the proxy account.
+/// Wallets sign the `0x`-prefixed hex of the message hash as text.
+pub(crate) fn verify_bitcoin(hash: H256, signature: &[u8; 65]) -> Result<Address, EcRecoverError> {
+    let message = format!("0x{}", hex::encode(hash.as_bytes()));
+    recover_address(&signed_message_hash(message.as_bytes()), signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::H256;
+
+    use super::{recover_address, signed_message_hash, verify_bitcoin, EcRecoverError};
+
+    fn signature(signature: &str) -> [u8; 65] {
+        let mut result = [0u8; 65];
+        result.copy_from_slice(&hex::decode(signature).unwrap());
+        result
+    }
+
+    #[test]
+    fn test_bitcoinjs_message_vector() {
+        // From bitcoinjs-message README, signed by 1F3sAm6ZtwLAUnj7d38pGFxtP3RVEvtsbV.
+        let message_hash = signed_message_hash(b"This is an example of a signed message.");
+        let signature = signature("1fd2f9c8b163b62d104c784fc85ad9093d55fcc32706d5ca9a888a0d9efc343063111922e994d065d48c0ad920a0d9a9d7b072f48b49afca3b0a15f45f163dd679");
+        assert_eq!(
+            hex::encode(recover_address(&message_hash, &signature).unwrap()),
+            "9a1c78a507689f6f54b847ad1cef1e614ee23f1e"
+        );
+    }
+
+    #[test]
+    fn test_address_kinds() {
+        // (message hash, signature, address hash), one for each header range, produced by
+        // libsecp256k1.
+        let vectors = [
+            (
+                "ca6c6588fa01171b200740344d354e8548b7470061fb32a34f4feee470ec281f",
+                "1b20a0d013242648c9debf6c9bfd4114fc4cfe50364bccc26531889d0517689c3b28c8410e33d7a18c4e04ecdc9fb2a37031b3f4ddb32ed1ad8d8a6729c755ec3a",
+                "b5aae91622c5a68fff6eab2a0e48df2becc22a2d",
+            ),
+            (
+                "ce43ee4403938454977cd110363e0771516c187e47afd4485926113d8a9f0f6b",
+                "20a74ecc7f0afb6c9474cbcf1020ebb009711ccb3ddefdbe874754358671873b42205680f444be7539721d282799ef0784b9f8d71056ee153eaaca6dddfb771999",
+                "14db4138d56a2ecfb10881a9be394d9f321985b2",
+            ),
+            (
+                "48986d85f8c0a0601ef7c44afc939dd0a8b0ed7bc309c7e373cb0fe94e30c7aa",
+                "24b39d231a3be82462bde05215a6cd67e9e59c6b6c6c15426feebefdbb43c873432f3a865ee7f165963316fe5a88aa77cf300bd2a08870782d42d7279199b533ef",
+                "9b789cd89d7db71d99074d5fbd803c92109d89c2",
+            ),
+            (
+                "af6e7a8f6997348343d9e0e718f697e5ec5fbf79b9f540db811e6f83d096ef73",
+                "283ece9a9e588dd0d7c4ebe9299c8e948a75b10263a9a50b097d00880337d344c329d0842f221ee65ddd28613b7dbeb72492d34fd283122016c815de010ea15f2c",
+                "477dcb11c3ff3d112ecd861c1af83d7b92d7cbfb",
+            ),
+        ];
+        for (hash, signature_hex, address) in vectors.iter() {
+            let hash = H256::from_slice(&hex::decode(hash).unwrap());
+            let signature = signature(signature_hex);
+            assert_eq!(
+                hex::encode(verify_bitcoin(hash, &signature).unwrap()),
+                *address
+            );
+            assert_ne!(
+                hex::encode(verify_bitcoin(H256::repeat_byte(0x33), &signature).unwrap()),
+                *address
+            );
+        }
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        let hash = H256::repeat_byte(0x33);
+        let mut signature = [1u8; 65];
+        signature[0] = 26;
+        assert_eq!(
+            verify_bitcoin(hash, &signature),
+            Err(EcRecoverError::InvalidRecoveryId)
+        );
+        signature[0] = 43;
+        assert_eq!(
+            verify_bitcoin(hash, &signature),
+            Err(EcRecoverError::InvalidRecoveryId)
+        );
+        signature[0] = 31;
+        signature[1..33].copy_from_slice(&[0; 32]);
+        assert_eq!(
+            verify_bitcoin(hash, &signature),
+            Err(EcRecoverError::InvalidScalar)
+        );
+    }
+}
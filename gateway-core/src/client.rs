@@ -0,0 +1,567 @@
+//! Building and signing meta calls, for relayers, bots and tests that talk to the gateway.
+
+use borsh::BorshSerialize;
+use k256::ecdsa::SigningKey;
+use primitive_types::U256;
+
+pub use crate::ecrecover::public_key_to_address;
+use crate::meta_parsing::{encode_meta_call, near_erc712_domain, MetaCallEncoding, ParsingResult};
+use crate::types::{
+    u256_to_arr, Address, ArgsEncoding, CallArgsFormat, DepositSource, InternalMetaCallArgs,
+    MetaCallArgs, MetaCallSignature, RawU256, TypedDataSchema, ValueUnit, CREATE_DEF,
+    CREATE_IMPLICIT_DEF, FT_TRANSFER_CALL_DEF, NFT_APPROVE_CALL_DEF, NFT_APPROVE_DEF,
+    NFT_TRANSFER_CALL_DEF, NFT_TRANSFER_DEF,
+};
+
+/// Meta call to the gateway at `gateway_id` on the given chain, built up field by field.
+/// Defaults to a transfer of nothing to no one with zero nonce and fee.
+#[derive(Debug, Clone)]
+pub struct MetaCallBuilder {
+    gateway_id: String,
+    chain_id: u64,
+    nonce: U256,
+    fee_amount: u128,
+    fee_address: String,
+    contract_address: String,
+    value: u128,
+    method_def: String,
+    method_id: Option<u32>,
+    args_encoding: ArgsEncoding,
+    call_args_format: CallArgsFormat,
+    args: Vec<u8>,
+    schema: TypedDataSchema,
+    tip: u128,
+    gas: u64,
+    deposit_source: DepositSource,
+    value_unit: ValueUnit,
+    multisig_account: Option<Address>,
+}
+
+impl MetaCallBuilder {
+    pub fn new(gateway_id: &str, chain_id: u64) -> Self {
+        Self {
+            gateway_id: gateway_id.to_string(),
+            chain_id,
+            nonce: U256::zero(),
+            fee_amount: 0,
+            fee_address: String::new(),
+            contract_address: String::new(),
+            value: 0,
+            method_def: String::new(),
+            method_id: None,
+            args_encoding: ArgsEncoding::Rlp,
+            call_args_format: CallArgsFormat::Json,
+            args: vec![],
+            schema: TypedDataSchema::Nested,
+            tip: 0,
+            gas: 0,
+            deposit_source: DepositSource::Account,
+            value_unit: ValueUnit::Yocto,
+            multisig_account: None,
+        }
+    }
+
+    pub fn nonce(mut self, nonce: U256) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    /// Fee paid to `fee_address` for relaying the call.
+    pub fn fee(mut self, fee_amount: u128, fee_address: &str) -> Self {
+        self.fee_amount = fee_amount;
+        self.fee_address = fee_address.to_string();
+        self
+    }
+
+    /// yoctoNEAR the account pays whoever submits the message once it executes.
+    pub fn tip(mut self, tip: u128) -> Self {
+        self.tip = tip;
+        self
+    }
+
+    /// Gas the called method gets, instead of what the gateway gives it by default.
+    pub fn gas(mut self, gas: u64) -> Self {
+        self.gas = gas;
+        self
+    }
+
+    /// Whether `value` comes from the proxy account or from the deposit the relayer attaches.
+    pub fn deposit_source(mut self, deposit_source: DepositSource) -> Self {
+        self.deposit_source = deposit_source;
+        self
+    }
+
+    /// Unit `value` is given and signed in, the gateway converts it to yoctoNEAR.
+    pub fn value_unit(mut self, value_unit: ValueUnit) -> Self {
+        self.value_unit = value_unit;
+        self
+    }
+
+    /// Multi-signature account the signers approve the message for. Its signatures must be
+    /// built into `MetaCallSignature::Multisig` for the same account.
+    pub fn multisig_account(mut self, account: Address) -> Self {
+        self.multisig_account = Some(account);
+        self
+    }
+
+    /// Contract to call, or the receiver of the transfer if no method is given.
+    pub fn receiver(mut self, contract_address: &str) -> Self {
+        self.contract_address = contract_address.to_string();
+        self
+    }
+
+    pub fn value(mut self, value: u128) -> Self {
+        self.value = value;
+        self
+    }
+
+    /// Method to call, e.g. "adopt(uint256 petId,PetObj petObject)PetObj(string name)".
+    pub fn method(mut self, method_def: &str) -> Self {
+        self.method_def = method_def.to_string();
+        self.method_id = None;
+        self
+    }
+
+    /// Method registered at `method_id`. The message only carries the id, but the signature
+    /// still covers the full definition.
+    pub fn registered_method(mut self, method_id: u32, method_def: &str) -> Self {
+        self.method_def = method_def.to_string();
+        self.method_id = Some(method_id);
+        self
+    }
+
+    /// Arguments of the method, RLP list or ABI encoded.
+    pub fn args(mut self, args_encoding: ArgsEncoding, args: Vec<u8>) -> Self {
+        self.args_encoding = args_encoding;
+        self.args = args;
+        self
+    }
+
+    /// NEP-141 `ft_transfer_call` of `amount` of the token to `receiver_id` with `msg`,
+    /// attaching the 1 yoctoNEAR the standard requires.
+    pub fn ft_transfer_call(self, token: &str, receiver_id: &str, amount: u128, msg: &str) -> Self {
+        let mut stream = rlp::RlpStream::new_list(3);
+        stream
+            .append(&receiver_id)
+            .append(&U256::from(amount))
+            .append(&msg);
+        self.standard_call(token, 1, FT_TRANSFER_CALL_DEF, stream)
+    }
+
+    /// NEP-171 `nft_transfer` of the owner's `token_id` on the NFT `contract` to `receiver_id`,
+    /// or `nft_transfer_call` with `msg` if given, attaching the required 1 yoctoNEAR.
+    pub fn nft_transfer(
+        self,
+        contract: &str,
+        receiver_id: &str,
+        token_id: &str,
+        msg: Option<&str>,
+    ) -> Self {
+        let mut stream = rlp::RlpStream::new_list(if msg.is_some() { 3 } else { 2 });
+        stream.append(&receiver_id).append(&token_id);
+        match msg {
+            Some(msg) => {
+                stream.append(&msg);
+                self.standard_call(contract, 1, NFT_TRANSFER_CALL_DEF, stream)
+            }
+            None => self.standard_call(contract, 1, NFT_TRANSFER_DEF, stream),
+        }
+    }
+
+    /// NEP-178 `nft_approve` of `account_id` for the owner's `token_id` on the NFT `contract`,
+    /// calling its `nft_on_approve` with `msg` if given. `deposit` pays for the approval's
+    /// storage.
+    pub fn nft_approve(
+        self,
+        contract: &str,
+        token_id: &str,
+        account_id: &str,
+        msg: Option<&str>,
+        deposit: u128,
+    ) -> Self {
+        let mut stream = rlp::RlpStream::new_list(if msg.is_some() { 3 } else { 2 });
+        stream.append(&token_id).append(&account_id);
+        match msg {
+            Some(msg) => {
+                stream.append(&msg);
+                self.standard_call(contract, deposit, NFT_APPROVE_CALL_DEF, stream)
+            }
+            None => self.standard_call(contract, deposit, NFT_APPROVE_DEF, stream),
+        }
+    }
+
+    /// Gateway's `create` of the signer's proxy account with the named proxy code template.
+    pub fn create(self, template: &str) -> Self {
+        let mut stream = rlp::RlpStream::new_list(1);
+        stream.append(&template);
+        let gateway_id = self.gateway_id.clone();
+        self.standard_call(&gateway_id, 0, CREATE_DEF, stream)
+    }
+
+    /// Gateway's `create_implicit` of the NEAR implicit account of the ed25519 public key.
+    pub fn create_implicit(self, public_key: &[u8; 32]) -> Self {
+        let mut stream = rlp::RlpStream::new_list(1);
+        stream.append(&public_key.to_vec());
+        let gateway_id = self.gateway_id.clone();
+        self.standard_call(&gateway_id, 0, CREATE_IMPLICIT_DEF, stream)
+    }
+
+    /// Call of the standard's method with the RLP list of its arguments, as JSON.
+    fn standard_call(
+        self,
+        contract: &str,
+        value: u128,
+        method_def: &str,
+        args: rlp::RlpStream,
+    ) -> Self {
+        self.receiver(contract)
+            .value(value)
+            .method(method_def)
+            .args(ArgsEncoding::Rlp, args.out().to_vec())
+            .call_args_format(CallArgsFormat::Json)
+    }
+
+    pub fn call_args_format(mut self, call_args_format: CallArgsFormat) -> Self {
+        self.call_args_format = call_args_format;
+        self
+    }
+
+    /// EIP-712 schema to sign the message with, the nested `NearTx` by default.
+    pub fn schema(mut self, schema: TypedDataSchema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// EIP-712 digest the wallet signs.
+    pub fn digest(&self) -> ParsingResult<RawU256> {
+        Ok(self.encode()?.digest)
+    }
+
+    /// EIP-712 encoding of the message, with the intermediate values.
+    pub fn encode(&self) -> ParsingResult<MetaCallEncoding> {
+        encode_meta_call(
+            &near_erc712_domain(U256::from(self.chain_id)),
+            self.gateway_id.as_bytes(),
+            &InternalMetaCallArgs {
+                sender: Address::zero(),
+                nonce: self.nonce,
+                fee_amount: self.fee_amount,
+                fee_address: self.fee_address.clone(),
+                contract_address: self.contract_address.clone(),
+                method_name: self.method_def.clone(),
+                value: self.value,
+                args_encoding: self.args_encoding,
+                call_args_format: self.call_args_format,
+                args: self.args.clone(),
+                signers: vec![],
+                schema: self.schema,
+                tip: self.tip,
+                gas: self.gas,
+                deposit_source: self.deposit_source,
+                value_unit: self.value_unit,
+                multisig_account: self.multisig_account,
+            },
+        )
+    }
+
+    /// Borsh encoded message with the given signature of `digest`, ready for the gateway.
+    pub fn build(&self, signature: MetaCallSignature) -> Vec<u8> {
+        MetaCallArgs {
+            signature,
+            nonce: u256_to_arr(&self.nonce),
+            fee_amount: u256_to_arr(&U256::from(self.fee_amount)),
+            fee_address: self.fee_address.clone(),
+            contract_address: self.contract_address.clone(),
+            value: u256_to_arr(&U256::from(self.value)),
+            method: match self.method_id {
+                Some(_) => String::new(),
+                None => self.method_def.clone(),
+            },
+            method_id: self.method_id,
+            args_encoding: self.args_encoding,
+            call_args_format: self.call_args_format,
+            args: self.args.clone(),
+            schema: self.schema,
+            tip: u256_to_arr(&U256::from(self.tip)),
+            gas: self.gas,
+            deposit_source: self.deposit_source,
+            value_unit: self.value_unit,
+        }
+        .try_to_vec()
+        .expect("Failed to serialize")
+    }
+
+    /// Signs the message with the Ethereum key, as a wallet does with `eth_signTypedData`.
+    pub fn sign(&self, signing_key: &SigningKey) -> ParsingResult<Vec<u8>> {
+        let digest = self.digest()?;
+        let (signature, recovery_id) = signing_key
+            .sign_prehash_recoverable(&digest)
+            .expect("Digest is 32 bytes");
+        let mut bytes = [0u8; 64];
+        bytes.copy_from_slice(&signature.to_bytes());
+        Ok(self.build(MetaCallSignature::Secp256k1 {
+            signature: bytes,
+            v: recovery_id.to_byte() + 27,
+        }))
+    }
+}
+
+/// Address of the Ethereum key, which names its proxy account on the gateway.
+pub fn signing_key_to_address(signing_key: &SigningKey) -> Address {
+    public_key_to_address(
+        &signing_key
+            .verifying_key()
+            .to_encoded_point(false)
+            .as_bytes()[1..],
+    )
+}
+
+/// Signed message calling `method_def` (or the registered `method_id`) of `contract_address`
+/// through the gateway, paying `fee_amount` to `fee_address`. Empty `method_def` is a transfer.
+#[allow(clippy::too_many_arguments)]
+pub fn encode_meta_call_function_args(
+    signing_key: &SigningKey,
+    gateway_id: &str,
+    chain_id: u64,
+    nonce: U256,
+    fee_amount: u128,
+    fee_address: &str,
+    contract_address: &str,
+    value: u128,
+    method_def: &str,
+    method_id: Option<u32>,
+    args: Vec<u8>,
+) -> ParsingResult<Vec<u8>> {
+    let builder = MetaCallBuilder::new(gateway_id, chain_id)
+        .nonce(nonce)
+        .fee(fee_amount, fee_address)
+        .receiver(contract_address)
+        .value(value)
+        .args(ArgsEncoding::Rlp, args);
+    let builder = match method_id {
+        Some(method_id) => builder.registered_method(method_id, method_def),
+        None => builder.method(method_def),
+    };
+    builder.sign(signing_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use k256::ecdsa::SigningKey;
+    use primitive_types::U256;
+
+    use super::{encode_meta_call_function_args, signing_key_to_address, MetaCallBuilder};
+    use crate::meta_parsing::{near_erc712_domain, parse_meta_call, ParsingError, PreparedMethod};
+    use crate::types::ValueUnit;
+
+    #[test]
+    fn test_signed_message_parses() {
+        let signing_key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+        let mut stream = rlp::RlpStream::new_list(2);
+        stream.append(&1u8).append(&"test");
+        let args = stream.out().to_vec();
+        let message = encode_meta_call_function_args(
+            &signing_key,
+            "gateway",
+            1,
+            U256::from(7),
+            5,
+            "relayer",
+            "alice",
+            10,
+            "test_call(uint64 x,string y)",
+            None,
+            args.clone(),
+        )
+        .unwrap();
+
+        let result = parse_meta_call(
+            &near_erc712_domain(U256::from(1)),
+            1,
+            b"gateway",
+            message,
+            |_| None,
+        )
+        .unwrap();
+        assert_eq!(result.sender, signing_key_to_address(&signing_key));
+        assert_eq!(result.nonce, U256::from(7));
+        assert_eq!(result.fee_amount, 5);
+        assert_eq!(result.contract_address, "alice");
+        assert_eq!(result.method_name, "test_call");
+
+        // Referencing the registered method keeps the same digest.
+        let builder = MetaCallBuilder::new("gateway", 1)
+            .receiver("alice")
+            .args(crate::types::ArgsEncoding::Rlp, args);
+        let by_def = builder.clone().method("test_call(uint64 x,string y)");
+        let by_id = builder.registered_method(3, "test_call(uint64 x,string y)");
+        assert_eq!(by_def.digest(), by_id.digest());
+        let result = parse_meta_call(
+            &near_erc712_domain(U256::from(1)),
+            1,
+            b"gateway",
+            by_id.sign(&signing_key).unwrap(),
+            |method_id| {
+                assert_eq!(method_id, 3);
+                PreparedMethod::parse("test_call(uint64 x,string y)").ok()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.sender, signing_key_to_address(&signing_key));
+    }
+
+    #[test]
+    fn test_wei_value() {
+        let signing_key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+        let builder = MetaCallBuilder::new("gateway", 1)
+            .receiver("alice")
+            .value(15 * 10u128.pow(17));
+        let wei = builder.clone().value_unit(ValueUnit::Wei);
+        assert!(wei
+            .encode()
+            .unwrap()
+            .type_string
+            .contains(",uint8 valueUnit)"));
+        assert_ne!(wei.digest(), builder.digest());
+
+        // The gateway sees the value in yoctoNEAR.
+        let parse = |builder: MetaCallBuilder| {
+            parse_meta_call(
+                &near_erc712_domain(U256::from(1)),
+                1,
+                b"gateway",
+                builder.sign(&signing_key).unwrap(),
+                |_| None,
+            )
+        };
+        let result = parse(wei).unwrap();
+        assert_eq!(result.value, 15 * 10u128.pow(23));
+        assert_eq!(result.value_unit, ValueUnit::Yocto);
+        assert_eq!(
+            parse(builder.value(u128::MAX).value_unit(ValueUnit::Wei)).unwrap_err(),
+            ParsingError::ValueOverflow
+        );
+    }
+
+    #[test]
+    fn test_ft_transfer_call() {
+        let signing_key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+        let message = MetaCallBuilder::new("gateway", 1)
+            .ft_transfer_call("wrap.near", "pool.near", 10u128.pow(24), "swap")
+            .tip(7)
+            .gas(60_000_000_000_000)
+            .sign(&signing_key)
+            .unwrap();
+        let result = parse_meta_call(
+            &near_erc712_domain(U256::from(1)),
+            1,
+            b"gateway",
+            message,
+            |_| None,
+        )
+        .unwrap();
+        assert_eq!(result.contract_address, "wrap.near");
+        assert_eq!(result.method_name, "ft_transfer_call");
+        assert_eq!(result.value, 1);
+        assert_eq!(result.tip, 7);
+        assert_eq!(result.gas, 60_000_000_000_000);
+        assert_eq!(
+            String::from_utf8(result.args).unwrap(),
+            r#"{"amount":"1000000000000000000000000","msg":"swap","receiver_id":"pool.near"}"#
+        );
+    }
+
+    #[test]
+    fn test_nft_calls() {
+        let signing_key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+        let parse = |builder: MetaCallBuilder| {
+            parse_meta_call(
+                &near_erc712_domain(U256::from(1)),
+                1,
+                b"gateway",
+                builder.sign(&signing_key).unwrap(),
+                |_| None,
+            )
+            .unwrap()
+        };
+        let builder = MetaCallBuilder::new("gateway", 1);
+
+        let result = parse(
+            builder
+                .clone()
+                .nft_transfer("nft.near", "bob.near", "1", None),
+        );
+        assert_eq!(result.method_name, "nft_transfer");
+        assert_eq!(result.value, 1);
+        assert_eq!(
+            String::from_utf8(result.args).unwrap(),
+            r#"{"receiver_id":"bob.near","token_id":"1"}"#
+        );
+
+        let result = parse(builder.clone().nft_transfer(
+            "nft.near",
+            "market.near",
+            "1",
+            Some("list"),
+        ));
+        assert_eq!(result.method_name, "nft_transfer_call");
+        assert_eq!(
+            String::from_utf8(result.args).unwrap(),
+            r#"{"msg":"list","receiver_id":"market.near","token_id":"1"}"#
+        );
+
+        let result = parse(builder.nft_approve("nft.near", "1", "market.near", Some("price"), 100));
+        assert_eq!(result.method_name, "nft_approve");
+        assert_eq!(result.value, 100);
+        assert_eq!(
+            String::from_utf8(result.args).unwrap(),
+            r#"{"account_id":"market.near","msg":"price","token_id":"1"}"#
+        );
+    }
+
+    #[test]
+    fn test_create_template() {
+        let signing_key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+        let message = MetaCallBuilder::new("gateway", 1)
+            .create("minimal")
+            .sign(&signing_key)
+            .unwrap();
+        let result = parse_meta_call(
+            &near_erc712_domain(U256::from(1)),
+            1,
+            b"gateway",
+            message,
+            |_| None,
+        )
+        .unwrap();
+        assert_eq!(result.contract_address, "gateway");
+        assert_eq!(result.method_name, "create");
+        assert_eq!(
+            String::from_utf8(result.args).unwrap(),
+            r#"{"template":"minimal"}"#
+        );
+    }
+
+    #[test]
+    fn test_create_implicit() {
+        let signing_key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+        let message = MetaCallBuilder::new("gateway", 1)
+            .create_implicit(&[0xab; 32])
+            .sign(&signing_key)
+            .unwrap();
+        let result = parse_meta_call(
+            &near_erc712_domain(U256::from(1)),
+            1,
+            b"gateway",
+            message,
+            |_| None,
+        )
+        .unwrap();
+        assert_eq!(result.contract_address, "gateway");
+        assert_eq!(result.method_name, "create_implicit");
+        assert_eq!(
+            String::from_utf8(result.args).unwrap(),
+            format!(r#"{{"public_key":"{}"}}"#, base64::encode([0xab; 32]))
+        );
+    }
+}
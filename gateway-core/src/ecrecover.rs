@@ -0,0 +1,300 @@
+use ethabi::Address;
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use k256::elliptic_curve::scalar::IsHigh;
+use primitive_types::H256;
+
+/// Reasons signature recovery or verification fails.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EcRecoverError {
+    /// Signature isn't 65 bytes of `r ‖ s ‖ v`.
+    InvalidLength,
+    /// `r` or `s` is zero or not below the curve order.
+    InvalidScalar,
+    /// `s` is in the upper half of the curve order, see EIP-2.
+    HighS,
+    /// `v` doesn't encode a recovery id.
+    InvalidRecoveryId,
+    /// `v` is an EIP-155 value for a different chain.
+    ChainIdMismatch,
+    /// No public key produces this signature of the message.
+    RecoveryFailed,
+    /// Public key isn't a point on the curve.
+    InvalidPublicKey,
+    /// Signature doesn't match the given public key and message.
+    VerificationFailed,
+}
+
+/// Address controlled by the raw 64-byte `x ‖ y` public key: last 20 bytes of its keccak256.
+pub fn public_key_to_address(public_key: &[u8]) -> Address {
+    use sha3::Digest;
+    let hash = sha3::Keccak256::digest(public_key);
+    Address::from_slice(&hash[12..])
+}
+
+/// See: https://ethereum.github.io/yellowpaper/paper.pdf
+/// See: https://docs.soliditylang.org/en/develop/units-and-global-variables.html#mathematical-and-cryptographic-functions
+/// See: https://etherscan.io/address/0000000000000000000000000000000000000001
+// Quite a few library methods rely on this and that should be changed. This
+// should only be for precompiles.
+/// `v` can be the raw recovery id, 27/28 or EIP-155 `chain_id * 2 + 35/36` for the given chain.
+/// Recovers with `NearEcRecover` inside the contract and `K256` elsewhere, unless a test swapped
+/// the backend with `testing::with_ecrecover`.
+pub(crate) fn ecrecover(
+    hash: H256,
+    signature: &[u8],
+    chain_id: u64,
+) -> Result<Address, EcRecoverError> {
+    #[cfg(feature = "testing")]
+    if let Some(result) = crate::testing::mocked_ecrecover(hash, signature, chain_id) {
+        return result;
+    }
+    #[cfg(all(feature = "near-sdk", target_arch = "wasm32"))]
+    return NearEcRecover.ecrecover(hash, signature, chain_id);
+    #[cfg(not(all(feature = "near-sdk", target_arch = "wasm32")))]
+    K256.ecrecover(hash, signature, chain_id)
+}
+
+/// Backend recovering the address that signed the message hash with a secp256k1 signature,
+/// for `ecrecover`.
+pub trait EcRecover {
+    fn ecrecover(
+        &self,
+        hash: H256,
+        signature: &[u8],
+        chain_id: u64,
+    ) -> Result<Address, EcRecoverError>;
+}
+
+/// Recovery with k256, the backend of the clients and of tests of the contracts.
+#[cfg_attr(all(feature = "near-sdk", target_arch = "wasm32"), allow(dead_code))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct K256;
+
+impl EcRecover for K256 {
+    fn ecrecover(
+        &self,
+        hash: H256,
+        signature: &[u8],
+        chain_id: u64,
+    ) -> Result<Address, EcRecoverError> {
+        k256_ecrecover(hash, signature, chain_id)
+    }
+}
+
+/// Recovery with the host's `ecrecover`, the backend of the contracts, as it costs less gas than
+/// recovering in wasm.
+#[cfg(all(feature = "near-sdk", target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NearEcRecover;
+
+#[cfg(all(feature = "near-sdk", target_arch = "wasm32"))]
+impl EcRecover for NearEcRecover {
+    fn ecrecover(
+        &self,
+        hash: H256,
+        signature: &[u8],
+        chain_id: u64,
+    ) -> Result<Address, EcRecoverError> {
+        let (signature, recovery_id) = parse_signature(signature, chain_id)?;
+        // Malleable signatures are rejected by `parse_signature` already.
+        near_sdk::env::ecrecover(
+            hash.as_bytes(),
+            &signature.to_bytes(),
+            recovery_id.to_byte(),
+            false,
+        )
+        .map(|public_key| public_key_to_address(&public_key))
+        .ok_or(EcRecoverError::RecoveryFailed)
+    }
+}
+
+#[cfg_attr(all(feature = "near-sdk", target_arch = "wasm32"), allow(dead_code))]
+fn k256_ecrecover(hash: H256, signature: &[u8], chain_id: u64) -> Result<Address, EcRecoverError> {
+    let (signature, recovery_id) = parse_signature(signature, chain_id)?;
+    let public_key = VerifyingKey::recover_from_prehash(hash.as_bytes(), &signature, recovery_id)
+        .map_err(|_| EcRecoverError::RecoveryFailed)?;
+    // uncompressed key is 0x04 followed by the raw 64-byte key addresses come from
+    Ok(public_key_to_address(
+        &public_key.to_encoded_point(false).as_bytes()[1..],
+    ))
+}
+
+/// Signature and recovery id of the 65-byte `r ‖ s ‖ v` signature for the chain, the same for
+/// every backend.
+fn parse_signature(
+    signature: &[u8],
+    chain_id: u64,
+) -> Result<(Signature, RecoveryId), EcRecoverError> {
+    if signature.len() != 65 {
+        return Err(EcRecoverError::InvalidLength);
+    }
+
+    let v = signature[64];
+    let signature =
+        Signature::from_slice(&signature[0..64]).map_err(|_| EcRecoverError::InvalidScalar)?;
+    // Both `s` and `-s` are valid for the same message, accepting only the lower one makes
+    // signatures non-malleable. See https://eips.ethereum.org/EIPS/eip-2
+    if bool::from(signature.s().is_high()) {
+        return Err(EcRecoverError::HighS);
+    }
+    let bit = match v {
+        0..=26 => v,
+        27..=34 => v - 27,
+        // See https://eips.ethereum.org/EIPS/eip-155
+        _ => {
+            if u64::from((v - 35) / 2) != chain_id {
+                return Err(EcRecoverError::ChainIdMismatch);
+            }
+            (v - 35) % 2
+        }
+    };
+
+    let recovery_id = RecoveryId::from_byte(bit).ok_or(EcRecoverError::InvalidRecoveryId)?;
+    Ok((signature, recovery_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::{H256, U256};
+
+    use super::{ecrecover, EcRecoverError};
+
+    /// (message hash, signature with v = 27/28, signer's address).
+    /// First one is from web3.js `accounts.sign` docs, others are produced by libsecp256k1,
+    /// which the gateway used before.
+    const VECTORS: &[(&str, &str, &str)] = &[
+        (
+            "1da44b586eb0729ff70a73c326926f6ed5a25f5b056e7f47fbc6e58d86871655",
+            "b91467e570a6466aa9e9876cbcd013baba02900b8979d43fe208a4a4f339f5fd6007e74cd82e037b800186422fc2da167c747ef045e5d18a5f5d4300f8e1a0291c",
+            "2c7536e3605d9c16a7a3d7b1898e529396a65c23",
+        ),
+        (
+            "5fe7f977e71dba2ea1a68e21057beebb9be2ac30c6410aa38d4f3fbe41dcffd2",
+            "90be583c47c290dd5e954c4415da3c0550e4fe9f5c31035aea694e2d846a6d89154a38ac8eeaa37946d8a8d17f0357ddabfcac579461e6a001733815b9a7aef41b",
+            "19e7e376e7c213b7e7e7e46cc70a5dd086daff2a",
+        ),
+        (
+            "f2ee15ea639b73fa3db9b34a245bdfa015c260c598b211bf05a1ecc4b3e3b4f2",
+            "4a61baad6abc1c9d772955f08c77294fb62dda6bf0ff4f096b99e1b1a1ae5df507103218834e1701dc66e1856156ec8f189e211678cc14d2dd2b293423ba77801c",
+            "1563915e194d8cfba1943570603f7606a3115508",
+        ),
+        (
+            "69c322e3248a5dfc29d73c5b0553b0185a35cd5bb6386747517ef7e53b15e287",
+            "5760de48a1318388b5717af34360dbce7f4e71e63318e1a0d8f945656670f4e6496c06f97b1b6118c2f6791d97e3b98260866971ce82870b97196451b87dc44c1b",
+            "5cbdd86a2fa8dc4bddd8a8f69dba48572eec07fb",
+        ),
+        (
+            "f343681465b9efe82c933c3e8748c70cb8aa06539c361de20f72eac04e766393",
+            "5769db898884cef7e328b8a8664786167112281d5ec2f1a8c227e30456a74c1328ffbdc3e62056c1b27c0560fad733b5486ad7d9a34f2dc1a61afae6f302daab1b",
+            "7564105e977516c53be337314c7e53838967bdac",
+        ),
+    ];
+
+    fn vector(i: usize) -> (H256, Vec<u8>, ethabi::Address) {
+        let (hash, signature, address) = VECTORS[i];
+        (
+            H256::from_slice(&hex::decode(hash).unwrap()),
+            hex::decode(signature).unwrap(),
+            ethabi::Address::from_slice(&hex::decode(address).unwrap()),
+        )
+    }
+
+    #[test]
+    fn test_recovery_vectors() {
+        for i in 0..VECTORS.len() {
+            let (hash, signature, address) = vector(i);
+            assert_eq!(ecrecover(hash, &signature, 1), Ok(address));
+            // Any other message gives some other address.
+            assert_ne!(
+                ecrecover(H256::repeat_byte(0x33), &signature, 1),
+                Ok(address)
+            );
+        }
+    }
+
+    #[test]
+    fn test_recovery_id_formats() {
+        let (hash, signature, address) = vector(1);
+        let recovery_id = signature[64] - 27;
+        let with_v = |v: u8| {
+            let mut signature = signature.clone();
+            signature[64] = v;
+            signature
+        };
+
+        assert_eq!(ecrecover(hash, &with_v(recovery_id), 1), Ok(address));
+        assert_eq!(ecrecover(hash, &with_v(recovery_id + 27), 1), Ok(address));
+        // EIP-155 for mainnet and a chain with id 100.
+        assert_eq!(ecrecover(hash, &with_v(recovery_id + 37), 1), Ok(address));
+        assert_eq!(
+            ecrecover(hash, &with_v(recovery_id + 235), 100),
+            Ok(address)
+        );
+
+        assert_eq!(
+            ecrecover(hash, &with_v(recovery_id + 37), 100),
+            Err(EcRecoverError::ChainIdMismatch)
+        );
+        assert_eq!(
+            ecrecover(hash, &with_v(recovery_id + 39), 1),
+            Err(EcRecoverError::ChainIdMismatch)
+        );
+        // The other recovery id gives a different address.
+        assert_ne!(
+            ecrecover(hash, &with_v((recovery_id ^ 1) + 37), 1),
+            Ok(address)
+        );
+    }
+
+    #[test]
+    fn test_high_s_rejected() {
+        let (hash, signature, address) = vector(2);
+
+        // (r, n - s) with the flipped recovery id is the same signature of the same message.
+        let curve_order = U256::from_str_radix(
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+            16,
+        )
+        .unwrap();
+        let s = U256::from_big_endian(&signature[32..64]);
+        let mut malleated = signature.clone();
+        (curve_order - s).to_big_endian(&mut malleated[32..64]);
+        malleated[64] ^= 1;
+        assert_eq!(ecrecover(hash, &malleated, 1), Err(EcRecoverError::HighS));
+        assert_eq!(ecrecover(hash, &signature, 1), Ok(address));
+    }
+    #[test]
+    fn test_malformed_input() {
+        let (hash, signature, _) = vector(0);
+        let with = |index: usize, bytes: &[u8]| {
+            let mut signature = signature.clone();
+            signature[index..index + bytes.len()].copy_from_slice(bytes);
+            signature
+        };
+
+        assert_eq!(
+            ecrecover(hash, &signature[..64], 1),
+            Err(EcRecoverError::InvalidLength)
+        );
+        assert_eq!(
+            ecrecover(hash, &[signature.as_slice(), &[0]].concat(), 1),
+            Err(EcRecoverError::InvalidLength)
+        );
+        assert_eq!(
+            ecrecover(hash, &with(0, &[0; 32]), 1),
+            Err(EcRecoverError::InvalidScalar)
+        );
+        assert_eq!(
+            ecrecover(hash, &with(32, &[0xff; 32]), 1),
+            Err(EcRecoverError::InvalidScalar)
+        );
+        assert_eq!(
+            ecrecover(hash, &with(64, &[5]), 1),
+            Err(EcRecoverError::InvalidRecoveryId)
+        );
+        assert_eq!(
+            ecrecover(hash, &with(64, &[31]), 1),
+            Err(EcRecoverError::InvalidRecoveryId)
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use ed25519_dalek::{Signature, VerifyingKey};
+use ethabi::Address;
+use primitive_types::H256;
+
+use crate::ecrecover::{public_key_to_address, EcRecoverError};
+
+/// Verifies ed25519 signature of the message hash by the 32-byte public key, as held by
+/// Solana and NEAR wallets. The hash itself is the signed message. Address is derived from
+/// the public key the same way as for the ECDSA keys, giving the proxy account name.
+/// Uses strict verification, which rejects non-canonical and small order encodings so the
+/// signature can't be malleated.
+pub(crate) fn verify_ed25519(
+    hash: H256,
+    public_key: &[u8; 32],
+    signature: &[u8; 64],
+) -> Result<Address, EcRecoverError> {
+    let verifying_key =
+        VerifyingKey::from_bytes(public_key).map_err(|_| EcRecoverError::InvalidPublicKey)?;
+    verifying_key
+        .verify_strict(hash.as_bytes(), &Signature::from_bytes(signature))
+        .map_err(|_| EcRecoverError::VerificationFailed)?;
+    Ok(public_key_to_address(public_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::H256;
+
+    use super::{verify_ed25519, EcRecoverError};
+
+    /// (message hash, signature, public key, address), signed with Python `cryptography`.
+    const VECTORS: &[(&str, &str, &str, &str)] = &[
+        (
+            "2b41ca7a88f78533ab3e3a564250157111fd2209459bf9fe9483cc65779e51e3",
+            "992f8fecd527b80ca16f5e5fb072d23ff3cdcb4fa744fe6f2195340af19a3382e09449e9d59041f7d6dc08220256235e69c5ba276d0848853dbc81dc32a20c0f",
+            "0c9188564e6f0984e201db57d001a1a61aeaf5c3c5a0178aa277b939d4485b89",
+            "d9a67a417a62eba33fcb8ba910614120eeb25963",
+        ),
+        (
+            "a211e40eb659fbaa9560d69a76f121f525e1f2c28ab8b8f05ae23f88ce2230f3",
+            "808bbd08a793879555633751d257d5822ac6c9dd6cd3f5c6eec882447d0c354e0ff621938b792e39cf94df21bc95848970c872ee3e0a02583628738fc7e61403",
+            "f276cbe67bb7b8100670844d53616bb3eab71f7949ec67126ba739784e41f140",
+            "cfc5c811052339b5186993a9dd8ba615841281f3",
+        ),
+    ];
+
+    fn vector(i: usize) -> (H256, [u8; 64], [u8; 32], ethabi::Address) {
+        let (hash, signature, public_key, address) = VECTORS[i];
+        let mut signature_bytes = [0u8; 64];
+        signature_bytes.copy_from_slice(&hex::decode(signature).unwrap());
+        let mut public_key_bytes = [0u8; 32];
+        public_key_bytes.copy_from_slice(&hex::decode(public_key).unwrap());
+        (
+            H256::from_slice(&hex::decode(hash).unwrap()),
+            signature_bytes,
+            public_key_bytes,
+            ethabi::Address::from_slice(&hex::decode(address).unwrap()),
+        )
+    }
+
+    #[test]
+    fn test_verify_vectors() {
+        for i in 0..VECTORS.len() {
+            let (hash, signature, public_key, address) = vector(i);
+            assert_eq!(verify_ed25519(hash, &public_key, &signature), Ok(address));
+            assert_eq!(
+                verify_ed25519(H256::repeat_byte(0x33), &public_key, &signature),
+                Err(EcRecoverError::VerificationFailed)
+            );
+        }
+        let (hash, signature, _, _) = vector(0);
+        let (_, _, other_key, _) = vector(1);
+        assert_eq!(
+            verify_ed25519(hash, &other_key, &signature),
+            Err(EcRecoverError::VerificationFailed)
+        );
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        let (hash, signature, public_key, _) = vector(0);
+
+        // `s` not reduced modulo the group order.
+        let mut unreduced = signature;
+        unreduced[32..].copy_from_slice(&[0xff; 32]);
+        assert_eq!(
+            verify_ed25519(hash, &public_key, &unreduced),
+            Err(EcRecoverError::VerificationFailed)
+        );
+
+        // Identity is a small order point, accepting it would let anyone sign for the key.
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        assert_eq!(
+            verify_ed25519(hash, &identity, &signature),
+            Err(EcRecoverError::VerificationFailed)
+        );
+    }
+}
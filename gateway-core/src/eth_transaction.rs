@@ -0,0 +1,346 @@
+use ethabi::Address;
+use primitive_types::{H256, U256};
+use rlp::{Rlp, RlpStream};
+
+use crate::ecrecover::{ecrecover, EcRecoverError};
+use crate::meta_parsing::{ParsingError, ParsingResult, MIN_GATEWAY_CHAIN_ID};
+use crate::types::{keccak256, u256_to_arr};
+
+/// Signed Ethereum transaction, with the fields the gateway maps onto a meta call.
+#[derive(Debug)]
+pub(crate) struct EthTransaction {
+    pub nonce: U256,
+    /// None for contract creation.
+    pub to: Option<Address>,
+    pub value: U256,
+    pub data: Vec<u8>,
+    /// Tip in wei the sender pays for inclusion, zero for legacy transactions.
+    pub fee: U256,
+    /// Hash the sender signed.
+    signing_hash: H256,
+    /// `r ‖ s ‖ recovery id`.
+    signature: [u8; 65],
+}
+
+fn field<T: rlp::Decodable>(rlp: &Rlp<'_>, index: usize) -> ParsingResult<T> {
+    rlp.val_at(index)
+        .map_err(|_| ParsingError::ArgumentParseError)
+}
+
+fn decode_to(rlp: &Rlp<'_>, index: usize) -> ParsingResult<Option<Address>> {
+    let to = rlp
+        .at(index)
+        .and_then(|item| item.data())
+        .map_err(|_| ParsingError::ArgumentParseError)?;
+    match to.len() {
+        0 => Ok(None),
+        20 => Ok(Some(Address::from_slice(to))),
+        _ => Err(ParsingError::ArgumentParseError),
+    }
+}
+
+/// Checks the access list is `[[address, [storageKey, ...]], ...]`. It's not used otherwise.
+fn check_access_list(rlp: &Rlp<'_>) -> ParsingResult<()> {
+    let has_size = |item: &Rlp<'_>, size: usize| item.data().map(<[u8]>::len) == Ok(size);
+    if !rlp.is_list() {
+        return Err(ParsingError::ArgumentParseError);
+    }
+    for entry in rlp.iter() {
+        let address = entry.at(0).map_err(|_| ParsingError::ArgumentParseError)?;
+        let keys = entry.at(1).map_err(|_| ParsingError::ArgumentParseError)?;
+        if entry.item_count() != Ok(2)
+            || !has_size(&address, 20)
+            || !keys.is_list()
+            || !keys.iter().all(|key| has_size(&key, 32))
+        {
+            return Err(ParsingError::ArgumentParseError);
+        }
+    }
+    Ok(())
+}
+
+fn decode_signature(rlp: &Rlp<'_>, index: usize, recovery_id: u8) -> ParsingResult<[u8; 65]> {
+    let mut signature = [0u8; 65];
+    signature[..32].copy_from_slice(&u256_to_arr(&field(rlp, index)?));
+    signature[32..64].copy_from_slice(&u256_to_arr(&field(rlp, index + 1)?));
+    signature[64] = recovery_id;
+    Ok(signature)
+}
+
+/// EIP-2718 type of EIP-1559 transactions.
+const EIP1559_TX_TYPE: u8 = 2;
+
+impl EthTransaction {
+    /// Whether the bytes are shaped as a legacy or EIP-1559 transaction: single RLP list,
+    /// prefixed by the type for the latter.
+    pub fn is_envelope(bytes: &[u8]) -> bool {
+        let payload = match bytes.first() {
+            Some(&EIP1559_TX_TYPE) => &bytes[1..],
+            _ => bytes,
+        };
+        let rlp = Rlp::new(payload);
+        rlp.is_list() && rlp.as_raw().len() == payload.len()
+    }
+
+    /// Data of the transaction, without checking its chain or signature.
+    pub fn data_of(bytes: &[u8]) -> Option<Vec<u8>> {
+        let (payload, index) = match bytes.first() {
+            Some(&EIP1559_TX_TYPE) => (&bytes[1..], 7),
+            _ => (bytes, 5),
+        };
+        Rlp::new(payload).val_at(index).ok()
+    }
+
+    /// Decodes signed legacy or EIP-1559 transaction for `chain_id`, which must be a gateway's
+    /// rather than a public chain's, see `gateway_chain_id`.
+    pub fn decode(chain_id: u64, bytes: &[u8]) -> ParsingResult<Self> {
+        if chain_id < MIN_GATEWAY_CHAIN_ID {
+            return Err(ParsingError::ChainIdMismatch);
+        }
+        // See https://eips.ethereum.org/EIPS/eip-2718
+        match bytes.first() {
+            Some(&EIP1559_TX_TYPE) => Self::decode_eip1559(chain_id, &bytes[1..]),
+            Some(0xc0..=0xff) => Self::decode_legacy(chain_id, bytes),
+            _ => Err(ParsingError::ArgumentParseError),
+        }
+    }
+
+    /// Decodes `rlp([nonce, gasPrice, gasLimit, to, value, data, v, r, s])`. Only EIP-155
+    /// signatures are accepted, as the older ones can be replayed on any chain.
+    fn decode_legacy(chain_id: u64, bytes: &[u8]) -> ParsingResult<Self> {
+        let rlp = Rlp::new(bytes);
+        if rlp.item_count() != Ok(9) || rlp.as_raw().len() != bytes.len() {
+            return Err(ParsingError::ArgumentParseError);
+        }
+        // See https://eips.ethereum.org/EIPS/eip-155
+        let v: u64 = field(&rlp, 6)?;
+        if v < 35 || (v - 35) / 2 != chain_id {
+            return Err(ParsingError::ChainIdMismatch);
+        }
+        let mut stream = RlpStream::new_list(9);
+        for index in 0..6 {
+            stream.append_raw(
+                rlp.at(index)
+                    .map_err(|_| ParsingError::ArgumentParseError)?
+                    .as_raw(),
+                1,
+            );
+        }
+        stream.append(&chain_id).append(&0u8).append(&0u8);
+        Ok(EthTransaction {
+            nonce: field(&rlp, 0)?,
+            to: decode_to(&rlp, 3)?,
+            value: field(&rlp, 4)?,
+            data: field(&rlp, 5)?,
+            fee: U256::zero(),
+            signing_hash: H256::from_slice(&keccak256(&stream.out())),
+            signature: decode_signature(&rlp, 7, ((v - 35) % 2) as u8)?,
+        })
+    }
+
+    /// Decodes payload of type 2 transaction `rlp([chainId, nonce, maxPriorityFeePerGas,
+    /// maxFeePerGas, gasLimit, to, value, data, accessList, yParity, r, s])`.
+    /// The fee is the priority fee, capped by the max fee, for the whole gas limit.
+    fn decode_eip1559(chain_id: u64, bytes: &[u8]) -> ParsingResult<Self> {
+        // See https://eips.ethereum.org/EIPS/eip-1559
+        let rlp = Rlp::new(bytes);
+        if rlp.item_count() != Ok(12) || rlp.as_raw().len() != bytes.len() {
+            return Err(ParsingError::ArgumentParseError);
+        }
+        if field::<u64>(&rlp, 0)? != chain_id {
+            return Err(ParsingError::ChainIdMismatch);
+        }
+        let max_priority_fee: U256 = field(&rlp, 2)?;
+        let max_fee: U256 = field(&rlp, 3)?;
+        let gas_limit: U256 = field(&rlp, 4)?;
+        let fee = max_priority_fee
+            .min(max_fee)
+            .checked_mul(gas_limit)
+            .ok_or(ParsingError::ArgumentParseError)?;
+        check_access_list(&rlp.at(8).map_err(|_| ParsingError::ArgumentParseError)?)?;
+        let y_parity: u8 = field(&rlp, 9)?;
+        if y_parity > 1 {
+            return Err(ParsingError::InvalidEcRecoverSignature);
+        }
+        let mut stream = RlpStream::new_list(9);
+        for index in 0..9 {
+            stream.append_raw(
+                rlp.at(index)
+                    .map_err(|_| ParsingError::ArgumentParseError)?
+                    .as_raw(),
+                1,
+            );
+        }
+        let mut payload = vec![EIP1559_TX_TYPE];
+        payload.extend_from_slice(&stream.out());
+        Ok(EthTransaction {
+            nonce: field(&rlp, 1)?,
+            to: decode_to(&rlp, 5)?,
+            value: field(&rlp, 6)?,
+            data: field(&rlp, 7)?,
+            fee,
+            signing_hash: H256::from_slice(&keccak256(&payload)),
+            signature: decode_signature(&rlp, 10, y_parity)?,
+        })
+    }
+
+    /// Address of the key that signed the transaction.
+    pub fn sender(&self, chain_id: u64) -> Result<Address, EcRecoverError> {
+        ecrecover(self.signing_hash, &self.signature, chain_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+
+    use super::EthTransaction;
+    use crate::meta_parsing::{gateway_chain_id, ParsingError};
+
+    /// Example from EIP-155, signed by the key 0x4646...46 for Ethereum mainnet.
+    const EIP155_TX: &str = "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83";
+
+    #[test]
+    fn test_eip155_transaction() {
+        let tx = EthTransaction::decode_legacy(1, &hex::decode(EIP155_TX).unwrap()).unwrap();
+        assert_eq!(tx.nonce, U256::from(9));
+        assert_eq!(tx.to.unwrap().as_bytes(), &[0x35; 20]);
+        assert_eq!(tx.value, U256::from(10).pow(U256::from(18)));
+        assert!(tx.data.is_empty());
+        assert_eq!(
+            hex::encode(tx.sender(1).unwrap()),
+            "9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f"
+        );
+    }
+
+    #[test]
+    fn test_mainnet_transaction() {
+        // The signer's mainnet transactions can't be replayed on a gateway, even one expecting
+        // mainnet's chain id.
+        let bytes = hex::decode(EIP155_TX).unwrap();
+        assert_eq!(
+            EthTransaction::decode(gateway_chain_id(b"gateway"), &bytes).unwrap_err(),
+            ParsingError::ChainIdMismatch
+        );
+        assert_eq!(
+            EthTransaction::decode(1, &bytes).unwrap_err(),
+            ParsingError::ChainIdMismatch
+        );
+    }
+
+    #[test]
+    fn test_wrong_chain_or_encoding() {
+        let bytes = hex::decode(EIP155_TX).unwrap();
+        assert!(EthTransaction::is_envelope(&bytes));
+        assert!(!EthTransaction::is_envelope(&bytes[1..]));
+        assert_eq!(
+            EthTransaction::decode_legacy(5, &bytes).unwrap_err(),
+            ParsingError::ChainIdMismatch
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            EthTransaction::decode_legacy(1, &trailing).unwrap_err(),
+            ParsingError::ArgumentParseError
+        );
+        assert_eq!(
+            EthTransaction::decode_legacy(1, &bytes[..bytes.len() - 1]).unwrap_err(),
+            ParsingError::ArgumentParseError
+        );
+    }
+
+    #[test]
+    fn test_gateway_chain_id() {
+        let chain_id = gateway_chain_id(b"gateway");
+        assert_eq!(chain_id, gateway_chain_id(b"gateway"));
+        assert_ne!(chain_id, gateway_chain_id(b"other"));
+        assert!(chain_id >= crate::meta_parsing::MIN_GATEWAY_CHAIN_ID);
+        // Largest chain id wallets accept, so `v` fits JavaScript numbers.
+        assert!(chain_id <= 4503599627370476);
+    }
+
+    #[test]
+    fn test_eip1559_transaction() {
+        use k256::ecdsa::SigningKey;
+        use rlp::RlpStream;
+
+        use crate::types::keccak256;
+
+        let mut access_list = RlpStream::new_list(1);
+        access_list
+            .begin_list(2)
+            .append(&vec![0x11u8; 20])
+            .begin_list(1)
+            .append(&vec![0x22u8; 32]);
+        let access_list = access_list.out().to_vec();
+        let fields = |stream: &mut RlpStream, chain_id: u64, access_list: &[u8]| {
+            stream
+                .append(&chain_id)
+                .append(&3u8)
+                .append(&U256::from(2_000_000_000u64))
+                .append(&U256::from(1_000_000_000u64))
+                .append(&21000u32)
+                .append(&vec![0x35u8; 20])
+                .append(&U256::from(10).pow(U256::from(18)))
+                .append(&Vec::<u8>::new())
+                .append_raw(access_list, 1);
+        };
+        let signing_key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+        let sign = |chain_id: u64, access_list: &[u8]| {
+            let mut stream = RlpStream::new_list(9);
+            fields(&mut stream, chain_id, access_list);
+            let mut payload = vec![2u8];
+            payload.extend_from_slice(&stream.out());
+            let (signature, recovery_id) = signing_key
+                .sign_prehash_recoverable(&keccak256(&payload))
+                .unwrap();
+            let signature = signature.to_bytes();
+            let mut stream = RlpStream::new_list(12);
+            fields(&mut stream, chain_id, access_list);
+            stream
+                .append(&recovery_id.to_byte())
+                .append(&U256::from_big_endian(&signature[..32]))
+                .append(&U256::from_big_endian(&signature[32..]));
+            let mut tx = vec![2u8];
+            tx.extend_from_slice(&stream.out());
+            tx
+        };
+
+        let chain_id = gateway_chain_id(b"gateway");
+        let tx = EthTransaction::decode(chain_id, &sign(chain_id, &access_list)).unwrap();
+        assert_eq!(tx.nonce, U256::from(3));
+        assert_eq!(tx.to.unwrap().as_bytes(), &[0x35; 20]);
+        // Priority fee is capped by the max fee.
+        assert_eq!(tx.fee, U256::from(21000u64 * 1_000_000_000));
+        assert_eq!(
+            hex::encode(tx.sender(chain_id).unwrap()),
+            "9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f"
+        );
+        // Signed for another gateway or for mainnet.
+        for other in [gateway_chain_id(b"other"), 1] {
+            assert_eq!(
+                EthTransaction::decode(chain_id, &sign(other, &access_list)).unwrap_err(),
+                ParsingError::ChainIdMismatch
+            );
+        }
+
+        // Storage key of the wrong size.
+        let mut access_list = RlpStream::new_list(1);
+        access_list
+            .begin_list(2)
+            .append(&vec![0x11u8; 20])
+            .begin_list(1)
+            .append(&vec![0x22u8; 31]);
+        assert_eq!(
+            EthTransaction::decode(chain_id, &sign(chain_id, &access_list.out())).unwrap_err(),
+            ParsingError::ArgumentParseError
+        );
+        // Unsupported EIP-2930 transaction type.
+        let mut tx = sign(chain_id, &[0xc0]);
+        tx[0] = 1;
+        assert_eq!(
+            EthTransaction::decode(chain_id, &tx).unwrap_err(),
+            ParsingError::ArgumentParseError
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Reference vectors produced by the Ethereum wallet libraries, checked against the hashing and
+//! recovery the gateway does, so incompatibilities with wallets show up in tests.
+
+use primitive_types::H256;
+
+use crate::ecrecover::ecrecover;
+use crate::meta_parsing::{eip_712_hash_argument, MethodAndTypes, RlpValue};
+use crate::siwe::personal_message_hash;
+use crate::types::keccak256;
+
+/// Typed data of the EIP-712 example, signed by the key `keccak256("cow")` with
+/// `eth_signTypedData_v4`. Used as the reference by eth-sig-util's `signTypedData` and ethers'
+/// `TypedDataEncoder` tests.
+/// See https://github.com/ethereum/EIPs/blob/master/assets/eip-712/Example.js
+mod mail {
+    pub const DOMAIN_TYPE: &str =
+        "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+    pub const VERIFYING_CONTRACT: &str = "CcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC";
+    pub const MAIL_TYPE: &str =
+        "Mail(Person from,Person to,string contents)Person(string name,address wallet)";
+    pub const FROM: (&str, &str) = ("Cow", "CD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826");
+    pub const TO: (&str, &str) = ("Bob", "bBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB");
+    pub const CONTENTS: &str = "Hello, Bob!";
+
+    pub const DOMAIN_SEPARATOR: &str =
+        "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f";
+    pub const HASH_STRUCT: &str =
+        "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e";
+    pub const DIGEST: &str = "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2";
+    pub const SIGNATURE: &str = "4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b915621c";
+}
+
+/// ethers' `hashMessage("Hello World")`, the EIP-191 hash `personal_sign` signs.
+const HELLO_WORLD_HASH: &str = "a1de988600a42c4b4ab089b619297c17d53cffae5d5120d82d8a92d0bb3b78f2";
+
+/// Hash of the single struct argument of `method(Type value)` with the types.
+fn hash_struct(types: &str, value: RlpValue) -> Vec<u8> {
+    let name = &types[..types.find('(').unwrap()];
+    let methods = MethodAndTypes::parse(&format!("f({} value){}", name, types)).unwrap();
+    eip_712_hash_argument(&methods.method.args[0].t, &value, &methods.types).unwrap()
+}
+
+fn bytes(value: &str) -> RlpValue {
+    RlpValue::Bytes(value.as_bytes().to_vec())
+}
+
+fn address(value: &str) -> RlpValue {
+    RlpValue::Bytes(hex::decode(value).unwrap())
+}
+
+#[test]
+fn test_eip712_mail() {
+    let domain = hash_struct(
+        mail::DOMAIN_TYPE,
+        RlpValue::List(vec![
+            bytes("Ether Mail"),
+            bytes("1"),
+            RlpValue::Bytes(vec![1]),
+            address(mail::VERIFYING_CONTRACT),
+        ]),
+    );
+    assert_eq!(hex::encode(&domain), mail::DOMAIN_SEPARATOR);
+
+    let person = |(name, wallet): (&str, &str)| RlpValue::List(vec![bytes(name), address(wallet)]);
+    let message = hash_struct(
+        mail::MAIL_TYPE,
+        RlpValue::List(vec![
+            person(mail::FROM),
+            person(mail::TO),
+            bytes(mail::CONTENTS),
+        ]),
+    );
+    assert_eq!(hex::encode(&message), mail::HASH_STRUCT);
+
+    let mut data = b"\x19\x01".to_vec();
+    data.extend_from_slice(&domain);
+    data.extend_from_slice(&message);
+    let digest = keccak256(&data);
+    assert_eq!(hex::encode(&digest), mail::DIGEST);
+
+    let signer = ecrecover(
+        H256::from_slice(&digest),
+        &hex::decode(mail::SIGNATURE).unwrap(),
+        1,
+    )
+    .unwrap();
+    assert_eq!(hex::encode(signer), mail::FROM.1.to_lowercase());
+}
+
+#[test]
+fn test_personal_message_hash() {
+    assert_eq!(
+        hex::encode(personal_message_hash(b"Hello World")),
+        HELLO_WORLD_HASH
+    );
+}
@@ -0,0 +1,24 @@
+//! Parsing, EIP-712 hashing and signer recovery of the gateway meta transactions.
+//! Shared by the gateway contract and off-chain tools, like relayers and wallets,
+//! to validate messages exactly as the contract does.
+
+mod abi_parsing;
+mod bitcoin;
+#[cfg(feature = "client")]
+pub mod client;
+mod ecrecover;
+mod ed25519;
+mod eth_transaction;
+#[cfg(test)]
+mod fixtures;
+pub mod meta_parsing;
+#[cfg(test)]
+mod proptests;
+mod secp256r1;
+pub mod siwe;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod transcoding;
+pub mod types;
+#[cfg(feature = "wasm")]
+mod wasm;
@@ -0,0 +1,2939 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+
+use borsh::maybestd::io::{self, Write};
+use borsh::{BorshDeserialize, BorshSerialize};
+use ethabi::{ParamType, Token};
+#[cfg(feature = "logos")]
+use logos::Logos;
+use primitive_types::{H256, U256};
+use rlp::{Decodable, DecoderError, Rlp};
+
+use crate::abi_parsing::normalize_method_def;
+use crate::ecrecover::EcRecoverError;
+use crate::transcoding;
+use crate::types::{
+    arr_to_u256, keccak256, u256_to_arr, Address, ArgsEncoding, CallArgsFormat, DepositSource,
+    InternalMetaCallArgs, MetaCallArgs, MetaCallArgsJson, MetaCallSignature, RawU256,
+    RecoveredSigner, TypedDataSchema, ValueUnit,
+};
+
+/// Internal errors to propagate up and format in the single place.
+#[derive(Debug, PartialEq)]
+pub enum ParsingError {
+    ArgumentParseError,
+    InvalidMetaTransactionMethodName,
+    InvalidMetaTransactionFunctionArg,
+    InvalidEcRecoverSignature,
+    /// Signature's `v` is EIP-155 encoded for a different chain id.
+    ChainIdMismatch,
+    ArgsLengthMismatch,
+    /// Value doesn't match the size declared by its type: `bytesN` width, `address` width,
+    /// number of elements in a fixed-size array or number of fields in a struct.
+    ArgSizeMismatch,
+    /// `intN` or `uintN` value doesn't fit in its N bits.
+    IntOutOfRange,
+    /// Meta transaction references a method definition that isn't registered.
+    UnknownMethodId,
+    /// Syntax error in the method definition.
+    UnexpectedToken {
+        /// Byte offset of the offending token in the method definition (or in the type given to `parse_type`).
+        offset: usize,
+        /// The offending token, empty at the end of input.
+        found: String,
+        /// What the parser expected to find at `offset`.
+        expected: &'static str,
+    },
+    /// SIWE message is for another domain.
+    SiweDomainMismatch,
+    /// SIWE message is past its expiration time.
+    SiweExpired,
+    /// SIWE message is before its not before time.
+    SiweNotYetValid,
+    /// Value doesn't fit in yoctoNEAR once converted from its unit.
+    ValueOverflow,
+    /// Amount signed as `uint256` is above what a NEAR balance can hold.
+    AmountOverflow {
+        /// Field of the message with the amount.
+        field: &'static str,
+    },
+}
+
+impl ParsingError {
+    /// Move the error position by `offset`, for errors found in a part of a larger text.
+    fn shifted(self, by: usize) -> Self {
+        match self {
+            ParsingError::UnexpectedToken {
+                offset,
+                found,
+                expected,
+            } => ParsingError::UnexpectedToken {
+                offset: offset + by,
+                found,
+                expected,
+            },
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParsingError::UnexpectedToken {
+                offset,
+                found,
+                expected,
+            } if found.is_empty() => write!(
+                f,
+                "expected {} at offset {}, found end of input",
+                expected, offset
+            ),
+            ParsingError::UnexpectedToken {
+                offset,
+                found,
+                expected,
+            } => write!(
+                f,
+                "expected {} at offset {}, found \"{}\"",
+                expected, offset, found
+            ),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+pub type ParsingResult<T> = core::result::Result<T, ParsingError>;
+
+/// Error for unexpected input at the start of `text`, which is the remainder of `source`.
+pub(crate) fn unexpected(source: &str, text: &str, expected: &'static str) -> ParsingError {
+    ParsingError::UnexpectedToken {
+        offset: source.len() - text.len(),
+        found: text.chars().next().map(String::from).unwrap_or_default(),
+        expected,
+    }
+}
+
+#[cfg(feature = "logos")]
+mod type_lexer {
+    use logos::{Lexer, Logos};
+
+    pub(super) type TypeLexer<'a> = Lexer<'a, Token>;
+
+    #[derive(Logos, Debug, PartialEq)]
+    pub(super) enum Token {
+        #[regex("byte|bytes[1-2][0-9]?|bytes3[0-2]?|bytes[4-9]", fixed_bytes_size)]
+        FixedBytes(u8),
+        #[regex("uint(8|16|24|32|40|48|56|64|72|80|88|96|104|112|120|128|136|144|152|160|168|176|184|192|200|208|216|224|232|240|248|256)?", |lex| fixed_int_size(lex, "uint"))]
+        Uint(usize),
+        #[regex("int(8|16|24|32|40|48|56|64|72|80|88|96|104|112|120|128|136|144|152|160|168|176|184|192|200|208|216|224|232|240|248|256)?", |lex| fixed_int_size(lex, "int"))]
+        Int(usize),
+        #[regex("bool")]
+        Bool,
+        #[regex("address")]
+        Address,
+        #[regex("bytes")]
+        Bytes,
+        #[regex("string")]
+        String,
+        #[regex("\\[[0-9]*\\]", reference_type_size)]
+        ReferenceType(Option<u64>),
+        #[regex("[a-zA-Z_$][a-zA-Z0-9_$]*")]
+        Identifier,
+
+        #[error]
+        Error,
+    }
+
+    fn fixed_bytes_size(lex: &mut Lexer<Token>) -> u8 {
+        let slice = lex.slice();
+
+        if slice == "byte" {
+            return 1;
+        }
+
+        let n = slice["bytes".len()..].parse();
+        n.ok().unwrap_or(1)
+    }
+
+    fn fixed_int_size(lex: &mut Lexer<Token>, prefix: &str) -> usize {
+        let slice = lex.slice();
+
+        if slice == prefix {
+            // uint and int are aliases for uint256 and int256
+            return 256;
+        }
+
+        let n = slice[prefix.len()..].parse();
+        n.unwrap_or(256)
+    }
+
+    fn reference_type_size(lex: &mut Lexer<Token>) -> Option<u64> {
+        let slice = lex.slice();
+
+        if slice == "[]" {
+            return None;
+        }
+
+        let end_index = slice.len() - 1;
+        let n = slice[1..end_index].parse();
+        n.ok()
+    }
+}
+
+/// Hand-written scanner producing the same tokens as the logos lexer above,
+/// for builds without the `logos` feature.
+#[cfg(not(feature = "logos"))]
+mod type_lexer {
+    use core::ops::Range;
+
+    #[derive(Debug, PartialEq)]
+    pub(super) enum Token {
+        FixedBytes(u8),
+        Uint(usize),
+        Int(usize),
+        Bool,
+        Address,
+        Bytes,
+        String,
+        ReferenceType(Option<u64>),
+        Identifier,
+        Error,
+    }
+
+    impl Token {
+        pub(super) fn lexer(source: &str) -> TypeLexer<'_> {
+            TypeLexer { source, span: 0..0 }
+        }
+    }
+
+    pub(super) struct TypeLexer<'a> {
+        source: &'a str,
+        span: Range<usize>,
+    }
+
+    impl<'a> TypeLexer<'a> {
+        /// Position of the last token in the source.
+        pub(super) fn span(&self) -> Range<usize> {
+            self.span.clone()
+        }
+
+        /// Text of the last token.
+        pub(super) fn slice(&self) -> &'a str {
+            &self.source[self.span.clone()]
+        }
+    }
+
+    impl<'a> Iterator for TypeLexer<'a> {
+        type Item = Token;
+
+        fn next(&mut self) -> Option<Token> {
+            let start = self.span.end;
+            let remains = &self.source[start..];
+            let first = remains.chars().next()?;
+            let (token, len) = if is_ident_start(first) {
+                let len = remains.find(|c| !is_ident_char(c)).unwrap_or(remains.len());
+                // Elementary type names win over identifiers only if they span the whole word,
+                // e.g. "uint256" is a type, while "uint256x" and "uint7" are identifiers.
+                (keyword(&remains[..len]).unwrap_or(Token::Identifier), len)
+            } else if first == '[' {
+                let digits_len = remains[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(remains.len() - 1);
+                if remains[1 + digits_len..].starts_with(']') {
+                    // "[]" and lengths that don't fit are both dynamic arrays, as with logos.
+                    let length = remains[1..1 + digits_len].parse().ok();
+                    (Token::ReferenceType(length), digits_len + 2)
+                } else {
+                    (Token::Error, 1)
+                }
+            } else {
+                (Token::Error, first.len_utf8())
+            };
+            self.span = start..start + len;
+            Some(token)
+        }
+    }
+
+    fn is_ident_start(c: char) -> bool {
+        c.is_ascii_alphabetic() || c == '_' || c == '$'
+    }
+
+    fn is_ident_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_' || c == '$'
+    }
+
+    /// Token of the elementary type named by the whole `word`, if any.
+    fn keyword(word: &str) -> Option<Token> {
+        match word {
+            "bool" => return Some(Token::Bool),
+            "address" => return Some(Token::Address),
+            "bytes" => return Some(Token::Bytes),
+            "string" => return Some(Token::String),
+            "byte" => return Some(Token::FixedBytes(1)),
+            // uint and int are aliases for uint256 and int256
+            "uint" => return Some(Token::Uint(256)),
+            "int" => return Some(Token::Int(256)),
+            _ => {}
+        }
+        if let Some(size) = word.strip_prefix("bytes") {
+            type_size(size)
+                .filter(|size| (1..=32).contains(size))
+                .map(|size| Token::FixedBytes(size as u8))
+        } else if let Some(size) = word.strip_prefix("uint") {
+            type_size(size).filter(is_int_size).map(Token::Uint)
+        } else if let Some(size) = word.strip_prefix("int") {
+            type_size(size).filter(is_int_size).map(Token::Int)
+        } else {
+            None
+        }
+    }
+
+    /// Size suffix of a type name, which must have no leading zeros.
+    fn type_size(digits: &str) -> Option<usize> {
+        if digits.starts_with('0') || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    }
+
+    fn is_int_size(size: &usize) -> bool {
+        (8..=256).step_by(8).any(|bits| bits == *size)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgType {
+    Address,
+    /// Unsigned integer with the given number of bits.
+    Uint(usize),
+    /// Signed integer with the given number of bits.
+    Int(usize),
+    String,
+    Bool,
+    Bytes,
+    Byte(u8),
+    Custom(String),
+    Array {
+        length: Option<u64>,
+        inner: Box<ArgType>,
+    },
+}
+
+// Borsh derive can't handle the recursive `Array` variant, so the encoding is spelled out.
+impl BorshSerialize for ArgType {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            ArgType::Address => 0u8.serialize(writer),
+            ArgType::Uint(size) => (1u8, *size as u64).serialize(writer),
+            ArgType::Int(size) => (2u8, *size as u64).serialize(writer),
+            ArgType::String => 3u8.serialize(writer),
+            ArgType::Bool => 4u8.serialize(writer),
+            ArgType::Bytes => 5u8.serialize(writer),
+            ArgType::Byte(size) => (6u8, *size).serialize(writer),
+            ArgType::Custom(name) => (7u8, name).serialize(writer),
+            ArgType::Array { length, inner } => {
+                (8u8, length).serialize(writer)?;
+                inner.as_ref().serialize(writer)
+            }
+        }
+    }
+}
+
+impl BorshDeserialize for ArgType {
+    fn deserialize(buf: &mut &[u8]) -> io::Result<Self> {
+        Ok(match u8::deserialize(buf)? {
+            0 => ArgType::Address,
+            1 => ArgType::Uint(u64::deserialize(buf)? as usize),
+            2 => ArgType::Int(u64::deserialize(buf)? as usize),
+            3 => ArgType::String,
+            4 => ArgType::Bool,
+            5 => ArgType::Bytes,
+            6 => ArgType::Byte(u8::deserialize(buf)?),
+            7 => ArgType::Custom(String::deserialize(buf)?),
+            8 => ArgType::Array {
+                length: Option::<u64>::deserialize(buf)?,
+                inner: Box::new(ArgType::deserialize(buf)?),
+            },
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "ArgType")),
+        })
+    }
+}
+
+/// the type string is being validated before it's parsed.
+/// field_type: A single evm function arg type in string, without the argument name
+/// e.g. "bytes" "uint256[][3]" "CustomStructName"
+pub fn parse_type(field_type: &str) -> ParsingResult<ArgType> {
+    let mut lexer = type_lexer::Token::lexer(field_type);
+    let mut current_token = lexer.next();
+    let mut inner_type: Option<ArgType> = None;
+
+    loop {
+        let typ = match current_token {
+            None => break,
+            Some(type_lexer::Token::Address) => ArgType::Address,
+            Some(type_lexer::Token::Bool) => ArgType::Bool,
+            Some(type_lexer::Token::String) => ArgType::String,
+            Some(type_lexer::Token::Bytes) => ArgType::Bytes,
+            Some(type_lexer::Token::Identifier) => ArgType::Custom(lexer.slice().to_owned()),
+            Some(type_lexer::Token::FixedBytes(size)) => ArgType::Byte(size),
+            Some(type_lexer::Token::Int(size)) => ArgType::Int(size),
+            Some(type_lexer::Token::Uint(size)) => ArgType::Uint(size),
+            Some(type_lexer::Token::ReferenceType(length)) => match inner_type {
+                None => return Err(unexpected_type_token(&lexer, "array element type")),
+                Some(t) => ArgType::Array {
+                    length,
+                    inner: Box::new(t),
+                },
+            },
+            Some(type_lexer::Token::Error) => return Err(unexpected_type_token(&lexer, "type")),
+        };
+        inner_type = Some(typ);
+        current_token = lexer.next();
+    }
+
+    inner_type.ok_or(ParsingError::UnexpectedToken {
+        offset: 0,
+        found: String::new(),
+        expected: "type",
+    })
+}
+
+fn unexpected_type_token(
+    lexer: &type_lexer::TypeLexer<'_>,
+    expected: &'static str,
+) -> ParsingError {
+    ParsingError::UnexpectedToken {
+        offset: lexer.span().start,
+        found: lexer.slice().to_owned(),
+        expected,
+    }
+}
+
+/// EIP-712 definition of the domain of NEAR meta transactions.
+pub const EIP712_DOMAIN_TYPE: &str = "EIP712Domain(string name,string version,uint256 chainId)";
+/// Name of the EIP-712 domain.
+pub const NEAR_DOMAIN_NAME: &str = "NEAR";
+/// Version of the EIP-712 domain.
+pub const NEAR_DOMAIN_VERSION: &str = "1";
+
+/// keccak256(EIP712_DOMAIN_TYPE)
+const EIP712_DOMAIN_TYPE_HASH: RawU256 = [
+    0xc2, 0xf8, 0x78, 0x71, 0x76, 0xb8, 0xac, 0x6b, 0xf7, 0x21, 0x5b, 0x4a, 0xdc, 0xc1, 0xe0, 0x69,
+    0xbf, 0x4a, 0xb8, 0x2d, 0x9a, 0xb1, 0xdf, 0x05, 0xa5, 0x7a, 0x91, 0xd4, 0x25, 0x93, 0x5b, 0x6e,
+];
+/// keccak256("NEAR"), hash of the domain name.
+const NEAR_DOMAIN_NAME_HASH: RawU256 = [
+    0xa4, 0x86, 0xe4, 0xb2, 0x7c, 0xce, 0x13, 0x1b, 0xfe, 0xac, 0xd0, 0x03, 0x01, 0x8c, 0x22, 0xa5,
+    0x57, 0x44, 0xbd, 0xb9, 0x48, 0x21, 0x82, 0x9f, 0x0f, 0xf1, 0xd4, 0x06, 0x1d, 0x8d, 0x05, 0x33,
+];
+/// keccak256("1"), hash of the domain version.
+const NEAR_DOMAIN_VERSION_HASH: RawU256 = [
+    0xc8, 0x9e, 0xfd, 0xaa, 0x54, 0xc0, 0xf2, 0x0c, 0x7a, 0xdf, 0x61, 0x28, 0x82, 0xdf, 0x09, 0x50,
+    0xf5, 0xa9, 0x51, 0x63, 0x7e, 0x03, 0x07, 0xcd, 0xcb, 0x4c, 0x67, 0x2f, 0x29, 0x8b, 0x8b, 0xc6,
+];
+
+/// NEAR's domainSeparator
+/// See https://eips.ethereum.org/EIPS/eip-712#definition-of-domainseparator
+/// and https://eips.ethereum.org/EIPS/eip-712#rationale-for-domainseparator
+/// for definition and rationale for domainSeparator.
+pub fn near_erc712_domain(chain_id: U256) -> RawU256 {
+    let mut bytes = Vec::with_capacity(128);
+    bytes.extend_from_slice(&EIP712_DOMAIN_TYPE_HASH);
+    bytes.extend_from_slice(&NEAR_DOMAIN_NAME_HASH);
+    bytes.extend_from_slice(&NEAR_DOMAIN_VERSION_HASH);
+    bytes.extend_from_slice(&u256_to_arr(&chain_id));
+    arr_to_u256(&keccak256(&bytes))
+}
+
+pub fn encode_address(addr: Address) -> Vec<u8> {
+    let mut bytes = vec![0u8; 12];
+    bytes.extend_from_slice(&addr.0);
+    bytes
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum RlpValue {
+    Bytes(Vec<u8>),
+    List(Vec<RlpValue>),
+}
+
+impl Decodable for RlpValue {
+    fn decode(rlp: &Rlp<'_>) -> core::result::Result<Self, DecoderError> {
+        if rlp.is_list() {
+            Ok(RlpValue::List(rlp.as_list()?))
+        } else {
+            Ok(RlpValue::Bytes(
+                rlp.decoder().decode_value(|bytes| Ok(bytes.to_vec()))?,
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, BorshSerialize, BorshDeserialize)]
+/// An argument specified in a evm method definition
+pub struct Arg {
+    pub name: String,
+    pub type_raw: String,
+    pub t: ArgType,
+}
+
+#[derive(Debug, Eq, PartialEq, BorshSerialize, BorshDeserialize)]
+/// A parsed evm method definition
+pub struct Method {
+    pub name: String,
+    pub raw: String,
+    pub args: Vec<Arg>,
+}
+
+#[derive(Debug, Eq, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct MethodAndTypes {
+    pub method: Method,
+    pub type_sequences: Vec<String>,
+    pub types: HashMap<String, Method>,
+}
+
+// Parsing functions below take the whole method definition as `source` to report error
+// positions, and `text` as the remainder of it left to parse.
+
+impl Arg {
+    fn parse<'a>(source: &str, text: &'a str) -> ParsingResult<(Arg, &'a str)> {
+        let (type_raw, remains) = parse_type_raw(source, text)?;
+        let t = parse_type(&type_raw).map_err(|e| e.shifted(source.len() - text.len()))?;
+        let remains = consume(source, remains, ' ')?;
+        let (name, remains) = parse_ident(source, remains)?;
+        Ok((Arg { name, type_raw, t }, remains))
+    }
+
+    fn parse_args<'a>(source: &str, text: &'a str) -> ParsingResult<(Vec<Arg>, &'a str)> {
+        let mut remains = consume(source, text, '(')?;
+        if remains.is_empty() {
+            return Err(unexpected(source, remains, "argument type or ')'"));
+        }
+        let mut args = vec![];
+        let first = remains.chars().next().unwrap();
+        if is_arg_start(first) {
+            let (arg, r) = Arg::parse(source, remains)?;
+            remains = r;
+            args.push(arg);
+            while remains.starts_with(',') {
+                remains = consume(source, remains, ',')?;
+                let (arg, r) = Arg::parse(source, remains)?;
+                remains = r;
+                args.push(arg);
+            }
+        }
+
+        let remains = consume(source, remains, ')')?;
+
+        Ok((args, remains))
+    }
+}
+
+impl Method {
+    fn parse<'a>(source: &str, method_def: &'a str) -> ParsingResult<(Method, &'a str)> {
+        let (name, remains) = parse_ident(source, method_def)?;
+        let (args, remains) = Arg::parse_args(source, remains)?;
+        Ok((
+            Method {
+                name,
+                args,
+                raw: method_def[..method_def.len() - remains.len()].to_string(),
+            },
+            remains,
+        ))
+    }
+}
+
+impl MethodAndTypes {
+    /// Parse method_def, which can also be given as Solidity JSON ABI or human readable ABI.
+    /// Syntax error positions refer to the method_def produced from the ABI in that case.
+    pub fn parse(method_def: &str) -> ParsingResult<Self> {
+        let method_def: &str = &normalize_method_def(method_def)?;
+        let mut parsed_types = HashMap::new();
+        let mut type_sequences = vec![];
+        let (method, mut types) = Method::parse(method_def, method_def)?;
+        while !types.is_empty() {
+            let (ty, remains) = Method::parse(method_def, types)?;
+            type_sequences.push(ty.name.clone());
+            parsed_types.insert(ty.name.clone(), ty);
+            types = remains;
+        }
+        Ok(MethodAndTypes {
+            method,
+            types: parsed_types,
+            type_sequences,
+        })
+    }
+}
+
+fn parse_ident<'a>(source: &str, text: &'a str) -> ParsingResult<(String, &'a str)> {
+    let mut chars = text.chars();
+    if text.is_empty() || !is_arg_start(chars.next().unwrap()) {
+        return Err(unexpected(source, text, "identifier"));
+    }
+
+    let mut i = 1;
+    for c in chars {
+        if !is_arg_char(c) {
+            break;
+        }
+        i += 1;
+    }
+    Ok((text[..i].to_string(), &text[i..]))
+}
+
+/// Tokenizer a type specifier from a method definition
+/// E.g. text: "uint256[] petIds,..."
+/// returns: "uint256[]", " petIds,..."
+/// "uint256[]" is not parsed further to "an array of uint256" in this fn
+fn parse_type_raw<'a>(source: &str, text: &'a str) -> ParsingResult<(String, &'a str)> {
+    let i = text.find(&[' ', ',', ')'][..]).unwrap_or(text.len());
+    if !text[i..].starts_with(' ') {
+        return Err(unexpected(
+            source,
+            &text[i..],
+            "' ' followed by argument name",
+        ));
+    }
+    Ok((text[..i].to_string(), &text[i..]))
+}
+
+/// Consume next char in text, it must be c or return parse error
+/// return text without the first char
+fn consume<'a>(source: &str, text: &'a str, c: char) -> ParsingResult<&'a str> {
+    let first = text.chars().next();
+    if first.is_none() || first.unwrap() != c {
+        return Err(unexpected(
+            source,
+            text,
+            match c {
+                '(' => "'('",
+                ')' => "')'",
+                ',' => "','",
+                _ => "' '",
+            },
+        ));
+    }
+
+    Ok(&text[1..])
+}
+
+/// Return true if c can be used as first char of a evm method arg
+fn is_arg_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+/// Return true if c can be used as consequent char of a evm method arg
+fn is_arg_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Return the name of the struct type referenced by `ty`, looking through arrays.
+/// E.g. "PetObj[][2]" -> Some("PetObj"), "uint256[]" -> None
+fn struct_type_name(ty: &ArgType) -> Option<&str> {
+    match ty {
+        ArgType::Custom(name) => Some(name),
+        ArgType::Array { inner, .. } => struct_type_name(inner),
+        _ => None,
+    }
+}
+
+/// Collect names of all struct types referenced by `method`, directly or through other structs.
+/// Names come out sorted, which is the order EIP-712 `encodeType` requires.
+fn struct_dependencies<'a>(
+    method: &'a Method,
+    types: &'a HashMap<String, Method>,
+) -> ParsingResult<BTreeSet<&'a str>> {
+    let mut dependencies = BTreeSet::new();
+    let mut pending = vec![method];
+    while let Some(current) = pending.pop() {
+        for arg in current.args.iter() {
+            let name = match struct_type_name(&arg.t) {
+                Some(name) => name,
+                None => continue,
+            };
+            if name != method.name && dependencies.insert(name) {
+                pending.push(
+                    types
+                        .get(name)
+                        .ok_or(ParsingError::InvalidMetaTransactionMethodName)?,
+                );
+            }
+        }
+    }
+    Ok(dependencies)
+}
+
+/// EIP-712 `encodeType` of a struct: its own definition followed by definitions of all the
+/// referenced struct types sorted by name.
+/// See https://eips.ethereum.org/EIPS/eip-712#definition-of-encodetype
+/// E.g. "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+fn encode_type(method: &Method, types: &HashMap<String, Method>) -> ParsingResult<String> {
+    let mut result = method.raw.clone();
+    for name in struct_dependencies(method, types)? {
+        result.push_str(&types[name].raw);
+    }
+    Ok(result)
+}
+
+/// Return a signature of the method_def with additional args
+/// E.g. methods_signature(Methods before parse: "adopt(uint256 petId,PetObj petobj)PetObj(string name)")
+/// -> "adopt(uint256,PetObj)"
+fn method_signature(method_and_type: &MethodAndTypes) -> String {
+    let mut result = method_and_type.method.name.clone();
+    result.push('(');
+    for (i, arg) in method_and_type.method.args.iter().enumerate() {
+        if i > 0 {
+            result.push(',');
+        }
+        result.push_str(&arg.type_raw);
+    }
+    result.push(')');
+    result
+}
+
+/// Decode rlp-encoded args into vector of Values
+fn rlp_decode(args: &[u8]) -> ParsingResult<Vec<RlpValue>> {
+    let rlp = Rlp::new(args);
+    let res: core::result::Result<Vec<RlpValue>, DecoderError> = rlp.as_list();
+    res.map_err(|_| ParsingError::InvalidMetaTransactionFunctionArg)
+}
+
+/// Solidity ABI type of the argument type, structs are encoded as tuples.
+/// `structs` holds the names of structs being expanded, as recursive structs can't be ABI encoded.
+fn abi_param_type<'a>(
+    ty: &'a ArgType,
+    types: &'a HashMap<String, Method>,
+    structs: &mut Vec<&'a str>,
+) -> ParsingResult<ParamType> {
+    Ok(match ty {
+        ArgType::Address => ParamType::Address,
+        ArgType::Uint(size) => ParamType::Uint(*size),
+        ArgType::Int(size) => ParamType::Int(*size),
+        ArgType::String => ParamType::String,
+        ArgType::Bool => ParamType::Bool,
+        ArgType::Bytes => ParamType::Bytes,
+        ArgType::Byte(size) => ParamType::FixedBytes(*size as usize),
+        ArgType::Array { length, inner } => {
+            let inner = Box::new(abi_param_type(inner, types, structs)?);
+            match length {
+                Some(length) => ParamType::FixedArray(inner, *length as usize),
+                None => ParamType::Array(inner),
+            }
+        }
+        ArgType::Custom(type_name) => {
+            if structs.contains(&type_name.as_str()) {
+                return Err(ParsingError::InvalidMetaTransactionMethodName);
+            }
+            let struct_type = types
+                .get(type_name)
+                .ok_or(ParsingError::InvalidMetaTransactionMethodName)?;
+            structs.push(type_name);
+            let fields = struct_type
+                .args
+                .iter()
+                .map(|arg| abi_param_type(&arg.t, types, structs))
+                .collect::<ParsingResult<Vec<_>>>()?;
+            structs.pop();
+            ParamType::Tuple(fields)
+        }
+    })
+}
+
+/// Convert ABI decoded value into the same form as RLP decoded one:
+/// numbers become 32-byte big-endian words, arrays and structs become lists.
+fn abi_token_to_rlp_value(token: Token) -> RlpValue {
+    match token {
+        Token::Address(address) => RlpValue::Bytes(address.as_bytes().to_vec()),
+        Token::Uint(value) | Token::Int(value) => RlpValue::Bytes(u256_to_arr(&value).to_vec()),
+        Token::Bool(value) => RlpValue::Bytes(vec![value as u8]),
+        Token::String(value) => RlpValue::Bytes(value.into_bytes()),
+        Token::Bytes(value) | Token::FixedBytes(value) => RlpValue::Bytes(value),
+        Token::Array(values) | Token::FixedArray(values) | Token::Tuple(values) => {
+            RlpValue::List(values.into_iter().map(abi_token_to_rlp_value).collect())
+        }
+    }
+}
+
+/// Decode ABI-encoded args (without function selector) of the given method into vector of Values
+fn abi_decode(args: &[u8], methods: &MethodAndTypes) -> ParsingResult<Vec<RlpValue>> {
+    let mut structs = vec![];
+    let param_types = methods
+        .method
+        .args
+        .iter()
+        .map(|arg| abi_param_type(&arg.t, &methods.types, &mut structs))
+        .collect::<ParsingResult<Vec<_>>>()?;
+    let tokens = ethabi::decode(&param_types, args)
+        .map_err(|_| ParsingError::InvalidMetaTransactionFunctionArg)?;
+    Ok(tokens.into_iter().map(abi_token_to_rlp_value).collect())
+}
+
+/// eip-712 hash a single argument, whose type is ty, and value is value.
+/// Definition of all types is in `types`.
+pub(crate) fn eip_712_hash_argument(
+    ty: &ArgType,
+    value: &RlpValue,
+    types: &HashMap<String, Method>,
+) -> ParsingResult<Vec<u8>> {
+    match ty {
+        ArgType::String | ArgType::Bytes => eip_712_rlp_value(value, |b| Ok(keccak256(b))),
+        ArgType::Byte(size) => eip_712_rlp_value(value, |b| {
+            if b.len() != *size as usize {
+                return Err(ParsingError::ArgSizeMismatch);
+            }
+            // bytesN is right padded to 32 bytes.
+            let mut result = b.clone();
+            result.resize(32, 0);
+            Ok(result)
+        }),
+        ArgType::Uint(size) => eip_712_rlp_value(value, |b| zero_extend_uint(b, *size)),
+        ArgType::Bool => eip_712_rlp_value(value, |b| {
+            if b.len() > 32 {
+                return Err(ParsingError::InvalidMetaTransactionFunctionArg);
+            }
+            Ok(u256_to_arr(&U256::from_big_endian(b)).to_vec())
+        }),
+        ArgType::Int(size) => eip_712_rlp_value(value, |b| sign_extend_int(b, *size)),
+        ArgType::Address => eip_712_rlp_value(value, |b| {
+            if b.len() != Address::len_bytes() {
+                return Err(ParsingError::ArgSizeMismatch);
+            }
+            Ok(encode_address(Address::from_slice(b)))
+        }),
+        ArgType::Array { inner, length } => eip_712_rlp_list(value, |l| {
+            if matches!(length, Some(length) if *length != l.len() as u64) {
+                return Err(ParsingError::ArgSizeMismatch);
+            }
+            let mut r = vec![];
+            for element in l {
+                r.extend_from_slice(&eip_712_hash_argument(inner, element, types)?);
+            }
+            Ok(keccak256(&r))
+        }),
+        ArgType::Custom(type_name) => eip_712_rlp_list(value, |l| {
+            let struct_type = types
+                .get(type_name)
+                .ok_or(ParsingError::InvalidMetaTransactionFunctionArg)?;
+            if struct_type.args.len() != l.len() {
+                return Err(ParsingError::ArgSizeMismatch);
+            }
+            let mut r = keccak256(encode_type(struct_type, types)?.as_bytes());
+            for (i, element) in l.iter().enumerate() {
+                r.extend_from_slice(&eip_712_hash_argument(
+                    &struct_type.args[i].t,
+                    element,
+                    types,
+                )?);
+            }
+            Ok(keccak256(&r))
+        }),
+    }
+}
+
+/// Zero extend rlp-encoded uint into 32-byte word, as EIP-712 encodes `uint<N>` values.
+/// Fails if the value doesn't fit in `bits`.
+pub(crate) fn zero_extend_uint(value: &[u8], bits: usize) -> ParsingResult<Vec<u8>> {
+    if value.len() > 32 {
+        return Err(ParsingError::InvalidMetaTransactionFunctionArg);
+    }
+    let value = U256::from_big_endian(value);
+    if value.bits() > bits {
+        return Err(ParsingError::IntOutOfRange);
+    }
+    Ok(u256_to_arr(&value).to_vec())
+}
+
+/// Sign extend rlp-encoded int into 32-byte word, as EIP-712 encodes `int<N>` values.
+/// The value is big-endian two's complement, negative when the highest bit of the first byte is set,
+/// e.g. -1 is [0xff], 255 must be encoded as [0x00, 0xff] and an empty value is 0.
+/// Fails if the value doesn't fit in `bits`, e.g. 255 or -129 for `int8`.
+pub(crate) fn sign_extend_int(value: &[u8], bits: usize) -> ParsingResult<Vec<u8>> {
+    if value.len() > 32 {
+        return Err(ParsingError::InvalidMetaTransactionFunctionArg);
+    }
+    let negative = matches!(value.first(), Some(b) if b & 0x80 != 0);
+    let mut result = vec![if negative { 0xff } else { 0 }; 32];
+    result[32 - value.len()..].copy_from_slice(value);
+    // Bits besides the sign bit, counted on the complement of negative values.
+    let word = U256::from_big_endian(&result);
+    let magnitude = if negative { !word } else { word };
+    if magnitude.bits() >= bits {
+        return Err(ParsingError::IntOutOfRange);
+    }
+    Ok(result)
+}
+
+/// EIP-712 hash a RLP list. f must contain actual logic of EIP-712 encoding
+/// This function serves as a guard to assert value is a List instead of Value
+fn eip_712_rlp_list<F>(value: &RlpValue, f: F) -> ParsingResult<Vec<u8>>
+where
+    F: Fn(&Vec<RlpValue>) -> ParsingResult<Vec<u8>>,
+{
+    match value {
+        RlpValue::Bytes(_) => Err(ParsingError::InvalidMetaTransactionFunctionArg),
+        RlpValue::List(l) => f(l),
+    }
+}
+
+/// EIP-712 hash a RLP value. f must contain actual logic of EIP-712 encoding
+/// This function serves as a guard to assert value is a Value instead of List
+fn eip_712_rlp_value<F>(value: &RlpValue, f: F) -> ParsingResult<Vec<u8>>
+where
+    F: Fn(&Vec<u8>) -> ParsingResult<Vec<u8>>,
+{
+    match value {
+        RlpValue::List(_) => Err(ParsingError::InvalidMetaTransactionFunctionArg),
+        RlpValue::Bytes(b) => f(b),
+    }
+}
+
+/// EIP-712 definition of the signed meta transaction struct, without the referenced types.
+pub const NEAR_TX_TYPE: &str = "NearTx(string gatewayId,uint256 nonce,uint256 feeAmount,address feeReceiver,address receiver,uint256 value,string method,Arguments arguments)";
+/// keccak256(NEAR_TX_TYPE ‖ "Arguments()"), typeHash of transfers, which call no method.
+const NEAR_TX_TRANSFER_TYPE_HASH: RawU256 = [
+    0xa0, 0x5a, 0x4e, 0xa6, 0x6c, 0x75, 0xb2, 0x6b, 0xdb, 0x3d, 0x4f, 0x10, 0xca, 0x21, 0x99, 0x72,
+    0x52, 0xa7, 0x48, 0xa1, 0x92, 0x94, 0x83, 0xda, 0x75, 0x16, 0x8a, 0x94, 0x96, 0xdd, 0xca, 0x40,
+];
+/// EIP-712 definition of the flat schema of the meta transaction, whose `encodeData` is the
+/// same as of `NearTx`: strings, the method signature and the arguments are hashed either way.
+pub const NEAR_TX_FLAT_TYPE: &str = "NearTxFlat(string gatewayId,uint256 nonce,uint256 feeAmount,string feeReceiver,string receiver,uint256 value,string method,bytes32 argumentsHash)";
+/// keccak256(NEAR_TX_FLAT_TYPE).
+const NEAR_TX_FLAT_TYPE_HASH: RawU256 = [
+    0x36, 0xe9, 0x1b, 0xb6, 0xde, 0xb7, 0xa6, 0x2a, 0x3c, 0x42, 0x43, 0x0e, 0xcb, 0x6a, 0x2f, 0xd0,
+    0x82, 0xc4, 0x03, 0x2b, 0xeb, 0x45, 0x3c, 0x37, 0xfb, 0xad, 0x94, 0x26, 0xa6, 0xc3, 0xc7, 0x5d,
+];
+/// keccak256(""), hash of the method name of transfers.
+const EMPTY_METHOD_HASH: RawU256 = [
+    0xc5, 0xd2, 0x46, 0x01, 0x86, 0xf7, 0x23, 0x3c, 0x92, 0x7e, 0x7d, 0xb2, 0xdc, 0xc7, 0x03, 0xc0,
+    0xe5, 0x00, 0xb6, 0x53, 0xca, 0x82, 0x27, 0x3b, 0x7b, 0xfa, 0xd8, 0x04, 0x5d, 0x85, 0xa4, 0x70,
+];
+/// hashStruct of empty `Arguments()`, i.e. keccak256(keccak256("Arguments()")).
+const EMPTY_ARGUMENTS_HASH: RawU256 = [
+    0x7e, 0x48, 0x63, 0x7b, 0x61, 0x5c, 0xf9, 0xc7, 0xbf, 0xb5, 0x7f, 0x9d, 0xce, 0x18, 0xe3, 0x3a,
+    0x43, 0x41, 0x32, 0xd1, 0x9e, 0x91, 0xec, 0xbd, 0x71, 0x2f, 0xb5, 0xc6, 0x8b, 0x91, 0xb3, 0x60,
+];
+
+/// EIP-712 encoding of the meta transaction, along with the call it authorizes.
+#[derive(Debug)]
+pub struct MetaCallEncoding {
+    /// `encodeType` of `NearTx` including all referenced struct types.
+    pub type_string: String,
+    pub type_hash: RawU256,
+    /// `encodeData` of the `NearTx` struct, hashStruct is keccak256(typeHash ‖ encodeData).
+    pub encoded_struct: Vec<u8>,
+    /// hashStruct of the `Arguments`, the last field of `encodeData`.
+    pub arguments_hash: RawU256,
+    /// Message to sign: keccak256("\x19\x01" ‖ domainSeparator ‖ hashStruct).
+    pub digest: RawU256,
+    pub method_name: String,
+    pub call_args: Vec<u8>,
+}
+
+/// eip-712 hash struct of entire meta txn and transcode function args for the NEAR method.
+/// Returns the message hash to sign, name of the method and its arguments.
+pub fn prepare_meta_call_args(
+    domain_separator: &RawU256,
+    account_id: &[u8],
+    input: &InternalMetaCallArgs,
+) -> ParsingResult<(RawU256, String, Vec<u8>)> {
+    let encoding = encode_meta_call(domain_separator, account_id, input)?;
+    Ok((encoding.digest, encoding.method_name, encoding.call_args))
+}
+
+/// Method definition parsed ahead of time, along with the hashes of the types it defines.
+/// Stored in the method registry, so meta calls referencing it skip parsing and type hashing.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+pub struct PreparedMethod {
+    pub method_def: String,
+    methods: MethodAndTypes,
+    /// `encodeType` of `NearTx` with `Arguments` of the method.
+    type_string: String,
+    type_hash: RawU256,
+    /// keccak256 of `encodeType` of `Arguments`.
+    arguments_type_hash: RawU256,
+    /// keccak256 of the method signature, e.g. "adopt(uint256,PetObj)".
+    method_signature_hash: RawU256,
+}
+
+impl PreparedMethod {
+    pub fn parse(method_def: &str) -> ParsingResult<Self> {
+        let methods = MethodAndTypes::parse(method_def)?;
+        // Note: method_def is like "adopt(uint256 petId,PetObj petObj)PetObj(string name,address owner)",
+        // MUST have no space after `,`. EIP-712 requires hashStruct start by packing the typeHash,
+        // See "Rationale for typeHash" in https://eips.ethereum.org/EIPS/eip-712#definition-of-hashstruct
+        // method_def is used here for typeHash of `Arguments` struct, followed by all referenced structs sorted by name.
+        let arguments = "Arguments".to_string() + &methods.method.raw[methods.method.name.len()..];
+        let mut arguments_type = arguments.clone();
+        // `NearTx` references `Arguments` and through it all the structs of the method.
+        let mut near_tx_types = BTreeMap::new();
+        near_tx_types.insert("Arguments", arguments.as_str());
+        for name in struct_dependencies(&methods.method, &methods.types)? {
+            if name == "Arguments" || name == "NearTx" {
+                return Err(ParsingError::InvalidMetaTransactionMethodName);
+            }
+            let raw = methods.types[name].raw.as_str();
+            arguments_type.push_str(raw);
+            near_tx_types.insert(name, raw);
+        }
+        let type_string =
+            NEAR_TX_TYPE.to_string() + &near_tx_types.values().copied().collect::<String>();
+        Ok(PreparedMethod {
+            method_def: method_def.to_string(),
+            type_hash: arr_to_u256(&keccak256(type_string.as_bytes())),
+            type_string,
+            arguments_type_hash: arr_to_u256(&keccak256(arguments_type.as_bytes())),
+            method_signature_hash: arr_to_u256(&keccak256(method_signature(&methods).as_bytes())),
+            methods,
+        })
+    }
+}
+
+/// eip-712 encode struct of entire meta txn, keeping all the intermediate values.
+pub fn encode_meta_call(
+    domain_separator: &RawU256,
+    account_id: &[u8],
+    input: &InternalMetaCallArgs,
+) -> ParsingResult<MetaCallEncoding> {
+    let method = if input.method_name.is_empty() {
+        None
+    } else {
+        Some(PreparedMethod::parse(&input.method_name)?)
+    };
+    encode_prepared_meta_call(domain_separator, account_id, input, method.as_ref())
+}
+
+/// eip-712 encode struct of entire meta txn calling already parsed `method`, or transfer if None.
+/// `input.method_name` is ignored.
+pub fn encode_prepared_meta_call(
+    domain_separator: &RawU256,
+    account_id: &[u8],
+    input: &InternalMetaCallArgs,
+    method: Option<&PreparedMethod>,
+) -> ParsingResult<MetaCallEncoding> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&keccak256(account_id));
+    bytes.extend_from_slice(&u256_to_arr(&input.nonce));
+    bytes.extend_from_slice(&u256_to_arr(&U256::from(input.fee_amount)));
+    bytes.extend_from_slice(&keccak256(input.fee_address.as_bytes()));
+    bytes.extend_from_slice(&keccak256(input.contract_address.as_bytes()));
+    bytes.extend_from_slice(&u256_to_arr(&U256::from(input.value)));
+
+    let (type_string, type_hash, method_name, call_args) = if let Some(method) = method {
+        let methods = &method.methods;
+        bytes.extend_from_slice(&method.method_signature_hash);
+
+        let mut arg_bytes = Vec::new();
+        arg_bytes.extend_from_slice(&method.arguments_type_hash);
+        let args_decoded: Vec<RlpValue> = match input.args_encoding {
+            ArgsEncoding::Rlp => rlp_decode(&input.args)?,
+            ArgsEncoding::Abi => abi_decode(&input.args, methods)?,
+        };
+        if methods.method.args.len() != args_decoded.len() {
+            return Err(ParsingError::ArgsLengthMismatch);
+        }
+        for (i, arg) in args_decoded.iter().enumerate() {
+            arg_bytes.extend_from_slice(&eip_712_hash_argument(
+                &methods.method.args[i].t,
+                arg,
+                &methods.types,
+            )?);
+        }
+        bytes.extend_from_slice(&keccak256(&arg_bytes));
+        let call_args = match input.call_args_format {
+            CallArgsFormat::Json => {
+                transcoding::args_to_json(&methods.method.args, &args_decoded, &methods.types)?
+            }
+            CallArgsFormat::Borsh => {
+                transcoding::args_to_borsh(&methods.method.args, &args_decoded, &methods.types)?
+            }
+            CallArgsFormat::Raw => input.args.clone(),
+        };
+        (
+            method.type_string.clone(),
+            method.type_hash,
+            methods.method.name.clone(),
+            call_args,
+        )
+    } else {
+        bytes.extend_from_slice(&EMPTY_METHOD_HASH);
+        bytes.extend_from_slice(&EMPTY_ARGUMENTS_HASH);
+        (
+            NEAR_TX_TYPE.to_string() + "Arguments()",
+            NEAR_TX_TRANSFER_TYPE_HASH,
+            "".to_string(),
+            vec![],
+        )
+    };
+
+    let (type_string, type_hash) = match input.schema {
+        TypedDataSchema::Nested => (type_string, type_hash),
+        TypedDataSchema::Flat => (NEAR_TX_FLAT_TYPE.to_string(), NEAR_TX_FLAT_TYPE_HASH),
+    };
+    let arguments_hash = arr_to_u256(&bytes[bytes.len() - 32..]);
+    // Tip, gas, deposit source, value unit and multisig account are signed as the last fields of
+    // the struct when set, messages without them keep the type hashes computed ahead of time.
+    let mut extra_fields = String::new();
+    if input.tip > 0 {
+        bytes.extend_from_slice(&u256_to_arr(&U256::from(input.tip)));
+        extra_fields.push_str(",uint256 tip");
+    }
+    if input.gas > 0 {
+        bytes.extend_from_slice(&u256_to_arr(&U256::from(input.gas)));
+        extra_fields.push_str(",uint64 gas");
+    }
+    if input.deposit_source == DepositSource::Relayer {
+        bytes.extend_from_slice(&u256_to_arr(&U256::one()));
+        extra_fields.push_str(",uint8 depositSource");
+    }
+    if input.value_unit == ValueUnit::Wei {
+        bytes.extend_from_slice(&u256_to_arr(&U256::one()));
+        extra_fields.push_str(",uint8 valueUnit");
+    }
+    if let Some(account) = input.multisig_account {
+        bytes.extend_from_slice(&[0; 12]);
+        bytes.extend_from_slice(account.as_bytes());
+        extra_fields.push_str(",address multisigAccount");
+    }
+    let (type_string, type_hash) = if extra_fields.is_empty() {
+        (type_string, type_hash)
+    } else {
+        let type_string = type_string.replacen(')', &format!("{})", extra_fields), 1);
+        let type_hash = arr_to_u256(&keccak256(type_string.as_bytes()));
+        (type_string, type_hash)
+    };
+    let mut struct_bytes = Vec::with_capacity(32 + bytes.len());
+    struct_bytes.extend_from_slice(&type_hash);
+    struct_bytes.extend_from_slice(&bytes);
+    let mut message = Vec::with_capacity(2 + 32 + 32);
+    message.extend_from_slice(&[0x19, 0x01]);
+    message.extend_from_slice(domain_separator);
+    message.extend_from_slice(&keccak256(&struct_bytes));
+    Ok(MetaCallEncoding {
+        type_string,
+        type_hash,
+        arguments_hash,
+        encoded_struct: bytes,
+        digest: arr_to_u256(&keccak256(&message)),
+        method_name,
+        call_args,
+    })
+}
+
+/// Converts the amount signed for the field to a balance, failing instead of truncating it.
+fn u256_to_balance(amount: RawU256, field: &'static str) -> ParsingResult<u128> {
+    let amount = U256::from(amount);
+    if amount > U256::from(u128::MAX) {
+        return Err(ParsingError::AmountOverflow { field });
+    }
+    Ok(amount.as_u128())
+}
+
+impl TryFrom<MetaCallArgs> for InternalMetaCallArgs {
+    type Error = ParsingError;
+
+    /// Converts incoming arguments, sender is left empty until signature is verified. Fails if
+    /// an amount doesn't fit in a balance.
+    fn try_from(meta_tx: MetaCallArgs) -> ParsingResult<Self> {
+        let multisig_account = match &meta_tx.signature {
+            MetaCallSignature::Multisig { account, .. } => Some(Address::from(*account)),
+            _ => None,
+        };
+        Ok(InternalMetaCallArgs {
+            sender: Address::zero(),
+            nonce: U256::from(meta_tx.nonce),
+            fee_amount: u256_to_balance(meta_tx.fee_amount, "fee_amount")?,
+            fee_address: meta_tx.fee_address,
+            contract_address: meta_tx.contract_address,
+            method_name: meta_tx.method,
+            value: u256_to_balance(meta_tx.value, "value")?,
+            args_encoding: meta_tx.args_encoding,
+            call_args_format: meta_tx.call_args_format,
+            args: meta_tx.args,
+            signers: vec![],
+            schema: meta_tx.schema,
+            tip: u256_to_balance(meta_tx.tip, "tip")?,
+            gas: meta_tx.gas,
+            deposit_source: meta_tx.deposit_source,
+            value_unit: meta_tx.value_unit,
+            multisig_account,
+        })
+    }
+}
+
+/// yoctoNEAR per wei, so wallets show amounts in NEAR.
+const YOCTO_PER_WEI: u128 = 1_000_000;
+
+/// Converts the value signed in the unit to yoctoNEAR, failing if it doesn't fit.
+pub fn value_to_yocto(value: u128, unit: ValueUnit) -> ParsingResult<u128> {
+    match unit {
+        ValueUnit::Yocto => Ok(value),
+        ValueUnit::Wei => value
+            .checked_mul(YOCTO_PER_WEI)
+            .ok_or(ParsingError::ValueOverflow),
+    }
+}
+
+/// Parses NEAR amount like `1.5 NEAR` into the unit exactly, or a plain integer already in the
+/// unit. Fails if the amount has more decimals than the unit or doesn't fit in it.
+pub fn parse_near_amount(text: &str, unit: ValueUnit) -> ParsingResult<u128> {
+    let is_number = |digits: &str| !digits.is_empty() && digits.bytes().all(|c| c.is_ascii_digit());
+    let amount = match text.strip_suffix(" NEAR") {
+        Some(amount) => amount,
+        None if is_number(text) => {
+            return text.parse().map_err(|_| ParsingError::ValueOverflow);
+        }
+        None => {
+            return Err(unexpected(
+                text,
+                text,
+                "amount like \"1.5 NEAR\" or integer",
+            ))
+        }
+    };
+    let (whole, fraction) = match amount.find('.') {
+        Some(dot) if is_number(&amount[dot + 1..]) => (&amount[..dot], &amount[dot + 1..]),
+        Some(_) => ("", ""),
+        None => (amount, ""),
+    };
+    if !is_number(whole) {
+        return Err(unexpected(
+            text,
+            text,
+            "amount like \"1.5 NEAR\" or integer",
+        ));
+    }
+    let (decimals, expected) = match unit {
+        ValueUnit::Yocto => (24, "at most 24 decimals"),
+        ValueUnit::Wei => (18, "at most 18 decimals"),
+    };
+    if fraction.len() > decimals {
+        return Err(unexpected(
+            text,
+            &text[whole.len() + 1 + decimals..],
+            expected,
+        ));
+    }
+    format!("{}{:0<width$}", whole, fraction, width = decimals)
+        .parse()
+        .map_err(|_| ParsingError::ValueOverflow)
+}
+
+/// Decimal number of `MetaCallArgsJson`.
+fn parse_json_number(value: &str, expected: &'static str) -> ParsingResult<RawU256> {
+    U256::from_dec_str(value)
+        .map(|value| u256_to_arr(&value))
+        .map_err(|_| unexpected(value, value, expected))
+}
+
+/// `0x` prefixed hex bytes of `MetaCallArgsJson`.
+fn parse_json_hex(value: &str, expected: &'static str) -> ParsingResult<Vec<u8>> {
+    value
+        .strip_prefix("0x")
+        .and_then(|digits| hex::decode(digits).ok())
+        .ok_or_else(|| unexpected(value, value, expected))
+}
+
+impl TryFrom<MetaCallArgsJson> for MetaCallArgs {
+    type Error = ParsingError;
+
+    /// Decodes the JSON fields into the arguments the message of the same signature encodes.
+    fn try_from(json: MetaCallArgsJson) -> ParsingResult<Self> {
+        let expected = "0x prefixed 65 bytes signature";
+        let bytes = parse_json_hex(&json.signature, expected)?;
+        if bytes.len() != 65 {
+            return Err(unexpected(&json.signature, &json.signature, expected));
+        }
+        let mut signature = [0u8; 64];
+        signature.copy_from_slice(&bytes[..64]);
+        let value_unit = json.value_unit.unwrap_or(ValueUnit::Yocto);
+        Ok(MetaCallArgs {
+            signature: MetaCallSignature::Secp256k1 {
+                signature,
+                v: bytes[64],
+            },
+            nonce: parse_json_number(&json.nonce, "decimal nonce")?,
+            fee_amount: parse_json_number(&json.fee_amount, "decimal fee amount")?,
+            fee_address: json.fee_address,
+            contract_address: json.contract_address,
+            value: u256_to_arr(&U256::from(parse_near_amount(&json.value, value_unit)?)),
+            method: json.method,
+            method_id: json.method_id,
+            args_encoding: json.args_encoding.unwrap_or(ArgsEncoding::Rlp),
+            call_args_format: json.call_args_format.unwrap_or(CallArgsFormat::Json),
+            args: match json.args {
+                Some(args) => parse_json_hex(&args, "0x prefixed hex arguments")?,
+                None => vec![],
+            },
+            schema: json.schema.unwrap_or(TypedDataSchema::Nested),
+            tip: match json.tip {
+                Some(tip) => u256_to_arr(&U256::from(parse_near_amount(&tip, ValueUnit::Yocto)?)),
+                None => RawU256::default(),
+            },
+            gas: json.gas.unwrap_or_default(),
+            deposit_source: json.deposit_source.unwrap_or(DepositSource::Account),
+            value_unit,
+        })
+    }
+}
+
+/// Method called by the meta transaction: registered one if it references `method_id`,
+/// otherwise parsed from the method definition. None for transfers.
+/// `registered_method` looks up the registry by id.
+fn resolve_method<F>(
+    meta_tx: &MetaCallArgs,
+    registered_method: F,
+) -> ParsingResult<Option<PreparedMethod>>
+where
+    F: Fn(u32) -> Option<PreparedMethod>,
+{
+    match meta_tx.method_id {
+        Some(_) if !meta_tx.method.is_empty() => {
+            Err(ParsingError::InvalidMetaTransactionMethodName)
+        }
+        Some(method_id) => registered_method(method_id)
+            .map(Some)
+            .ok_or(ParsingError::UnknownMethodId),
+        None if meta_tx.method.is_empty() => Ok(None),
+        None => Ok(Some(PreparedMethod::parse(&meta_tx.method)?)),
+    }
+}
+
+/// Parse encoded `MetaCallArgs` and compute its EIP-712 encoding, without checking the signature.
+pub fn encode_unsigned_meta_call<F>(
+    domain_separator: &RawU256,
+    account_id: &[u8],
+    args: Vec<u8>,
+    registered_method: F,
+) -> ParsingResult<MetaCallEncoding>
+where
+    F: Fn(u32) -> Option<PreparedMethod>,
+{
+    let meta_tx =
+        MetaCallArgs::try_from_slice(&args).map_err(|_| ParsingError::ArgumentParseError)?;
+    let method = resolve_method(&meta_tx, registered_method)?;
+    encode_prepared_meta_call(
+        domain_separator,
+        account_id,
+        &InternalMetaCallArgs::try_from(meta_tx)?,
+        method.as_ref(),
+    )
+}
+
+/// Address of the key that signed the message hash with given signature.
+fn verify_signature(
+    hash: H256,
+    signature: &MetaCallSignature,
+    chain_id: u64,
+) -> Result<Address, EcRecoverError> {
+    match signature {
+        MetaCallSignature::Secp256k1 { signature, v } => {
+            let mut bytes: [u8; 65] = [0; 65];
+            bytes[..64].copy_from_slice(signature);
+            bytes[64] = *v;
+            crate::ecrecover::ecrecover(hash, &bytes, chain_id)
+        }
+        MetaCallSignature::P256 {
+            public_key,
+            signature,
+        } => crate::secp256r1::verify_p256(hash, public_key, signature),
+        MetaCallSignature::Ed25519 {
+            public_key,
+            signature,
+        } => crate::ed25519::verify_ed25519(hash, public_key, signature),
+        MetaCallSignature::Bitcoin { signature } => crate::bitcoin::verify_bitcoin(hash, signature),
+        // Has no single signer, see `verify_signers`.
+        MetaCallSignature::Multisig { .. } => Err(EcRecoverError::RecoveryFailed),
+    }
+}
+
+/// Sender of the signed digest, and for multi-signature the account along with the recovered
+/// address of each signature. Multi-signature must have at least one signature, none nested.
+fn verify_signers(
+    hash: H256,
+    signature: &MetaCallSignature,
+    chain_id: u64,
+) -> Result<(Address, Vec<Address>), EcRecoverError> {
+    match signature {
+        MetaCallSignature::Multisig {
+            account,
+            signatures,
+        } => {
+            if signatures.0.is_empty() {
+                return Err(EcRecoverError::RecoveryFailed);
+            }
+            let signers = signatures
+                .0
+                .iter()
+                .map(|signature| verify_signature(hash, signature, chain_id))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((Address::from(*account), signers))
+        }
+        _ => verify_signature(hash, signature, chain_id).map(|sender| (sender, vec![])),
+    }
+}
+
+/// Parse encoded `MetaCallArgs`, validate with given domain and account and recover the sender's address from the signature.
+/// Method definitions referenced by id are looked up with `registered_method`.
+/// Returns error if method definition or arguments are wrong, invalid signature or EC recovery failed.
+pub fn parse_meta_call<F>(
+    domain_separator: &RawU256,
+    chain_id: u64,
+    account_id: &[u8],
+    args: Vec<u8>,
+    registered_method: F,
+) -> ParsingResult<InternalMetaCallArgs>
+where
+    F: Fn(u32) -> Option<PreparedMethod>,
+{
+    verify_meta_call(
+        domain_separator,
+        chain_id,
+        account_id,
+        args,
+        registered_method,
+    )
+    .map(|(result, _)| result)
+}
+
+/// `parse_meta_call` that also returns the signed digest.
+pub fn verify_meta_call<F>(
+    domain_separator: &RawU256,
+    chain_id: u64,
+    account_id: &[u8],
+    args: Vec<u8>,
+    registered_method: F,
+) -> ParsingResult<(InternalMetaCallArgs, RawU256)>
+where
+    F: Fn(u32) -> Option<PreparedMethod>,
+{
+    verify_meta_call_with_checkpoints(
+        domain_separator,
+        chain_id,
+        account_id,
+        args,
+        registered_method,
+        |_| (),
+    )
+}
+
+/// `verify_meta_call` calling `checkpoint` with the name of each phase once it's done:
+/// "parsing" of the message and its method, "hashing" and signer "recovery", e.g. to trace
+/// the gas each of them uses.
+pub fn verify_meta_call_with_checkpoints<F, C>(
+    domain_separator: &RawU256,
+    chain_id: u64,
+    account_id: &[u8],
+    args: Vec<u8>,
+    registered_method: F,
+    checkpoint: C,
+) -> ParsingResult<(InternalMetaCallArgs, RawU256)>
+where
+    F: Fn(u32) -> Option<PreparedMethod>,
+    C: FnMut(&'static str),
+{
+    let meta_tx =
+        MetaCallArgs::try_from_slice(&args).map_err(|_| ParsingError::ArgumentParseError)?;
+    verify_decoded_meta_call(
+        domain_separator,
+        chain_id,
+        account_id,
+        meta_tx,
+        registered_method,
+        checkpoint,
+    )
+}
+
+/// `verify_meta_call_with_checkpoints` of the decoded message, for callers checking the message
+/// before it's verified without decoding it twice.
+pub fn verify_decoded_meta_call<F, C>(
+    domain_separator: &RawU256,
+    chain_id: u64,
+    account_id: &[u8],
+    meta_tx: MetaCallArgs,
+    registered_method: F,
+    mut checkpoint: C,
+) -> ParsingResult<(InternalMetaCallArgs, RawU256)>
+where
+    F: Fn(u32) -> Option<PreparedMethod>,
+    C: FnMut(&'static str),
+{
+    let signature = meta_tx.signature.clone();
+    let method = resolve_method(&meta_tx, registered_method)?;
+    let mut result = InternalMetaCallArgs::try_from(meta_tx)?;
+    checkpoint("parsing");
+    let encoding =
+        encode_prepared_meta_call(domain_separator, account_id, &result, method.as_ref())?;
+    checkpoint("hashing");
+    let signers = verify_signers(H256::from_slice(&encoding.digest), &signature, chain_id);
+    checkpoint("recovery");
+    match signers {
+        Ok((sender, signers)) => {
+            result.sender = sender;
+            result.signers = signers;
+            result.method_name = encoding.method_name;
+            result.args = encoding.call_args;
+            result.value = value_to_yocto(result.value, result.value_unit)?;
+            result.value_unit = ValueUnit::Yocto;
+            Ok((result, encoding.digest))
+        }
+        Err(EcRecoverError::ChainIdMismatch) => Err(ParsingError::ChainIdMismatch),
+        Err(_) => Err(ParsingError::InvalidEcRecoverSignature),
+    }
+}
+
+/// Parse and verify the message like `parse_meta_call`, and check its nonce against the one
+/// `expected_nonce` returns for the sender and the message's nonce: the next one under its key,
+/// or for salted nonce the nonce itself while it can be used.
+pub fn recover_signer<F, N>(
+    domain_separator: &RawU256,
+    chain_id: u64,
+    account_id: &[u8],
+    args: Vec<u8>,
+    registered_method: F,
+    expected_nonce: N,
+) -> ParsingResult<RecoveredSigner>
+where
+    F: Fn(u32) -> Option<PreparedMethod>,
+    N: Fn(&Address, U256) -> U256,
+{
+    let (result, digest) = verify_meta_call(
+        domain_separator,
+        chain_id,
+        account_id,
+        args,
+        registered_method,
+    )?;
+    let nonce = expected_nonce(&result.sender, result.nonce);
+    Ok(RecoveredSigner {
+        address: format!("0x{}", hex::encode(result.sender)),
+        digest: format!("0x{}", hex::encode(digest)),
+        expected_nonce: nonce.to_string(),
+        nonce_matches: result.nonce == nonce,
+    })
+}
+
+/// `recover_signer` for each of the messages, so relayers can drop the invalid ones
+/// before paying for their submission.
+pub fn recover_signers<F, N>(
+    domain_separator: &RawU256,
+    chain_id: u64,
+    account_id: &[u8],
+    messages: Vec<Vec<u8>>,
+    registered_method: F,
+    expected_nonce: N,
+) -> Vec<ParsingResult<RecoveredSigner>>
+where
+    F: Fn(u32) -> Option<PreparedMethod>,
+    N: Fn(&Address, U256) -> U256,
+{
+    messages
+        .into_iter()
+        .map(|args| {
+            recover_signer(
+                domain_separator,
+                chain_id,
+                account_id,
+                args,
+                &registered_method,
+                &expected_nonce,
+            )
+        })
+        .collect()
+}
+
+/// Smallest chain id of Ethereum transactions for a gateway. Public chains register ids far
+/// below it, so transactions signed for them, like the signer's Ethereum mainnet ones, can't be
+/// submitted to a gateway.
+pub const MIN_GATEWAY_CHAIN_ID: u64 = 1 << 47;
+
+/// Chain id of Ethereum transactions for the gateway at `account_id`: `MIN_GATEWAY_CHAIN_ID`
+/// plus the first 47 bits of keccak256 of the account id, so transactions for one gateway
+/// can't be replayed on another. Stays within the chain ids wallets accept.
+pub fn gateway_chain_id(account_id: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes[2..].copy_from_slice(&keccak256(account_id)[..6]);
+    MIN_GATEWAY_CHAIN_ID | (u64::from_be_bytes(bytes) & (MIN_GATEWAY_CHAIN_ID - 1))
+}
+
+/// Receiver, method definition and arguments of the call in the data of Ethereum transaction.
+fn decode_eth_call(data: &[u8]) -> ParsingResult<(String, String, Vec<u8>)> {
+    let call = Rlp::new(data);
+    if call.item_count() != Ok(3) || call.as_raw().len() != data.len() {
+        return Err(ParsingError::ArgumentParseError);
+    }
+    let field = |index| -> ParsingResult<Vec<u8>> {
+        call.val_at(index)
+            .map_err(|_| ParsingError::ArgumentParseError)
+    };
+    let utf8 = |bytes| String::from_utf8(bytes).map_err(|_| ParsingError::ArgumentParseError);
+    Ok((utf8(field(0)?)?, utf8(field(1)?)?, field(2)?))
+}
+
+/// Whether the bytes look like signed Ethereum transaction rather than borsh encoded
+/// `MetaCallArgs`, for services accepting both.
+pub fn is_eth_transaction(bytes: &[u8]) -> bool {
+    crate::eth_transaction::EthTransaction::is_envelope(bytes)
+}
+
+/// Method definition and arguments of the call in signed Ethereum transaction, without
+/// verifying it, so their size can be checked before parsing. None for transfers and
+/// transactions that don't decode.
+pub fn eth_transaction_call(tx: &[u8]) -> Option<(String, Vec<u8>)> {
+    let data = crate::eth_transaction::EthTransaction::data_of(tx)?;
+    if data.is_empty() {
+        return None;
+    }
+    let (_, method_def, args) = decode_eth_call(&data).ok()?;
+    Some((method_def, args))
+}
+
+/// Parse signed Ethereum transaction into the meta call it authorizes, for wallets that can sign
+/// only transactions. It must be signed for `gateway_chain_id` of `account_id`. Transaction without data transfers its value to the proxy account of `to`
+/// under `account_id`. Otherwise data is `rlp([receiver, method_def, args])` of the call, with
+/// `args` RLP encoded like in `MetaCallArgs`, and `to` is not used.
+/// Value is scaled from 18 to 24 decimals. The fee of EIP-1559 transactions, priority fee for
+/// the gas limit, is scaled the same and left for the relayer submitting it, as `fee_address`
+/// is empty. Legacy transactions pay no fee.
+pub fn parse_eth_transaction(
+    domain_separator: &RawU256,
+    account_id: &[u8],
+    tx: &[u8],
+) -> ParsingResult<InternalMetaCallArgs> {
+    let chain_id = gateway_chain_id(account_id);
+    let tx = crate::eth_transaction::EthTransaction::decode(chain_id, tx)?;
+    let wei_to_yocto = |wei: U256, field| {
+        if wei > U256::from(u128::MAX / YOCTO_PER_WEI) {
+            return Err(ParsingError::AmountOverflow { field });
+        }
+        Ok(wei.as_u128() * YOCTO_PER_WEI)
+    };
+    let value = wei_to_yocto(tx.value, "value")?;
+    let fee_amount = wei_to_yocto(tx.fee, "fee_amount")?;
+    let (contract_address, method_name, args) = if tx.data.is_empty() {
+        let to = tx.to.ok_or(ParsingError::ArgumentParseError)?;
+        let account_id =
+            core::str::from_utf8(account_id).map_err(|_| ParsingError::ArgumentParseError)?;
+        (
+            format!("{}.{}", hex::encode(to), account_id),
+            String::new(),
+            vec![],
+        )
+    } else {
+        decode_eth_call(&tx.data)?
+    };
+    let mut result = InternalMetaCallArgs {
+        sender: Address::zero(),
+        nonce: tx.nonce,
+        fee_amount,
+        fee_address: String::new(),
+        contract_address,
+        method_name,
+        value,
+        args_encoding: ArgsEncoding::Rlp,
+        call_args_format: CallArgsFormat::Json,
+        args,
+        signers: vec![],
+        schema: TypedDataSchema::Nested,
+        tip: 0,
+        gas: 0,
+        deposit_source: DepositSource::Account,
+        value_unit: ValueUnit::Yocto,
+        multisig_account: None,
+    };
+    // Transcodes the arguments for the NEAR method, the EIP-712 digest isn't used.
+    let encoding = encode_meta_call(domain_separator, account_id, &result)?;
+    result.sender = match tx.sender(chain_id) {
+        Ok(sender) => sender,
+        Err(EcRecoverError::ChainIdMismatch) => return Err(ParsingError::ChainIdMismatch),
+        Err(_) => return Err(ParsingError::InvalidEcRecoverSignature),
+    };
+    result.method_name = encoding.method_name;
+    result.args = encoding.call_args;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+    use rand::Rng;
+
+    use std::convert::TryFrom;
+
+    use super::{ArgType, MethodAndTypes, ParsingError, RlpValue};
+    use borsh::{BorshDeserialize, BorshSerialize};
+
+    use crate::types::{
+        keccak256, u256_to_arr, Address, ArgsEncoding, CallArgsFormat, DepositSource,
+        InternalMetaCallArgs, MetaCallArgs, MetaCallArgsJson, MetaCallSignature,
+        MultisigSignatures, TypedDataSchema, ValueUnit,
+    };
+
+    #[test]
+    fn test_parse_type() {
+        // # atomic types
+
+        // ## bytesN
+        for n in 1..=32 {
+            let s = format!("bytes{}", n);
+            assert_arg_type(&s, ArgType::Byte(n));
+        }
+        assert_arg_type("byte", ArgType::Byte(1));
+
+        // ## uintN
+        for n in 1..=32 {
+            let s = format!("uint{}", 8 * n);
+            assert_arg_type(&s, ArgType::Uint(8 * n));
+        }
+        assert_arg_type("uint", ArgType::Uint(256));
+
+        // ## intN
+        for n in 1..=32 {
+            let s = format!("int{}", 8 * n);
+            assert_arg_type(&s, ArgType::Int(8 * n));
+        }
+        assert_arg_type("int", ArgType::Int(256));
+
+        // ## bool
+        assert_arg_type("bool", ArgType::Bool);
+
+        // ## address
+        assert_arg_type("address", ArgType::Address);
+
+        // ## custom
+        let mut rng = rand::thread_rng();
+        for _ in 0..u8::MAX {
+            let name = rand_identifier(&mut rng);
+            assert_arg_type(&name, ArgType::Custom(name.clone()));
+        }
+
+        // ## elementary type names are only matched as whole words
+        for name in &[
+            "uint7", "int264", "uint08", "bytes0", "bytes33", "uint256x", "bool_",
+        ] {
+            assert_arg_type(name, ArgType::Custom(name.to_string()));
+        }
+
+        // # dynamic types
+
+        // ## bytes
+        assert_arg_type("bytes", ArgType::Bytes);
+
+        // ## string
+        assert_arg_type("string", ArgType::String);
+
+        // # arrays
+        let inner_types: Vec<String> = (1..=32)
+            .map(|n| format!("bytes{}", n))
+            .chain((1..=32).map(|n| format!("uint{}", 8 * n)))
+            .chain((1..=32).map(|n| format!("int{}", 8 * n)))
+            .chain(std::iter::once("bool".to_string()))
+            .chain(std::iter::once("address".to_string()))
+            .chain(std::iter::once(rand_identifier(&mut rng)))
+            .chain(std::iter::once("bytes".to_string()))
+            .chain(std::iter::once("string".to_string()))
+            .collect();
+        for t in inner_types {
+            let inner_type = super::parse_type(&t).ok().unwrap();
+            let size: Option<u8> = rng.gen();
+
+            // single array
+            let single_array_string = create_array_type_string(&t, size);
+            let expected = ArgType::Array {
+                length: size.map(|x| x as u64),
+                inner: Box::new(inner_type),
+            };
+            assert_arg_type(&single_array_string, expected.clone());
+
+            // nested array
+            let inner_type = expected;
+            let size: Option<u8> = rng.gen();
+            let nested_array_string = create_array_type_string(&single_array_string, size);
+            let expected = ArgType::Array {
+                length: size.map(|x| x as u64),
+                inner: Box::new(inner_type),
+            };
+            assert_arg_type(&nested_array_string, expected);
+        }
+
+        // # errors
+        // ## only numbers
+        super::parse_type("27182818").unwrap_err();
+        // ## invalid characters
+        super::parse_type("Some.InvalidType").unwrap_err();
+        super::parse_type("Some::NotType").unwrap_err();
+        super::parse_type("*AThing*").unwrap_err();
+    }
+
+    #[test]
+    fn test_keccak_constants() {
+        let hash = |data: &str| crate::types::arr_to_u256(&keccak256(data.as_bytes()));
+        assert_eq!(
+            super::EIP712_DOMAIN_TYPE_HASH,
+            hash(super::EIP712_DOMAIN_TYPE)
+        );
+        assert_eq!(super::NEAR_DOMAIN_NAME_HASH, hash(super::NEAR_DOMAIN_NAME));
+        assert_eq!(
+            super::NEAR_DOMAIN_VERSION_HASH,
+            hash(super::NEAR_DOMAIN_VERSION)
+        );
+        assert_eq!(
+            super::NEAR_TX_TRANSFER_TYPE_HASH,
+            hash(&(super::NEAR_TX_TYPE.to_string() + "Arguments()"))
+        );
+        assert_eq!(
+            super::NEAR_TX_FLAT_TYPE_HASH,
+            hash(super::NEAR_TX_FLAT_TYPE)
+        );
+        assert_eq!(super::EMPTY_METHOD_HASH, hash(""));
+        assert_eq!(
+            super::EMPTY_ARGUMENTS_HASH,
+            crate::types::arr_to_u256(&keccak256(&hash("Arguments()")))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let unexpected = |offset, found: &str, expected| ParsingError::UnexpectedToken {
+            offset,
+            found: found.to_string(),
+            expected,
+        };
+        assert_eq!(
+            MethodAndTypes::parse("adopt(uint256 petId,PetObj pet-obj)").unwrap_err(),
+            unexpected(30, "-", "')'")
+        );
+        assert_eq!(
+            MethodAndTypes::parse("adopt(uint256)").unwrap_err(),
+            unexpected(13, ")", "' ' followed by argument name")
+        );
+        assert_eq!(
+            MethodAndTypes::parse("adopt(uint256.x a)").unwrap_err(),
+            unexpected(13, ".", "type")
+        );
+        assert_eq!(
+            MethodAndTypes::parse("adopt(Pet a)Pet(string name").unwrap_err(),
+            unexpected(27, "", "')'")
+        );
+        assert_eq!(
+            MethodAndTypes::parse("adopt(Pet a) Pet(string name)").unwrap_err(),
+            unexpected(12, " ", "identifier")
+        );
+        assert_eq!(
+            super::parse_type("[]uint").unwrap_err(),
+            unexpected(0, "[]", "array element type")
+        );
+        assert_eq!(
+            unexpected(13, ".", "type").to_string(),
+            "expected type at offset 13, found \".\""
+        );
+        assert_eq!(
+            unexpected(27, "", "')'").to_string(),
+            "expected ')' at offset 27, found end of input"
+        );
+    }
+
+    #[test]
+    fn test_sign_extend_int() {
+        let hash_int = |bytes: &[u8]| {
+            super::eip_712_hash_argument(
+                &ArgType::Int(256),
+                &RlpValue::Bytes(bytes.to_vec()),
+                &Default::default(),
+            )
+            .unwrap()
+        };
+        let word = |value: i128| {
+            let fill = if value < 0 { 0xff } else { 0 };
+            let mut result = vec![fill; 16];
+            result.extend_from_slice(&value.to_be_bytes());
+            result
+        };
+        assert_eq!(hash_int(&[]), word(0));
+        assert_eq!(hash_int(&[0x7f]), word(127));
+        assert_eq!(hash_int(&[0x00, 0xff]), word(255));
+        assert_eq!(hash_int(&[0xff]), word(-1));
+        assert_eq!(hash_int(&[0x80]), word(-128));
+        assert_eq!(hash_int(&[0xff, 0x00]), word(-256));
+        assert_eq!(
+            hash_int(&(-1_000_000_007i64).to_be_bytes()),
+            word(-1_000_000_007)
+        );
+        assert_eq!(hash_int(&[0xff; 32]), word(-1));
+        // Signed and unsigned values agree while the highest bit is not set.
+        assert_eq!(
+            hash_int(&[0x12, 0x34]),
+            super::eip_712_hash_argument(
+                &ArgType::Uint(256),
+                &RlpValue::Bytes(vec![0x12, 0x34]),
+                &Default::default()
+            )
+            .unwrap()
+        );
+
+        super::eip_712_hash_argument(
+            &ArgType::Int(256),
+            &RlpValue::Bytes(vec![0xff; 33]),
+            &Default::default(),
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_int_width() {
+        let hash = |ty: ArgType, bytes: &[u8]| {
+            super::eip_712_hash_argument(&ty, &RlpValue::Bytes(bytes.to_vec()), &Default::default())
+        };
+        assert!(hash(ArgType::Int(8), &[0x7f]).is_ok());
+        assert!(hash(ArgType::Int(8), &[0x80]).is_ok());
+        assert!(hash(ArgType::Int(8), &[0xff, 0x80]).is_ok());
+        assert_eq!(
+            hash(ArgType::Int(8), &[0x00, 0x80]),
+            Err(ParsingError::IntOutOfRange)
+        );
+        assert_eq!(
+            hash(ArgType::Int(8), &[0x00, 0xff]),
+            Err(ParsingError::IntOutOfRange)
+        );
+        assert_eq!(
+            hash(ArgType::Int(8), &[0xff, 0x7f]),
+            Err(ParsingError::IntOutOfRange)
+        );
+        assert!(hash(ArgType::Int(24), &[0x80, 0x00, 0x00]).is_ok());
+        assert_eq!(
+            hash(ArgType::Int(24), &[0xff, 0x7f, 0xff, 0xff]),
+            Err(ParsingError::IntOutOfRange)
+        );
+
+        assert!(hash(ArgType::Uint(8), &[0x00, 0xff]).is_ok());
+        assert_eq!(
+            hash(ArgType::Uint(8), &[0x01, 0x00]),
+            Err(ParsingError::IntOutOfRange)
+        );
+        assert!(hash(ArgType::Uint(256), &[0xff; 32]).is_ok());
+        assert_eq!(
+            hash(ArgType::Uint(248), &[0xff; 32]),
+            Err(ParsingError::IntOutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_arg_size_validation() {
+        let methods = MethodAndTypes::parse(
+            "f(bytes4 b,uint256[2] a,address owner,Pet pet)Pet(string name,uint8 age)",
+        )
+        .unwrap();
+        let hash = |i: usize, value: RlpValue| {
+            super::eip_712_hash_argument(&methods.method.args[i].t, &value, &methods.types)
+        };
+        let bytes = |b: &[u8]| RlpValue::Bytes(b.to_vec());
+
+        // bytesN is right padded to 32 bytes.
+        let mut expected = vec![1, 2, 3, 4];
+        expected.resize(32, 0);
+        assert_eq!(hash(0, bytes(&[1, 2, 3, 4])).unwrap(), expected);
+        assert!(matches!(
+            hash(0, bytes(&[1, 2, 3])),
+            Err(ParsingError::ArgSizeMismatch)
+        ));
+        assert!(matches!(
+            hash(0, bytes(&[1, 2, 3, 4, 5])),
+            Err(ParsingError::ArgSizeMismatch)
+        ));
+
+        hash(1, RlpValue::List(vec![bytes(&[1]), bytes(&[2])])).unwrap();
+        assert!(matches!(
+            hash(1, RlpValue::List(vec![bytes(&[1])])),
+            Err(ParsingError::ArgSizeMismatch)
+        ));
+        assert!(matches!(
+            hash(
+                1,
+                RlpValue::List(vec![bytes(&[1]), bytes(&[2]), bytes(&[3])])
+            ),
+            Err(ParsingError::ArgSizeMismatch)
+        ));
+
+        hash(2, bytes(&[0xab; 20])).unwrap();
+        assert!(matches!(
+            hash(2, bytes(&[0xab; 19])),
+            Err(ParsingError::ArgSizeMismatch)
+        ));
+
+        hash(3, RlpValue::List(vec![bytes(b"Rex"), bytes(&[3])])).unwrap();
+        assert!(matches!(
+            hash(3, RlpValue::List(vec![bytes(b"Rex")])),
+            Err(ParsingError::ArgSizeMismatch)
+        ));
+        assert!(matches!(
+            hash(
+                3,
+                RlpValue::List(vec![bytes(b"Rex"), bytes(&[3]), bytes(&[4])])
+            ),
+            Err(ParsingError::ArgSizeMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_abi_args() {
+        use ethabi::{ParamType, Token};
+
+        let methods = MethodAndTypes::parse(
+            "f(int8 delta,bytes2 tag,Pet[] pets,bool ok)Pet(string name,address owner)",
+        )
+        .unwrap();
+        let param_types = methods
+            .method
+            .args
+            .iter()
+            .map(|arg| super::abi_param_type(&arg.t, &methods.types, &mut vec![]))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            param_types,
+            vec![
+                ParamType::Int(8),
+                ParamType::FixedBytes(2),
+                ParamType::Array(Box::new(ParamType::Tuple(vec![
+                    ParamType::String,
+                    ParamType::Address
+                ]))),
+                ParamType::Bool,
+            ]
+        );
+
+        // ABI decoded values hash the same way as RLP encoded ones.
+        let owner = Address::repeat_byte(0x11);
+        let tokens = vec![
+            Token::Int(U256::MAX - 1),
+            Token::FixedBytes(vec![0xab, 0xcd]),
+            Token::Array(vec![Token::Tuple(vec![
+                Token::String("Rex".to_string()),
+                Token::Address(owner),
+            ])]),
+            Token::Bool(true),
+        ];
+        let rlp_values = vec![
+            RlpValue::Bytes(vec![0xfe]),
+            RlpValue::Bytes(vec![0xab, 0xcd]),
+            RlpValue::List(vec![RlpValue::List(vec![
+                RlpValue::Bytes(b"Rex".to_vec()),
+                RlpValue::Bytes(owner.as_bytes().to_vec()),
+            ])]),
+            RlpValue::Bytes(vec![1]),
+        ];
+        for ((arg, token), rlp_value) in methods.method.args.iter().zip(tokens).zip(rlp_values) {
+            assert_eq!(
+                super::eip_712_hash_argument(
+                    &arg.t,
+                    &super::abi_token_to_rlp_value(token),
+                    &methods.types
+                )
+                .unwrap(),
+                super::eip_712_hash_argument(&arg.t, &rlp_value, &methods.types).unwrap()
+            );
+        }
+
+        // Recursive structs can't be ABI encoded.
+        let methods = MethodAndTypes::parse("f(Node root)Node(Node[] children)").unwrap();
+        super::abi_param_type(&methods.method.args[0].t, &methods.types, &mut vec![]).unwrap_err();
+    }
+
+    #[test]
+    fn test_encode_type() {
+        // Example from https://eips.ethereum.org/EIPS/eip-712#definition-of-encodetype
+        let methods =
+            MethodAndTypes::parse("mail(Mail mail)Person(string name,address wallet)Mail(Person from,Person to,string contents)")
+                .unwrap();
+        let mail = &methods.types["Mail"];
+        assert_eq!(
+            super::encode_type(mail, &methods.types).unwrap(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+
+        // Referenced types are included transitively, sorted by name and without duplicates.
+        let methods = MethodAndTypes::parse(
+            "buy(Order order)Order(Item[] items,Person buyer)Person(string name,Address home)Item(string name,Address origin)Address(string city)",
+        )
+        .unwrap();
+        assert_eq!(
+            super::encode_type(&methods.types["Order"], &methods.types).unwrap(),
+            "Order(Item[] items,Person buyer)Address(string city)Item(string name,Address origin)Person(string name,Address home)"
+        );
+
+        // Unknown referenced type.
+        let methods = MethodAndTypes::parse("mail(Mail mail)Mail(Person from)").unwrap();
+        super::encode_type(&methods.types["Mail"], &methods.types).unwrap_err();
+    }
+
+    #[test]
+    fn test_hash_struct() {
+        // Example from https://github.com/ethereum/EIPs/blob/master/assets/eip-712/Example.js
+        let methods = MethodAndTypes::parse(
+            "mail(Mail mail)Mail(Person from,Person to,string contents)Person(string name,address wallet)",
+        )
+        .unwrap();
+        let person = |name: &str, wallet: &str| {
+            RlpValue::List(vec![
+                RlpValue::Bytes(name.as_bytes().to_vec()),
+                RlpValue::Bytes(hex::decode(wallet).unwrap()),
+            ])
+        };
+        let mail = RlpValue::List(vec![
+            person("Cow", "CD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"),
+            person("Bob", "bBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"),
+            RlpValue::Bytes(b"Hello, Bob!".to_vec()),
+        ]);
+        assert_eq!(
+            hex::encode(
+                super::eip_712_hash_argument(&methods.method.args[0].t, &mail, &methods.types)
+                    .unwrap()
+            ),
+            "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+        );
+    }
+
+    #[test]
+    fn test_registered_method() {
+        let domain_separator = super::near_erc712_domain(U256::from(1));
+        let method_def = "adopt(uint256 petId,Pet pet)Pet(string name)";
+        let stored = super::PreparedMethod::parse(method_def)
+            .unwrap()
+            .try_to_vec()
+            .unwrap();
+        let registry = |method_id: u32| match method_id {
+            0 => Some(super::PreparedMethod::try_from_slice(&stored).unwrap()),
+            _ => None,
+        };
+        let mut stream = rlp::RlpStream::new_list(2);
+        stream.append(&42u8).begin_list(1).append(&"Rex");
+        let args = stream.out().to_vec();
+        let message = |method: &str, method_id: Option<u32>| {
+            MetaCallArgs {
+                signature: MetaCallSignature::Secp256k1 {
+                    signature: [0; 64],
+                    v: 27,
+                },
+                nonce: [0; 32],
+                fee_amount: [0; 32],
+                fee_address: "relayer".to_string(),
+                contract_address: "pets".to_string(),
+                value: [0; 32],
+                method: method.to_string(),
+                method_id,
+                args_encoding: ArgsEncoding::Rlp,
+                call_args_format: CallArgsFormat::Json,
+                args: args.clone(),
+                schema: TypedDataSchema::Nested,
+                tip: [0; 32],
+                gas: 0,
+                deposit_source: DepositSource::Account,
+                value_unit: ValueUnit::Yocto,
+            }
+            .try_to_vec()
+            .unwrap()
+        };
+
+        let by_def = super::encode_unsigned_meta_call(
+            &domain_separator,
+            b"gateway",
+            message(method_def, None),
+            registry,
+        )
+        .unwrap();
+        let by_id = super::encode_unsigned_meta_call(
+            &domain_separator,
+            b"gateway",
+            message("", Some(0)),
+            registry,
+        )
+        .unwrap();
+        assert_eq!(by_def.digest, by_id.digest);
+        assert_eq!(by_def.type_string, by_id.type_string);
+        assert_eq!(by_id.method_name, "adopt");
+        assert_eq!(by_def.call_args, by_id.call_args);
+
+        assert_eq!(
+            super::encode_unsigned_meta_call(
+                &domain_separator,
+                b"gateway",
+                message("", Some(1)),
+                registry
+            )
+            .unwrap_err(),
+            ParsingError::UnknownMethodId
+        );
+        // Method can't be given both ways.
+        super::encode_unsigned_meta_call(
+            &domain_separator,
+            b"gateway",
+            message(method_def, Some(0)),
+            registry,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_flat_schema() {
+        let domain_separator = super::near_erc712_domain(U256::from(1));
+        let mut stream = rlp::RlpStream::new_list(2);
+        stream.append(&42u8).begin_list(1).append(&"Rex");
+        let args = stream.out().to_vec();
+        let input = |schema: TypedDataSchema| InternalMetaCallArgs {
+            sender: Address::zero(),
+            nonce: U256::zero(),
+            fee_amount: 0,
+            fee_address: "relayer".to_string(),
+            contract_address: "pets".to_string(),
+            method_name: "adopt(uint256 petId,Pet pet)Pet(string name)".to_string(),
+            value: 0,
+            args_encoding: ArgsEncoding::Rlp,
+            call_args_format: CallArgsFormat::Json,
+            args: args.clone(),
+            signers: vec![],
+            schema,
+            tip: 0,
+            gas: 0,
+            deposit_source: DepositSource::Account,
+            value_unit: ValueUnit::Yocto,
+            multisig_account: None,
+        };
+
+        let nested = super::encode_meta_call(
+            &domain_separator,
+            b"gateway",
+            &input(TypedDataSchema::Nested),
+        )
+        .unwrap();
+        let flat =
+            super::encode_meta_call(&domain_separator, b"gateway", &input(TypedDataSchema::Flat))
+                .unwrap();
+        assert_eq!(flat.type_string, super::NEAR_TX_FLAT_TYPE);
+        // Same values, but signed as different structs.
+        assert_eq!(flat.encoded_struct, nested.encoded_struct);
+        assert_eq!(flat.arguments_hash, nested.arguments_hash);
+        assert_ne!(flat.digest, nested.digest);
+        assert_eq!(flat.call_args, nested.call_args);
+
+        let transfer = InternalMetaCallArgs {
+            method_name: String::new(),
+            args: vec![],
+            ..input(TypedDataSchema::Flat)
+        };
+        let flat = super::encode_meta_call(&domain_separator, b"gateway", &transfer).unwrap();
+        assert_eq!(flat.arguments_hash, super::EMPTY_ARGUMENTS_HASH);
+    }
+
+    #[test]
+    fn test_optional_fields() {
+        let domain_separator = super::near_erc712_domain(U256::from(1));
+        let input = |schema: TypedDataSchema, tip: u128| InternalMetaCallArgs {
+            sender: Address::zero(),
+            nonce: U256::zero(),
+            fee_amount: 0,
+            fee_address: "relayer".to_string(),
+            contract_address: "bob".to_string(),
+            method_name: String::new(),
+            value: 100,
+            args_encoding: ArgsEncoding::Rlp,
+            call_args_format: CallArgsFormat::Json,
+            args: vec![],
+            signers: vec![],
+            schema,
+            tip,
+            gas: 0,
+            deposit_source: DepositSource::Account,
+            value_unit: ValueUnit::Yocto,
+            multisig_account: None,
+        };
+        let encode = |schema: TypedDataSchema, tip: u128| {
+            super::encode_meta_call(&domain_separator, b"gateway", &input(schema, tip)).unwrap()
+        };
+
+        let plain = encode(TypedDataSchema::Nested, 0);
+        let tipped = encode(TypedDataSchema::Nested, 5);
+        assert_eq!(
+            tipped.type_string,
+            plain.type_string.replacen(')', ",uint256 tip)", 1)
+        );
+        assert!(tipped.type_string.starts_with("NearTx(string gatewayId,"));
+        assert_eq!(
+            tipped.encoded_struct[..plain.encoded_struct.len()],
+            plain.encoded_struct[..]
+        );
+        assert_eq!(
+            U256::from_big_endian(&tipped.encoded_struct[plain.encoded_struct.len()..]),
+            U256::from(5)
+        );
+        assert_eq!(tipped.arguments_hash, plain.arguments_hash);
+        assert_ne!(tipped.digest, plain.digest);
+        assert_ne!(tipped.digest, encode(TypedDataSchema::Nested, 6).digest);
+
+        let flat = encode(TypedDataSchema::Flat, 5);
+        assert_eq!(
+            flat.type_string,
+            super::NEAR_TX_FLAT_TYPE.replacen(')', ",uint256 tip)", 1)
+        );
+
+        // Declared gas follows the tip.
+        let with_gas = |tip: u128| {
+            let input = InternalMetaCallArgs {
+                gas: 30_000_000_000_000,
+                ..input(TypedDataSchema::Nested, tip)
+            };
+            super::encode_meta_call(&domain_separator, b"gateway", &input).unwrap()
+        };
+        assert_eq!(
+            with_gas(0).type_string,
+            plain.type_string.replacen(')', ",uint64 gas)", 1)
+        );
+        let both = with_gas(5);
+        assert_eq!(
+            both.type_string,
+            plain
+                .type_string
+                .replacen(')', ",uint256 tip,uint64 gas)", 1)
+        );
+        assert_eq!(
+            both.encoded_struct[..tipped.encoded_struct.len()],
+            tipped.encoded_struct[..]
+        );
+        assert_ne!(both.digest, tipped.digest);
+
+        let relayer_deposit = super::encode_meta_call(
+            &domain_separator,
+            b"gateway",
+            &InternalMetaCallArgs {
+                deposit_source: DepositSource::Relayer,
+                ..input(TypedDataSchema::Nested, 0)
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            relayer_deposit.type_string,
+            plain.type_string.replacen(')', ",uint8 depositSource)", 1)
+        );
+        assert_ne!(relayer_deposit.digest, plain.digest);
+        // Multisig account is the last field, the address padded to 32 bytes.
+        let multisig = |account: Address| {
+            let input = InternalMetaCallArgs {
+                multisig_account: Some(account),
+                ..input(TypedDataSchema::Nested, 5)
+            };
+            super::encode_meta_call(&domain_separator, b"gateway", &input).unwrap()
+        };
+        let account = Address::repeat_byte(7);
+        let multisig_encoding = multisig(account);
+        assert_eq!(
+            multisig_encoding.type_string,
+            plain
+                .type_string
+                .replacen(')', ",uint256 tip,address multisigAccount)", 1)
+        );
+        assert_eq!(
+            multisig_encoding.encoded_struct[tipped.encoded_struct.len()..],
+            [&[0; 12][..], account.as_bytes()].concat()[..]
+        );
+        assert_ne!(multisig_encoding.digest, tipped.digest);
+        assert_ne!(
+            multisig_encoding.digest,
+            multisig(Address::repeat_byte(8)).digest
+        );
+    }
+
+    /// Transfer message from a P-256 key, signed for the `gateway` account.
+    fn p256_signed_message(nonce: u64) -> (Vec<u8>, Address) {
+        use p256::ecdsa::signature::hazmat::PrehashSigner;
+        use p256::ecdsa::{Signature, SigningKey};
+
+        let domain_separator = super::near_erc712_domain(U256::from(1));
+        let signing_key = SigningKey::from_slice(&[0x42; 32]).unwrap();
+        let mut public_key = [0u8; 64];
+        public_key.copy_from_slice(
+            &signing_key
+                .verifying_key()
+                .to_encoded_point(false)
+                .as_bytes()[1..],
+        );
+        let message = |signature: [u8; 64]| {
+            MetaCallArgs {
+                signature: MetaCallSignature::P256 {
+                    public_key,
+                    signature,
+                },
+                nonce: u256_to_arr(&U256::from(nonce)),
+                fee_amount: [0; 32],
+                fee_address: "relayer".to_string(),
+                contract_address: "alice".to_string(),
+                value: [0; 32],
+                method: String::new(),
+                method_id: None,
+                args_encoding: ArgsEncoding::Rlp,
+                call_args_format: CallArgsFormat::Json,
+                args: vec![],
+                schema: TypedDataSchema::Nested,
+                tip: [0; 32],
+                gas: 0,
+                deposit_source: DepositSource::Account,
+                value_unit: ValueUnit::Yocto,
+            }
+            .try_to_vec()
+            .unwrap()
+        };
+
+        let encoding = super::encode_unsigned_meta_call(
+            &domain_separator,
+            b"gateway",
+            message([0; 64]),
+            |_| None,
+        )
+        .unwrap();
+        let signature: Signature = signing_key.sign_prehash(&encoding.digest).unwrap();
+        let signature = signature.normalize_s().unwrap_or(signature);
+        let mut signature_bytes = [0u8; 64];
+        signature_bytes.copy_from_slice(&signature.to_bytes());
+        (
+            message(signature_bytes),
+            crate::ecrecover::public_key_to_address(&public_key),
+        )
+    }
+
+    #[test]
+    fn test_p256_signed_meta_call() {
+        let domain_separator = super::near_erc712_domain(U256::from(1));
+        let (message, address) = p256_signed_message(0);
+        let no_methods = |_: u32| None;
+
+        let result = super::parse_meta_call(
+            &domain_separator,
+            1,
+            b"gateway",
+            message.clone(),
+            no_methods,
+        )
+        .unwrap();
+        assert_eq!(result.sender, address);
+        assert_eq!(
+            super::parse_meta_call(&domain_separator, 1, b"other-gateway", message, no_methods)
+                .unwrap_err(),
+            ParsingError::InvalidEcRecoverSignature
+        );
+    }
+
+    #[test]
+    fn test_parse_near_amount() {
+        use super::parse_near_amount;
+
+        let near = 10u128.pow(24);
+        assert_eq!(
+            parse_near_amount("1.5 NEAR", ValueUnit::Yocto),
+            Ok(near * 3 / 2)
+        );
+        assert_eq!(parse_near_amount("2 NEAR", ValueUnit::Yocto), Ok(2 * near));
+        assert_eq!(
+            parse_near_amount("0.25 NEAR", ValueUnit::Wei),
+            Ok(25 * 10u128.pow(16))
+        );
+        assert_eq!(parse_near_amount("7", ValueUnit::Wei), Ok(7));
+        assert_eq!(
+            parse_near_amount("0.0000000000000000001 NEAR", ValueUnit::Wei)
+                .unwrap_err()
+                .to_string(),
+            "expected at most 18 decimals at offset 20, found \"1\""
+        );
+        assert!(parse_near_amount("0.0000000000000000001 NEAR", ValueUnit::Yocto).is_ok());
+        for invalid in &["1.5 ETH", "1.5", ".5 NEAR", "1. NEAR", "-1 NEAR", ""] {
+            assert!(matches!(
+                parse_near_amount(invalid, ValueUnit::Yocto),
+                Err(ParsingError::UnexpectedToken { .. })
+            ));
+        }
+        assert_eq!(
+            parse_near_amount("1000000000000000 NEAR", ValueUnit::Yocto),
+            Err(ParsingError::ValueOverflow)
+        );
+        assert_eq!(
+            parse_near_amount(&format!("{}0", u128::MAX), ValueUnit::Yocto),
+            Err(ParsingError::ValueOverflow)
+        );
+    }
+
+    #[test]
+    fn test_meta_call_args_json() {
+        let json: MetaCallArgsJson = serde_json::from_value(serde_json::json!({
+            "signature": format!("0x{}{}", "11".repeat(64), "1b"),
+            "nonce": "3",
+            "fee_amount": "5",
+            "fee_address": "token",
+            "contract_address": "user2",
+            "value": "1000000000000000000000000",
+            "method": "ping(uint64 n)",
+            "args": "0xc109",
+            "gas": 7,
+        }))
+        .unwrap();
+        let meta_tx = MetaCallArgs::try_from(json.clone()).unwrap();
+        let expected = MetaCallArgs {
+            signature: MetaCallSignature::Secp256k1 {
+                signature: [0x11; 64],
+                v: 27,
+            },
+            nonce: u256_to_arr(&U256::from(3)),
+            fee_amount: u256_to_arr(&U256::from(5)),
+            fee_address: "token".to_string(),
+            contract_address: "user2".to_string(),
+            value: u256_to_arr(&U256::from(10).pow(U256::from(24))),
+            method: "ping(uint64 n)".to_string(),
+            method_id: None,
+            args_encoding: ArgsEncoding::Rlp,
+            call_args_format: CallArgsFormat::Json,
+            args: vec![0xc1, 0x09],
+            schema: TypedDataSchema::Nested,
+            tip: [0; 32],
+            gas: 7,
+            deposit_source: DepositSource::Account,
+            value_unit: ValueUnit::Yocto,
+        };
+        assert_eq!(
+            meta_tx.try_to_vec().unwrap(),
+            expected.try_to_vec().unwrap()
+        );
+
+        let mut invalid = json.clone();
+        invalid.nonce = "0x3".to_string();
+        assert_eq!(
+            MetaCallArgs::try_from(invalid).unwrap_err().to_string(),
+            "expected decimal nonce at offset 0, found \"0\""
+        );
+        let mut invalid = json;
+        invalid.signature = "0x1b".to_string();
+        assert!(MetaCallArgs::try_from(invalid).is_err());
+    }
+
+    #[test]
+    fn test_amount_overflow() {
+        let domain_separator = super::near_erc712_domain(U256::from(1));
+        let (message, _) = p256_signed_message(0);
+        let meta_tx = || MetaCallArgs::try_from_slice(&message).unwrap();
+        let mut max = [0u8; 32];
+        max[16..].copy_from_slice(&[0xff; 16]);
+        let mut above_max = [0u8; 32];
+        above_max[15] = 1;
+
+        let mut valid = meta_tx();
+        valid.value = max;
+        assert_eq!(
+            InternalMetaCallArgs::try_from(valid).unwrap().value,
+            u128::MAX
+        );
+        let mut invalid = meta_tx();
+        invalid.value = above_max;
+        assert_eq!(
+            super::parse_meta_call(
+                &domain_separator,
+                1,
+                b"gateway",
+                invalid.try_to_vec().unwrap(),
+                |_| None
+            )
+            .unwrap_err(),
+            ParsingError::AmountOverflow { field: "value" }
+        );
+        let mut invalid = meta_tx();
+        invalid.fee_amount = [0xff; 32];
+        assert_eq!(
+            InternalMetaCallArgs::try_from(invalid).unwrap_err(),
+            ParsingError::AmountOverflow {
+                field: "fee_amount"
+            }
+        );
+    }
+
+    #[test]
+    fn test_multisig_signed_meta_call() {
+        use p256::ecdsa::signature::hazmat::PrehashSigner;
+        use p256::ecdsa::{Signature, SigningKey};
+
+        let domain_separator = super::near_erc712_domain(U256::from(1));
+        let (message, address) = p256_signed_message(0);
+        let single = MetaCallArgs::try_from_slice(&message).unwrap().signature;
+        let public_key = match single {
+            MetaCallSignature::P256 { public_key, .. } => public_key,
+            _ => unreachable!(),
+        };
+        let multisig = |account: [u8; 20], signatures: Vec<MetaCallSignature>| {
+            let mut meta_tx = MetaCallArgs::try_from_slice(&message).unwrap();
+            meta_tx.signature = MetaCallSignature::Multisig {
+                account,
+                signatures: MultisigSignatures(signatures),
+            };
+            meta_tx.try_to_vec().unwrap()
+        };
+        // Signature of the message for the account by the key of `p256_signed_message`.
+        let sign = |account: [u8; 20]| {
+            let encoding = super::encode_unsigned_meta_call(
+                &domain_separator,
+                b"gateway",
+                multisig(account, vec![]),
+                |_| None,
+            )
+            .unwrap();
+            assert!(encoding.type_string.contains(",address multisigAccount)"));
+            let signing_key = SigningKey::from_slice(&[0x42; 32]).unwrap();
+            let signature: Signature = signing_key.sign_prehash(&encoding.digest).unwrap();
+            let signature = signature.normalize_s().unwrap_or(signature);
+            let mut bytes = [0u8; 64];
+            bytes.copy_from_slice(&signature.to_bytes());
+            MetaCallSignature::P256 {
+                public_key,
+                signature: bytes,
+            }
+        };
+        let parse =
+            |message| super::parse_meta_call(&domain_separator, 1, b"gateway", message, |_| None);
+
+        let signature = sign([7; 20]);
+        let message = multisig([7; 20], vec![signature.clone(), signature.clone()]);
+        let result = parse(message).unwrap();
+        assert_eq!(result.sender, Address::from([7; 20]));
+        assert_eq!(result.signers, vec![address, address]);
+
+        // Signatures for one account can't be replayed for another one with the same signers.
+        let message = multisig([8; 20], vec![signature.clone(), signature]);
+        assert_eq!(
+            parse(message).unwrap_err(),
+            ParsingError::InvalidEcRecoverSignature
+        );
+        // Neither can a single signature message be wrapped for an account of its signer.
+        let message = multisig([7; 20], vec![single]);
+        assert_eq!(
+            parse(message).unwrap_err(),
+            ParsingError::InvalidEcRecoverSignature
+        );
+
+        let message = multisig([7; 20], vec![]);
+        assert_eq!(
+            parse(message).unwrap_err(),
+            ParsingError::InvalidEcRecoverSignature
+        );
+        // Multi-signatures don't nest, the message doesn't decode.
+        let signature = sign([7; 20]);
+        let nested = MetaCallSignature::Multisig {
+            account: [7; 20],
+            signatures: MultisigSignatures(vec![signature.clone()]),
+        };
+        let message = multisig([7; 20], vec![signature, nested]);
+        assert_eq!(
+            parse(message).unwrap_err(),
+            ParsingError::ArgumentParseError
+        );
+    }
+
+    #[test]
+    fn test_recover_signers() {
+        let domain_separator = super::near_erc712_domain(U256::from(1));
+        let (first, address) = p256_signed_message(0);
+        let (second, _) = p256_signed_message(1);
+        let results = super::recover_signers(
+            &domain_separator,
+            1,
+            b"gateway",
+            vec![first, b"garbage".to_vec(), second],
+            |_| None,
+            |sender, nonce| {
+                assert_eq!(*sender, address);
+                assert!(nonce <= U256::one());
+                U256::zero()
+            },
+        );
+
+        let first = results[0].as_ref().unwrap();
+        assert_eq!(first.address, format!("0x{}", hex::encode(address)));
+        assert_eq!(first.digest.len(), 66);
+        assert_eq!(first.expected_nonce, "0");
+        assert!(first.nonce_matches);
+        assert_eq!(
+            results[1].as_ref().unwrap_err(),
+            &ParsingError::ArgumentParseError
+        );
+        let second = results[2].as_ref().unwrap();
+        assert_ne!(second.digest, first.digest);
+        assert!(!second.nonce_matches);
+    }
+
+    #[test]
+    fn test_verify_checkpoints() {
+        let domain_separator = super::near_erc712_domain(U256::from(1));
+        let (message, address) = p256_signed_message(0);
+        let verify = |message: Vec<u8>| {
+            let mut phases = vec![];
+            let result = super::verify_meta_call_with_checkpoints(
+                &domain_separator,
+                1,
+                b"gateway",
+                message,
+                |_| None,
+                |phase| phases.push(phase),
+            );
+            (result, phases)
+        };
+
+        let (result, phases) = verify(message.clone());
+        assert_eq!(result.unwrap().0.sender, address);
+        assert_eq!(phases, vec!["parsing", "hashing", "recovery"]);
+        let (result, phases) = verify(b"garbage".to_vec());
+        assert_eq!(result.unwrap_err(), ParsingError::ArgumentParseError);
+        assert!(phases.is_empty());
+    }
+
+    #[test]
+    fn test_digest_covers_message() {
+        use k256::ecdsa::SigningKey;
+
+        let domain_separator = super::near_erc712_domain(U256::from(1));
+        let signing_key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+        let address = crate::ecrecover::public_key_to_address(
+            &signing_key
+                .verifying_key()
+                .to_encoded_point(false)
+                .as_bytes()[1..],
+        );
+        let message = |contract_address: &str, digest: &[u8]| {
+            let (signature, recovery_id) = signing_key.sign_prehash_recoverable(digest).unwrap();
+            let mut signature_bytes = [0u8; 64];
+            signature_bytes.copy_from_slice(&signature.to_bytes());
+            MetaCallArgs {
+                signature: MetaCallSignature::Secp256k1 {
+                    signature: signature_bytes,
+                    v: recovery_id.to_byte() + 27,
+                },
+                nonce: [0; 32],
+                fee_amount: [0; 32],
+                fee_address: String::new(),
+                contract_address: contract_address.to_string(),
+                value: u256_to_arr(&U256::from(1)),
+                method: String::new(),
+                method_id: None,
+                args_encoding: ArgsEncoding::Rlp,
+                call_args_format: CallArgsFormat::Json,
+                args: vec![],
+                schema: TypedDataSchema::Nested,
+                tip: [0; 32],
+                gas: 0,
+                deposit_source: DepositSource::Account,
+                value_unit: ValueUnit::Yocto,
+            }
+            .try_to_vec()
+            .unwrap()
+        };
+        let sender = |message: Vec<u8>| {
+            super::parse_meta_call(&domain_separator, 1, b"gateway", message, |_| None)
+                .map(|args| args.sender)
+        };
+
+        // The first gateway signed `keccak256(0x1901 ‖ domain ‖ keccak256(0x1901 ‖ domain))`,
+        // the same for every message, so one signature authorized any message.
+        let mut baseline = vec![0x19, 0x01];
+        baseline.extend_from_slice(&domain_separator);
+        let inner = keccak256(&baseline);
+        baseline.extend_from_slice(&inner);
+        let baseline_digest = keccak256(&baseline);
+        assert_ne!(
+            sender(message("alice", &baseline_digest)),
+            Ok(address),
+            "Baseline-style digest must not verify"
+        );
+
+        let digest = super::encode_unsigned_meta_call(
+            &domain_separator,
+            b"gateway",
+            message("alice", &[1; 32]),
+            |_| None,
+        )
+        .unwrap()
+        .digest;
+        assert_eq!(sender(message("alice", &digest)), Ok(address));
+        // The signature doesn't carry over to another receiver.
+        let mut redirected = MetaCallArgs::try_from_slice(&message("alice", &digest)).unwrap();
+        redirected.contract_address = "mallory".to_string();
+        assert_ne!(sender(redirected.try_to_vec().unwrap()), Ok(address));
+    }
+
+    #[test]
+    fn test_parse_eth_transaction() {
+        use k256::ecdsa::SigningKey;
+
+        let domain_separator = super::near_erc712_domain(U256::from(1));
+        let chain_id = super::gateway_chain_id(b"gateway");
+        let sign = |chain_id: u64, to: &[u8], value: U256, data: &[u8]| {
+            let unsigned = |stream: &mut rlp::RlpStream| {
+                stream
+                    .append(&0u8)
+                    .append(&1u8)
+                    .append(&21000u32)
+                    .append(&to.to_vec())
+                    .append(&value)
+                    .append(&data.to_vec());
+            };
+            let mut stream = rlp::RlpStream::new_list(9);
+            unsigned(&mut stream);
+            stream.append(&chain_id).append(&0u8).append(&0u8);
+            let signing_key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+            let (signature, recovery_id) = signing_key
+                .sign_prehash_recoverable(&keccak256(&stream.out()))
+                .unwrap();
+            let signature = signature.to_bytes();
+            let mut stream = rlp::RlpStream::new_list(9);
+            unsigned(&mut stream);
+            stream
+                .append(&(chain_id * 2 + 35 + u64::from(recovery_id.to_byte())))
+                .append(&U256::from_big_endian(&signature[..32]))
+                .append(&U256::from_big_endian(&signature[32..]));
+            stream.out().to_vec()
+        };
+
+        // Transfer of 1 ETH to the proxy account of 0x3535...35.
+        let transfer = sign(
+            chain_id,
+            &[0x35; 20],
+            U256::from(10).pow(U256::from(18)),
+            &[],
+        );
+        let result =
+            super::parse_eth_transaction(&domain_separator, b"gateway", &transfer).unwrap();
+        assert_eq!(
+            hex::encode(result.sender),
+            "9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f"
+        );
+        assert_eq!(result.nonce, U256::zero());
+        assert_eq!(
+            result.contract_address,
+            format!("{}.gateway", hex::encode([0x35; 20]))
+        );
+        assert_eq!(result.value, 10u128.pow(24));
+        assert!(result.method_name.is_empty());
+        // Other gateways would map it to their own proxy account of `to`, but it's signed for
+        // this one only.
+        assert_eq!(
+            super::parse_eth_transaction(&domain_separator, b"other", &transfer).unwrap_err(),
+            ParsingError::ChainIdMismatch
+        );
+
+        // Mainnet transfer from the EIP-155 example, signed by the same key, can't be replayed.
+        let mainnet = hex::decode("f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83").unwrap();
+        assert_eq!(
+            super::parse_eth_transaction(&domain_separator, b"gateway", &mainnet).unwrap_err(),
+            ParsingError::ChainIdMismatch
+        );
+
+        // Call with the receiver, method and arguments in the data.
+        let mut args = rlp::RlpStream::new_list(2);
+        args.append(&1u8).append(&"test");
+        let args = args.out().to_vec();
+        let mut data = rlp::RlpStream::new_list(3);
+        data.append(&"alice")
+            .append(&"test_call(uint64 x,string y)")
+            .append(&args);
+        let data = data.out().to_vec();
+        let call = sign(chain_id, &[0; 20], U256::zero(), &data);
+        assert_eq!(
+            super::eth_transaction_call(&call),
+            Some(("test_call(uint64 x,string y)".to_string(), args))
+        );
+        assert_eq!(super::eth_transaction_call(&transfer), None);
+
+        let result = super::parse_eth_transaction(&domain_separator, b"gateway", &call).unwrap();
+        assert_eq!(
+            hex::encode(result.sender),
+            "9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f"
+        );
+        assert_eq!(result.contract_address, "alice");
+        assert_eq!(result.method_name, "test_call");
+        assert_eq!(result.args, br#"{"x":"1","y":"test"}"#.to_vec());
+        let mainnet = sign(1, &[0; 20], U256::zero(), &data);
+        assert_eq!(
+            super::parse_eth_transaction(&domain_separator, b"gateway", &mainnet).unwrap_err(),
+            ParsingError::ChainIdMismatch
+        );
+    }
+
+    #[test]
+    fn test_prepare_meta_call_args_struct_order() {
+        let domain_separator = super::near_erc712_domain(U256::from(1));
+        let input = |method_name: &str| InternalMetaCallArgs {
+            sender: Address::zero(),
+            nonce: U256::zero(),
+            fee_amount: 0,
+            fee_address: "relayer".to_string(),
+            contract_address: "pets".to_string(),
+            method_name: method_name.to_string(),
+            value: 0,
+            args_encoding: ArgsEncoding::Rlp,
+            call_args_format: CallArgsFormat::Json,
+            args: vec![],
+            signers: vec![],
+            schema: TypedDataSchema::Nested,
+            tip: 0,
+            gas: 0,
+            deposit_source: DepositSource::Account,
+            value_unit: ValueUnit::Yocto,
+            multisig_account: None,
+        };
+        let mut stream = rlp::RlpStream::new_list(1);
+        stream.begin_list(1).begin_list(1).append(&"Cat");
+        let pet_args = stream.out().to_vec();
+        let (msg1, _, _) = super::prepare_meta_call_args(
+            &domain_separator,
+            b"gateway",
+            &InternalMetaCallArgs {
+                args: pet_args.clone(),
+                ..input("adopt(Pet pet)Pet(Name name)Name(string value)")
+            },
+        )
+        .unwrap();
+        let (msg2, _, _) = super::prepare_meta_call_args(
+            &domain_separator,
+            b"gateway",
+            &InternalMetaCallArgs {
+                args: pet_args,
+                ..input("adopt(Pet pet)Name(string value)Pet(Name name)")
+            },
+        )
+        .unwrap();
+        // The order in which types are listed in the method definition doesn't change the digest.
+        assert_eq!(msg1, msg2);
+
+        // Structs can't shadow the types of the meta transaction itself.
+        super::prepare_meta_call_args(
+            &domain_separator,
+            b"gateway",
+            &input("adopt(Arguments pet)Arguments(string name)"),
+        )
+        .unwrap_err();
+    }
+
+    fn create_array_type_string(inner_type: &str, size: Option<u8>) -> String {
+        format!(
+            "{}[{}]",
+            inner_type,
+            size.map(|x| x.to_string()).unwrap_or_default()
+        )
+    }
+
+    fn assert_arg_type(s: &str, expected: ArgType) {
+        assert_eq!(super::parse_type(s).ok().unwrap(), expected);
+    }
+
+    fn rand_identifier<T: Rng>(rng: &mut T) -> String {
+        use rand::distributions::Alphanumeric;
+        use rand::seq::IteratorRandom;
+
+        // The first character must be a letter, so we sample that separately.
+        let first_char = ('a'..='z').chain('A'..='Z').choose(rng).unwrap();
+        let other_letters = (0..7).map(|_| rng.sample(Alphanumeric));
+
+        std::iter::once(first_char).chain(other_letters).collect()
+    }
+}
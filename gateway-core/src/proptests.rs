@@ -0,0 +1,387 @@
+//! Property tests of the arguments of meta calls: random method definitions with matching RLP
+//! argument lists are hashed and transcoded to JSON by the gateway, and checked against a
+//! reference encoding of the values they were generated from. Mismatched arguments must fail
+//! parsing, never panic.
+
+use primitive_types::U256;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use rlp::RlpStream;
+use serde_json::{json, Value};
+
+use crate::meta_parsing::{
+    encode_meta_call, encode_prepared_meta_call, near_erc712_domain, MetaCallEncoding,
+    ParsingResult, PreparedMethod,
+};
+use crate::types::{
+    keccak256, Address, ArgsEncoding, CallArgsFormat, DepositSource, InternalMetaCallArgs,
+    TypedDataSchema, ValueUnit,
+};
+
+/// Name of the struct type methods may take, with the fields in `Method::fields`.
+const STRUCT_NAME: &str = "Item";
+
+/// Argument type, as generated.
+#[derive(Debug, Clone)]
+enum Type {
+    Address,
+    Uint(usize),
+    Int(usize),
+    String,
+    Bool,
+    Bytes,
+    Byte(usize),
+    Array(Option<usize>, Box<Type>),
+    Struct,
+}
+
+/// Argument value, generated for its type: the RLP encoded bytes of atomic values.
+#[derive(Debug, Clone)]
+enum Arg {
+    Bytes(Vec<u8>),
+    Int(i128),
+    String(String),
+    List(Vec<Arg>),
+}
+
+/// Method definition with arguments of the types, and the fields of the struct they may use.
+#[derive(Debug, Clone)]
+struct Method {
+    args: Vec<Type>,
+    fields: Vec<Type>,
+}
+
+impl Type {
+    fn definition(&self) -> String {
+        match self {
+            Type::Address => "address".to_string(),
+            Type::Uint(bits) => format!("uint{}", bits),
+            Type::Int(bits) => format!("int{}", bits),
+            Type::String => "string".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Bytes => "bytes".to_string(),
+            Type::Byte(size) => format!("bytes{}", size),
+            Type::Array(None, inner) => format!("{}[]", inner.definition()),
+            Type::Array(Some(length), inner) => format!("{}[{}]", inner.definition(), length),
+            Type::Struct => STRUCT_NAME.to_string(),
+        }
+    }
+
+    fn uses_struct(&self) -> bool {
+        match self {
+            Type::Struct => true,
+            Type::Array(_, inner) => inner.uses_struct(),
+            _ => false,
+        }
+    }
+}
+
+/// Comma separated typed fields, named by their prefix and position.
+fn fields(prefix: &str, types: &[Type]) -> String {
+    types
+        .iter()
+        .enumerate()
+        .map(|(i, ty)| format!("{} {}{}", ty.definition(), prefix, i))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl Method {
+    fn uses_struct(&self) -> bool {
+        self.args.iter().any(Type::uses_struct)
+    }
+
+    fn struct_definition(&self) -> String {
+        format!("{}({})", STRUCT_NAME, fields("f", &self.fields))
+    }
+
+    fn definition(&self) -> String {
+        let mut definition = format!("test({})", fields("a", &self.args));
+        if self.uses_struct() {
+            definition.push_str(&self.struct_definition());
+        }
+        definition
+    }
+
+    /// hashStruct of the `Arguments` with the values, per EIP-712.
+    fn arguments_hash(&self, values: &[Arg]) -> Vec<u8> {
+        let mut arguments_type = format!("Arguments({})", fields("a", &self.args));
+        if self.uses_struct() {
+            arguments_type.push_str(&self.struct_definition());
+        }
+        let mut encoded = keccak256(arguments_type.as_bytes());
+        for (ty, value) in self.args.iter().zip(values) {
+            encoded.extend_from_slice(&self.encode_data(ty, value));
+        }
+        keccak256(&encoded)
+    }
+
+    /// EIP-712 `encodeData` of the value: the 32 byte word of atomic values, keccak256 of
+    /// dynamic ones and of the concatenated words of arrays, hashStruct of structs.
+    fn encode_data(&self, ty: &Type, value: &Arg) -> Vec<u8> {
+        let left_padded = |bytes: &[u8]| [vec![0; 32 - bytes.len()], bytes.to_vec()].concat();
+        match (ty, value) {
+            (Type::Address, Arg::Bytes(bytes))
+            | (Type::Uint(_), Arg::Bytes(bytes))
+            | (Type::Bool, Arg::Bytes(bytes)) => left_padded(bytes),
+            (Type::Int(_), Arg::Int(value)) => {
+                let sign = if *value < 0 { 0xff } else { 0 };
+                [vec![sign; 16], value.to_be_bytes().to_vec()].concat()
+            }
+            (Type::String, Arg::String(text)) => keccak256(text.as_bytes()),
+            (Type::Bytes, Arg::Bytes(bytes)) => keccak256(bytes),
+            (Type::Byte(_), Arg::Bytes(bytes)) => {
+                [bytes.to_vec(), vec![0; 32 - bytes.len()]].concat()
+            }
+            (Type::Array(_, inner), Arg::List(elements)) => keccak256(
+                &elements
+                    .iter()
+                    .flat_map(|element| self.encode_data(inner, element))
+                    .collect::<Vec<_>>(),
+            ),
+            (Type::Struct, Arg::List(values)) => {
+                let mut encoded = keccak256(self.struct_definition().as_bytes());
+                for (ty, value) in self.fields.iter().zip(values) {
+                    encoded.extend_from_slice(&self.encode_data(ty, value));
+                }
+                keccak256(&encoded)
+            }
+            _ => unreachable!("Value generated for another type"),
+        }
+    }
+
+    /// JSON of the value as the NEAR method gets it.
+    fn json(&self, ty: &Type, value: &Arg) -> Value {
+        match (ty, value) {
+            (Type::Address, Arg::Bytes(bytes)) => json!(format!("0x{}", hex::encode(bytes))),
+            (Type::Uint(_), Arg::Bytes(bytes)) => json!(U256::from_big_endian(bytes).to_string()),
+            (Type::Int(_), Arg::Int(value)) => json!(value.to_string()),
+            (Type::Bool, Arg::Bytes(bytes)) => json!(!bytes.is_empty()),
+            (Type::String, Arg::String(text)) => json!(text),
+            (Type::Bytes, Arg::Bytes(bytes)) | (Type::Byte(_), Arg::Bytes(bytes)) => {
+                json!(base64::encode(bytes))
+            }
+            (Type::Array(_, inner), Arg::List(elements)) => Value::Array(
+                elements
+                    .iter()
+                    .map(|element| self.json(inner, element))
+                    .collect(),
+            ),
+            (Type::Struct, Arg::List(values)) => self.object("f", &self.fields, values),
+            _ => unreachable!("Value generated for another type"),
+        }
+    }
+
+    fn object(&self, prefix: &str, types: &[Type], values: &[Arg]) -> Value {
+        Value::Object(
+            types
+                .iter()
+                .zip(values)
+                .enumerate()
+                .map(|(i, (ty, value))| (format!("{}{}", prefix, i), self.json(ty, value)))
+                .collect(),
+        )
+    }
+}
+
+/// Minimal big-endian two's complement bytes of the integer, as wallets RLP encode `int<N>`.
+fn int_bytes(value: i128) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let sign = if value < 0 { 0xff } else { 0 };
+    // A leading sign byte is redundant if the next byte has the same sign bit, zero is empty.
+    let redundant = |i: usize| {
+        bytes[i] == sign
+            && match bytes.get(i + 1) {
+                Some(next) => (next & 0x80 != 0) == (value < 0),
+                None => value == 0,
+            }
+    };
+    let mut start = 0;
+    while start < bytes.len() && redundant(start) {
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+fn append(stream: &mut RlpStream, value: &Arg) {
+    match value {
+        Arg::Bytes(bytes) => {
+            stream.append(bytes);
+        }
+        Arg::Int(value) => {
+            stream.append(&int_bytes(*value));
+        }
+        Arg::String(text) => {
+            stream.append(&text.as_bytes());
+        }
+        Arg::List(elements) => {
+            stream.begin_list(elements.len());
+            for element in elements {
+                append(stream, element);
+            }
+        }
+    }
+}
+
+fn rlp_args(values: &[Arg]) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(values.len());
+    for value in values {
+        append(&mut stream, value);
+    }
+    stream.out().to_vec()
+}
+
+fn atomic_type() -> impl Strategy<Value = Type> {
+    prop_oneof![
+        Just(Type::Address),
+        (1..=32usize).prop_map(|bytes| Type::Uint(bytes * 8)),
+        (1..=32usize).prop_map(|bytes| Type::Int(bytes * 8)),
+        Just(Type::String),
+        Just(Type::Bool),
+        Just(Type::Bytes),
+        (1..=32usize).prop_map(Type::Byte),
+    ]
+}
+
+/// Atomic types, the struct and arrays of them nested up to twice.
+fn arg_type() -> impl Strategy<Value = Type> {
+    prop_oneof![atomic_type(), Just(Type::Struct)].prop_recursive(2, 8, 1, |inner| {
+        (proptest::option::of(1..=3usize), inner)
+            .prop_map(|(length, inner)| Type::Array(length, Box::new(inner)))
+    })
+}
+
+fn method() -> impl Strategy<Value = Method> {
+    (vec(arg_type(), 0..4), vec(atomic_type(), 1..4))
+        .prop_map(|(args, fields)| Method { args, fields })
+}
+
+fn value(method: &Method, ty: &Type) -> BoxedStrategy<Arg> {
+    match ty {
+        Type::Address => vec(any::<u8>(), 20).prop_map(Arg::Bytes).boxed(),
+        // Values fill the whole range of the type, the gateway rejects wider ones.
+        Type::Uint(bits) => {
+            prop_oneof![vec(any::<u8>(), 0..=bits / 8), Just(vec![0xff; bits / 8]),]
+                .prop_map(Arg::Bytes)
+                .boxed()
+        }
+        Type::Int(bits) => {
+            // Arithmetic shift keeps the sign and narrows i128 down to the type.
+            let shift = 128 - (*bits).min(128) as u32;
+            prop_oneof![any::<i128>(), Just(i128::MIN), Just(i128::MAX), Just(-1)]
+                .prop_map(move |value| Arg::Int(value >> shift))
+                .boxed()
+        }
+        Type::String => any::<String>().prop_map(Arg::String).boxed(),
+        Type::Bool => prop_oneof![Just(vec![]), Just(vec![1])]
+            .prop_map(Arg::Bytes)
+            .boxed(),
+        Type::Bytes => vec(any::<u8>(), 0..80).prop_map(Arg::Bytes).boxed(),
+        Type::Byte(size) => vec(any::<u8>(), *size).prop_map(Arg::Bytes).boxed(),
+        Type::Array(length, inner) => {
+            let length = length.map_or(0..3, |length| length..length + 1);
+            vec(value(method, inner), length)
+                .prop_map(Arg::List)
+                .boxed()
+        }
+        Type::Struct => values(method, &method.fields).prop_map(Arg::List).boxed(),
+    }
+}
+
+fn values(method: &Method, types: &[Type]) -> BoxedStrategy<Vec<Arg>> {
+    types
+        .iter()
+        .map(|ty| value(method, ty))
+        .collect::<Vec<_>>()
+        .boxed()
+}
+
+/// Method with values of its arguments.
+fn method_call() -> impl Strategy<Value = (Method, Vec<Arg>)> {
+    method().prop_flat_map(|method| {
+        let values = values(&method, &method.args);
+        (Just(method), values)
+    })
+}
+
+fn encode(method_def: &str, args: Vec<u8>) -> ParsingResult<MetaCallEncoding> {
+    encode_meta_call(
+        &near_erc712_domain(U256::from(1)),
+        b"gateway",
+        &meta_call(method_def, args),
+    )
+}
+
+fn meta_call(method_def: &str, args: Vec<u8>) -> InternalMetaCallArgs {
+    InternalMetaCallArgs {
+        sender: Address::zero(),
+        nonce: U256::zero(),
+        fee_amount: 0,
+        fee_address: String::new(),
+        contract_address: "alice".to_string(),
+        method_name: method_def.to_string(),
+        value: 0,
+        args_encoding: ArgsEncoding::Rlp,
+        call_args_format: CallArgsFormat::Json,
+        args,
+        signers: vec![],
+        schema: TypedDataSchema::Nested,
+        tip: 0,
+        gas: 0,
+        deposit_source: DepositSource::Account,
+        value_unit: ValueUnit::Yocto,
+        multisig_account: None,
+    }
+}
+
+proptest! {
+    #[test]
+    fn test_args_round_trip((method, values) in method_call()) {
+        let method_def = method.definition();
+        let encoding = encode(&method_def, rlp_args(&values)).unwrap();
+        prop_assert_eq!(encoding.method_name, "test");
+        prop_assert_eq!(
+            serde_json::from_slice::<Value>(&encoding.call_args).unwrap(),
+            method.object("a", &method.args, &values)
+        );
+        prop_assert_eq!(encoding.arguments_hash.to_vec(), method.arguments_hash(&values));
+
+        // Registered method definitions hash the same.
+        let prepared = PreparedMethod::parse(&method_def).unwrap();
+        let registered = encode_prepared_meta_call(
+            &near_erc712_domain(U256::from(1)),
+            b"gateway",
+            &meta_call("", rlp_args(&values)),
+            Some(&prepared),
+        )
+        .unwrap();
+        prop_assert_eq!(registered.digest, encoding.digest);
+    }
+
+    #[test]
+    fn test_mismatched_args_fail(
+        (method, values) in method_call(),
+        (_, other_values) in method_call(),
+        garbage in vec(any::<u8>(), 0..64),
+    ) {
+        let method_def = method.definition();
+        // Arguments of another method may happen to fit, but either way parsing returns.
+        let _ = encode(&method_def, rlp_args(&other_values));
+        let _ = encode(&method_def, garbage);
+        let mut missing = values;
+        if missing.pop().is_some() {
+            prop_assert!(encode(&method_def, rlp_args(&missing)).is_err());
+        }
+    }
+}
+
+#[test]
+fn test_int_bytes() {
+    assert_eq!(int_bytes(0), Vec::<u8>::new());
+    assert_eq!(int_bytes(-1), vec![0xff]);
+    assert_eq!(int_bytes(127), vec![0x7f]);
+    assert_eq!(int_bytes(128), vec![0x00, 0x80]);
+    assert_eq!(int_bytes(-128), vec![0x80]);
+    assert_eq!(int_bytes(-129), vec![0xff, 0x7f]);
+    assert_eq!(int_bytes(i128::MIN).len(), 16);
+}
@@ -0,0 +1,119 @@
+use ethabi::Address;
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use p256::elliptic_curve::scalar::IsHigh;
+use primitive_types::H256;
+
+use crate::ecrecover::{public_key_to_address, EcRecoverError};
+
+/// Verifies P-256 signature `r ‖ s` of the message hash by the raw 64-byte `x ‖ y` public key,
+/// as held by passkeys and secure enclaves. P-256 signatures don't allow recovering the key,
+/// so it comes with the message, and its address is derived the same way as Ethereum's.
+/// Authenticators don't normalize `s`, wallets must replace high `s` with `n - s` before sending,
+/// same as for secp256k1 signatures.
+pub(crate) fn verify_p256(
+    hash: H256,
+    public_key: &[u8; 64],
+    signature: &[u8; 64],
+) -> Result<Address, EcRecoverError> {
+    let signature = Signature::from_slice(signature).map_err(|_| EcRecoverError::InvalidScalar)?;
+    if bool::from(signature.s().is_high()) {
+        return Err(EcRecoverError::HighS);
+    }
+    let mut encoded_point = [0u8; 65];
+    encoded_point[0] = 0x04;
+    encoded_point[1..].copy_from_slice(public_key);
+    let verifying_key = VerifyingKey::from_sec1_bytes(&encoded_point)
+        .map_err(|_| EcRecoverError::InvalidPublicKey)?;
+    verifying_key
+        .verify_prehash(hash.as_bytes(), &signature)
+        .map_err(|_| EcRecoverError::VerificationFailed)?;
+    Ok(public_key_to_address(public_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::{H256, U256};
+
+    use super::{verify_p256, EcRecoverError};
+
+    /// (message hash, low-s signature, public key, address), signed with Python `cryptography`.
+    const VECTORS: &[(&str, &str, &str, &str)] = &[
+        (
+            "8ccbc221a14cf20144ec4838721e00c21790009d81f48edf8e41eb7be018fc97",
+            "83b845c2918bc72e8afcd76b736b4f184977e3b56bc1507dd4d01acf0f4e64667edd0dbf1f713bb065b1fd838e565f7c1ebccaee69b78ae668aa3a1695703816",
+            "acebdd429cbeaa2356b6e93670ec7d1990a9cd0d7decb5127fa51ce6a76cc214a48dbd686537360e736b1bb5a32fdc4ce1451dc1882dac12bf080e1ec4acb6f8",
+            "84a6af384d872bcd4cb2c3deb11869acb0e7488d",
+        ),
+        (
+            "1afeba42b253133cd8bdc2490dadd7e7185592d7d9638d86f02d31ea5a632fe5",
+            "d175add7626357adcbbf3b98a202dda26f355cf03cd0375c8b409b4ed02ab57a54f7f808179b80e9bffd6db46d295f17fcb72a0fd0097c2216dcb8086ee57dde",
+            "9beef87725b104c52a300f1a54808d61b5370fdba5d2678b9d0492126727a075caaec7bc52378f6244d557a1022c88b67e9b28722873f6393b82577a145eb2f1",
+            "e61d3195cc6c6fe571b4eb0e918e28a38fa6a30c",
+        ),
+    ];
+
+    fn vector(i: usize) -> (H256, [u8; 64], [u8; 64], ethabi::Address) {
+        let (hash, signature, public_key, address) = VECTORS[i];
+        let mut signature_bytes = [0u8; 64];
+        signature_bytes.copy_from_slice(&hex::decode(signature).unwrap());
+        let mut public_key_bytes = [0u8; 64];
+        public_key_bytes.copy_from_slice(&hex::decode(public_key).unwrap());
+        (
+            H256::from_slice(&hex::decode(hash).unwrap()),
+            signature_bytes,
+            public_key_bytes,
+            ethabi::Address::from_slice(&hex::decode(address).unwrap()),
+        )
+    }
+
+    #[test]
+    fn test_verify_vectors() {
+        for i in 0..VECTORS.len() {
+            let (hash, signature, public_key, address) = vector(i);
+            assert_eq!(verify_p256(hash, &public_key, &signature), Ok(address));
+            assert_eq!(
+                verify_p256(H256::repeat_byte(0x33), &public_key, &signature),
+                Err(EcRecoverError::VerificationFailed)
+            );
+        }
+        // Signature by one key doesn't verify with the other.
+        let (hash, signature, _, _) = vector(0);
+        let (_, _, other_key, _) = vector(1);
+        assert_eq!(
+            verify_p256(hash, &other_key, &signature),
+            Err(EcRecoverError::VerificationFailed)
+        );
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        let (hash, signature, public_key, _) = vector(0);
+
+        let curve_order = U256::from_str_radix(
+            "ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551",
+            16,
+        )
+        .unwrap();
+        let mut malleated = signature;
+        (curve_order - U256::from_big_endian(&signature[32..])).to_big_endian(&mut malleated[32..]);
+        assert_eq!(
+            verify_p256(hash, &public_key, &malleated),
+            Err(EcRecoverError::HighS)
+        );
+
+        let mut zero_r = signature;
+        zero_r[..32].copy_from_slice(&[0; 32]);
+        assert_eq!(
+            verify_p256(hash, &public_key, &zero_r),
+            Err(EcRecoverError::InvalidScalar)
+        );
+
+        let mut off_curve = public_key;
+        off_curve[63] ^= 1;
+        assert_eq!(
+            verify_p256(hash, &off_curve, &signature),
+            Err(EcRecoverError::InvalidPublicKey)
+        );
+    }
+}
@@ -0,0 +1,433 @@
+//! Sign-In-With-Ethereum messages, see https://eips.ethereum.org/EIPS/eip-4361
+//! Wallets show them in a readable form and sign them with `personal_sign`, so they can
+//! authenticate an address without an EIP-712 prompt.
+
+use primitive_types::H256;
+
+use crate::meta_parsing::{unexpected, ParsingError, ParsingResult};
+use crate::types::{checksum_address, keccak256, Address};
+
+const PREAMBLE: &str = " wants you to sign in with your Ethereum account:";
+
+/// Parsed SIWE message. Timestamps are in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiweMessage {
+    /// Domain requesting the signing, with the port if any.
+    pub domain: String,
+    pub address: Address,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: u64,
+    /// At least 8 alphanumeric characters, to prevent replays.
+    pub nonce: String,
+    pub issued_at: u64,
+    pub expiration_time: Option<u64>,
+    pub not_before: Option<u64>,
+    pub request_id: Option<String>,
+    pub resources: Vec<String>,
+}
+
+/// Reads the message line by line, keeping track of the position for errors.
+struct Lines<'a> {
+    source: &'a str,
+    rest: &'a str,
+}
+
+impl<'a> Lines<'a> {
+    /// Next line, without the line feed. The last line may have none.
+    fn line(&mut self, expected: &'static str) -> ParsingResult<&'a str> {
+        if self.rest.is_empty() {
+            return Err(unexpected(self.source, self.rest, expected));
+        }
+        let (line, rest) = match self.rest.find('\n') {
+            Some(end) => (&self.rest[..end], &self.rest[end + 1..]),
+            None => (self.rest, ""),
+        };
+        self.rest = rest;
+        Ok(line)
+    }
+
+    /// Empty line.
+    fn blank(&mut self) -> ParsingResult<()> {
+        if !self.rest.starts_with('\n') {
+            return Err(unexpected(self.source, self.rest, "line feed"));
+        }
+        self.rest = &self.rest[1..];
+        Ok(())
+    }
+
+    /// Value of the `<name>: <value>` line.
+    fn field(&mut self, prefix: &'static str) -> ParsingResult<&'a str> {
+        self.optional_field(prefix)
+            .ok_or_else(|| unexpected(self.source, self.rest, prefix.trim_end()))
+    }
+
+    fn optional_field(&mut self, prefix: &str) -> Option<&'a str> {
+        if !self.rest.starts_with(prefix) {
+            return None;
+        }
+        self.rest = &self.rest[prefix.len()..];
+        // Can't fail, the prefix isn't empty.
+        self.line("value").ok()
+    }
+
+    /// Error for the value just read from the line, which ends at the current position.
+    fn invalid(&self, value: &str, expected: &'static str) -> ParsingError {
+        let end = self.source.len() - self.rest.len();
+        let line_end = if self.source[..end].ends_with('\n') {
+            end - 1
+        } else {
+            end
+        };
+        unexpected(
+            self.source,
+            &self.source[line_end - value.len()..],
+            expected,
+        )
+    }
+}
+
+/// SIWE messages carry the address exactly as EIP-55 encodes it, without case leeway.
+fn parse_checksummed_address(value: &str) -> Option<Address> {
+    let bytes = hex::decode(value.strip_prefix("0x")?).ok()?;
+    if bytes.len() != 20 {
+        return None;
+    }
+    let address = Address::from_slice(&bytes);
+    if checksum_address(&address.0) == value {
+        Some(address)
+    } else {
+        None
+    }
+}
+
+/// Days since 1970-01-01 of the proleptic Gregorian date.
+/// See: http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Seconds since the epoch of the RFC 3339 timestamp, e.g. `2021-12-07T18:28:18.807Z`.
+/// Fractions of a second are dropped.
+fn parse_timestamp(value: &str) -> Option<u64> {
+    let bytes = value.as_bytes();
+    if bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+    let number = |range: core::ops::Range<usize>| -> Option<i64> {
+        let digits = value.get(range)?;
+        if digits.bytes().all(|b| b.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    };
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    // Allows a leap second.
+    if second > 60 {
+        return None;
+    }
+    let mut rest = &value[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return None;
+        }
+        rest = &fraction[digits..];
+    }
+    let offset = match rest.as_bytes() {
+        [b'Z'] | [b'z'] => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let offset = number(value.len() - 5..value.len() - 3)? * 3600
+                + number(value.len() - 2..value.len())? * 60;
+            if *sign == b'+' {
+                offset
+            } else {
+                -offset
+            }
+        }
+        _ => return None,
+    };
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    if seconds < 0 {
+        return None;
+    }
+    Some(seconds as u64)
+}
+
+impl SiweMessage {
+    /// Parses the message text as the ABNF of EIP-4361 defines it.
+    pub fn parse(text: &str) -> ParsingResult<Self> {
+        let mut lines = Lines {
+            source: text,
+            rest: text,
+        };
+        let header = lines.line("SIWE preamble")?;
+        let domain = header
+            .strip_suffix(PREAMBLE)
+            .filter(|domain| !domain.is_empty() && !domain.contains(char::is_whitespace))
+            .ok_or_else(|| unexpected(text, text, "SIWE preamble"))?;
+        let address = lines.line("address")?;
+        let address = parse_checksummed_address(address)
+            .ok_or_else(|| lines.invalid(address, "EIP-55 address"))?;
+        lines.blank()?;
+        let statement = if lines.rest.starts_with('\n') {
+            None
+        } else {
+            Some(lines.line("statement")?.to_string())
+        };
+        lines.blank()?;
+        let uri = lines.field("URI: ")?;
+        let version = lines.field("Version: ")?;
+        if version != "1" {
+            return Err(lines.invalid(version, "version 1"));
+        }
+        let chain_id = lines.field("Chain ID: ")?;
+        let chain_id = chain_id
+            .parse()
+            .map_err(|_| lines.invalid(chain_id, "chain id"))?;
+        let nonce = lines.field("Nonce: ")?;
+        if nonce.len() < 8 || !nonce.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(lines.invalid(nonce, "alphanumeric nonce of at least 8 characters"));
+        }
+        let timestamp = |lines: &mut Lines, value: &str| {
+            parse_timestamp(value).ok_or_else(|| lines.invalid(value, "RFC 3339 timestamp"))
+        };
+        let issued_at = lines.field("Issued At: ")?;
+        let issued_at = timestamp(&mut lines, issued_at)?;
+        let expiration_time = match lines.optional_field("Expiration Time: ") {
+            Some(value) => Some(timestamp(&mut lines, value)?),
+            None => None,
+        };
+        let not_before = match lines.optional_field("Not Before: ") {
+            Some(value) => Some(timestamp(&mut lines, value)?),
+            None => None,
+        };
+        let request_id = lines.optional_field("Request ID: ").map(String::from);
+        let mut resources = vec![];
+        if lines.optional_field("Resources:").is_some() {
+            while let Some(resource) = lines.optional_field("- ") {
+                resources.push(resource.to_string());
+            }
+        }
+        if !lines.rest.is_empty() {
+            return Err(unexpected(text, lines.rest, "end of message"));
+        }
+        Ok(Self {
+            domain: domain.to_string(),
+            address,
+            statement,
+            uri: uri.to_string(),
+            version: version.to_string(),
+            chain_id,
+            nonce: nonce.to_string(),
+            issued_at,
+            expiration_time,
+            not_before,
+            request_id,
+            resources,
+        })
+    }
+}
+
+/// EIP-191 hash of the message wallets sign with `personal_sign`.
+pub fn personal_message_hash(message: &[u8]) -> H256 {
+    let mut data = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    data.extend_from_slice(message);
+    H256::from_slice(&keccak256(&data))
+}
+
+/// Parses the SIWE message and checks it is signed by its address, for the domain and chain
+/// and valid at `now` in seconds.
+pub fn verify_siwe(
+    message: &str,
+    signature: &[u8],
+    domain: &str,
+    chain_id: u64,
+    now: u64,
+) -> ParsingResult<SiweMessage> {
+    let siwe = SiweMessage::parse(message)?;
+    if siwe.domain != domain {
+        return Err(ParsingError::SiweDomainMismatch);
+    }
+    if siwe.chain_id != chain_id {
+        return Err(ParsingError::ChainIdMismatch);
+    }
+    if matches!(siwe.expiration_time, Some(expiration) if expiration <= now) {
+        return Err(ParsingError::SiweExpired);
+    }
+    if matches!(siwe.not_before, Some(not_before) if not_before > now) {
+        return Err(ParsingError::SiweNotYetValid);
+    }
+    let signer = crate::ecrecover::ecrecover(
+        personal_message_hash(message.as_bytes()),
+        signature,
+        chain_id,
+    )
+    .map_err(|_| ParsingError::InvalidEcRecoverSignature)?;
+    if signer != siwe.address {
+        return Err(ParsingError::InvalidEcRecoverSignature);
+    }
+    Ok(siwe)
+}
+
+#[cfg(test)]
+mod tests {
+    use k256::ecdsa::SigningKey;
+
+    use super::{checksum_address, parse_timestamp, verify_siwe, SiweMessage};
+    use crate::meta_parsing::ParsingError;
+
+    /// Example from EIP-4361.
+    const MESSAGE: &str = "service.org wants you to sign in with your Ethereum account:
+0xe5A12547fe4E872D192E3eCecb76F2Ce1aeA4946
+
+I accept the ServiceOrg Terms of Service: https://service.org/tos
+
+URI: https://service.org/login
+Version: 1
+Chain ID: 1
+Nonce: 32891757
+Issued At: 2021-09-30T16:25:24.000Z
+Resources:
+- ipfs://Qme7ss3ARVgxv6rXqVPiikMJ8u2NLgmgszg13pYrDKEoiu
+- https://example.com/my-web2-claim.json";
+
+    #[test]
+    fn test_parse() {
+        let siwe = SiweMessage::parse(MESSAGE).unwrap();
+        assert_eq!(siwe.domain, "service.org");
+        assert_eq!(
+            hex::encode(siwe.address),
+            "e5a12547fe4e872d192e3ececb76f2ce1aea4946"
+        );
+        assert_eq!(
+            siwe.statement.as_deref(),
+            Some("I accept the ServiceOrg Terms of Service: https://service.org/tos")
+        );
+        assert_eq!(siwe.uri, "https://service.org/login");
+        assert_eq!(siwe.chain_id, 1);
+        assert_eq!(siwe.nonce, "32891757");
+        assert_eq!(siwe.issued_at, 1633019124);
+        assert_eq!(siwe.expiration_time, None);
+        assert_eq!(siwe.resources.len(), 2);
+
+        let without_statement = MESSAGE.replace(
+            "I accept the ServiceOrg Terms of Service: https://service.org/tos\n\n",
+            "\n",
+        );
+        assert_eq!(
+            SiweMessage::parse(&without_statement).unwrap().statement,
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let lowercase = MESSAGE.replace(
+            "0xe5A12547fe4E872D192E3eCecb76F2Ce1aeA4946",
+            "0xe5a12547fe4e872d192e3ececb76f2ce1aea4946",
+        );
+        assert_eq!(
+            SiweMessage::parse(&lowercase).unwrap_err(),
+            ParsingError::UnexpectedToken {
+                offset: 61,
+                found: "0".to_string(),
+                expected: "EIP-55 address",
+            }
+        );
+        let short_nonce = MESSAGE.replace("Nonce: 32891757", "Nonce: 1234");
+        assert_eq!(
+            SiweMessage::parse(&short_nonce).unwrap_err().to_string(),
+            "expected alphanumeric nonce of at least 8 characters at offset 233, found \"1\""
+        );
+        let missing_version = MESSAGE.replace("Version: 1\n", "");
+        assert_eq!(
+            SiweMessage::parse(&missing_version)
+                .unwrap_err()
+                .to_string(),
+            "expected Version: at offset 203, found \"C\""
+        );
+        SiweMessage::parse(&format!("{}\nextra", MESSAGE)).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_timestamp("2021-12-07T18:28:18.807Z"),
+            Some(1638901698)
+        );
+        assert_eq!(
+            parse_timestamp("2021-12-07T20:28:18+02:00"),
+            Some(1638901698)
+        );
+        assert_eq!(parse_timestamp("2000-02-29T00:00:00Z"), Some(951782400));
+        assert_eq!(parse_timestamp("2021-12-07 18:28:18Z"), None);
+        assert_eq!(parse_timestamp("2021-13-07T18:28:18Z"), None);
+        assert_eq!(parse_timestamp("2021-12-07T18:28:18"), None);
+    }
+
+    #[test]
+    fn test_verify_siwe() {
+        let signing_key = SigningKey::from_slice(&[0x42; 32]).unwrap();
+        let address = crate::ecrecover::public_key_to_address(
+            &signing_key
+                .verifying_key()
+                .to_encoded_point(false)
+                .as_bytes()[1..],
+        );
+        let message = format!(
+            "gateway wants you to sign in with your Ethereum account:
+{}
+
+
+URI: near:alice
+Version: 1
+Chain ID: 1
+Nonce: abcdefgh
+Issued At: 2021-09-30T16:25:24Z
+Expiration Time: 2021-10-01T16:25:24Z",
+            checksum_address(&address.0)
+        );
+        let hash = super::personal_message_hash(message.as_bytes());
+        let (signature, recovery_id) = signing_key.sign_prehash_recoverable(&hash.0).unwrap();
+        let mut signature = signature.to_bytes().to_vec();
+        signature.push(recovery_id.to_byte() + 27);
+
+        let issued_at = 1633019124;
+        let siwe = verify_siwe(&message, &signature, "gateway", 1, issued_at).unwrap();
+        assert_eq!(siwe.address, address);
+        assert_eq!(siwe.expiration_time, Some(issued_at + 86400));
+        assert_eq!(
+            verify_siwe(&message, &signature, "other", 1, issued_at).unwrap_err(),
+            ParsingError::SiweDomainMismatch
+        );
+        assert_eq!(
+            verify_siwe(&message, &signature, "gateway", 1, issued_at + 86400).unwrap_err(),
+            ParsingError::SiweExpired
+        );
+        let tampered = message.replace("alice", "bob");
+        assert_eq!(
+            verify_siwe(&tampered, &signature, "gateway", 1, issued_at).unwrap_err(),
+            ParsingError::InvalidEcRecoverSignature
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! Deterministic Ethereum wallets for tests of relayers and dApps built on the gateway: the
+//! same seed always gives the same key and address, and the wallet tracks its nonce, so the
+//! messages it signs are valid in sequence and reproducible across runs. Also swaps the
+//! backend recovering secp256k1 signers, for tests of the paths where recovery fails.
+
+use std::cell::RefCell;
+
+use k256::ecdsa::SigningKey;
+use primitive_types::{H256, U256};
+
+use crate::client::{signing_key_to_address, MetaCallBuilder};
+pub use crate::ecrecover::{EcRecover, EcRecoverError, K256};
+use crate::siwe::personal_message_hash;
+use crate::types::{keccak256, Address, ArgsEncoding};
+
+/// Key of the test wallet with the given seed, the keccak256 of the seed.
+pub fn signing_key_from_seed(seed: &str) -> SigningKey {
+    SigningKey::from_slice(&keccak256(seed.as_bytes())).expect("Seed hash is a valid key")
+}
+
+/// Wallet signing messages for the gateway at `gateway_id`, like an Ethereum wallet would, with
+/// the nonce of its next message.
+#[derive(Debug, Clone)]
+pub struct TestWallet {
+    pub signing_key: SigningKey,
+    pub gateway_id: String,
+    pub chain_id: u64,
+    pub nonce: U256,
+}
+
+impl TestWallet {
+    pub fn new(gateway_id: &str) -> Self {
+        Self::from_seed(gateway_id, "test")
+    }
+
+    /// Wallet with the key derived from `seed`, on chain 1 and starting at nonce zero.
+    pub fn from_seed(gateway_id: &str, seed: &str) -> Self {
+        Self {
+            signing_key: signing_key_from_seed(seed),
+            gateway_id: gateway_id.to_string(),
+            chain_id: 1,
+            nonce: U256::zero(),
+        }
+    }
+
+    pub fn chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    pub fn address(&self) -> Address {
+        signing_key_to_address(&self.signing_key)
+    }
+
+    /// Account the gateway creates for the wallet's address.
+    pub fn account_id(&self) -> String {
+        format!("{}.{}", hex::encode(self.address()), self.gateway_id)
+    }
+
+    /// Builder of a message to the wallet's gateway, to pass to `sign`.
+    pub fn builder(&self) -> MetaCallBuilder {
+        MetaCallBuilder::new(&self.gateway_id, self.chain_id)
+    }
+
+    /// Signs the message with the wallet's next nonce, and moves on to the one after it.
+    pub fn sign(&mut self, builder: MetaCallBuilder) -> Vec<u8> {
+        let message = builder
+            .nonce(self.nonce)
+            .sign(&self.signing_key)
+            .expect("Failed to sign");
+        self.nonce += U256::one();
+        message
+    }
+
+    /// Signed message calling `method_def` of `receiver_id` with the RLP encoded `args`.
+    /// Empty `method_def` is a transfer.
+    pub fn message(
+        &mut self,
+        receiver_id: &str,
+        value: u128,
+        method_def: &str,
+        args: Vec<u8>,
+    ) -> Vec<u8> {
+        let builder = self
+            .builder()
+            .receiver(receiver_id)
+            .value(value)
+            .method(method_def)
+            .args(ArgsEncoding::Rlp, args);
+        self.sign(builder)
+    }
+
+    /// Signature of the text, as a wallet makes with `personal_sign`, e.g. for SIWE.
+    pub fn personal_sign(&self, message: &[u8]) -> Vec<u8> {
+        let hash = personal_message_hash(message);
+        let (signature, recovery_id) = self
+            .signing_key
+            .sign_prehash_recoverable(&hash.0)
+            .expect("Hash is 32 bytes");
+        let mut signature = signature.to_bytes().to_vec();
+        signature.push(recovery_id.to_byte() + 27);
+        signature
+    }
+}
+
+/// `EcRecover` backend with the same result for every signature, e.g. an error to fail the
+/// recovery of messages that are otherwise valid, or the address of a signer to stand in for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MockEcRecover(pub Result<Address, EcRecoverError>);
+
+impl EcRecover for MockEcRecover {
+    fn ecrecover(
+        &self,
+        _hash: H256,
+        _signature: &[u8],
+        _chain_id: u64,
+    ) -> Result<Address, EcRecoverError> {
+        self.0
+    }
+}
+
+thread_local! {
+    static ECRECOVER: RefCell<Option<Box<dyn EcRecover>>> = RefCell::new(None);
+}
+
+/// Restores the backend `with_ecrecover` replaced, also when the test panics.
+struct RestoreEcRecover(Option<Box<dyn EcRecover>>);
+
+impl Drop for RestoreEcRecover {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        ECRECOVER.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Runs `f` with `backend` recovering the secp256k1 signers of messages, SIWE texts and
+/// Ethereum transactions parsed on the current thread, instead of `K256`.
+pub fn with_ecrecover<T>(backend: impl EcRecover + 'static, f: impl FnOnce() -> T) -> T {
+    let previous = ECRECOVER.with(|current| current.replace(Some(Box::new(backend))));
+    let _restore = RestoreEcRecover(previous);
+    f()
+}
+
+/// Result of the backend `with_ecrecover` set, if any.
+pub(crate) fn mocked_ecrecover(
+    hash: H256,
+    signature: &[u8],
+    chain_id: u64,
+) -> Option<Result<Address, EcRecoverError>> {
+    ECRECOVER.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map(|backend| backend.ecrecover(hash, signature, chain_id))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+
+    use super::{with_ecrecover, EcRecoverError, MockEcRecover, TestWallet};
+    use crate::meta_parsing::{near_erc712_domain, parse_meta_call, recover_signers, ParsingError};
+    use crate::siwe::verify_siwe;
+    use crate::types::checksum_address;
+    use crate::types::Address;
+
+    #[test]
+    fn test_wallet_messages() {
+        let mut wallet = TestWallet::from_seed("gateway", "alice");
+        assert_eq!(
+            wallet.address(),
+            TestWallet::from_seed("x", "alice").address()
+        );
+        assert_ne!(
+            wallet.address(),
+            TestWallet::from_seed("gateway", "bob").address()
+        );
+        assert_eq!(
+            wallet.account_id(),
+            format!("{}.gateway", hex::encode(wallet.address()))
+        );
+
+        for nonce in 0..2 {
+            let message = wallet.message("bob", 10, "", vec![]);
+            let result = parse_meta_call(
+                &near_erc712_domain(U256::from(1)),
+                1,
+                b"gateway",
+                message,
+                |_| None,
+            )
+            .unwrap();
+            assert_eq!(result.sender, wallet.address());
+            assert_eq!(result.nonce, U256::from(nonce));
+            assert_eq!(result.contract_address, "bob");
+            assert_eq!(result.value, 10);
+        }
+        assert_eq!(wallet.nonce, U256::from(2));
+    }
+
+    #[test]
+    fn test_wallet_personal_sign() {
+        let wallet = TestWallet::new("gateway");
+        let message = format!(
+            "gateway wants you to sign in with your Ethereum account:
+{}
+
+
+URI: near:alice
+Version: 1
+Chain ID: 1
+Nonce: abcdefgh
+Issued At: 2021-09-30T16:25:24Z",
+            checksum_address(&wallet.address().0)
+        );
+        let signature = wallet.personal_sign(message.as_bytes());
+        let siwe = verify_siwe(&message, &signature, "gateway", 1, 1633019124).unwrap();
+        assert_eq!(siwe.address, wallet.address());
+    }
+
+    #[test]
+    fn test_mock_ecrecover() {
+        let mut wallet = TestWallet::new("gateway");
+        let messages = vec![
+            wallet.message("bob", 10, "", vec![]),
+            wallet.message("bob", 10, "", vec![]),
+        ];
+        let recover = |messages: Vec<Vec<u8>>| {
+            recover_signers(
+                &near_erc712_domain(U256::from(1)),
+                1,
+                b"gateway",
+                messages,
+                |_| None,
+                |_, _| U256::zero(),
+            )
+        };
+
+        let failed = with_ecrecover(MockEcRecover(Err(EcRecoverError::RecoveryFailed)), || {
+            recover(messages.clone())
+        });
+        for result in failed {
+            assert_eq!(result.unwrap_err(), ParsingError::InvalidEcRecoverSignature);
+        }
+        let mismatch = with_ecrecover(MockEcRecover(Err(EcRecoverError::ChainIdMismatch)), || {
+            recover(messages.clone())
+        });
+        assert_eq!(
+            mismatch[0].as_ref().unwrap_err(),
+            &ParsingError::ChainIdMismatch
+        );
+
+        // Stands in for the signer, the nonce check goes on with its address.
+        let other = Address::repeat_byte(7);
+        let recovered = with_ecrecover(MockEcRecover(Ok(other)), || recover(messages.clone()));
+        let recovered = recovered[1].as_ref().unwrap();
+        assert_eq!(recovered.address, format!("0x{}", hex::encode(other)));
+        assert!(!recovered.nonce_matches);
+
+        // The real backend is back once `with_ecrecover` returns, even if it panicked.
+        let _ = std::panic::catch_unwind(|| {
+            with_ecrecover(MockEcRecover(Ok(other)), || panic!("Test failed"))
+        });
+        let recovered = recover(messages);
+        assert_eq!(
+            recovered[0].as_ref().unwrap().address,
+            format!("0x{}", hex::encode(wallet.address()))
+        );
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+
+use primitive_types::U256;
+use serde_json::{Map, Value};
+
+use crate::meta_parsing::{
+    sign_extend_int, zero_extend_uint, Arg, ArgType, Method, ParsingError, ParsingResult, RlpValue,
+};
+use crate::types::Address;
+
+/// Transcode decoded method arguments into JSON object for the NEAR method, keyed by argument names.
+/// E.g. "adopt(uint256 petId,PetObj petObj)PetObj(string name,address owner)" ->
+/// `{"petId": "1", "petObj": {"name": "Rex", "owner": "0x..."}}`
+pub(crate) fn args_to_json(
+    args: &[Arg],
+    values: &[RlpValue],
+    types: &HashMap<String, Method>,
+) -> ParsingResult<Vec<u8>> {
+    let object = fields_to_json(args, values, types)?;
+    serde_json::to_vec(&object).map_err(|_| ParsingError::InvalidMetaTransactionFunctionArg)
+}
+
+fn fields_to_json(
+    args: &[Arg],
+    values: &[RlpValue],
+    types: &HashMap<String, Method>,
+) -> ParsingResult<Value> {
+    if args.len() != values.len() {
+        return Err(ParsingError::ArgSizeMismatch);
+    }
+    let mut object = Map::new();
+    for (arg, value) in args.iter().zip(values) {
+        object.insert(arg.name.clone(), arg_to_json(&arg.t, value, types)?);
+    }
+    Ok(Value::Object(object))
+}
+
+/// JSON value of a single argument, following NEAR conventions:
+/// integers are decimal strings (like `U128`), bytes are base64 (like `Base64VecU8`)
+/// and addresses are 0x-prefixed hex.
+fn arg_to_json(
+    ty: &ArgType,
+    value: &RlpValue,
+    types: &HashMap<String, Method>,
+) -> ParsingResult<Value> {
+    match (ty, value) {
+        (ArgType::String, RlpValue::Bytes(b)) => String::from_utf8(b.clone())
+            .map(Value::String)
+            .map_err(|_| ParsingError::InvalidMetaTransactionFunctionArg),
+        (ArgType::Bytes, RlpValue::Bytes(b)) | (ArgType::Byte(_), RlpValue::Bytes(b)) => {
+            Ok(Value::String(base64::encode(b)))
+        }
+        (ArgType::Bool, RlpValue::Bytes(b)) => Ok(Value::Bool(b.iter().any(|x| *x != 0))),
+        (ArgType::Uint(bits), RlpValue::Bytes(b)) => Ok(Value::String(
+            U256::from_big_endian(&zero_extend_uint(b, *bits)?).to_string(),
+        )),
+        (ArgType::Int(bits), RlpValue::Bytes(b)) => {
+            let value = U256::from_big_endian(&sign_extend_int(b, *bits)?);
+            if value.bit(255) {
+                // Two's complement negation gives the absolute value.
+                let abs = (!value).overflowing_add(U256::one()).0;
+                Ok(Value::String(format!("-{}", abs)))
+            } else {
+                Ok(Value::String(value.to_string()))
+            }
+        }
+        (ArgType::Address, RlpValue::Bytes(b)) => {
+            if b.len() != Address::len_bytes() {
+                return Err(ParsingError::ArgSizeMismatch);
+            }
+            Ok(Value::String(format!("0x{}", hex::encode(b))))
+        }
+        (ArgType::Array { inner, .. }, RlpValue::List(l)) => l
+            .iter()
+            .map(|element| arg_to_json(inner, element, types))
+            .collect::<ParsingResult<Vec<_>>>()
+            .map(Value::Array),
+        (ArgType::Custom(type_name), RlpValue::List(l)) => {
+            let struct_type = types
+                .get(type_name)
+                .ok_or(ParsingError::InvalidMetaTransactionFunctionArg)?;
+            fields_to_json(&struct_type.args, l, types)
+        }
+        _ => Err(ParsingError::InvalidMetaTransactionFunctionArg),
+    }
+}
+
+/// Borsh serialization of decoded method arguments, in the order they are defined in the method.
+/// Integers must be 8, 16, 32, 64 or 128 bit wide, dynamic arrays are `Vec`s and fixed-size arrays,
+/// `bytesN` and `address` are fixed-size byte arrays.
+pub(crate) fn args_to_borsh(
+    args: &[Arg],
+    values: &[RlpValue],
+    types: &HashMap<String, Method>,
+) -> ParsingResult<Vec<u8>> {
+    let mut result = vec![];
+    fields_to_borsh(args, values, types, &mut result)?;
+    Ok(result)
+}
+
+fn fields_to_borsh(
+    args: &[Arg],
+    values: &[RlpValue],
+    types: &HashMap<String, Method>,
+    result: &mut Vec<u8>,
+) -> ParsingResult<()> {
+    if args.len() != values.len() {
+        return Err(ParsingError::ArgSizeMismatch);
+    }
+    for (arg, value) in args.iter().zip(values) {
+        arg_to_borsh(&arg.t, value, types, result)?;
+    }
+    Ok(())
+}
+
+fn arg_to_borsh(
+    ty: &ArgType,
+    value: &RlpValue,
+    types: &HashMap<String, Method>,
+    result: &mut Vec<u8>,
+) -> ParsingResult<()> {
+    match (ty, value) {
+        (ArgType::String, RlpValue::Bytes(b)) => {
+            std::str::from_utf8(b).map_err(|_| ParsingError::InvalidMetaTransactionFunctionArg)?;
+            borsh_length(b.len(), result);
+            result.extend_from_slice(b);
+        }
+        (ArgType::Bytes, RlpValue::Bytes(b)) => {
+            borsh_length(b.len(), result);
+            result.extend_from_slice(b);
+        }
+        (ArgType::Byte(size), RlpValue::Bytes(b)) => {
+            if b.len() != *size as usize {
+                return Err(ParsingError::ArgSizeMismatch);
+            }
+            result.extend_from_slice(b);
+        }
+        (ArgType::Address, RlpValue::Bytes(b)) => {
+            if b.len() != Address::len_bytes() {
+                return Err(ParsingError::ArgSizeMismatch);
+            }
+            result.extend_from_slice(b);
+        }
+        (ArgType::Bool, RlpValue::Bytes(b)) => result.push(b.iter().any(|x| *x != 0) as u8),
+        (ArgType::Uint(bits), RlpValue::Bytes(b)) => {
+            let size = borsh_int_size(*bits)?;
+            let word = zero_extend_uint(b, *bits)?;
+            result.extend(word[32 - size..].iter().rev());
+        }
+        (ArgType::Int(bits), RlpValue::Bytes(b)) => {
+            let size = borsh_int_size(*bits)?;
+            let word = sign_extend_int(b, *bits)?;
+            result.extend(word[32 - size..].iter().rev());
+        }
+        (ArgType::Array { length, inner }, RlpValue::List(l)) => {
+            match length {
+                Some(length) if *length != l.len() as u64 => {
+                    return Err(ParsingError::ArgSizeMismatch)
+                }
+                Some(_) => {}
+                None => borsh_length(l.len(), result),
+            }
+            for element in l {
+                arg_to_borsh(inner, element, types, result)?;
+            }
+        }
+        (ArgType::Custom(type_name), RlpValue::List(l)) => {
+            let struct_type = types
+                .get(type_name)
+                .ok_or(ParsingError::InvalidMetaTransactionFunctionArg)?;
+            fields_to_borsh(&struct_type.args, l, types, result)?;
+        }
+        _ => return Err(ParsingError::InvalidMetaTransactionFunctionArg),
+    }
+    Ok(())
+}
+
+/// Borsh prefixes strings and `Vec`s with u32 length.
+fn borsh_length(length: usize, result: &mut Vec<u8>) {
+    result.extend_from_slice(&(length as u32).to_le_bytes());
+}
+
+/// Size in bytes of the Rust integer type for the given number of bits.
+fn borsh_int_size(bits: usize) -> ParsingResult<usize> {
+    match bits {
+        8 | 16 | 32 | 64 | 128 => Ok(bits / 8),
+        _ => Err(ParsingError::InvalidMetaTransactionFunctionArg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::meta_parsing::{MethodAndTypes, RlpValue};
+
+    fn bytes(b: &[u8]) -> RlpValue {
+        RlpValue::Bytes(b.to_vec())
+    }
+
+    #[test]
+    fn test_args_to_json() {
+        let methods = MethodAndTypes::parse(
+            "adopt(uint256 petId,int64 delta,bool ok,bytes data,PetObj[] pets)PetObj(string name,address owner)",
+        )
+        .unwrap();
+        let values = vec![
+            bytes(&[1]),
+            bytes(&[0xff, 0x00]),
+            bytes(&[1]),
+            bytes(b"hi"),
+            RlpValue::List(vec![RlpValue::List(vec![
+                bytes(b"Rex"),
+                bytes(&[0x11; 20]),
+            ])]),
+        ];
+        let result = super::args_to_json(&methods.method.args, &values, &methods.types).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&result).unwrap(),
+            json!({
+                "petId": "1",
+                "delta": "-256",
+                "ok": true,
+                "data": "aGk=",
+                "pets": [{"name": "Rex", "owner": "0x1111111111111111111111111111111111111111"}],
+            })
+        );
+    }
+
+    #[test]
+    fn test_args_to_borsh() {
+        use borsh::BorshSerialize;
+
+        #[derive(BorshSerialize)]
+        struct Pet {
+            name: String,
+            age: u128,
+        }
+
+        #[derive(BorshSerialize)]
+        struct Args {
+            a: u8,
+            b: u64,
+            c: i16,
+            s: String,
+            t: [u8; 2],
+            ok: bool,
+            xs: Vec<u32>,
+            ys: [i8; 2],
+            pet: Pet,
+        }
+
+        let methods = MethodAndTypes::parse(
+            "f(uint8 a,uint64 b,int16 c,string s,bytes2 t,bool ok,uint32[] xs,int8[2] ys,Pet pet)Pet(string name,uint128 age)",
+        )
+        .unwrap();
+        let values = vec![
+            bytes(&[0xfe]),
+            bytes(&[0x01, 0x02, 0x03]),
+            bytes(&[0xfe, 0x0c]),
+            bytes(b"hi"),
+            bytes(&[0xab, 0xcd]),
+            bytes(&[1]),
+            RlpValue::List(vec![bytes(&[]), bytes(&[0xff, 0xff, 0xff, 0xff])]),
+            RlpValue::List(vec![bytes(&[0x80]), bytes(&[0x7f])]),
+            RlpValue::List(vec![bytes(b"Rex"), bytes(&[0x01; 16])]),
+        ];
+        let expected = Args {
+            a: 0xfe,
+            b: 0x010203,
+            c: -500,
+            s: "hi".to_string(),
+            t: [0xab, 0xcd],
+            ok: true,
+            xs: vec![0, u32::MAX],
+            ys: [-128, 127],
+            pet: Pet {
+                name: "Rex".to_string(),
+                age: u128::from_be_bytes([0x01; 16]),
+            },
+        };
+        assert_eq!(
+            super::args_to_borsh(&methods.method.args, &values, &methods.types).unwrap(),
+            expected.try_to_vec().unwrap()
+        );
+
+        // Values out of range of the integer type.
+        let to_borsh = |method_def: &str, value: &[u8]| {
+            let methods = MethodAndTypes::parse(method_def).unwrap();
+            super::args_to_borsh(&methods.method.args, &[bytes(value)], &methods.types)
+        };
+        to_borsh("f(uint8 a)", &[0x01, 0x00]).unwrap_err();
+        to_borsh("f(int8 a)", &[0x00, 0x80]).unwrap_err();
+        to_borsh("f(int8 a)", &[0xff, 0x7f]).unwrap_err();
+        assert_eq!(to_borsh("f(int8 a)", &[0xff, 0x80]).unwrap(), vec![0x80]);
+        // Integer types without Rust counterpart.
+        to_borsh("f(uint256 a)", &[0x01]).unwrap_err();
+        to_borsh("f(int24 a)", &[0x01]).unwrap_err();
+    }
+
+    #[test]
+    fn test_args_to_json_errors() {
+        let methods = MethodAndTypes::parse("f(string name,Pet pet)Pet(string name)").unwrap();
+        let args = &methods.method.args;
+        // Invalid UTF-8.
+        super::args_to_json(
+            args,
+            &[bytes(&[0xff]), RlpValue::List(vec![bytes(b"a")])],
+            &methods.types,
+        )
+        .unwrap_err();
+        // List where value is expected and the other way around.
+        super::args_to_json(
+            args,
+            &[RlpValue::List(vec![]), RlpValue::List(vec![bytes(b"a")])],
+            &methods.types,
+        )
+        .unwrap_err();
+        super::args_to_json(args, &[bytes(b"a"), bytes(b"a")], &methods.types).unwrap_err();
+        // Missing struct field.
+        super::args_to_json(args, &[bytes(b"a"), RlpValue::List(vec![])], &methods.types)
+            .unwrap_err();
+    }
+}
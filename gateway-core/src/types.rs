@@ -0,0 +1,531 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use primitive_types::{H160, U256};
+use serde::{Deserialize, Serialize};
+
+#[cfg(all(feature = "near-sdk", target_arch = "wasm32"))]
+use near_sdk::env;
+
+#[cfg(not(all(feature = "near-sdk", target_arch = "wasm32")))]
+use sha3::Digest;
+
+pub type RawAddress = [u8; 20];
+pub type RawU256 = [u8; 32];
+
+/// See: https://ethereum-magicians.org/t/increasing-address-size-from-20-to-32-bytes/5485
+pub type Address = H160;
+
+/// Encoding of the method arguments in the meta call.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, BorshSerialize, BorshDeserialize, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ArgsEncoding {
+    /// RLP list of values, structs and arrays are nested lists.
+    Rlp,
+    /// Standard Solidity ABI encoding of the method arguments, without selector.
+    Abi,
+}
+
+/// Encoding of the arguments passed to the NEAR method.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, BorshSerialize, BorshDeserialize, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum CallArgsFormat {
+    /// JSON object keyed by argument names.
+    Json,
+    /// Borsh serialization of the arguments in order, using the types from the method definition.
+    Borsh,
+    /// Pass `args` as is.
+    Raw,
+}
+
+/// Where the `value` attached to the called method comes from.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, BorshSerialize, BorshDeserialize, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum DepositSource {
+    /// Balance of the proxy account.
+    Account,
+    /// Deposit the relayer attaches to the gateway call, forwarded through the proxy account.
+    /// Signed as `uint8 depositSource` of 1 after `gas`.
+    Relayer,
+}
+
+/// Unit of the signed `value`, converted to yoctoNEAR once the message is verified.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, BorshSerialize, BorshDeserialize, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ValueUnit {
+    /// yoctoNEAR, NEAR with 24 decimals.
+    Yocto,
+    /// NEAR with 18 decimals like wei, so Ethereum wallets show the value in NEAR. Signed as
+    /// `uint8 valueUnit` of 1 after `depositSource`.
+    Wei,
+}
+
+/// EIP-712 schema of the signed `NearTx` struct. Serialized as the schema version byte, new
+/// schemas must be added at the end.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, BorshSerialize, BorshDeserialize, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum TypedDataSchema {
+    /// `NearTx` with the method arguments as nested `Arguments` struct of the method's types.
+    Nested,
+    /// `NearTxFlat` of plain values, with the hashStruct of the `Arguments` computed by the
+    /// client as `argumentsHash`. For hardware wallets, like Ledger, that can't handle deeply
+    /// nested dynamic types.
+    Flat,
+}
+
+/// Method definition of NEP-141 `ft_transfer_call`, without the optional memo. The call must
+/// attach exactly 1 yoctoNEAR.
+pub const FT_TRANSFER_CALL_DEF: &str =
+    "ft_transfer_call(string receiver_id,uint128 amount,string msg)";
+
+/// Method definition of NEP-171 `nft_transfer` by the owner, without approval id and memo. The
+/// call must attach exactly 1 yoctoNEAR, as must `nft_transfer_call`.
+pub const NFT_TRANSFER_DEF: &str = "nft_transfer(string receiver_id,string token_id)";
+
+/// Method definition of NEP-171 `nft_transfer_call` by the owner, without approval id and memo.
+pub const NFT_TRANSFER_CALL_DEF: &str =
+    "nft_transfer_call(string receiver_id,string token_id,string msg)";
+
+/// Method definition of NEP-178 `nft_approve` without `msg`, so the approved account isn't
+/// called. The call must attach a deposit for the approval's storage.
+pub const NFT_APPROVE_DEF: &str = "nft_approve(string token_id,string account_id)";
+
+/// Method definition of NEP-178 `nft_approve` calling the approved account's `nft_on_approve`
+/// with `msg`.
+pub const NFT_APPROVE_CALL_DEF: &str = "nft_approve(string token_id,string account_id,string msg)";
+
+/// Method definition of the gateway's `create` selecting the named proxy code template.
+pub const CREATE_DEF: &str = "create(string template)";
+
+/// Method definition of the gateway's `create_implicit`, creating the implicit account of the
+/// ed25519 public key for the signer.
+pub const CREATE_IMPLICIT_DEF: &str = "create_implicit(bytes32 public_key)";
+
+/// Receiver of messages managing the signer's own account instead of calling a contract:
+/// `update(bytes32 code_hash)`, `add_key(bytes32 public_key)`, `lock()`, `unlock()` and
+/// `set_controller(address new_controller)`.
+pub const SELF_RECEIVER: &str = "self";
+
+/// Signature of the meta call, the sender's address is derived from the signing key.
+/// Serialized first in the message, so the variant index is the version byte selecting the
+/// signature scheme. New schemes must be added at the end.
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub enum MetaCallSignature {
+    /// Ethereum wallet signature `r ‖ s` with `v` to recover the secp256k1 key.
+    Secp256k1 { signature: [u8; 64], v: u8 },
+    /// Passkey signature `r ‖ s` with low `s`, along with the P-256 public key `x ‖ y`.
+    P256 {
+        public_key: [u8; 64],
+        signature: [u8; 64],
+    },
+    /// Solana or NEAR wallet ed25519 signature of the message hash, along with the public key.
+    Ed25519 {
+        public_key: [u8; 32],
+        signature: [u8; 64],
+    },
+    /// Bitcoin wallet BIP-137 signed message `header ‖ r ‖ s`, the sender is the hash in the
+    /// signer's Bitcoin address.
+    Bitcoin { signature: [u8; 65] },
+    /// Signatures of several addresses governing the account of `account` together. Each one is
+    /// a single signature of the same digest, which signs `account` too, the gateway checks the
+    /// recovered addresses against the account's signer set.
+    Multisig {
+        account: [u8; 20],
+        signatures: MultisigSignatures,
+    },
+}
+
+/// Signatures of a multi-signature message, borsh encoded as a vector. Implemented by hand as
+/// the derive can't bound the recursive type. Each is a single signature, a nested
+/// `Multisig` fails to decode.
+#[derive(Debug, Clone)]
+pub struct MultisigSignatures(pub Vec<MetaCallSignature>);
+
+/// Borsh variant index of `MetaCallSignature::Multisig`.
+const MULTISIG_VARIANT: u8 = 4;
+
+impl BorshSerialize for MultisigSignatures {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        BorshSerialize::serialize(&(self.0.len() as u32), writer)?;
+        for signature in &self.0 {
+            BorshSerialize::serialize(signature, writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl BorshDeserialize for MultisigSignatures {
+    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
+        let len = <u32 as BorshDeserialize>::deserialize(buf)?;
+        let mut signatures = vec![];
+        for _ in 0..len {
+            // Checked before decoding, so nested signatures can't make the decoder recurse.
+            if buf.first() == Some(&MULTISIG_VARIANT) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Nested multisig signature",
+                ));
+            }
+            signatures.push(<MetaCallSignature as BorshDeserialize>::deserialize(buf)?);
+        }
+        Ok(Self(signatures))
+    }
+}
+
+/// Incoming argument encoding. Fields added since `schema` are appended at the end, and decode
+/// to their defaults when the message ends before them, so encoders that don't know them keep
+/// working. New fields must be added the same way, see `CHANGELOG.md`.
+#[derive(Debug, BorshSerialize)]
+pub struct MetaCallArgs {
+    pub signature: MetaCallSignature,
+    pub nonce: RawU256,
+    pub fee_amount: RawU256,
+    pub fee_address: String,
+    pub contract_address: String,
+    pub value: RawU256,
+    pub method: String,
+    /// Id of a registered method definition to call, `method` must be empty then.
+    pub method_id: Option<u32>,
+    pub args_encoding: ArgsEncoding,
+    pub call_args_format: CallArgsFormat,
+    pub args: Vec<u8>,
+    pub schema: TypedDataSchema,
+    /// yoctoNEAR the account pays whoever submits the message once it executes. Signed as the
+    /// `tip` field at the end of the struct when not zero.
+    pub tip: RawU256,
+    /// Gas the called method gets, signed as the `gas` field after `tip` when not zero.
+    /// Zero leaves it to the gateway.
+    pub gas: u64,
+    pub deposit_source: DepositSource,
+    pub value_unit: ValueUnit,
+}
+
+/// Decodes the trailing field, or returns the default if the message ends before it.
+fn deserialize_or<T: BorshDeserialize>(buf: &mut &[u8], default: T) -> std::io::Result<T> {
+    if buf.is_empty() {
+        return Ok(default);
+    }
+    T::deserialize(buf)
+}
+
+impl BorshDeserialize for MetaCallArgs {
+    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
+        Ok(Self {
+            signature: BorshDeserialize::deserialize(buf)?,
+            nonce: BorshDeserialize::deserialize(buf)?,
+            fee_amount: BorshDeserialize::deserialize(buf)?,
+            fee_address: BorshDeserialize::deserialize(buf)?,
+            contract_address: BorshDeserialize::deserialize(buf)?,
+            value: BorshDeserialize::deserialize(buf)?,
+            method: BorshDeserialize::deserialize(buf)?,
+            method_id: BorshDeserialize::deserialize(buf)?,
+            args_encoding: BorshDeserialize::deserialize(buf)?,
+            call_args_format: BorshDeserialize::deserialize(buf)?,
+            args: BorshDeserialize::deserialize(buf)?,
+            schema: BorshDeserialize::deserialize(buf)?,
+            tip: deserialize_or(buf, [0; 32])?,
+            gas: deserialize_or(buf, 0)?,
+            deposit_source: deserialize_or(buf, DepositSource::Account)?,
+            value_unit: deserialize_or(buf, ValueUnit::Yocto)?,
+        })
+    }
+}
+
+/// `MetaCallArgs` as human-readable JSON, for near-cli and scripts without a borsh encoder.
+/// Amounts and the nonce are decimal strings, `value` and `tip` may also be given in NEAR like
+/// `1.5 NEAR`. Bytes are `0x` prefixed hex. Omitted optional fields take the defaults the
+/// builders use.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+pub struct MetaCallArgsJson {
+    /// Ethereum wallet signature `r ‖ s ‖ v`, as `eth_signTypedData` returns it.
+    pub signature: String,
+    pub nonce: String,
+    pub fee_amount: String,
+    pub fee_address: String,
+    pub contract_address: String,
+    pub value: String,
+    /// Method definition, e.g. `transfer(string receiver_id,uint128 amount)`, empty for
+    /// transfers or with `method_id`.
+    #[serde(default)]
+    pub method: String,
+    pub method_id: Option<u32>,
+    /// `rlp` if omitted.
+    pub args_encoding: Option<ArgsEncoding>,
+    /// `json` if omitted.
+    pub call_args_format: Option<CallArgsFormat>,
+    /// Encoded method arguments, none if omitted.
+    pub args: Option<String>,
+    /// `nested` if omitted.
+    pub schema: Option<TypedDataSchema>,
+    /// Zero if omitted.
+    pub tip: Option<String>,
+    /// Zero if omitted.
+    pub gas: Option<u64>,
+    /// `account` if omitted.
+    pub deposit_source: Option<DepositSource>,
+    /// `yocto` if omitted.
+    pub value_unit: Option<ValueUnit>,
+}
+
+/// Internal args format for meta call.
+#[derive(Debug)]
+pub struct InternalMetaCallArgs {
+    pub sender: Address,
+    pub nonce: U256,
+    pub fee_amount: u128,
+    pub fee_address: String,
+    pub contract_address: String,
+    pub method_name: String,
+    pub value: u128,
+    pub args_encoding: ArgsEncoding,
+    pub call_args_format: CallArgsFormat,
+    pub args: Vec<u8>,
+    /// Addresses recovered from the signatures of a multi-signature message, whose sender is
+    /// the account they govern. Empty for single signature.
+    pub signers: Vec<Address>,
+    pub schema: TypedDataSchema,
+    pub tip: u128,
+    pub gas: u64,
+    pub deposit_source: DepositSource,
+    /// Unit of `value` as signed, yoctoNEAR once the message is verified.
+    pub value_unit: ValueUnit,
+    /// Account of a multi-signature message, signed along with it so the signatures can't be
+    /// used for another account. None for single signature.
+    pub multisig_account: Option<Address>,
+}
+
+/// EIP-712 encoding of a meta call, hex encoded to compare against the wallet's hashing.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+pub struct MetaCallHash {
+    /// Message to sign.
+    pub digest: String,
+    /// `encodeType` of the `NearTx` struct.
+    pub type_string: String,
+    pub type_hash: String,
+    /// `encodeData` of the `NearTx` struct.
+    pub encoded_struct: String,
+    /// hashStruct of the `Arguments`, the `argumentsHash` of the flat schema.
+    pub arguments_hash: String,
+}
+
+/// Signer of a meta call, as seen by the gateway.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+pub struct RecoveredSigner {
+    /// Hex encoded address of the signing key.
+    pub address: String,
+    /// Hex encoded EIP-712 digest the signature is for.
+    pub digest: String,
+    /// Nonce the gateway expects from this address next, for the key of the message's nonce.
+    pub expected_nonce: String,
+    /// Whether the nonce of the message matches the expected one.
+    pub nonce_matches: bool,
+}
+
+/// Nonces are 2D as in EIP-4337: the upper 192 bits are a key, the lower 64 bits the sequence
+/// within it. Each key of the sender has its own sequence, so messages with different keys
+/// don't wait for each other. Key 0 is the plain sequential nonce.
+pub const NONCE_SEQUENCE_BITS: usize = 64;
+
+/// Key of the 2D nonce.
+pub fn nonce_key(nonce: U256) -> U256 {
+    nonce >> NONCE_SEQUENCE_BITS
+}
+
+/// Sequence of the 2D nonce within its key.
+pub fn nonce_sequence(nonce: U256) -> u64 {
+    nonce.low_u64()
+}
+
+/// 2D nonce of the sequence within the key, which must be below 2^192.
+pub fn make_nonce(key: U256, sequence: u64) -> U256 {
+    (key << NONCE_SEQUENCE_BITS) | U256::from(sequence)
+}
+
+/// Whether the nonce is salted rather than sequential: its top bit is set, the rest of the key
+/// is a random salt and the sequence is the expiry timestamp in seconds. The gateway remembers
+/// the salted nonces it has seen until they expire instead of counting, so such messages
+/// execute in any order.
+pub fn is_salted_nonce(nonce: U256) -> bool {
+    nonce.bit(255)
+}
+
+/// Salted nonce with the lower 191 bits of the salt, valid until the expiry timestamp in seconds.
+pub fn salted_nonce(salt: U256, expires_at: u64) -> U256 {
+    let key = (salt & ((U256::one() << 191) - 1)) | (U256::one() << 191);
+    make_nonce(key, expires_at)
+}
+
+pub fn u256_to_arr(value: &U256) -> [u8; 32] {
+    let mut result = [0u8; 32];
+    value.to_big_endian(&mut result);
+    result
+}
+
+pub fn arr_to_u256(value: &[u8]) -> RawU256 {
+    let mut result = RawU256::default();
+    result.copy_from_slice(value);
+    result
+}
+
+/// Uses the host function inside the contract, as it costs less gas than hashing in wasm.
+#[cfg(all(feature = "near-sdk", target_arch = "wasm32"))]
+pub fn keccak256(data: &[u8]) -> Vec<u8> {
+    env::keccak256(data)
+}
+
+#[cfg(not(all(feature = "near-sdk", target_arch = "wasm32")))]
+pub fn keccak256(data: &[u8]) -> Vec<u8> {
+    sha3::Keccak256::digest(data).to_vec()
+}
+
+/// Error of parsing a hex encoded address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressError {
+    /// Not 20 bytes of hex.
+    Invalid,
+    /// Mixed case that doesn't match the EIP-55 checksum, likely a mistyped or miscopied address.
+    Checksum,
+}
+
+/// EIP-55 mixed-case hex of the address, `0x` prefixed.
+/// See: https://eips.ethereum.org/EIPS/eip-55
+pub fn checksum_address(address: &RawAddress) -> String {
+    let hex_digits = hex::encode(address);
+    let hash = keccak256(hex_digits.as_bytes());
+    let checksummed: String = hex_digits
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0xf;
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
+
+/// Parses hex encoded address, with or without `0x`. All lowercase or all uppercase hex
+/// carries no checksum, mixed case must match EIP-55.
+pub fn parse_checksum_address(address: &str) -> Result<RawAddress, AddressError> {
+    let hex_address = address.strip_prefix("0x").unwrap_or(address);
+    let bytes = hex::decode(hex_address).map_err(|_| AddressError::Invalid)?;
+    if bytes.len() != 20 {
+        return Err(AddressError::Invalid);
+    }
+    let mut result = RawAddress::default();
+    result.copy_from_slice(&bytes);
+    let has_lower = hex_address.chars().any(|c| c.is_ascii_lowercase());
+    let has_upper = hex_address.chars().any(|c| c.is_ascii_uppercase());
+    if has_lower && has_upper && checksum_address(&result)[2..] != *hex_address {
+        return Err(AddressError::Checksum);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Vectors of EIP-55.
+    const CHECKSUMMED: [&str; 4] = [
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+        "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+    ];
+
+    #[test]
+    fn test_checksum_address() {
+        for address in CHECKSUMMED.iter() {
+            let raw = parse_checksum_address(&address.to_lowercase()).unwrap();
+            assert_eq!(checksum_address(&raw), *address);
+            assert_eq!(parse_checksum_address(address), Ok(raw));
+            assert_eq!(parse_checksum_address(&address[2..]), Ok(raw));
+        }
+    }
+
+    #[test]
+    fn test_parse_checksum_address_errors() {
+        assert_eq!(
+            parse_checksum_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD"),
+            Err(AddressError::Checksum)
+        );
+        assert_eq!(
+            parse_checksum_address("0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED").map(|_| ()),
+            Ok(())
+        );
+        assert_eq!(
+            parse_checksum_address("0x5aaeb6053f3e94c9b9a09f33669435e7ef1bea"),
+            Err(AddressError::Invalid)
+        );
+        assert_eq!(
+            parse_checksum_address("0xzaaeb6053f3e94c9b9a09f33669435e7ef1beaed"),
+            Err(AddressError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_meta_call_args_trailing_defaults() {
+        let args = MetaCallArgs {
+            signature: MetaCallSignature::Secp256k1 {
+                signature: [1; 64],
+                v: 27,
+            },
+            nonce: [0; 32],
+            fee_amount: [0; 32],
+            fee_address: String::new(),
+            contract_address: "test".to_string(),
+            value: [0; 32],
+            method: String::new(),
+            method_id: None,
+            args_encoding: ArgsEncoding::Rlp,
+            call_args_format: CallArgsFormat::Json,
+            args: vec![],
+            schema: TypedDataSchema::Nested,
+            tip: [2; 32],
+            gas: 3,
+            deposit_source: DepositSource::Relayer,
+            value_unit: ValueUnit::Wei,
+        };
+        let bytes = args.try_to_vec().unwrap();
+        let decoded = MetaCallArgs::try_from_slice(&bytes).unwrap();
+        assert_eq!(decoded.tip, [2; 32]);
+        assert_eq!(decoded.gas, 3);
+        assert_eq!(decoded.deposit_source, DepositSource::Relayer);
+        assert_eq!(decoded.value_unit, ValueUnit::Wei);
+
+        // Messages of encoders predating the tip.
+        let old = &bytes[..bytes.len() - 32 - 8 - 1 - 1];
+        let decoded = MetaCallArgs::try_from_slice(old).unwrap();
+        assert_eq!(decoded.contract_address, "test");
+        assert_eq!(decoded.tip, [0; 32]);
+        assert_eq!(decoded.gas, 0);
+        assert_eq!(decoded.deposit_source, DepositSource::Account);
+        assert_eq!(decoded.value_unit, ValueUnit::Yocto);
+        // With the tip but predating the gas.
+        let decoded = MetaCallArgs::try_from_slice(&bytes[..bytes.len() - 8 - 1 - 1]).unwrap();
+        assert_eq!(decoded.tip, [2; 32]);
+        assert_eq!(decoded.gas, 0);
+        // A field cut short still fails.
+        assert!(MetaCallArgs::try_from_slice(&bytes[..bytes.len() - 8 - 1 - 1 - 4]).is_err());
+    }
+}
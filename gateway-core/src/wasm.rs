@@ -0,0 +1,165 @@
+//! Browser bindings for building messages, so dApps hash and encode them with the same code
+//! as the contract. Built with `wasm-pack build gateway-core -- --features wasm`.
+
+use primitive_types::U256;
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::client::MetaCallBuilder;
+use crate::meta_parsing::parse_near_amount;
+use crate::types::{
+    ArgsEncoding, CallArgsFormat, DepositSource, MetaCallHash, MetaCallSignature, TypedDataSchema,
+    ValueUnit,
+};
+
+/// Meta call fields as given from JS. Amounts are decimal strings, `args` is hex.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MetaCallInput {
+    gateway_id: String,
+    chain_id: u64,
+    #[serde(default)]
+    nonce: Option<String>,
+    #[serde(default)]
+    fee_amount: Option<String>,
+    #[serde(default)]
+    fee_address: String,
+    #[serde(default)]
+    contract_address: String,
+    /// Integer in `valueUnit`, or NEAR like "1.5 NEAR".
+    #[serde(default)]
+    value: Option<String>,
+    /// Method definition, empty for transfers.
+    #[serde(default)]
+    method: String,
+    /// Id of the registered `method`, which is still needed for hashing.
+    #[serde(default)]
+    method_id: Option<u32>,
+    #[serde(default)]
+    args_encoding: Option<ArgsEncoding>,
+    #[serde(default)]
+    call_args_format: Option<CallArgsFormat>,
+    #[serde(default)]
+    args: String,
+    /// `nested` or `flat`, for hardware wallets.
+    #[serde(default)]
+    schema: Option<TypedDataSchema>,
+    /// yoctoNEAR paid to the submitter, signed only when not zero.
+    #[serde(default)]
+    tip: Option<String>,
+    /// Gas of the called method, signed only when not zero.
+    #[serde(default)]
+    gas: Option<String>,
+    /// `account` or `relayer`, where `value` comes from.
+    #[serde(default)]
+    deposit_source: Option<DepositSource>,
+    /// `yocto` or `wei`, the unit `value` is signed in.
+    #[serde(default)]
+    value_unit: Option<ValueUnit>,
+}
+
+fn error(message: String) -> JsValue {
+    JsValue::from_str(&message)
+}
+
+fn parse_hex(name: &str, value: &str) -> Result<Vec<u8>, JsValue> {
+    hex::decode(value.trim_start_matches("0x"))
+        .map_err(|err| error(format!("Invalid {}: {}", name, err)))
+}
+
+fn parse_nonce(value: &Option<String>) -> Result<U256, JsValue> {
+    match value {
+        Some(value) => {
+            U256::from_dec_str(value).map_err(|_| error(format!("Invalid nonce: {}", value)))
+        }
+        None => Ok(U256::zero()),
+    }
+}
+
+fn parse_amount(name: &str, value: &Option<String>) -> Result<u128, JsValue> {
+    match value {
+        Some(value) => value
+            .parse()
+            .map_err(|_| error(format!("Invalid {}: {}", name, value))),
+        None => Ok(0),
+    }
+}
+
+fn parse_value(value: &Option<String>, unit: ValueUnit) -> Result<u128, JsValue> {
+    match value {
+        Some(value) => parse_near_amount(value, unit)
+            .map_err(|err| error(format!("Invalid value {}: {}", value, err))),
+        None => Ok(0),
+    }
+}
+
+fn parse_gas(value: &Option<String>) -> Result<u64, JsValue> {
+    match value {
+        Some(value) => value
+            .parse()
+            .map_err(|_| error(format!("Invalid gas: {}", value))),
+        None => Ok(0),
+    }
+}
+
+fn builder(args: JsValue) -> Result<MetaCallBuilder, JsValue> {
+    let input: MetaCallInput =
+        serde_wasm_bindgen::from_value(args).map_err(|err| error(err.to_string()))?;
+    let value_unit = input.value_unit.unwrap_or(ValueUnit::Yocto);
+    let builder = MetaCallBuilder::new(&input.gateway_id, input.chain_id)
+        .nonce(parse_nonce(&input.nonce)?)
+        .fee(
+            parse_amount("feeAmount", &input.fee_amount)?,
+            &input.fee_address,
+        )
+        .receiver(&input.contract_address)
+        .value(parse_value(&input.value, value_unit)?)
+        .args(
+            input.args_encoding.unwrap_or(ArgsEncoding::Rlp),
+            parse_hex("args", &input.args)?,
+        )
+        .call_args_format(input.call_args_format.unwrap_or(CallArgsFormat::Json))
+        .schema(input.schema.unwrap_or(TypedDataSchema::Nested))
+        .tip(parse_amount("tip", &input.tip)?)
+        .gas(parse_gas(&input.gas)?)
+        .deposit_source(input.deposit_source.unwrap_or(DepositSource::Account))
+        .value_unit(value_unit);
+    Ok(match input.method_id {
+        Some(method_id) => builder.registered_method(method_id, &input.method),
+        None => builder.method(&input.method),
+    })
+}
+
+/// EIP-712 encoding of the meta call, in the same form as the gateway's `hash_meta_call`.
+/// `digest` is what the wallet signs.
+#[wasm_bindgen(js_name = prepareTypedData)]
+pub fn prepare_typed_data(args: JsValue) -> Result<JsValue, JsValue> {
+    let encoding = builder(args)?
+        .encode()
+        .map_err(|err| error(err.to_string()))?;
+    let hash = MetaCallHash {
+        digest: format!("0x{}", hex::encode(encoding.digest)),
+        type_string: encoding.type_string,
+        type_hash: format!("0x{}", hex::encode(encoding.type_hash)),
+        encoded_struct: format!("0x{}", hex::encode(encoding.encoded_struct)),
+        arguments_hash: format!("0x{}", hex::encode(encoding.arguments_hash)),
+    };
+    serde_wasm_bindgen::to_value(&hash).map_err(|err| error(err.to_string()))
+}
+
+/// Base64 encoded message with the wallet's hex `r ‖ s ‖ v` signature of the digest,
+/// to pass as `message` to the gateway.
+#[wasm_bindgen(js_name = encodeMetaCallArgs)]
+pub fn encode_meta_call_args(signature: &str, args: JsValue) -> Result<String, JsValue> {
+    let bytes = parse_hex("signature", signature)?;
+    if bytes.len() != 65 {
+        return Err(error("Signature must be 65 bytes".to_string()));
+    }
+    let mut signature = [0u8; 64];
+    signature.copy_from_slice(&bytes[..64]);
+    let message = builder(args)?.build(MetaCallSignature::Secp256k1 {
+        signature,
+        v: bytes[64],
+    });
+    Ok(base64::encode(message))
+}
@@ -0,0 +1,50 @@
+//! Builds the proxy contract for wasm and puts it at `$OUT_DIR/proxy.wasm`, where the gateway
+//! embeds it as the code of new proxy accounts. With the `testing` feature, also builds the
+//! `test-token` contract the sandbox tests deploy, at `$OUT_DIR/test_token.wasm`.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    build_contract(&out_dir, "proxy", "proxy");
+    if env::var_os("CARGO_FEATURE_TESTING").is_some() {
+        build_contract(&out_dir, "test-token", "test_token");
+    }
+}
+
+/// Builds the crate in the directory next to the gateway for wasm, as `$OUT_DIR/<name>.wasm`.
+fn build_contract(out_dir: &Path, crate_dir: &str, name: &str) {
+    let contract_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+        .join("..")
+        .join(crate_dir);
+    println!(
+        "cargo:rerun-if-changed={}",
+        contract_dir.join("src").display()
+    );
+    println!(
+        "cargo:rerun-if-changed={}",
+        contract_dir.join("Cargo.toml").display()
+    );
+
+    // Own target directory, so the build doesn't wait for the lock held by the outer one.
+    let target_dir = out_dir.join(format!("{}-target", crate_dir));
+    let status = Command::new(env::var("CARGO").unwrap())
+        .args(["build", "--release", "--target", "wasm32-unknown-unknown"])
+        .arg("--manifest-path")
+        .arg(contract_dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .env("RUSTFLAGS", "-C link-arg=-s")
+        .env_remove("CARGO_ENCODED_RUSTFLAGS")
+        .status()
+        .unwrap_or_else(|_| panic!("Failed to run cargo for the {}", crate_dir));
+    assert!(status.success(), "Failed to build the {}", crate_dir);
+    fs::copy(
+        target_dir.join(format!("wasm32-unknown-unknown/release/{}.wasm", name)),
+        out_dir.join(format!("{}.wasm", name)),
+    )
+    .unwrap_or_else(|_| panic!("Failed to copy the {} wasm", crate_dir));
+}
@@ -0,0 +1,222 @@
+//! Errors the gateway's methods fail with. Each fails the call with its `ERR_` code, so relayers
+//! and wallets can tell what's wrong with a message.
+
+use std::fmt;
+
+use gateway_core::meta_parsing::ParsingError;
+use gateway_core::types::AddressError;
+use near_sdk::FunctionError;
+
+/// Error of a gateway method, returned by the methods marked `#[handle_result]`, which fail with
+/// its code when it's an error.
+#[derive(FunctionError, Debug, PartialEq)]
+pub enum GatewayError {
+    /// Message that doesn't parse or verify, with the details, so the wallet can tell what's wrong
+    /// with it.
+    MetaTxParse(ParsingError),
+    InvalidAddress,
+    AddressChecksum,
+    /// Receiver or account id that isn't a valid NEAR account id.
+    InvalidAccountId,
+    UnexpectedMethod,
+    InvalidArgs,
+    OneYoctoDeposit,
+    ApproveDeposit,
+    MessageTooLarge,
+    MethodTooLarge,
+    ArgsTooLarge,
+    NoSignerSet,
+    MultisigRequired,
+    NotSigner,
+    NotEnoughSigners,
+    DuplicateSigner,
+    NotController,
+    ImplicitAccount,
+    StandaloneAccount,
+    AccountLocked,
+    IncorrectNonce,
+    RateLimited,
+    SiweExpirationRequired,
+    NonceOverflow,
+    NonceExpired,
+    NonceExpiryTooFar,
+    NonceUsed,
+    SaltedNoncesQueue,
+    InvalidNonceKey,
+    NotAllowed,
+    InvalidNonce,
+    NotEnoughDeposit,
+    UnknownTemplate,
+    UnknownCode,
+    AlreadyCreated,
+    AlreadyLinked,
+    FactoryTemplate,
+    NoProxyCode,
+    DropExists,
+    NoDrop,
+    NoStandaloneCode,
+    InvalidPublicKey,
+    ConfirmationRequired,
+    InvalidConfirmation,
+    NoConfirmer,
+    InvalidConfirmer,
+    SpendingLimit,
+    RelayerDeposit,
+    CircuitBreaker,
+    SelfValue,
+    NotProxyAccount,
+    NotLocked,
+    LockTimelock,
+    NoAllowance,
+    AllowanceExpired,
+    AllowanceExceeded,
+    AllowanceCall,
+    SessionExpired,
+    SiweResources,
+    NoSession,
+    SessionReceiver,
+    SessionMethod,
+    SessionValue,
+    LedgerReceiver,
+    SelfReceiver,
+    NotEnoughBalance,
+    TokenRegistered,
+    NoPaymaster,
+    NotSponsored,
+    SubsidyExceeded,
+    InvalidPrice,
+    NotFeeToken,
+    InvalidGuardians,
+    InvalidSignerSet,
+    LinkMismatch,
+    NotLinked,
+    RecoveryMismatch,
+    DuplicateGuardian,
+    NotEnoughGuardians,
+    NoGuardians,
+    NotGuardian,
+    AddressInUse,
+    NoRotation,
+    RotationTimelock,
+    PolicyViolation,
+    /// No state of the gateway `migrate` can read.
+    NoState,
+}
+
+impl GatewayError {
+    /// `ERR_` code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            GatewayError::MetaTxParse(_) => "ERR_META_TX_PARSE",
+            GatewayError::InvalidAddress => "ERR_INVALID_ADDRESS",
+            GatewayError::AddressChecksum => "ERR_ADDRESS_CHECKSUM",
+            GatewayError::InvalidAccountId => "ERR_INVALID_ACCOUNT_ID",
+            GatewayError::UnexpectedMethod => "ERR_UNEXPECTED_METHOD",
+            GatewayError::InvalidArgs => "ERR_INVALID_ARGS",
+            GatewayError::OneYoctoDeposit => "ERR_ONE_YOCTO_DEPOSIT",
+            GatewayError::ApproveDeposit => "ERR_APPROVE_DEPOSIT",
+            GatewayError::MessageTooLarge => "ERR_MESSAGE_TOO_LARGE",
+            GatewayError::MethodTooLarge => "ERR_METHOD_TOO_LARGE",
+            GatewayError::ArgsTooLarge => "ERR_ARGS_TOO_LARGE",
+            GatewayError::NoSignerSet => "ERR_NO_SIGNER_SET",
+            GatewayError::MultisigRequired => "ERR_MULTISIG_REQUIRED",
+            GatewayError::NotSigner => "ERR_NOT_SIGNER",
+            GatewayError::NotEnoughSigners => "ERR_NOT_ENOUGH_SIGNERS",
+            GatewayError::DuplicateSigner => "ERR_DUPLICATE_SIGNER",
+            GatewayError::NotController => "ERR_NOT_CONTROLLER",
+            GatewayError::ImplicitAccount => "ERR_IMPLICIT_ACCOUNT",
+            GatewayError::StandaloneAccount => "ERR_STANDALONE_ACCOUNT",
+            GatewayError::AccountLocked => "ERR_ACCOUNT_LOCKED",
+            GatewayError::IncorrectNonce => "ERR_INCORRECT_NONCE",
+            GatewayError::RateLimited => "ERR_RATE_LIMITED",
+            GatewayError::SiweExpirationRequired => "ERR_SIWE_EXPIRATION_REQUIRED",
+            GatewayError::NonceOverflow => "ERR_NONCE_OVERFLOW",
+            GatewayError::NonceExpired => "ERR_NONCE_EXPIRED",
+            GatewayError::NonceExpiryTooFar => "ERR_NONCE_EXPIRY_TOO_FAR",
+            GatewayError::NonceUsed => "ERR_NONCE_USED",
+            GatewayError::SaltedNoncesQueue => "ERR_SALTED_NONCES_QUEUE",
+            GatewayError::InvalidNonceKey => "ERR_INVALID_NONCE_KEY",
+            GatewayError::NotAllowed => "ERR_NOT_ALLOWED",
+            GatewayError::InvalidNonce => "ERR_INVALID_NONCE",
+            GatewayError::NotEnoughDeposit => "ERR_NOT_ENOUGH_DEPOSIT",
+            GatewayError::UnknownTemplate => "ERR_UNKNOWN_TEMPLATE",
+            GatewayError::UnknownCode => "ERR_UNKNOWN_CODE",
+            GatewayError::AlreadyCreated => "ERR_ALREADY_CREATED",
+            GatewayError::AlreadyLinked => "ERR_ALREADY_LINKED",
+            GatewayError::FactoryTemplate => "ERR_FACTORY_TEMPLATE",
+            GatewayError::NoProxyCode => "ERR_NO_PROXY_CODE",
+            GatewayError::DropExists => "ERR_DROP_EXISTS",
+            GatewayError::NoDrop => "ERR_NO_DROP",
+            GatewayError::NoStandaloneCode => "ERR_NO_STANDALONE_CODE",
+            GatewayError::InvalidPublicKey => "ERR_INVALID_PUBLIC_KEY",
+            GatewayError::ConfirmationRequired => "ERR_CONFIRMATION_REQUIRED",
+            GatewayError::InvalidConfirmation => "ERR_INVALID_CONFIRMATION",
+            GatewayError::NoConfirmer => "ERR_NO_CONFIRMER",
+            GatewayError::InvalidConfirmer => "ERR_INVALID_CONFIRMER",
+            GatewayError::SpendingLimit => "ERR_SPENDING_LIMIT",
+            GatewayError::RelayerDeposit => "ERR_RELAYER_DEPOSIT",
+            GatewayError::CircuitBreaker => "ERR_CIRCUIT_BREAKER",
+            GatewayError::SelfValue => "ERR_SELF_VALUE",
+            GatewayError::NotProxyAccount => "ERR_NOT_PROXY_ACCOUNT",
+            GatewayError::NotLocked => "ERR_NOT_LOCKED",
+            GatewayError::LockTimelock => "ERR_LOCK_TIMELOCK",
+            GatewayError::NoAllowance => "ERR_NO_ALLOWANCE",
+            GatewayError::AllowanceExpired => "ERR_ALLOWANCE_EXPIRED",
+            GatewayError::AllowanceExceeded => "ERR_ALLOWANCE_EXCEEDED",
+            GatewayError::AllowanceCall => "ERR_ALLOWANCE_CALL",
+            GatewayError::SessionExpired => "ERR_SESSION_EXPIRED",
+            GatewayError::SiweResources => "ERR_SIWE_RESOURCES",
+            GatewayError::NoSession => "ERR_NO_SESSION",
+            GatewayError::SessionReceiver => "ERR_SESSION_RECEIVER",
+            GatewayError::SessionMethod => "ERR_SESSION_METHOD",
+            GatewayError::SessionValue => "ERR_SESSION_VALUE",
+            GatewayError::LedgerReceiver => "ERR_LEDGER_RECEIVER",
+            GatewayError::SelfReceiver => "ERR_SELF_RECEIVER",
+            GatewayError::NotEnoughBalance => "ERR_NOT_ENOUGH_BALANCE",
+            GatewayError::TokenRegistered => "ERR_TOKEN_REGISTERED",
+            GatewayError::NoPaymaster => "ERR_NO_PAYMASTER",
+            GatewayError::NotSponsored => "ERR_NOT_SPONSORED",
+            GatewayError::SubsidyExceeded => "ERR_SUBSIDY_EXCEEDED",
+            GatewayError::InvalidPrice => "ERR_INVALID_PRICE",
+            GatewayError::NotFeeToken => "ERR_NOT_FEE_TOKEN",
+            GatewayError::InvalidGuardians => "ERR_INVALID_GUARDIANS",
+            GatewayError::InvalidSignerSet => "ERR_INVALID_SIGNER_SET",
+            GatewayError::LinkMismatch => "ERR_LINK_MISMATCH",
+            GatewayError::NotLinked => "ERR_NOT_LINKED",
+            GatewayError::RecoveryMismatch => "ERR_RECOVERY_MISMATCH",
+            GatewayError::DuplicateGuardian => "ERR_DUPLICATE_GUARDIAN",
+            GatewayError::NotEnoughGuardians => "ERR_NOT_ENOUGH_GUARDIANS",
+            GatewayError::NoGuardians => "ERR_NO_GUARDIANS",
+            GatewayError::NotGuardian => "ERR_NOT_GUARDIAN",
+            GatewayError::AddressInUse => "ERR_ADDRESS_IN_USE",
+            GatewayError::NoRotation => "ERR_NO_ROTATION",
+            GatewayError::RotationTimelock => "ERR_ROTATION_TIMELOCK",
+            GatewayError::PolicyViolation => "ERR_POLICY_VIOLATION",
+            GatewayError::NoState => "ERR_NO_STATE",
+        }
+    }
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GatewayError::MetaTxParse(error) => write!(f, "{}: {}", self.code(), error),
+            _ => f.write_str(self.code()),
+        }
+    }
+}
+
+impl From<ParsingError> for GatewayError {
+    fn from(error: ParsingError) -> Self {
+        GatewayError::MetaTxParse(error)
+    }
+}
+
+impl From<AddressError> for GatewayError {
+    fn from(error: AddressError) -> Self {
+        match error {
+            AddressError::Invalid => GatewayError::InvalidAddress,
+            AddressError::Checksum => GatewayError::AddressChecksum,
+        }
+    }
+}
@@ -1,32 +1,1276 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::LookupMap;
-use near_sdk::json_types::Base64VecU8;
-use near_sdk::{env, near_bindgen, Gas, PanicOnDefault, Promise};
+use near_sdk::collections::{LazyOption, LookupMap, LookupSet, UnorderedMap, Vector};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use near_sdk::serde_json::{self, json};
+use near_sdk::{
+    env, near_bindgen, AccountId, Balance, Gas, PanicOnDefault, Promise, PromiseOrValue,
+    PromiseResult, PublicKey,
+};
 use primitive_types::U256;
+use std::convert::TryFrom;
 
-pub use crate::meta_parsing::{near_erc712_domain, prepare_meta_call_args};
-pub use crate::types::{u256_to_arr, InternalMetaCallArgs, MetaCallArgs};
-use crate::types::{RawAddress, RawU256};
+use gateway_core::meta_parsing::{self, PreparedMethod};
+pub use gateway_core::meta_parsing::{near_erc712_domain, prepare_meta_call_args};
+use gateway_core::siwe::SiweMessage;
+use gateway_core::types::{
+    checksum_address, parse_checksum_address, AddressError, RawAddress, RawU256,
+};
+pub use gateway_core::types::{
+    is_salted_nonce, make_nonce, nonce_key, nonce_sequence, salted_nonce, u256_to_arr,
+    ArgsEncoding, CallArgsFormat, DepositSource, InternalMetaCallArgs, MetaCallArgs,
+    MetaCallArgsJson, MetaCallHash, MetaCallSignature, MultisigSignatures, RecoveredSigner,
+    TypedDataSchema, SELF_RECEIVER,
+};
 
-mod ecrecover;
-mod meta_parsing;
-mod types;
+mod error;
+#[cfg(feature = "testing")]
+pub mod testing;
 
-near_sdk::setup_alloc!();
+pub use error::GatewayError;
 
 const CHAIN_ID: u64 = 1;
 
-const CODE: &[u8] = include_bytes!("../../res/proxy.wasm");
+const TGAS: u64 = 1_000_000_000_000;
+const GAS_FOR_PROXY: Gas = Gas(10 * TGAS);
+const GAS_FOR_ON_CREATE: Gas = Gas(5 * TGAS);
+const GAS_FOR_ON_PROXY: Gas = Gas(5 * TGAS);
+const GAS_FOR_ON_LEDGER_CALL: Gas = Gas(5 * TGAS);
+const GAS_FOR_ON_SPONSORED: Gas = Gas(5 * TGAS);
+const GAS_FOR_FT_BALANCE_OF: Gas = Gas(5 * TGAS);
+const GAS_FOR_ON_BALANCES: Gas = Gas(10 * TGAS);
+/// Default gas of the proxy account's transfers.
+const GAS_FOR_TRANSFER: Gas = Gas(10 * TGAS);
+/// Default gas the proxy account gives its calls.
+const GAS_FOR_CALL: Gas = Gas(20 * TGAS);
+/// Default gas of the proxy account's code update.
+const GAS_FOR_UPDATE: Gas = Gas(20 * TGAS);
+/// Gas of the callback recording a proxy account's migrated code.
+const GAS_FOR_ON_MIGRATE: Gas = Gas(5 * TGAS);
+/// Most addresses `airdrop` funds per call.
+const MAX_AIRDROPS_PER_CALL: usize = 20;
+/// Most proxy accounts `migrate_proxies` updates per call, each update carries the whole code.
+const MAX_MIGRATIONS_PER_CALL: usize = 20;
+/// Messages kept in the history of each address.
+const MAX_HISTORY: usize = 10;
+/// Gas of the proxy account's `add_key`.
+const GAS_FOR_ADD_KEY: Gas = Gas(10 * TGAS);
+/// Gas of the standalone proxy's `new`, initializing it with its owner.
+const GAS_FOR_STANDALONE_INIT: Gas = Gas(10 * TGAS);
+/// Gas of the factory's `create_proxy`, covering the account creation it makes.
+const GAS_FOR_FACTORY_CREATE: Gas = Gas(30 * TGAS);
+/// Gas added to the called method's budget per byte of its arguments, for parsing them.
+const GAS_PER_ARGS_BYTE: Gas = Gas(10_000_000);
+/// Gas for NEP-141 `ft_transfer_call` and NEP-171 `nft_transfer_call`, covering the receiver's
+/// `ft_on_transfer`/`nft_on_transfer` and the contract resolving the transfer. NEP-141 asks for
+/// at least 35 TGas. Also given to `nft_approve`, which may call the account's `nft_on_approve`.
+const GAS_FOR_TRANSFER_CALL: Gas = Gas(50 * TGAS);
 
-const TGAS: Gas = 1_000_000_000_000;
-const GAS_FOR_PROXY: Gas = 10 * TGAS;
+/// Proxy code built with the gateway by `build.rs`, deployed on new proxy accounts until the
+/// gateway account sets other code.
+const PROXY_CODE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/proxy.wasm"));
+
+/// Deposit on top of the proxy code storage for creating accounts, covers the account record.
+const DEFAULT_CREATE_DEPOSIT_BUFFER: Balance = 10_000_000_000_000_000_000_000;
+
+/// Gas allowance of a drop's access key, paid from the drop, covers the `claim_drop` transaction.
+const DROP_KEY_ALLOWANCE: Balance = 100_000_000_000_000_000_000_000;
+/// Methods a drop's access key can call on the gateway.
+const DROP_KEY_METHODS: &str = "claim_drop";
+
+/// Longest time a salted nonce may be valid for, bounds how long it's remembered.
+const MAX_SALTED_NONCE_LIFETIME: u64 = 7 * 24 * 60 * 60;
+
+/// Delay before `rotate_owner` can be executed, unless the gateway account changes it.
+const DEFAULT_ROTATION_DELAY: u64 = 24 * 60 * 60;
+
+/// Expired salted nonces forgotten along with using a new one.
+const SALTED_NONCES_PRUNED_PER_USE: u64 = 2;
+
+/// Default longest method definition a message may carry, in bytes.
+const DEFAULT_MAX_METHOD_LEN: u32 = 1024;
+/// Default longest arguments a message may carry, in bytes.
+const DEFAULT_MAX_ARGS_LEN: u32 = 16 * 1024;
+/// Bytes a message may carry besides its method definition and arguments: the fixed size
+/// fields, account ids and a few dozen multi-signature signatures.
+const MAX_MESSAGE_OVERHEAD: usize = 4 * 1024;
 
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
 pub struct Contract {
+    /// Sequential nonces under key 0, enumerable as the registry of the addresses that used the
+    /// gateway.
+    nonces: UnorderedMap<RawAddress, u64>,
+    /// 32-byte key 0 nonces written by the first gateway before `nonces` became enumerable, read
+    /// when an address has none in `nonces` yet, and moved there once it uses a nonce.
+    legacy_nonces: LookupMap<RawAddress, RawU256>,
+    /// EIP-712 domain separator, computed once at init.
+    domain_separator: RawU256,
+    /// Registered method definitions, indexed by their id.
+    method_defs: Vector<PreparedMethod>,
+    method_def_ids: LookupMap<String, u32>,
+    /// Deposit required by `create` on top of the proxy code storage.
+    create_deposit_buffer: Balance,
+    /// Sequences of the non-zero nonce keys, key 0 is in `nonces`.
+    keyed_nonces: LookupMap<(RawAddress, [u8; 24]), u64>,
+    /// Addresses whose proxy accounts are created or being created.
+    created: LookupSet<RawAddress>,
+    /// Funds sent to addresses before their proxy accounts are created.
+    escrow: LookupMap<RawAddress, Balance>,
+    /// Expiry of the salted nonces used by each sender.
+    used_salted_nonces: LookupMap<(RawAddress, RawU256), u64>,
+    /// Used salted nonces in the order of use, from `salted_nonces_head` to
+    /// `salted_nonces_tail`, for pruning them once expired.
+    salted_nonces_queue: LookupMap<u64, (RawAddress, RawU256)>,
+    salted_nonces_head: u64,
+    salted_nonces_tail: u64,
+    /// Spending limits senders set on themselves.
+    spending_limits: LookupMap<RawAddress, SpendingLimit>,
+    /// Addresses confirming messages over the spending limits, by account address.
+    confirmers: LookupMap<RawAddress, RawAddress>,
+    /// Allowances by owner and spender address.
+    allowances: UnorderedMap<(RawAddress, RawAddress), Allowance>,
+    /// Session keys by owner and session key address.
+    sessions: LookupMap<(RawAddress, RawAddress), Session>,
+    /// Addresses now controlling the proxy accounts of other addresses, by account address.
+    controllers: LookupMap<RawAddress, RawAddress>,
+    /// Reverse of `controllers`: the account address by its controlling address.
+    controlled: LookupMap<RawAddress, RawAddress>,
+    /// Guardians that can rotate the controlling address, by account address.
+    guardians: LookupMap<RawAddress, Guardians>,
+    /// Rotations of the controlling address waiting for their timelock, by account address.
+    pending_rotations: LookupMap<RawAddress, PendingRotation>,
+    /// Delay in seconds before rotations started by the controller itself can be executed.
+    rotation_delay: u64,
+    /// Addresses that must sign the messages of multi-signature proxy accounts together, by
+    /// account address.
+    signer_sets: LookupMap<RawAddress, SignerSet>,
+    /// Existing NEAR accounts that opted in to act for addresses instead of their proxy
+    /// accounts, by address.
+    linked_accounts: LookupMap<RawAddress, AccountId>,
+    /// Balances of addresses in ledger mode, held by the gateway itself instead of proxy
+    /// accounts.
+    ledger: LookupMap<RawAddress, Balance>,
+    /// NEP-141 tokens, like wNEAR, reported by `balances_of`.
+    tokens: Vector<AccountId>,
+    /// Token allowances by owner address, token and spender account, from permits.
+    token_allowances: LookupMap<(RawAddress, AccountId, AccountId), Allowance>,
+    /// Balances and policies of the accounts sponsoring meta calls, by their account id.
+    paymasters: LookupMap<AccountId, Paymaster>,
+    /// Subsidies each paymaster paid for each address so far.
+    subsidies: LookupMap<(AccountId, RawAddress), Balance>,
+    /// NEP-141 tokens relayer fees can be paid in, with their price in NEAR.
+    fee_tokens: LookupMap<AccountId, FeeToken>,
+    /// NEAR paid to relayers for fees in tokens.
+    gas_station: Balance,
+    /// Token fees on their way to the gateway, by the id sent as their `ft_transfer_call` msg.
+    pending_fees: LookupMap<u64, PendingFee>,
+    next_fee_id: u64,
+    /// Gas budgets of the actions taken for messages.
+    gas_schedule: GasSchedule,
+    /// Account creating new proxy accounts under its own name, instead of the gateway.
+    factory: Option<AccountId>,
+    /// Proxy accounts created by a factory, by address. Others are named after the gateway.
+    factory_accounts: LookupMap<RawAddress, AccountId>,
+    /// Implicit accounts of addresses that chose them over proxy accounts, by address.
+    implicit_accounts: LookupMap<RawAddress, AccountId>,
+    /// Code deployed on new proxy accounts, the one built with the gateway until the gateway
+    /// account sets another.
+    proxy_code: LazyOption<Vec<u8>>,
+    /// Length and sha256 of `proxy_code`, so they are known without reading it.
+    proxy_code_len: u64,
+    proxy_code_hash: Option<Vec<u8>>,
+    /// Named proxy code templates new accounts can be created with instead, indexed by their id.
+    proxy_templates: Vector<ProxyTemplate>,
+    proxy_template_ids: LookupMap<String, u32>,
+    /// Code of the proxy templates, by their id.
+    proxy_template_codes: LookupMap<u32, Vec<u8>>,
+    /// Hex encoded sha256 of the code deployed on proxy accounts the gateway created, by
+    /// address. Accounts created before it was tracked have none.
+    account_code_hashes: LookupMap<RawAddress, String>,
+    /// Addresses whose proxy accounts `migrate_proxies` leaves alone.
+    migration_opt_outs: LookupSet<RawAddress>,
+    /// Account besides the gateway account that may call `migrate_proxies`, e.g. a relayer.
+    migrator: Option<AccountId>,
+    /// NEAR claimable with the access key of each drop, by its public key.
+    drops: LookupMap<Vec<u8>, Balance>,
+    /// Code of standalone proxy accounts, verifying their owner's messages themselves, set by
+    /// the gateway account.
+    standalone_code: LazyOption<Vec<u8>>,
+    standalone_code_len: u64,
+    /// Addresses with standalone proxy accounts, the gateway doesn't act from them.
+    standalone_accounts: LookupSet<RawAddress>,
+    /// Time in seconds each locked account was locked at, by account address.
+    locks: LookupMap<RawAddress, u64>,
+    /// Receivers and methods the gateway doesn't forward calls to.
+    denylist: Denylist,
+    /// Preferences of signers for their messages, by address.
+    settings: LookupMap<RawAddress, Settings>,
+    /// Last messages executed for each address, oldest first.
+    history: LookupMap<RawAddress, Vec<ExecutedMessage>>,
+    /// Messages each relayer may submit per epoch, unless it has its own quota.
+    relayer_quota: Option<u64>,
+    /// Quotas of the relayers with their own.
+    relayer_quotas: LookupMap<AccountId, u64>,
+    /// Epoch and the number of messages each relayer submitted in it.
+    relayer_usage: LookupMap<AccountId, (u64, u64)>,
+    /// Cap on the value of messages executed by `proxy` per epoch.
+    circuit_breaker: Option<CircuitBreaker>,
+    /// Epoch and the value of the messages `proxy` executed in it.
+    epoch_value: (u64, Balance),
+    /// Whether each meta call is also logged as an Ethereum log, see `emit_eth_log`.
+    eth_logs: bool,
+    /// Size limits of the messages, checked before hashing them.
+    message_limits: MessageLimits,
+}
+
+/// Gas budgets of the actions the gateway takes for messages, set by the gateway account.
+/// Each is capped by what the transaction's prepaid gas leaves.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct GasSchedule {
+    /// Gas of the callback finishing the creation of proxy accounts.
+    pub create: U64,
+    /// Gas of the proxy account's `transfer`, of values and tips.
+    pub transfer: U64,
+    /// Gas the proxy account gives the called method, unless the message declares it. NEP-141
+    /// and NEP-171 transfer calls and `nft_approve` get at least `GAS_FOR_TRANSFER_CALL`.
+    pub call: U64,
+    /// Gas of the proxy account's `update` of its code.
+    pub update: U64,
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        Self {
+            create: U64(GAS_FOR_ON_CREATE.0),
+            transfer: U64(GAS_FOR_TRANSFER.0),
+            call: U64(GAS_FOR_CALL.0),
+            update: U64(GAS_FOR_UPDATE.0),
+        }
+    }
+}
+
+/// Size limits of the method definition and arguments of messages, set by the gateway account.
+/// Larger messages are rejected before they are hashed, instead of running out of gas. Messages
+/// longer than both limits and `MAX_MESSAGE_OVERHEAD` fail with `ERR_MESSAGE_TOO_LARGE` before
+/// they are decoded.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct MessageLimits {
+    /// Longest method definition in bytes, or `ERR_METHOD_TOO_LARGE`.
+    pub method: u32,
+    /// Longest arguments in bytes, or `ERR_ARGS_TOO_LARGE`.
+    pub args: u32,
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        Self {
+            method: DEFAULT_MAX_METHOD_LEN,
+            args: DEFAULT_MAX_ARGS_LEN,
+        }
+    }
+}
+
+/// Outcome of a message executed by `proxy` or creating an account, which their promises resolve
+/// to so relayers and frontends can confirm it.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct MessageOutcome {
+    /// Hex encoded EIP-712 digest of the message, none for accounts created without one.
+    pub digest: Option<String>,
+    /// Account acting for the sender.
+    pub account_id: AccountId,
+    /// Whether the account was created, or took the message's action.
+    pub success: bool,
+}
+
+/// NEP-330 metadata of the contract's source, for explorers and auditors verifying the build.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ContractSourceMetadata {
+    pub version: Option<String>,
+    /// Repository of the source code.
+    pub link: Option<String>,
+    pub standards: Vec<Standard>,
+}
+
+/// Standard the contract implements, with its version.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Standard {
+    pub standard: String,
+    pub version: String,
+}
+
+/// EIP-712 domain and types of the messages the gateway takes, for wallets building typed data.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct TypedDataInfo {
+    /// Definition of the domain type, `EIP712Domain`.
+    pub domain_type: String,
+    pub name: String,
+    pub version: String,
+    pub chain_id: U64,
+    /// Hex encoded hash of the domain.
+    pub domain_separator: String,
+    /// Primary type of messages, `NearTx`, without the `Arguments` type it references, which
+    /// follows from the called method.
+    pub near_tx_type: String,
+    /// Primary type of messages signed with the flat schema, `NearTxFlat`.
+    pub near_tx_flat_type: String,
+}
+
+/// Verdict of `dry_run` on a message, as `proxy` would see it.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct DryRunVerdict {
+    /// Hex encoded address of the sender, none if the message doesn't verify.
+    pub address: Option<String>,
+    /// Hex encoded EIP-712 digest of the message, none if the message doesn't verify.
+    pub digest: Option<String>,
+    /// Account acting for the sender.
+    pub account_id: Option<AccountId>,
+    /// Deposit the relayer must attach, the value of messages funded by the relayer.
+    pub deposit: U128,
+    /// Error the message would fail with, none if it would be executed.
+    pub error: Option<String>,
+}
+
+/// Cap on the total value of the messages `proxy` executes per epoch. Messages that would take
+/// the total over `cap` fail, unless their value is below `threshold`.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct CircuitBreaker {
+    pub cap: U128,
+    pub threshold: U128,
+}
+
+/// Message the gateway executed for an address, as kept in its history.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ExecutedMessage {
+    /// Hex encoded EIP-712 digest of the message.
+    pub digest: String,
+    /// Height of the block the gateway executed the message in.
+    pub block_height: U64,
+    /// Whether the account took the message's action or was created, none until known.
+    pub success: Option<bool>,
+}
+
+/// Preferences of a signer, which the gateway applies to its messages omitting the fields.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Settings {
+    /// Gas of the called method for messages not declaring it, 0 to leave it to the gateway.
+    pub gas: U64,
+    /// Fee token of messages without a fee address, as paid through `proxy_token_fee`.
+    pub fee_token: Option<String>,
+    /// Longest time in seconds the signer's salted nonces may be valid for, 0 for the gateway's
+    /// limit.
+    pub max_nonce_lifetime: U64,
+}
+
+/// Receiver accounts and method names the gateway refuses to forward calls to, e.g. for
+/// compliance, set by the gateway account. Empty by default.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Denylist {
+    /// Receivers of neither transfers nor calls.
+    pub receivers: Vec<String>,
+    /// Methods not called on any receiver.
+    pub methods: Vec<String>,
+}
+
+/// Named proxy code new accounts can be created with, e.g. a minimal or a full-featured proxy.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProxyTemplate {
+    pub name: String,
+    /// Hex encoded sha256 of the code.
+    pub code_hash: String,
+    pub code_len: U64,
+}
+
+/// Most value the sender's messages may spend per period without a confirmation, and how much
+/// they spent in the current one.
+#[derive(BorshSerialize, BorshDeserialize, Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct SpendingLimit {
+    pub amount: U128,
+    /// Length of the period in seconds.
+    pub period: U64,
+    /// Start of the current period in seconds.
+    pub period_start: U64,
+    pub spent: U128,
+}
+
+/// State of the first gateway, built with near-sdk 3, read by `migrate`.
+#[derive(BorshDeserialize)]
+struct LegacyContract {
     nonces: LookupMap<RawAddress, RawU256>,
 }
 
+/// Arguments of the signed `set_settings(uint64 gas,string fee_token,uint64 max_nonce_lifetime)`
+/// call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct SetSettingsArgs {
+    gas: U64,
+    fee_token: String,
+    max_nonce_lifetime: U64,
+}
+
+/// Arguments of the signed `set_spending_limit(uint128 amount,uint64 period)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct SetSpendingLimitArgs {
+    amount: U128,
+    period: U64,
+}
+
+/// Arguments of the signed `set_confirmer(address confirmer)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct SetConfirmerArgs {
+    #[serde(deserialize_with = "deserialize_address")]
+    confirmer: RawAddress,
+}
+
+/// yoctoNEAR the spender may still send from the owner's proxy account, until the expiry
+/// timestamp in seconds.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Allowance {
+    pub amount: U128,
+    pub expires_at: U64,
+}
+
+/// Nonce and settings of an address, as exported by one gateway and imported by another.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountSnapshot {
+    /// Hex encoded address.
+    pub address: String,
+    /// Sequential nonce under key 0, as a decimal string.
+    pub nonce: String,
+    pub settings: Option<Settings>,
+}
+
+/// Allowance of a spender from an owner, both hex encoded addresses.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct AllowanceSnapshot {
+    pub owner: String,
+    pub spender: String,
+    pub allowance: Allowance,
+}
+
+/// Page of the gateway state carried over to a new deployment.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct StateSnapshot {
+    pub accounts: Vec<AccountSnapshot>,
+    pub allowances: Vec<AllowanceSnapshot>,
+}
+
+/// Arguments of the signed `approve(address spender,uint128 amount,uint64 expires_at)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct ApproveArgs {
+    #[serde(deserialize_with = "deserialize_address")]
+    spender: RawAddress,
+    amount: U128,
+    expires_at: U64,
+}
+
+/// Arguments of the signed `permit(string token,string spender,uint128 amount,uint64 expires_at)`
+/// call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct PermitArgs {
+    token: AccountId,
+    spender: AccountId,
+    amount: U128,
+    expires_at: U64,
+}
+
+/// Scope of a session key acting for its owner until the expiry timestamp in seconds: messages
+/// it signs may only call the receivers, the methods if any are listed, and attach at most
+/// `value_cap` yoctoNEAR each.
+#[derive(BorshSerialize, BorshDeserialize, Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Session {
+    pub receivers: Vec<String>,
+    pub methods: Vec<String>,
+    pub value_cap: U128,
+    pub expires_at: U64,
+}
+
+/// Arguments of the signed `grant_session(address session,string[] receivers,string[] methods,uint128 value_cap,uint64 expires_at)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct GrantSessionArgs {
+    #[serde(deserialize_with = "deserialize_address")]
+    session: RawAddress,
+    receivers: Vec<String>,
+    methods: Vec<String>,
+    value_cap: U128,
+    expires_at: U64,
+}
+
+/// Arguments of the signed `revoke_session(address session)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct RevokeSessionArgs {
+    #[serde(deserialize_with = "deserialize_address")]
+    session: RawAddress,
+}
+
+/// Addresses that can together rotate the controlling address of a proxy account: `threshold`
+/// of them sign the recovery, which can be executed `delay` seconds later.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Guardians {
+    #[serde(
+        serialize_with = "serialize_addresses",
+        deserialize_with = "deserialize_addresses"
+    )]
+    #[cfg_attr(feature = "abi-generate", schemars(with = "Vec<String>"))]
+    pub guardians: Vec<RawAddress>,
+    #[serde(deserialize_with = "deserialize_u32")]
+    pub threshold: u32,
+    pub delay: U64,
+}
+
+/// Addresses governing a multi-signature proxy account: its messages need signatures of
+/// `threshold` of them.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct SignerSet {
+    #[serde(
+        serialize_with = "serialize_addresses",
+        deserialize_with = "deserialize_addresses"
+    )]
+    #[cfg_attr(feature = "abi-generate", schemars(with = "Vec<String>"))]
+    pub signers: Vec<RawAddress>,
+    #[serde(deserialize_with = "deserialize_u32")]
+    pub threshold: u32,
+}
+
+/// Account sponsoring meta calls to its receivers: pays the relayer `subsidy` for each from its
+/// balance, at most `max_per_user` in total for each address.
+#[derive(BorshSerialize, BorshDeserialize, Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Paymaster {
+    pub balance: U128,
+    pub receivers: Vec<String>,
+    pub subsidy: U128,
+    pub max_per_user: U128,
+}
+
+/// Price of a fee token: `token_amount` of it is worth `near_amount` yoctoNEAR.
+#[derive(BorshSerialize, BorshDeserialize, Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeToken {
+    pub token_amount: U128,
+    pub near_amount: U128,
+}
+
+/// Token fee the proxy account is sending to the gateway, paying the relayer `near` on arrival.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct PendingFee {
+    account_id: AccountId,
+    token: AccountId,
+    amount: Balance,
+    relayer: AccountId,
+    near: Balance,
+}
+
+/// Balances of the account acting for an address, see `balances_of`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Balances {
+    pub account_id: AccountId,
+    /// Ledger balance of the address, held by the gateway.
+    pub ledger: U128,
+    /// Balances of the registered tokens, in order.
+    pub tokens: Vec<TokenBalance>,
+}
+
+/// Balance of the registered NEP-141 token, None if `ft_balance_of` failed.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenBalance {
+    pub token_id: AccountId,
+    pub balance: Option<U128>,
+}
+
+/// Controlling address a proxy account rotates to, once the timestamp in seconds is reached.
+#[derive(BorshSerialize, BorshDeserialize, Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingRotation {
+    #[serde(serialize_with = "serialize_address")]
+    #[cfg_attr(feature = "abi-generate", schemars(with = "String"))]
+    pub new_controller: RawAddress,
+    pub executable_at: U64,
+}
+
+/// Arguments of the signed `recover(address account,address new_controller)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct RecoverArgs {
+    #[serde(deserialize_with = "deserialize_address")]
+    account: RawAddress,
+    #[serde(deserialize_with = "deserialize_address")]
+    new_controller: RawAddress,
+}
+
+/// Arguments of the signed `update(bytes32 code_hash)` call of `SELF_RECEIVER`.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct UpdateArgs {
+    code_hash: Base64VecU8,
+}
+
+/// Arguments of the signed `add_key(bytes32 public_key)` call of `SELF_RECEIVER`.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct AddKeyArgs {
+    public_key: Base64VecU8,
+}
+
+/// Arguments of the signed `set_controller(address new_controller)` call of `SELF_RECEIVER`.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct SetControllerArgs {
+    #[serde(deserialize_with = "deserialize_address")]
+    new_controller: RawAddress,
+}
+
+/// Arguments of the signed `rotate_owner(address new_address)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct RotateOwnerArgs {
+    #[serde(deserialize_with = "deserialize_address")]
+    new_address: RawAddress,
+}
+
+/// Arguments of the signed `link_account(string account_id)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct LinkAccountArgs {
+    account_id: AccountId,
+}
+
+/// Arguments of the signed `create(string template)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct CreateArgs {
+    template: String,
+}
+
+/// Arguments of the signed `set_migration_opt_out(bool opt_out)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct MigrationOptOutArgs {
+    opt_out: bool,
+}
+
+/// Arguments of the signed `withdraw(uint128 amount,string receiver)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct WithdrawArgs {
+    amount: U128,
+    receiver: AccountId,
+}
+
+/// Arguments of the signed `create_implicit(bytes32 public_key)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct CreateImplicitArgs {
+    public_key: Base64VecU8,
+}
+
+/// How the NEAR account acting for an address is controlled.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum AccountMode {
+    /// Proxy account the gateway created, acting on the address' messages.
+    Proxy,
+    /// Existing account that opted in to act on the address' messages.
+    Linked,
+    /// Implicit account controlled by its own ed25519 key, the gateway doesn't act from it.
+    Implicit,
+    /// Standalone proxy account verifying the address' messages itself, the gateway doesn't act
+    /// from it.
+    Standalone,
+}
+
+/// Arguments of the signed `confirm(bytes digest)` call.
+#[derive(BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct ConfirmArgs {
+    digest: Base64VecU8,
+}
+
+/// Signed message argument, base64 or `0x` prefixed hex as Ethereum tooling produces it.
+#[derive(Clone)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+pub struct SignedMessage(
+    #[cfg_attr(feature = "abi-generate", schemars(with = "String"))] pub Vec<u8>,
+);
+
+impl From<Base64VecU8> for SignedMessage {
+    fn from(message: Base64VecU8) -> Self {
+        Self(message.0)
+    }
+}
+
+impl Serialize for SignedMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Serialize::serialize(&Base64VecU8(self.0.clone()), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SignedMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let message = <String as Deserialize>::deserialize(deserializer)?;
+        if let Some(bytes) = message
+            .strip_prefix("0x")
+            .and_then(|hex| hex::decode(hex).ok())
+        {
+            return Ok(Self(bytes));
+        }
+        let message = de::IntoDeserializer::<D::Error>::into_deserializer(message);
+        <Base64VecU8 as Deserialize>::deserialize(message).map(Self::from)
+    }
+}
+
+/// NEP-297 events of the gateway, logged as `EVENT_JSON:` followed by the JSON, so indexers can
+/// follow deposits, withdrawals and the gateway account's interventions. The data of each is a
+/// list of one entry.
+#[near_bindgen(event_json(standard = "eth-gateway"))]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+pub enum GatewayEvent {
+    #[event_version("1.0.0")]
+    ImportState(Vec<ImportStateData>),
+    #[event_version("1.0.0")]
+    MigrateProxies(Vec<MigrateProxiesData>),
+    #[event_version("1.0.0")]
+    ProxyMigrated(Vec<ProxyMigratedData>),
+    #[event_version("1.0.0")]
+    ClaimDrop(Vec<ClaimDropData>),
+    #[event_version("1.0.0")]
+    Airdrop(Vec<AirdropData>),
+    #[event_version("1.0.0")]
+    CircuitBreaker(Vec<CircuitBreakerData>),
+    #[event_version("1.0.0")]
+    SelfCall(Vec<SelfCallData>),
+    #[event_version("1.0.0")]
+    Deposit(Vec<DepositData>),
+    #[event_version("1.0.0")]
+    Withdraw(Vec<WithdrawData>),
+    #[event_version("1.0.0")]
+    Sponsor(Vec<SponsorData>),
+    #[event_version("1.0.0")]
+    PolicyViolation(Vec<PolicyViolationData>),
+    #[event_version("1.0.0")]
+    RelayerDeposit(Vec<RelayerDepositData>),
+}
+
+/// Page of accounts and allowances `import_state` imported.
+#[derive(Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ImportStateData {
+    pub accounts: u64,
+    pub allowances: u64,
+}
+
+/// Batch of `migrate_proxies`: the hex encoded addresses whose proxy accounts are updated, and
+/// the ones skipped.
+#[derive(Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct MigrateProxiesData {
+    pub from_hash: String,
+    pub to_hash: String,
+    pub migrated: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+/// Update of a proxy account by `migrate_proxies` or `update`, once it finished.
+#[derive(Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProxyMigratedData {
+    pub address: String,
+    pub code_hash: String,
+    pub success: bool,
+}
+
+/// Drop claimed for an address.
+#[derive(Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimDropData {
+    pub address: String,
+    pub amount: U128,
+}
+
+/// Batch of `airdrop`: the hex encoded addresses whose proxy accounts are created, and the ones
+/// funded.
+#[derive(Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct AirdropData {
+    pub sender: AccountId,
+    pub amount_each: U128,
+    pub created: Vec<String>,
+    pub funded: Vec<String>,
+}
+
+/// Value executed in the epoch going over the circuit breaker's cap.
+#[derive(Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct CircuitBreakerData {
+    pub epoch: U64,
+    pub total: U128,
+}
+
+/// Management method of an account called by its message, see `self_call`.
+#[derive(Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct SelfCallData {
+    pub address: String,
+    pub method: String,
+}
+
+/// Deposit to the ledger balance of an address.
+#[derive(Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct DepositData {
+    pub address: String,
+    pub amount: U128,
+    pub sender: AccountId,
+}
+
+/// Withdrawal from the ledger balance of an address.
+#[derive(Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawData {
+    pub address: String,
+    pub amount: U128,
+    pub receiver: AccountId,
+}
+
+/// Subsidy a paymaster paid the relayer of an address' message.
+#[derive(Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct SponsorData {
+    pub paymaster: AccountId,
+    pub address: String,
+    pub relayer: AccountId,
+    pub amount: U128,
+}
+
+/// Message refused for calling a receiver or method on the denylist.
+#[derive(Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct PolicyViolationData {
+    pub address: String,
+    pub receiver: String,
+    pub method: String,
+}
+
+/// Value of an address' message attached by its relayer.
+#[derive(Serialize)]
+#[cfg_attr(feature = "abi-generate", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct RelayerDepositData {
+    pub address: String,
+    pub relayer: AccountId,
+    pub amount: U128,
+}
+
+/// Fails with the error unless the condition holds.
+fn ensure(condition: bool, error: GatewayError) -> Result<(), GatewayError> {
+    if condition {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+/// Fails with `ERR_NOT_ALLOWED` unless the gateway account itself is calling.
+fn check_gateway_caller() -> Result<(), GatewayError> {
+    ensure(
+        env::predecessor_account_id() == env::current_account_id(),
+        GatewayError::NotAllowed,
+    )
+}
+
+/// Parses hex encoded address, with or without `0x`, rejecting mixed case that doesn't match
+/// the EIP-55 checksum.
+fn parse_address(address: &str) -> Result<RawAddress, GatewayError> {
+    Ok(parse_checksum_address(address)?)
+}
+
+/// NEAR account the message's receiver names, failing with `ERR_INVALID_ACCOUNT_ID` if it isn't
+/// a valid account id.
+fn receiver_account_id(receiver: &str) -> Result<AccountId, GatewayError> {
+    receiver.parse().map_err(|_| GatewayError::InvalidAccountId)
+}
+
+/// Fails with `ERR_ADDRESS_CHECKSUM` if the receiver or the fee address of the message is a
+/// miscased `0x` address, which no account can have, likely a copy error.
+fn check_address_checksums(args: &InternalMetaCallArgs) -> Result<(), GatewayError> {
+    for account in [&args.contract_address, &args.fee_address].iter() {
+        if account.starts_with("0x")
+            && parse_checksum_address(account) == Err(AddressError::Checksum)
+        {
+            return Err(AddressError::Checksum.into());
+        }
+    }
+    Ok(())
+}
+
+/// Hex encoded address in JSON arguments, as transcoded from `address` type.
+fn deserialize_address<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RawAddress, D::Error> {
+    let address = <String as Deserialize>::deserialize(deserializer)?;
+    parse_checksum_address(&address).map_err(|error| de::Error::custom(GatewayError::from(error)))
+}
+
+fn deserialize_addresses<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<RawAddress>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(crate = "near_sdk::serde")]
+    struct HexAddress(#[serde(deserialize_with = "deserialize_address")] RawAddress);
+    let addresses = <Vec<HexAddress> as Deserialize>::deserialize(deserializer)?;
+    Ok(addresses.into_iter().map(|address| address.0).collect())
+}
+
+/// Decimal string in JSON arguments, as transcoded from `uint32` type.
+fn deserialize_u32<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    <String as Deserialize>::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+fn serialize_address<S: Serializer>(
+    address: &RawAddress,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&checksum_address(address))
+}
+
+fn serialize_addresses<S: Serializer>(
+    addresses: &[RawAddress],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(addresses.iter().map(checksum_address))
+}
+
+/// Storage key of the sender's sequence for the 2D nonce key, which fits in 192 bits.
+fn keyed_nonce_id(sender: &RawAddress, key: U256) -> (RawAddress, [u8; 24]) {
+    let mut id = [0u8; 24];
+    id.copy_from_slice(&u256_to_arr(&key)[8..]);
+    (*sender, id)
+}
+
+fn block_seconds() -> u64 {
+    env::block_timestamp() / 1_000_000_000
+}
+
+/// Fails with `ERR_UNEXPECTED_METHOD` unless the meta call is a call of the method on the gateway
+/// itself.
+fn check_gateway_call(args: &InternalMetaCallArgs, method_name: &str) -> Result<(), GatewayError> {
+    ensure(
+        args.method_name == method_name
+            && args.contract_address == env::current_account_id().as_str(),
+        GatewayError::UnexpectedMethod,
+    )
+}
+
+/// Arguments of the meta call of the method on the gateway itself.
+fn gateway_call_args<T>(args: &InternalMetaCallArgs, method_name: &str) -> Result<T, GatewayError>
+where
+    T: BorshDeserialize + for<'de> Deserialize<'de>,
+{
+    check_gateway_call(args, method_name)?;
+    call_args(args)
+}
+
+/// Arguments of the meta call, decoded in its call arguments format.
+fn call_args<T>(args: &InternalMetaCallArgs) -> Result<T, GatewayError>
+where
+    T: BorshDeserialize + for<'de> Deserialize<'de>,
+{
+    match args.call_args_format {
+        CallArgsFormat::Json => serde_json::from_slice(&args.args).ok(),
+        CallArgsFormat::Borsh => T::try_from_slice(&args.args).ok(),
+        CallArgsFormat::Raw => None,
+    }
+    .ok_or(GatewayError::InvalidArgs)
+}
+
+/// Values of the SIWE message's `near:<kind>:<value>` resources, in order.
+fn siwe_resources<'a>(siwe: &'a SiweMessage, kind: &str) -> Vec<&'a str> {
+    let prefix = format!("near:{}:", kind);
+    siwe.resources
+        .iter()
+        .filter_map(|resource| resource.strip_prefix(prefix.as_str()))
+        .collect()
+}
+
+/// Gas the proxy account gives the call of the method, `call` from the gas schedule unless the
+/// method needs more. Checks NEP-141 and NEP-171 transfers attach exactly the 1 yoctoNEAR they
+/// require, and NEP-178 `nft_approve` a deposit for the approval's storage.
+fn call_gas(method_name: &str, value: Balance, call: Gas) -> Result<Gas, GatewayError> {
+    Ok(match method_name {
+        "ft_transfer" | "nft_transfer" => {
+            ensure(value == 1, GatewayError::OneYoctoDeposit)?;
+            call
+        }
+        "ft_transfer_call" | "nft_transfer_call" => {
+            ensure(value == 1, GatewayError::OneYoctoDeposit)?;
+            call.max(GAS_FOR_TRANSFER_CALL)
+        }
+        "nft_approve" => {
+            ensure(value > 0, GatewayError::ApproveDeposit)?;
+            call.max(GAS_FOR_TRANSFER_CALL)
+        }
+        _ => call,
+    })
+}
+
+/// Gas left for the promises of the call, after keeping `GAS_FOR_PROXY` for the gateway.
+fn remaining_gas() -> Gas {
+    env::prepaid_gas() - env::used_gas() - GAS_FOR_PROXY
+}
+
+/// Gas the method called for the message gets, at most `available`. Messages declaring gas get
+/// it, others the schedule's `call` for the method plus `GAS_PER_ARGS_BYTE` for the arguments.
+fn method_gas(args: &InternalMetaCallArgs, call: Gas, available: Gas) -> Result<Gas, GatewayError> {
+    let budget =
+        call_gas(&args.method_name, args.value, call)? + GAS_PER_ARGS_BYTE * args.args.len() as u64;
+    let gas = if args.gas > 0 { Gas(args.gas) } else { budget };
+    Ok(gas.min(available))
+}
+
+/// Arguments of the proxy account's `transfer`: little endian amount followed by the receiver.
+fn transfer_args(amount: Balance, receiver_id: &str) -> Vec<u8> {
+    let mut args = amount.to_le_bytes().to_vec();
+    args.extend_from_slice(receiver_id.as_bytes());
+    args
+}
+
+/// yoctoNEAR the message takes from the account: the tip for the relayer and the value, unless
+/// the relayer attaches it.
+fn spent(args: &InternalMetaCallArgs) -> Balance {
+    match args.deposit_source {
+        DepositSource::Account => args.value.saturating_add(args.tip),
+        DepositSource::Relayer => args.tip,
+    }
+}
+
+/// Account of the address under the gateway, e.g. `<hex address>.gateway`.
+fn proxy_account_id(address: &[u8]) -> AccountId {
+    AccountId::new_unchecked(format!(
+        "{}.{}",
+        hex::encode(address),
+        env::current_account_id()
+    ))
+}
+
+/// Logs the gas used so far once the phase of handling a message is done, as `GAS_TRACE:`
+/// followed by JSON of the phase, the used and the prepaid gas, with the `gas-trace` feature.
+/// Phases of `proxy` are "parsing", "hashing", "recovery" and "promises", once they are built,
+/// so operators see where the gas goes when tuning `GAS_FOR_PROXY`.
+#[cfg(feature = "gas-trace")]
+fn trace_gas(phase: &str) {
+    let trace = json!({
+        "phase": phase,
+        "used_gas": U64(env::used_gas().0),
+        "prepaid_gas": U64(env::prepaid_gas().0),
+    });
+    env::log_str(&format!("GAS_TRACE:{}", trace));
+}
+
+#[cfg(not(feature = "gas-trace"))]
+fn trace_gas(_phase: &str) {}
+
+/// Signature of the Ethereum log of meta calls, whose keccak256 is the first topic.
+const META_CALL_EVENT: &str = "MetaCall(address,uint256,string,string,uint256)";
+
+/// ABI encoding of the strings and uint256s in order, as Solidity encodes event data.
+fn abi_encode(strings: &[&str], values: &[u128]) -> Vec<u8> {
+    let head_len = 32 * (strings.len() + values.len());
+    let mut head = vec![];
+    let mut tail = vec![];
+    for string in strings {
+        head.extend_from_slice(&u256_to_arr(&U256::from(head_len + tail.len())));
+        tail.extend_from_slice(&u256_to_arr(&U256::from(string.len())));
+        tail.extend_from_slice(string.as_bytes());
+        tail.extend(vec![0; (32 - string.len() % 32) % 32]);
+    }
+    for value in values {
+        head.extend_from_slice(&u256_to_arr(&U256::from(*value)));
+    }
+    head.extend(tail);
+    head
+}
+
+/// Logs the meta call shaped like the Ethereum log of
+/// `event MetaCall(address indexed sender, uint256 indexed nonce, string receiver, string method, uint256 value)`,
+/// as `EVM_LOG:` followed by JSON of its `address`, `topics` and `data`, so Ethereum indexers
+/// can follow the gateway. The address is the last 20 bytes of keccak256 of the gateway account.
+fn emit_eth_log(args: &InternalMetaCallArgs) {
+    let mut address = RawAddress::default();
+    address.copy_from_slice(&env::keccak256(env::current_account_id().as_bytes())[12..]);
+    let mut sender = [0u8; 32];
+    sender[12..].copy_from_slice(&args.sender.0);
+    let data = abi_encode(&[&args.contract_address, &args.method_name], &[args.value]);
+    let log = json!({
+        "address": checksum_address(&address),
+        "topics": [
+            format!("0x{}", hex::encode(env::keccak256(META_CALL_EVENT.as_bytes()))),
+            format!("0x{}", hex::encode(sender)),
+            format!("0x{}", hex::encode(u256_to_arr(&args.nonce))),
+        ],
+        "data": format!("0x{}", hex::encode(data)),
+    });
+    env::log_str(&format!("EVM_LOG:{}", log));
+}
+
+/// Callback of a `proxy_ledger` transfer or call or a withdrawal from the ledger, returning
+/// `amount` to the address' balance if it failed.
+fn on_ledger_callback(address: &RawAddress, amount: Balance) -> Promise {
+    Promise::new(env::current_account_id()).function_call(
+        "on_ledger_call".to_string(),
+        json!({
+            "address": checksum_address(address),
+            "amount": U128(amount),
+        })
+        .to_string()
+        .into_bytes(),
+        0,
+        GAS_FOR_ON_LEDGER_CALL,
+    )
+}
+
+/// Callback of a proxy account's `update` to the code hashed `code_hash`, recording it.
+fn on_migrate_callback(address: &RawAddress, code_hash: &str) -> Promise {
+    Promise::new(env::current_account_id()).function_call(
+        "on_migrate".to_string(),
+        json!({ "address": checksum_address(address), "code_hash": code_hash })
+            .to_string()
+            .into_bytes(),
+        0,
+        GAS_FOR_ON_MIGRATE,
+    )
+}
+
+/// Callback of the creation of the address' proxy account, refunding `refund` of the attached
+/// deposit to the caller if the account was created and `deposit` otherwise, returning
+/// `escrowed` funds to the escrow. Resolves to the `MessageOutcome` of the creating message
+/// with the digest, if any.
+fn on_create_callback(
+    address: &RawAddress,
+    digest: Option<&RawU256>,
+    deposit: Balance,
+    refund: Balance,
+    escrowed: Balance,
+    gas: Gas,
+) -> Promise {
+    Promise::new(env::current_account_id()).function_call(
+        "on_create".to_string(),
+        json!({
+            "address": checksum_address(address),
+            "digest": digest.map(|digest| format!("0x{}", hex::encode(digest))),
+            "caller": env::predecessor_account_id(),
+            "deposit": U128(deposit),
+            "refund": U128(refund),
+            "escrowed": U128(escrowed),
+        })
+        .to_string()
+        .into_bytes(),
+        0,
+        gas,
+    )
+}
+
+/// Callback of the promise executing the address' message with the digest from the account,
+/// resolving to its `MessageOutcome`.
+fn on_proxy_callback(address: &RawAddress, digest: &RawU256, account_id: &AccountId) -> Promise {
+    Promise::new(env::current_account_id()).function_call(
+        "on_proxy".to_string(),
+        json!({
+            "address": checksum_address(address),
+            "digest": format!("0x{}", hex::encode(digest)),
+            "account_id": account_id,
+        })
+        .to_string()
+        .into_bytes(),
+        0,
+        GAS_FOR_ON_PROXY,
+    )
+}
+
+/// Callback of the address' message sponsored by the paymaster, paying its subsidy to the
+/// calling relayer if the message executed and returning it to the paymaster otherwise.
+fn on_sponsored_callback(paymaster: &AccountId, address: &RawAddress, subsidy: Balance) -> Promise {
+    Promise::new(env::current_account_id()).function_call(
+        "on_sponsored".to_string(),
+        json!({
+            "paymaster": paymaster,
+            "address": checksum_address(address),
+            "relayer": env::predecessor_account_id(),
+            "subsidy": U128(subsidy),
+        })
+        .to_string()
+        .into_bytes(),
+        0,
+        GAS_FOR_ON_SPONSORED,
+    )
+}
+
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 struct CallArgs {
     gas: u64,
@@ -36,84 +1280,3421 @@ struct CallArgs {
     args: Vec<u8>,
 }
 
+/// Call from the proxy or linked account, as the gateway makes for meta calls.
+fn account_call(account_id: AccountId, call_args: CallArgs) -> Promise {
+    Promise::new(account_id).function_call(
+        "call".to_string(),
+        call_args.try_to_vec().unwrap(),
+        0,
+        remaining_gas(),
+    )
+}
+
 #[near_bindgen]
 impl Contract {
     #[init]
     pub fn new() -> Self {
         Self {
-            nonces: LookupMap::new(b"n".to_vec()),
+            nonces: UnorderedMap::new(b"acc".to_vec()),
+            legacy_nonces: LookupMap::new(b"n".to_vec()),
+            domain_separator: near_erc712_domain(U256::from(CHAIN_ID)),
+            method_defs: Vector::new(b"m".to_vec()),
+            method_def_ids: LookupMap::new(b"i".to_vec()),
+            create_deposit_buffer: DEFAULT_CREATE_DEPOSIT_BUFFER,
+            keyed_nonces: LookupMap::new(b"k".to_vec()),
+            created: LookupSet::new(b"c".to_vec()),
+            escrow: LookupMap::new(b"e".to_vec()),
+            used_salted_nonces: LookupMap::new(b"u".to_vec()),
+            salted_nonces_queue: LookupMap::new(b"q".to_vec()),
+            salted_nonces_head: 0,
+            salted_nonces_tail: 0,
+            spending_limits: LookupMap::new(b"l".to_vec()),
+            confirmers: LookupMap::new(b"cf".to_vec()),
+            allowances: UnorderedMap::new(b"a".to_vec()),
+            sessions: LookupMap::new(b"s".to_vec()),
+            controllers: LookupMap::new(b"o".to_vec()),
+            controlled: LookupMap::new(b"r".to_vec()),
+            guardians: LookupMap::new(b"g".to_vec()),
+            pending_rotations: LookupMap::new(b"p".to_vec()),
+            signer_sets: LookupMap::new(b"x".to_vec()),
+            linked_accounts: LookupMap::new(b"t".to_vec()),
+            ledger: LookupMap::new(b"b".to_vec()),
+            tokens: Vector::new(b"f".to_vec()),
+            token_allowances: LookupMap::new(b"v".to_vec()),
+            paymasters: LookupMap::new(b"y".to_vec()),
+            subsidies: LookupMap::new(b"z".to_vec()),
+            fee_tokens: LookupMap::new(b"h".to_vec()),
+            gas_station: 0,
+            pending_fees: LookupMap::new(b"j".to_vec()),
+            next_fee_id: 0,
+            gas_schedule: GasSchedule::default(),
+            factory: None,
+            factory_accounts: LookupMap::new(b"w".to_vec()),
+            implicit_accounts: LookupMap::new(b"d".to_vec()),
+            proxy_code: LazyOption::new(b"code".to_vec(), Some(&PROXY_CODE.to_vec())),
+            proxy_code_len: PROXY_CODE.len() as u64,
+            proxy_code_hash: Some(env::sha256(PROXY_CODE)),
+            proxy_templates: Vector::new(b"tmpl".to_vec()),
+            proxy_template_ids: LookupMap::new(b"tmpi".to_vec()),
+            proxy_template_codes: LookupMap::new(b"tmpc".to_vec()),
+            account_code_hashes: LookupMap::new(b"ach".to_vec()),
+            migration_opt_outs: LookupSet::new(b"mopt".to_vec()),
+            migrator: None,
+            drops: LookupMap::new(b"drop".to_vec()),
+            standalone_code: LazyOption::new(b"scode".to_vec(), None),
+            standalone_code_len: 0,
+            standalone_accounts: LookupSet::new(b"sa".to_vec()),
+            locks: LookupMap::new(b"lock".to_vec()),
+            denylist: Denylist::default(),
+            settings: LookupMap::new(b"set".to_vec()),
+            history: LookupMap::new(b"hist".to_vec()),
+            relayer_quota: None,
+            relayer_quotas: LookupMap::new(b"rq".to_vec()),
+            relayer_usage: LookupMap::new(b"ru".to_vec()),
+            circuit_breaker: None,
+            epoch_value: (0, 0),
+            eth_logs: false,
+            message_limits: MessageLimits::default(),
+            rotation_delay: DEFAULT_ROTATION_DELAY,
         }
     }
 
+    /// Migrates the state of the first gateway, built with near-sdk 3, after deploying this build
+    /// on top of it. Its nonces stay where they are as `legacy_nonces`, everything else starts as
+    /// in `new`. Fails with `ERR_NO_STATE` unless the state is the first gateway's, so deploy and
+    /// call it in one batch and the upgrade reverts otherwise. Only the gateway account can call
+    /// it.
+    #[private]
+    #[init(ignore_state)]
+    #[handle_result]
+    pub fn migrate() -> Result<Self, GatewayError> {
+        let state = env::storage_read(b"STATE").ok_or(GatewayError::NoState)?;
+        let legacy = LegacyContract::try_from_slice(&state).map_err(|_| GatewayError::NoState)?;
+        Ok(Self {
+            legacy_nonces: legacy.nonces,
+            ..Self::new()
+        })
+    }
+
     /// Parses given message into meta call arguments.
-    /// Asserts that all the information is correct, like chain_id, destination contract and nonce.
-    fn parse_message(&mut self, message: Base64VecU8) -> InternalMetaCallArgs {
-        let domain_separator = crate::meta_parsing::near_erc712_domain(U256::from(CHAIN_ID));
-        let args = crate::meta_parsing::parse_meta_call(
-            &domain_separator,
-            &env::current_account_id().into_bytes(),
-            message.0,
+    /// Checks that all the information is correct, like chain_id, destination contract and nonce.
+    /// Counts the message against the calling relayer's quota.
+    fn parse_message(
+        &mut self,
+        message: SignedMessage,
+    ) -> Result<InternalMetaCallArgs, GatewayError> {
+        self.count_submission()?;
+        let (args, _) = self.verify_message(message)?;
+        self.use_nonce(&args)?;
+        Ok(args)
+    }
+
+    /// Parses given message and verifies its signature, without checking the nonce.
+    /// Returns the meta call arguments and the signed digest.
+    fn verify_message(
+        &self,
+        message: SignedMessage,
+    ) -> Result<(InternalMetaCallArgs, RawU256), GatewayError> {
+        let meta_tx = self.check_message_limits(&message.0)?;
+        let (mut args, digest) = gateway_core::meta_parsing::verify_decoded_meta_call(
+            &self.domain_separator,
+            CHAIN_ID,
+            env::current_account_id().as_bytes(),
+            meta_tx,
+            |method_id| self.method_defs.get(u64::from(method_id)),
+            trace_gas,
+        )?;
+        check_address_checksums(&args)?;
+        self.check_signers(&args)?;
+        self.apply_settings(&mut args);
+        Ok((args, digest))
+    }
+
+    /// Decodes the message once it's checked to fit the limits: its length before decoding, so
+    /// oversized messages fail before their fields are allocated, then its method definition
+    /// and arguments. Messages that don't decode fail parsing.
+    fn check_message_limits(&self, message: &[u8]) -> Result<MetaCallArgs, GatewayError> {
+        let max_len = (self.message_limits.method as usize)
+            .saturating_add(self.message_limits.args as usize)
+            .saturating_add(MAX_MESSAGE_OVERHEAD);
+        ensure(message.len() <= max_len, GatewayError::MessageTooLarge)?;
+        let args = MetaCallArgs::try_from_slice(message)
+            .map_err(|_| meta_parsing::ParsingError::ArgumentParseError)?;
+        self.check_call_limits(args.method.len(), args.args.len())?;
+        Ok(args)
+    }
+
+    /// Checks sizes of the method definition and arguments of a call fit the limits.
+    fn check_call_limits(&self, method: usize, args: usize) -> Result<(), GatewayError> {
+        ensure(
+            method <= self.message_limits.method as usize,
+            GatewayError::MethodTooLarge,
+        )?;
+        ensure(
+            args <= self.message_limits.args as usize,
+            GatewayError::ArgsTooLarge,
         )
-        .expect("ERR_META_TX_PARSE");
-        let nonce = self
-            .nonces
-            .get(&args.sender.0)
-            .map(|value| U256::from(value))
-            .unwrap_or_default();
-        assert_eq!(args.nonce, nonce, "ERR_INCORRECT_NONCE");
-        self.nonces
-            .insert(&args.sender.0, &u256_to_arr(&(nonce + 1)));
-        args
     }
 
-    #[payable]
-    pub fn create(&mut self, message: Base64VecU8) -> Promise {
-        let args = self.parse_message(message);
-        let account_id = format!("{}.{}", hex::encode(args.sender), env::current_account_id());
-        Promise::new(account_id)
-            .create_account()
-            .deploy_contract(CODE.to_vec())
-            .transfer(env::attached_deposit())
-    }
-
-    pub fn proxy(&mut self, message: Base64VecU8) -> Promise {
-        let args = self.parse_message(message);
-        let account_id = format!("{}.{}", hex::encode(args.sender), env::current_account_id());
-        let used_gas = env::used_gas();
-        if args.method_name.is_empty() {
-            let mut transfer_args = vec![0u8; 16 + args.contract_address.len()];
-            transfer_args[..16].copy_from_slice(&args.value.to_le_bytes());
-            transfer_args[16..].copy_from_slice(args.contract_address.as_bytes());
-            Promise::new(account_id).function_call(
-                "transfer".as_bytes().to_vec(),
-                transfer_args,
-                0,
-                env::prepaid_gas() - used_gas - GAS_FOR_PROXY,
-            )
-        } else {
-            let call_args = CallArgs {
-                gas: TGAS * 20,
-                amount: args.value,
-                receiver_id: args.contract_address,
-                method_name: args.method_name,
-                args: args.args,
-            };
-            let call_args_bytes = call_args.try_to_vec().unwrap();
-            Promise::new(account_id).function_call(
-                "call".as_bytes().to_vec(),
-                call_args_bytes,
-                0,
-                env::prepaid_gas() - used_gas - GAS_FOR_PROXY,
-            )
+    /// Parses signed Ethereum transaction for the gateway and verifies it like `verify_message`
+    /// does messages. The digest is the transaction hash.
+    fn verify_eth_tx(&self, tx: &[u8]) -> Result<(InternalMetaCallArgs, RawU256), GatewayError> {
+        if let Some((method, args)) = gateway_core::meta_parsing::eth_transaction_call(tx) {
+            self.check_call_limits(method.len(), args.len())?;
         }
+        let mut args = gateway_core::meta_parsing::parse_eth_transaction(
+            &self.domain_separator,
+            env::current_account_id().as_bytes(),
+            tx,
+        )?;
+        check_address_checksums(&args)?;
+        self.check_signers(&args)?;
+        self.apply_settings(&mut args);
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&env::keccak256(tx));
+        Ok((args, digest))
     }
 
-    // pub fn update(&self, message: Base64VecU8) -> Promise {
-    //     Promise::new(account_id).function_call("update", )
-    // }
+    /// Fills in the fields the message omits from the signer's settings.
+    fn apply_settings(&self, args: &mut InternalMetaCallArgs) {
+        let settings = match self.settings.get(&args.sender.0) {
+            Some(settings) => settings,
+            None => return,
+        };
+        if args.gas == 0 {
+            args.gas = settings.gas.0;
+        }
+        if args.fee_address.is_empty() {
+            args.fee_address = settings.fee_token.unwrap_or_default();
+        }
+    }
 
-    // TODO: just for test purposes
-    pub fn test_call(&self, x: u64, y: String) -> u64 {
-        x + y.len() as u64
+    /// Longest time the sender's salted nonces may be valid for, at most
+    /// `MAX_SALTED_NONCE_LIFETIME`.
+    fn max_nonce_lifetime(&self, sender: &RawAddress) -> u64 {
+        match self.settings.get(sender) {
+            Some(settings) if settings.max_nonce_lifetime.0 > 0 => {
+                settings.max_nonce_lifetime.0.min(MAX_SALTED_NONCE_LIFETIME)
+            }
+            _ => MAX_SALTED_NONCE_LIFETIME,
+        }
+    }
+
+    /// Checks multi-signature messages are signed by enough distinct members of the signer set
+    /// of their sender's account, and that accounts with a signer set only take such messages.
+    /// Signer sets are kept by account address, which a controller signs for after a rotation.
+    fn check_signers(&self, args: &InternalMetaCallArgs) -> Result<(), GatewayError> {
+        let account = self.controlled.get(&args.sender.0).unwrap_or(args.sender.0);
+        let signer_set = match self.signer_sets.get(&account) {
+            Some(signer_set) => signer_set,
+            None if args.signers.is_empty() => return Ok(()),
+            None => return Err(GatewayError::NoSignerSet),
+        };
+        ensure(!args.signers.is_empty(), GatewayError::MultisigRequired)?;
+        let mut approvals: Vec<RawAddress> = vec![];
+        for signer in &args.signers {
+            ensure(
+                signer_set.signers.contains(&signer.0),
+                GatewayError::NotSigner,
+            )?;
+            if !approvals.contains(&signer.0) {
+                approvals.push(signer.0);
+            }
+        }
+        ensure(
+            approvals.len() >= signer_set.threshold as usize,
+            GatewayError::NotEnoughSigners,
+        )
+    }
+
+    /// Address of the proxy account the signer controls: its own unless control over another
+    /// account was rotated to it. Fails with `ERR_NOT_CONTROLLER` if the signer's own account
+    /// was rotated away.
+    fn account_of(&self, signer: &RawAddress) -> Result<RawAddress, GatewayError> {
+        if let Some(account) = self.controlled.get(signer) {
+            return Ok(account);
+        }
+        ensure(
+            !self.controllers.contains_key(signer),
+            GatewayError::NotController,
+        )?;
+        Ok(*signer)
+    }
+
+    /// Account the gateway acts from for the address. Fails for implicit accounts, which only
+    /// their own key controls.
+    fn acting_account_id(&self, address: &RawAddress) -> Result<AccountId, GatewayError> {
+        ensure(
+            !self.implicit_accounts.contains_key(address),
+            GatewayError::ImplicitAccount,
+        )?;
+        ensure(
+            !self.standalone_accounts.contains(address),
+            GatewayError::StandaloneAccount,
+        )?;
+        ensure(
+            !self.locks.contains_key(address),
+            GatewayError::AccountLocked,
+        )?;
+        Ok(self.account_id_of(address))
+    }
+
+    /// NEAR account acting for the address: the account linked to it, the factory or implicit
+    /// account created for it, otherwise its proxy account.
+    fn account_id_of(&self, address: &RawAddress) -> AccountId {
+        self.linked_accounts
+            .get(address)
+            .or_else(|| self.factory_accounts.get(address))
+            .or_else(|| self.implicit_accounts.get(address))
+            .unwrap_or_else(|| proxy_account_id(address))
+    }
+
+    /// Checks the meta call has the nonce expected from its sender for the key of the nonce
+    /// and moves on to the next one in that key. Salted nonce must instead be unexpired and
+    /// not used before.
+    fn use_nonce(&mut self, args: &InternalMetaCallArgs) -> Result<(), GatewayError> {
+        if is_salted_nonce(args.nonce) {
+            return self.use_salted_nonce(&args.sender.0, args.nonce);
+        }
+        let key = nonce_key(args.nonce);
+        let nonce = self.current_nonce(&args.sender.0, key);
+        ensure(args.nonce == nonce, GatewayError::IncorrectNonce)?;
+        self.set_used_nonce(&args.sender.0, nonce)
+    }
+
+    /// Counts a message submitted by the calling relayer in this epoch, failing with
+    /// `ERR_RATE_LIMITED` once it's over its quota. The gateway's own calls aren't counted.
+    fn count_submission(&mut self) -> Result<(), GatewayError> {
+        let relayer = env::predecessor_account_id();
+        let (submitted, quota) = match self.relayer_submissions(&relayer) {
+            Some(submissions) => submissions,
+            None => return Ok(()),
+        };
+        ensure(submitted < quota, GatewayError::RateLimited)?;
+        self.relayer_usage
+            .insert(&relayer, &(env::epoch_height(), submitted + 1));
+        Ok(())
+    }
+
+    /// Messages the relayer submitted in this epoch along with its quota, none if it has no
+    /// quota or is the gateway itself.
+    fn relayer_submissions(&self, relayer: &AccountId) -> Option<(u64, u64)> {
+        if *relayer == env::current_account_id() {
+            return None;
+        }
+        let quota = self.relayer_quotas.get(relayer).or(self.relayer_quota)?;
+        Some((self.get_relayer_usage(relayer.clone()).0, quota))
+    }
+
+    /// Verifies the SIWE message is signed for the gateway's domain and chain, and uses its
+    /// nonce: it's remembered like a salted nonce of the signer until the message expires,
+    /// which it must within the salted nonce lifetime. Returns the message and the account of
+    /// the signer.
+    fn use_siwe(
+        &mut self,
+        message: &str,
+        signature: &[u8],
+    ) -> Result<(SiweMessage, RawAddress), GatewayError> {
+        let siwe = gateway_core::siwe::verify_siwe(
+            message,
+            signature,
+            env::current_account_id().as_str(),
+            CHAIN_ID,
+            block_seconds(),
+        )?;
+        let expires_at = siwe
+            .expiration_time
+            .ok_or(GatewayError::SiweExpirationRequired)?;
+        let signer = siwe.address.0;
+        // Multi-signature accounts only act through messages of their signers.
+        ensure(
+            !self.signer_sets.contains_key(&signer),
+            GatewayError::MultisigRequired,
+        )?;
+        let salt = U256::from_big_endian(&env::keccak256(siwe.nonce.as_bytes()));
+        self.use_salted_nonce(&signer, salted_nonce(salt, expires_at))?;
+        let account = self.account_of(&signer)?;
+        Ok((siwe, account))
+    }
+
+    /// Sets the address' key 0 nonce, moving it out of `legacy_nonces` if it was still there.
+    fn set_sequential_nonce(
+        &mut self,
+        address: &RawAddress,
+        nonce: U256,
+    ) -> Result<(), GatewayError> {
+        ensure(nonce.bits() <= 64, GatewayError::NonceOverflow)?;
+        self.nonces.insert(address, &nonce.as_u64());
+        self.legacy_nonces.remove(address);
+        Ok(())
+    }
+
+    /// Moves the sequence of the nonce's key past the nonce.
+    fn set_used_nonce(&mut self, sender: &RawAddress, nonce: U256) -> Result<(), GatewayError> {
+        let key = nonce_key(nonce);
+        let sequence = nonce_sequence(nonce)
+            .checked_add(1)
+            .ok_or(GatewayError::NonceOverflow)?;
+        if key.is_zero() {
+            self.set_sequential_nonce(sender, U256::from(sequence))?;
+        } else {
+            self.keyed_nonces
+                .insert(&keyed_nonce_id(sender, key), &sequence);
+        }
+        Ok(())
+    }
+
+    fn use_salted_nonce(&mut self, sender: &RawAddress, nonce: U256) -> Result<(), GatewayError> {
+        let now = block_seconds();
+        let expires_at = nonce_sequence(nonce);
+        ensure(expires_at > now, GatewayError::NonceExpired)?;
+        ensure(
+            expires_at - now <= self.max_nonce_lifetime(sender),
+            GatewayError::NonceExpiryTooFar,
+        )?;
+        self.prune_salted_nonces(now, SALTED_NONCES_PRUNED_PER_USE)?;
+        let id = (*sender, u256_to_arr(&nonce));
+        ensure(
+            self.used_salted_nonces.insert(&id, &expires_at).is_none(),
+            GatewayError::NonceUsed,
+        )?;
+        self.salted_nonces_queue
+            .insert(&self.salted_nonces_tail, &id);
+        self.salted_nonces_tail += 1;
+        Ok(())
+    }
+
+    /// Forgets up to `limit` of the oldest used salted nonces that have expired, they can't be
+    /// replayed anymore. Stops at the first one still valid.
+    fn prune_salted_nonces(&mut self, now: u64, limit: u64) -> Result<u64, GatewayError> {
+        let mut pruned = 0;
+        while pruned < limit && self.salted_nonces_head < self.salted_nonces_tail {
+            let id = self
+                .salted_nonces_queue
+                .get(&self.salted_nonces_head)
+                .ok_or(GatewayError::SaltedNoncesQueue)?;
+            if self.used_salted_nonces.get(&id).unwrap_or_default() > now {
+                break;
+            }
+            self.used_salted_nonces.remove(&id);
+            self.salted_nonces_queue.remove(&self.salted_nonces_head);
+            self.salted_nonces_head += 1;
+            pruned += 1;
+        }
+        Ok(pruned)
+    }
+
+    /// Frees the storage of up to `limit` expired salted nonces, returns how many were pruned.
+    /// Using salted nonces prunes a few already, this catches up after bursts.
+    #[handle_result]
+    pub fn prune_expired_nonces(&mut self, limit: u64) -> Result<u64, GatewayError> {
+        self.prune_salted_nonces(block_seconds(), limit)
+    }
+
+    /// Nonce the gateway expects from the sender for the message with the given nonce: the next
+    /// one under its key, or for salted nonce the nonce itself while it's usable and 0 otherwise.
+    fn expected_nonce(&self, sender: &RawAddress, nonce: U256) -> U256 {
+        if !is_salted_nonce(nonce) {
+            return self.current_nonce(sender, nonce_key(nonce));
+        }
+        let now = block_seconds();
+        let expires_at = nonce_sequence(nonce);
+        let used = self
+            .used_salted_nonces
+            .get(&(*sender, u256_to_arr(&nonce)))
+            .is_some();
+        if used || expires_at <= now || expires_at - now > self.max_nonce_lifetime(sender) {
+            U256::zero()
+        } else {
+            nonce
+        }
+    }
+
+    fn current_nonce(&self, sender: &RawAddress, key: U256) -> U256 {
+        if key.is_zero() {
+            match self.nonces.get(sender) {
+                Some(nonce) => U256::from(nonce),
+                None => self
+                    .legacy_nonces
+                    .get(sender)
+                    .map_or_else(U256::zero, |nonce| U256::from_big_endian(&nonce)),
+            }
+        } else {
+            let sequence = self
+                .keyed_nonces
+                .get(&keyed_nonce_id(sender, key))
+                .unwrap_or_default();
+            make_nonce(key, sequence)
+        }
+    }
+
+    /// Returns the nonce the next meta call from the hex encoded address must have, for the
+    /// given decimal nonce key or the plain sequential nonce without it.
+    #[handle_result]
+    pub fn get_nonce(&self, address: String, key: Option<String>) -> Result<String, GatewayError> {
+        let key = match key {
+            Some(key) => {
+                let key = U256::from_dec_str(&key).map_err(|_| GatewayError::InvalidNonceKey)?;
+                // Keys with the top bit set are salted, they have no sequence.
+                ensure(key.bits() <= 191, GatewayError::InvalidNonceKey)?;
+                key
+            }
+            None => U256::zero(),
+        };
+        Ok(self
+            .current_nonce(&parse_address(&address)?, key)
+            .to_string())
+    }
+
+    /// Hex encoded addresses that used the gateway, `limit` of them starting at `from_index`.
+    /// Addresses that only used the first gateway, before the registry, aren't listed until they
+    /// use a nonce again.
+    pub fn get_accounts(&self, from_index: u64, limit: u64) -> Vec<String> {
+        let keys = self.nonces.keys_as_vector();
+        (from_index..std::cmp::min(from_index.saturating_add(limit), keys.len()))
+            .filter_map(|index| keys.get(index))
+            .map(|address| checksum_address(&address))
+            .collect()
+    }
+
+    /// Number of addresses that used the gateway.
+    pub fn get_num_accounts(&self) -> U64 {
+        U64(self.nonces.len())
+    }
+
+    /// Page of the state to import into a new gateway: `limit` of the accounts with their nonces
+    /// and settings, and `limit` of the allowances, both starting at `from_index`. Pages are
+    /// exported until both lists come back empty.
+    pub fn export_state(&self, from_index: u64, limit: u64) -> StateSnapshot {
+        let to_index = from_index.saturating_add(limit);
+        let addresses = self.nonces.keys_as_vector();
+        let accounts = (from_index..std::cmp::min(to_index, addresses.len()))
+            .filter_map(|index| addresses.get(index))
+            .map(|address| AccountSnapshot {
+                address: checksum_address(&address),
+                nonce: self.current_nonce(&address, U256::zero()).to_string(),
+                settings: self.settings.get(&address),
+            })
+            .collect();
+        let ids = self.allowances.keys_as_vector();
+        let allowances = (from_index..std::cmp::min(to_index, ids.len()))
+            .filter_map(|index| ids.get(index))
+            .filter_map(|(owner, spender)| {
+                Some(AllowanceSnapshot {
+                    owner: checksum_address(&owner),
+                    spender: checksum_address(&spender),
+                    allowance: self.allowances.get(&(owner, spender))?,
+                })
+            })
+            .collect();
+        StateSnapshot {
+            accounts,
+            allowances,
+        }
+    }
+
+    /// Imports a page exported by `export_state` of another gateway. Nonces never go back, so
+    /// messages executed by either gateway can't be replayed; settings and allowances replace the
+    /// existing ones. Only the gateway account can call it.
+    #[handle_result]
+    pub fn import_state(&mut self, snapshot: StateSnapshot) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        for account in &snapshot.accounts {
+            let address = parse_address(&account.address)?;
+            let nonce =
+                U256::from_dec_str(&account.nonce).map_err(|_| GatewayError::InvalidNonce)?;
+            ensure(nonce.bits() <= 64, GatewayError::InvalidNonce)?;
+            let nonce = std::cmp::max(nonce, self.current_nonce(&address, U256::zero()));
+            self.set_sequential_nonce(&address, nonce)?;
+            if let Some(settings) = &account.settings {
+                self.settings.insert(&address, settings);
+            }
+        }
+        for allowance in &snapshot.allowances {
+            self.allowances.insert(
+                &(
+                    parse_address(&allowance.owner)?,
+                    parse_address(&allowance.spender)?,
+                ),
+                &allowance.allowance,
+            );
+        }
+        GatewayEvent::ImportState(vec![ImportStateData {
+            accounts: snapshot.accounts.len() as u64,
+            allowances: snapshot.allowances.len() as u64,
+        }])
+        .emit();
+        Ok(())
+    }
+
+    /// Sequential nonces under key 0 of the hex encoded addresses, in the same order, so relayers
+    /// can refresh many senders in one view call.
+    #[handle_result]
+    pub fn get_nonces(&self, addresses: Vec<String>) -> Result<Vec<String>, GatewayError> {
+        addresses
+            .iter()
+            .map(|address| {
+                Ok(self
+                    .current_nonce(&parse_address(address)?, U256::zero())
+                    .to_string())
+            })
+            .collect()
+    }
+
+    /// Registers method definition, so meta calls can reference it by the returned id instead of
+    /// sending and parsing it every time. Registering the same definition again returns its id.
+    /// Attached deposit must cover the storage used, the rest is refunded.
+    #[payable]
+    #[handle_result]
+    pub fn register_method_def(&mut self, method_def: String) -> Result<u32, GatewayError> {
+        let initial_storage_usage = env::storage_usage();
+        let method_id = match self.method_def_ids.get(&method_def) {
+            Some(method_id) => method_id,
+            None => {
+                let method = PreparedMethod::parse(&method_def)?;
+                let method_id = self.method_defs.len() as u32;
+                self.method_defs.push(&method);
+                self.method_def_ids.insert(&method_def, &method_id);
+                method_id
+            }
+        };
+        let storage_cost =
+            Balance::from(env::storage_usage() - initial_storage_usage) * env::storage_byte_cost();
+        ensure(
+            env::attached_deposit() >= storage_cost,
+            GatewayError::NotEnoughDeposit,
+        )?;
+        let refund = env::attached_deposit() - storage_cost;
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+        Ok(method_id)
+    }
+
+    /// Returns the method definition registered with given id.
+    pub fn get_method_def(&self, method_id: u32) -> Option<String> {
+        self.method_defs
+            .get(u64::from(method_id))
+            .map(|method| method.method_def)
+    }
+
+    /// Deposit `create` requires: storage of the proxy code and a buffer for the account itself.
+    pub fn required_create_deposit(&self) -> U128 {
+        U128(
+            Balance::from(self.proxy_code_len) * env::storage_byte_cost()
+                + self.create_deposit_buffer,
+        )
+    }
+
+    /// Sets the code deployed on proxy accounts created from now on. Existing accounts keep
+    /// theirs. Only the gateway account can call it.
+    #[handle_result]
+    pub fn set_proxy_code(&mut self, code: Base64VecU8) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        self.proxy_code_len = code.0.len() as u64;
+        self.proxy_code_hash = Some(env::sha256(&code.0));
+        self.proxy_code.set(&code.0);
+        Ok(())
+    }
+
+    /// Hex encoded sha256 of the code deployed on new proxy accounts. Unless the gateway account
+    /// set other code it's the hash of the proxy built with the gateway.
+    pub fn proxy_code_hash(&self) -> Option<String> {
+        self.proxy_code_hash.as_ref().map(hex::encode)
+    }
+
+    /// Registers the named proxy code template, or replaces its code. Accounts created with it
+    /// before keep their code. Only the gateway account can call it.
+    #[handle_result]
+    pub fn set_proxy_template(
+        &mut self,
+        name: String,
+        code: Base64VecU8,
+    ) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        let template = ProxyTemplate {
+            name: name.clone(),
+            code_hash: hex::encode(env::sha256(&code.0)),
+            code_len: U64(code.0.len() as u64),
+        };
+        let template_id = match self.proxy_template_ids.get(&name) {
+            Some(template_id) => {
+                self.proxy_templates
+                    .replace(u64::from(template_id), &template);
+                template_id
+            }
+            None => {
+                let template_id = self.proxy_templates.len() as u32;
+                self.proxy_templates.push(&template);
+                self.proxy_template_ids.insert(&name, &template_id);
+                template_id
+            }
+        };
+        self.proxy_template_codes.insert(&template_id, &code.0);
+        Ok(())
+    }
+
+    /// Proxy code templates the signed `create(string template)` can select, in order of
+    /// registration.
+    pub fn get_proxy_templates(&self) -> Vec<ProxyTemplate> {
+        self.proxy_templates.to_vec()
+    }
+
+    /// Deposit `create` requires with the named template, like `required_create_deposit`.
+    #[handle_result]
+    pub fn required_template_deposit(&self, template: String) -> Result<U128, GatewayError> {
+        Ok(U128(
+            self.template_deposit(self.proxy_template_id(&template)?),
+        ))
+    }
+
+    fn proxy_template_id(&self, template: &str) -> Result<u32, GatewayError> {
+        self.proxy_template_ids
+            .get(&template.to_string())
+            .ok_or(GatewayError::UnknownTemplate)
+    }
+
+    fn template_deposit(&self, template_id: u32) -> Balance {
+        let template = self.proxy_templates.get(u64::from(template_id)).unwrap();
+        Balance::from(template.code_len.0) * env::storage_byte_cost() + self.create_deposit_buffer
+    }
+
+    /// Whether the address' account is a proxy account running code the gateway deployed.
+    fn is_gateway_proxy(&self, address: &RawAddress) -> bool {
+        self.created.contains(address)
+            && !self.factory_accounts.contains_key(address)
+            && !self.implicit_accounts.contains_key(address)
+            && !self.standalone_accounts.contains(address)
+    }
+
+    /// Code hashed by the hex encoded `code_hash`: the proxy code or a template's.
+    fn code_of_hash(&self, code_hash: &str) -> Option<Vec<u8>> {
+        if self.proxy_code_hash().as_deref() == Some(code_hash) {
+            return self.proxy_code.get();
+        }
+        let template_id = self
+            .proxy_templates
+            .iter()
+            .position(|template| template.code_hash == code_hash)?;
+        self.proxy_template_codes.get(&(template_id as u32))
+    }
+
+    /// Deploys the code hashed `to_hash`, the proxy code or a template's, on the proxy accounts
+    /// of the hex encoded addresses running the code hashed `from_hash`, through their `update`.
+    /// Accounts created before their code was tracked are taken to run `from_hash`. Skips
+    /// accounts that opted out, run other code, or weren't created by the gateway itself. Handles
+    /// at most `MAX_MIGRATIONS_PER_CALL` addresses and as many as the prepaid gas allows, and
+    /// returns how many, so the caller continues with the rest. Logs a NEP-297
+    /// `migrate_proxies` event of the batch and a `proxy_migrated` event once each update
+    /// finishes. Only the gateway account and the migrator can call it.
+    #[handle_result]
+    pub fn migrate_proxies(
+        &mut self,
+        from_hash: String,
+        to_hash: String,
+        accounts: Vec<String>,
+    ) -> Result<U64, GatewayError> {
+        let predecessor = env::predecessor_account_id();
+        ensure(
+            predecessor == env::current_account_id()
+                || self.migrator.as_ref() == Some(&predecessor),
+            GatewayError::NotAllowed,
+        )?;
+        let code = self
+            .code_of_hash(&to_hash)
+            .ok_or(GatewayError::UnknownCode)?;
+        let update_gas = Gas(self.gas_schedule.update.0);
+        let mut handled = 0;
+        let mut migrated = vec![];
+        let mut skipped = vec![];
+        for account in accounts.iter().take(MAX_MIGRATIONS_PER_CALL) {
+            if remaining_gas() < update_gas + GAS_FOR_ON_MIGRATE {
+                break;
+            }
+            handled += 1;
+            let address = parse_address(account)?;
+            let code_hash = self
+                .account_code_hashes
+                .get(&address)
+                .unwrap_or_else(|| from_hash.clone());
+            if !self.is_gateway_proxy(&address)
+                || self.migration_opt_outs.contains(&address)
+                || code_hash != from_hash
+            {
+                skipped.push(checksum_address(&address));
+                continue;
+            }
+            Promise::new(proxy_account_id(&address))
+                .function_call("update".to_string(), code.clone(), 0, update_gas)
+                .then(on_migrate_callback(&address, &to_hash));
+            migrated.push(checksum_address(&address));
+        }
+        GatewayEvent::MigrateProxies(vec![MigrateProxiesData {
+            from_hash,
+            to_hash,
+            migrated,
+            skipped,
+        }])
+        .emit();
+        Ok(U64(handled))
+    }
+
+    /// Records the code deployed by `migrate_proxies` on the proxy account of the hex encoded
+    /// address, if the update succeeded.
+    #[private]
+    #[handle_result]
+    pub fn on_migrate(&mut self, address: String, code_hash: String) -> Result<(), GatewayError> {
+        let address = parse_address(&address)?;
+        let success = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        if success {
+            self.account_code_hashes.insert(&address, &code_hash);
+        }
+        GatewayEvent::ProxyMigrated(vec![ProxyMigratedData {
+            address: checksum_address(&address),
+            code_hash,
+            success,
+        }])
+        .emit();
+        Ok(())
+    }
+
+    /// Hex encoded sha256 of the code on the proxy account of the hex encoded address, None if
+    /// the gateway didn't deploy it or created the account before tracking its code.
+    #[handle_result]
+    pub fn get_account_code_hash(&self, address: String) -> Result<Option<String>, GatewayError> {
+        Ok(self.account_code_hashes.get(&parse_address(&address)?))
+    }
+
+    /// Sets whether `migrate_proxies` leaves the signer's proxy account alone, from the message
+    /// calling `set_migration_opt_out(bool opt_out)` on the gateway.
+    #[handle_result]
+    pub fn set_migration_opt_out(&mut self, message: SignedMessage) -> Result<(), GatewayError> {
+        let args = self.parse_message(message)?;
+        let opt_out: MigrationOptOutArgs = gateway_call_args(&args, "set_migration_opt_out")?;
+        let account = self.account_of(&args.sender.0)?;
+        if opt_out.opt_out {
+            self.migration_opt_outs.insert(&account);
+        } else {
+            self.migration_opt_outs.remove(&account);
+        }
+        Ok(())
+    }
+
+    /// Whether the proxy account of the hex encoded address opted out of migrations.
+    #[handle_result]
+    pub fn is_migration_opt_out(&self, address: String) -> Result<bool, GatewayError> {
+        Ok(self.migration_opt_outs.contains(&parse_address(&address)?))
+    }
+
+    /// Sets the account that may call `migrate_proxies` besides the gateway account, or none.
+    /// Only the gateway account can call it.
+    #[handle_result]
+    pub fn set_migrator(&mut self, migrator: Option<AccountId>) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        self.migrator = migrator;
+        Ok(())
+    }
+
+    /// Account that may call `migrate_proxies` besides the gateway account.
+    pub fn get_migrator(&self) -> Option<AccountId> {
+        self.migrator.clone()
+    }
+
+    /// Sets the buffer `required_create_deposit` adds to the code storage. Only the gateway
+    /// account can call it.
+    #[handle_result]
+    pub fn set_create_deposit_buffer(&mut self, buffer: U128) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        self.create_deposit_buffer = buffer.0;
+        Ok(())
+    }
+
+    /// Starts creating the proxy account of the address with the code of the template, or the
+    /// proxy code, funded with `amount` and the funds escrowed for it, which are returned along
+    /// with the promise. Fails with `ERR_ALREADY_CREATED` if it's created or being created,
+    /// before any nonce is consumed.
+    fn create_proxy_account(
+        &mut self,
+        address: &RawAddress,
+        amount: Balance,
+        template_id: Option<u32>,
+    ) -> Result<(Promise, Balance), GatewayError> {
+        // An address controlling another account can't have its own.
+        ensure(
+            !self.controlled.contains_key(address) && self.created.insert(address),
+            GatewayError::AlreadyCreated,
+        )?;
+        ensure(
+            !self.linked_accounts.contains_key(address),
+            GatewayError::AlreadyLinked,
+        )?;
+        let escrowed = self.escrow.remove(address).unwrap_or_default();
+        let promise = match &self.factory {
+            Some(factory) => {
+                // The factory deploys its own code.
+                ensure(template_id.is_none(), GatewayError::FactoryTemplate)?;
+                let account_id =
+                    AccountId::new_unchecked(format!("{}.{}", hex::encode(address), factory));
+                self.factory_accounts.insert(address, &account_id);
+                Promise::new(factory.clone()).function_call(
+                    "create_proxy".to_string(),
+                    json!({ "name": hex::encode(address) })
+                        .to_string()
+                        .into_bytes(),
+                    amount + escrowed,
+                    GAS_FOR_FACTORY_CREATE,
+                )
+            }
+            None => {
+                let (code, code_hash) = match template_id {
+                    Some(template_id) => (
+                        self.proxy_template_codes.get(&template_id).unwrap(),
+                        self.proxy_templates
+                            .get(u64::from(template_id))
+                            .unwrap()
+                            .code_hash,
+                    ),
+                    None => (
+                        self.proxy_code.get().ok_or(GatewayError::NoProxyCode)?,
+                        self.proxy_code_hash().unwrap(),
+                    ),
+                };
+                self.account_code_hashes.insert(address, &code_hash);
+                Promise::new(proxy_account_id(address))
+                    .create_account()
+                    .deploy_contract(code)
+                    .transfer(amount + escrowed)
+            }
+        };
+        Ok((promise, escrowed))
+    }
+
+    /// Sends the attached deposit to the proxy account of the hex encoded address. Until the
+    /// account is created the gateway holds the funds, which go to the account on creation.
+    #[payable]
+    #[handle_result]
+    pub fn deposit_for(&mut self, address: String) -> Result<(), GatewayError> {
+        let address = parse_address(&address)?;
+        if self.created.contains(&address) || self.linked_accounts.contains_key(&address) {
+            Promise::new(self.account_id_of(&address)).transfer(env::attached_deposit());
+        } else {
+            let escrowed = self.escrow.get(&address).unwrap_or_default();
+            self.escrow
+                .insert(&address, &(escrowed + env::attached_deposit()));
+        }
+        Ok(())
+    }
+
+    /// Funds held for the hex encoded address until its proxy account is created.
+    #[handle_result]
+    pub fn get_escrow(&self, address: String) -> Result<U128, GatewayError> {
+        Ok(U128(
+            self.escrow
+                .get(&parse_address(&address)?)
+                .unwrap_or_default(),
+        ))
+    }
+
+    /// Whether the proxy account of the hex encoded address is created, or being created.
+    #[handle_result]
+    pub fn is_created(&self, address: String) -> Result<bool, GatewayError> {
+        Ok(self.created.contains(&parse_address(&address)?))
+    }
+
+    /// Funds a claim link: the attached deposit, less `DROP_KEY_ALLOWANCE`, goes to the proxy
+    /// account of whichever address the holder of the public key's secret names through
+    /// `claim_drop`. Adds the key to the gateway as an access key only calling `claim_drop`.
+    #[payable]
+    #[handle_result]
+    pub fn send_drop(&mut self, public_key: PublicKey) -> Result<Promise, GatewayError> {
+        ensure(
+            env::attached_deposit() > DROP_KEY_ALLOWANCE,
+            GatewayError::NotEnoughDeposit,
+        )?;
+        let amount = env::attached_deposit() - DROP_KEY_ALLOWANCE;
+        ensure(
+            self.drops
+                .insert(&public_key.as_bytes().to_vec(), &amount)
+                .is_none(),
+            GatewayError::DropExists,
+        )?;
+        Ok(Promise::new(env::current_account_id()).add_access_key(
+            public_key,
+            DROP_KEY_ALLOWANCE,
+            env::current_account_id(),
+            DROP_KEY_METHODS.to_string(),
+        ))
+    }
+
+    /// Claims the drop of the key signing the transaction for the hex encoded address, so the
+    /// claim link's holder onboards their Ethereum wallet. Creates the address' proxy account
+    /// with the drop if it covers `required_create_deposit`, otherwise sends it to the account
+    /// acting for the address or escrows it until the account is created. Deletes the key.
+    #[handle_result]
+    pub fn claim_drop(&mut self, address: String) -> Result<Promise, GatewayError> {
+        check_gateway_caller()?;
+        let public_key = env::signer_account_pk();
+        let amount = self
+            .drops
+            .remove(&public_key.as_bytes().to_vec())
+            .ok_or(GatewayError::NoDrop)?;
+        let address = parse_address(&address)?;
+        GatewayEvent::ClaimDrop(vec![ClaimDropData {
+            address: checksum_address(&address),
+            amount: U128(amount),
+        }])
+        .emit();
+        let delete_key = Promise::new(env::current_account_id()).delete_key(public_key);
+        if self.created.contains(&address) || self.linked_accounts.contains_key(&address) {
+            return Ok(delete_key.and(Promise::new(self.account_id_of(&address)).transfer(amount)));
+        }
+        if amount < self.required_create_deposit().0 {
+            let escrowed = self.escrow.get(&address).unwrap_or_default();
+            self.escrow.insert(&address, &(escrowed + amount));
+            return Ok(delete_key);
+        }
+        // If creation fails the drop stays escrowed for the address.
+        let (promise, escrowed) = self.create_proxy_account(&address, amount, None)?;
+        Ok(delete_key.and(promise.then(on_create_callback(
+            &address,
+            None,
+            0,
+            0,
+            escrowed + amount,
+            Gas(self.gas_schedule.create.0),
+        ))))
+    }
+
+    /// NEAR claimable with the base58 encoded public key's drop.
+    pub fn get_drop(&self, public_key: PublicKey) -> U128 {
+        U128(self.drops.get(&public_key.into_bytes()).unwrap_or_default())
+    }
+
+    /// Creates the signer's proxy account with `required_create_deposit` and the funds escrowed for
+    /// it, the rest of the attached deposit is refunded once the account is created. Messages
+    /// calling `create(string template)` on the gateway deploy the named template's code instead,
+    /// with its `required_template_deposit`.
+    #[payable]
+    #[handle_result]
+    pub fn create(&mut self, message: SignedMessage) -> Result<Promise, GatewayError> {
+        self.count_submission()?;
+        let (args, digest) = self.verify_message(message)?;
+        self.use_nonce(&args)?;
+        let template_id = if args.method_name == "create" && !args.args.is_empty() {
+            let create: CreateArgs = gateway_call_args(&args, "create")?;
+            Some(self.proxy_template_id(&create.template)?)
+        } else {
+            None
+        };
+        let required = match template_id {
+            Some(template_id) => self.template_deposit(template_id),
+            None => self.required_create_deposit().0,
+        };
+        ensure(
+            env::attached_deposit() >= required,
+            GatewayError::NotEnoughDeposit,
+        )?;
+        let (promise, escrowed) =
+            self.create_proxy_account(&args.sender.0, required, template_id)?;
+        self.record_message(&args.sender.0, &digest, None);
+        Ok(promise.then(on_create_callback(
+            &args.sender.0,
+            Some(&digest),
+            env::attached_deposit(),
+            env::attached_deposit() - required,
+            escrowed,
+            Gas(self.gas_schedule.create.0),
+        )))
+    }
+
+    /// Refunds the excess deposit to the caller once the account is created. If creation failed,
+    /// e.g. the account was created before the gateway tracked it, the address is released and
+    /// the whole deposit is refunded, as the funding returns to the gateway.
+    #[private]
+    #[handle_result]
+    pub fn on_create(
+        &mut self,
+        address: String,
+        digest: Option<String>,
+        caller: AccountId,
+        deposit: U128,
+        refund: U128,
+        escrowed: U128,
+    ) -> Result<MessageOutcome, GatewayError> {
+        let address = parse_address(&address)?;
+        let account_id = self.account_id_of(&address);
+        let success = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        if let Some(digest) = &digest {
+            self.record_outcome(&address, digest, success);
+        }
+        let refund = if success {
+            refund.0
+        } else {
+            self.created.remove(&address);
+            self.factory_accounts.remove(&address);
+            self.account_code_hashes.remove(&address);
+            self.standalone_accounts.remove(&address);
+            if escrowed.0 > 0 {
+                let escrow = self.escrow.get(&address).unwrap_or_default();
+                self.escrow.insert(&address, &(escrow + escrowed.0));
+            }
+            deposit.0
+        };
+        if refund > 0 {
+            Promise::new(caller).transfer(refund);
+        }
+        Ok(MessageOutcome {
+            digest,
+            account_id,
+            success,
+        })
+    }
+
+    /// Creates the proxy account of the hex encoded address without its signature, funded with
+    /// the attached deposit. Lets anyone onboard the address before its owner ever signs,
+    /// as the account is only controlled through messages signed by the address. Along with the
+    /// funds escrowed for the address, the deposit must cover `required_create_deposit`.
+    #[payable]
+    #[handle_result]
+    pub fn create_for(&mut self, address: String) -> Result<Promise, GatewayError> {
+        let address = parse_address(&address)?;
+        let escrow = self.escrow.get(&address).unwrap_or_default();
+        ensure(
+            env::attached_deposit() + escrow >= self.required_create_deposit().0,
+            GatewayError::NotEnoughDeposit,
+        )?;
+        let (promise, escrowed) =
+            self.create_proxy_account(&address, env::attached_deposit(), None)?;
+        Ok(promise.then(on_create_callback(
+            &address,
+            None,
+            env::attached_deposit(),
+            0,
+            escrowed,
+            Gas(self.gas_schedule.create.0),
+        )))
+    }
+
+    /// Sets the code of standalone proxy accounts created from now on. Only the gateway account
+    /// can call it.
+    #[handle_result]
+    pub fn set_standalone_code(&mut self, code: Base64VecU8) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        self.standalone_code_len = code.0.len() as u64;
+        self.standalone_code.set(&code.0);
+        Ok(())
+    }
+
+    /// Deposit `create_standalone` requires: storage of the standalone proxy code and the buffer.
+    pub fn required_standalone_deposit(&self) -> U128 {
+        U128(
+            Balance::from(self.standalone_code_len) * env::storage_byte_cost()
+                + self.create_deposit_buffer,
+        )
+    }
+
+    /// Creates the signer's standalone proxy account from the message calling
+    /// `create_standalone()` on the gateway, with `required_standalone_deposit` and the funds
+    /// escrowed for it. The account verifies the signer's messages submitted to its `execute`
+    /// itself, so it doesn't trust the gateway once created, and the gateway's proxy methods
+    /// refuse to act for the address. The rest of the deposit is refunded once it's created.
+    #[payable]
+    #[handle_result]
+    pub fn create_standalone(&mut self, message: SignedMessage) -> Result<Promise, GatewayError> {
+        let args = self.parse_message(message)?;
+        check_gateway_call(&args, "create_standalone")?;
+        let code = self
+            .standalone_code
+            .get()
+            .ok_or(GatewayError::NoStandaloneCode)?;
+        let required = self.required_standalone_deposit().0;
+        ensure(
+            env::attached_deposit() >= required,
+            GatewayError::NotEnoughDeposit,
+        )?;
+        let address = args.sender.0;
+        ensure(
+            !self.controlled.contains_key(&address) && self.created.insert(&address),
+            GatewayError::AlreadyCreated,
+        )?;
+        ensure(
+            !self.linked_accounts.contains_key(&address),
+            GatewayError::AlreadyLinked,
+        )?;
+        self.standalone_accounts.insert(&address);
+        let escrowed = self.escrow.remove(&address).unwrap_or_default();
+        Ok(Promise::new(proxy_account_id(&address))
+            .create_account()
+            .deploy_contract(code)
+            .function_call(
+                "new".to_string(),
+                json!({ "owner": checksum_address(&address) })
+                    .to_string()
+                    .into_bytes(),
+                0,
+                GAS_FOR_STANDALONE_INIT,
+            )
+            .transfer(required + escrowed)
+            .then(on_create_callback(
+                &address,
+                None,
+                env::attached_deposit(),
+                env::attached_deposit() - required,
+                escrowed,
+                Gas(self.gas_schedule.create.0),
+            )))
+    }
+
+    /// Sends `amount_each` to the account acting for each of the hex encoded addresses, creating
+    /// the missing proxy accounts with `required_create_deposit` on top, e.g. for token
+    /// distribution campaigns. Anyone can fund one. Handles at most `MAX_AIRDROPS_PER_CALL`
+    /// addresses and as many as the prepaid gas allows, and returns how many, so the caller
+    /// continues with the rest. The deposit left over is refunded, as is the deposit of accounts
+    /// whose creation fails. Logs a NEP-297 `airdrop` event.
+    #[payable]
+    #[handle_result]
+    pub fn airdrop(
+        &mut self,
+        addresses: Vec<String>,
+        amount_each: U128,
+    ) -> Result<U64, GatewayError> {
+        let create_gas = Gas(self.gas_schedule.create.0);
+        let required = self.required_create_deposit().0;
+        let mut total = 0;
+        let mut created = vec![];
+        let mut funded = vec![];
+        for account in addresses.iter().take(MAX_AIRDROPS_PER_CALL) {
+            if remaining_gas() < create_gas + GAS_FOR_PROXY {
+                break;
+            }
+            let address = parse_address(account)?;
+            if self.created.contains(&address) || self.linked_accounts.contains_key(&address) {
+                Promise::new(self.account_id_of(&address)).transfer(amount_each.0);
+                total += amount_each.0;
+                funded.push(checksum_address(&address));
+            } else {
+                let deposit = required + amount_each.0;
+                let (promise, escrowed) = self.create_proxy_account(&address, deposit, None)?;
+                promise.then(on_create_callback(
+                    &address, None, deposit, 0, escrowed, create_gas,
+                ));
+                total += deposit;
+                created.push(checksum_address(&address));
+            }
+        }
+        ensure(
+            env::attached_deposit() >= total,
+            GatewayError::NotEnoughDeposit,
+        )?;
+        let refund = env::attached_deposit() - total;
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+        let handled = (created.len() + funded.len()) as u64;
+        GatewayEvent::Airdrop(vec![AirdropData {
+            sender: env::predecessor_account_id(),
+            amount_each,
+            created,
+            funded,
+        }])
+        .emit();
+        Ok(U64(handled))
+    }
+
+    /// Creates the NEAR implicit account of the ed25519 key in the message calling
+    /// `create_implicit(bytes32 public_key)` on the gateway, funded with the attached deposit and
+    /// the funds escrowed for the signer. Cheaper than a proxy account, as no code is deployed,
+    /// but the key controls it directly: the gateway doesn't act from it, it just names it as
+    /// the signer's account.
+    #[payable]
+    #[handle_result]
+    pub fn create_implicit(&mut self, message: SignedMessage) -> Result<Promise, GatewayError> {
+        let args = self.parse_message(message)?;
+        let implicit: CreateImplicitArgs = gateway_call_args(&args, "create_implicit")?;
+        ensure(
+            implicit.public_key.0.len() == 32,
+            GatewayError::InvalidPublicKey,
+        )?;
+        let address = args.sender.0;
+        ensure(
+            !self.controlled.contains_key(&address) && self.created.insert(&address),
+            GatewayError::AlreadyCreated,
+        )?;
+        ensure(
+            !self.linked_accounts.contains_key(&address),
+            GatewayError::AlreadyLinked,
+        )?;
+        let account_id = AccountId::new_unchecked(hex::encode(&implicit.public_key.0));
+        self.implicit_accounts.insert(&address, &account_id);
+        let escrowed = self.escrow.remove(&address).unwrap_or_default();
+        let amount = env::attached_deposit() + escrowed;
+        ensure(amount > 0, GatewayError::NotEnoughDeposit)?;
+        Ok(Promise::new(account_id).transfer(amount))
+    }
+
+    /// How the account acting for the hex encoded address is controlled, None if it has none.
+    #[handle_result]
+    pub fn get_account_mode(&self, address: String) -> Result<Option<AccountMode>, GatewayError> {
+        let address = parse_address(&address)?;
+        Ok(if self.implicit_accounts.contains_key(&address) {
+            Some(AccountMode::Implicit)
+        } else if self.standalone_accounts.contains(&address) {
+            Some(AccountMode::Standalone)
+        } else if self.linked_accounts.contains_key(&address) {
+            Some(AccountMode::Linked)
+        } else if self.created.contains(&address) {
+            Some(AccountMode::Proxy)
+        } else {
+            None
+        })
+    }
+
+    /// Cancels outstanding messages of the signer without executing anything, so relayers
+    /// holding old signatures can't submit them later. The message must be a call of `cancel()`
+    /// on the gateway. Sequential nonce may be ahead of the expected one, all the messages up to
+    /// and including it under its key are cancelled. Salted nonce is marked as used.
+    #[handle_result]
+    pub fn cancel(&mut self, message: SignedMessage) -> Result<(), GatewayError> {
+        self.count_submission()?;
+        let (args, _) = self.verify_message(message)?;
+        check_gateway_call(&args, "cancel")?;
+        if is_salted_nonce(args.nonce) {
+            self.use_salted_nonce(&args.sender.0, args.nonce)?;
+        } else {
+            let current = self.current_nonce(&args.sender.0, nonce_key(args.nonce));
+            ensure(args.nonce >= current, GatewayError::IncorrectNonce)?;
+            self.set_used_nonce(&args.sender.0, args.nonce)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the spending limit of the signer, from the message calling
+    /// `set_spending_limit(uint128 amount,uint64 period)` on the gateway. Messages spending more
+    /// than `amount` yoctoNEAR within `period` seconds then need a confirmation, see
+    /// `proxy_confirmed`. Period 0 removes the limit. Loosening or removing an existing limit
+    /// needs a confirmation of this message too, so a single leaked signature can't lift it.
+    /// Limits can only be set once the account has a confirmer, see `set_confirmer`.
+    #[handle_result]
+    pub fn set_spending_limit(
+        &mut self,
+        message: SignedMessage,
+        confirmation: Option<SignedMessage>,
+    ) -> Result<(), GatewayError> {
+        self.count_submission()?;
+        let (args, digest) = self.verify_message(message)?;
+        self.use_nonce(&args)?;
+        let limit: SetSpendingLimitArgs = gateway_call_args(&args, "set_spending_limit")?;
+        let sender = self.account_of(&args.sender.0)?;
+        ensure(
+            self.confirmers.contains_key(&sender),
+            GatewayError::NoConfirmer,
+        )?;
+        let current = self.spending_limits.get(&sender);
+        if let Some(current) = &current {
+            let stricter = limit.period.0 > 0
+                && limit.amount.0 <= current.amount.0
+                && limit.period.0 >= current.period.0;
+            if !stricter {
+                let confirmation = confirmation.ok_or(GatewayError::ConfirmationRequired)?;
+                self.check_confirmed(&args, &digest, confirmation)?;
+            }
+        }
+        if limit.period.0 == 0 {
+            self.spending_limits.remove(&sender);
+            return Ok(());
+        }
+        // Spending so far carries over, changing the limit doesn't start a new period.
+        let (period_start, spent) = current
+            .map(|current| (current.period_start, current.spent))
+            .unwrap_or((U64(block_seconds()), U128(0)));
+        self.spending_limits.insert(
+            &sender,
+            &SpendingLimit {
+                amount: limit.amount,
+                period: limit.period,
+                period_start,
+                spent,
+            },
+        );
+        Ok(())
+    }
+
+    /// Sets the signer's settings from the message calling
+    /// `set_settings(uint64 gas,string fee_token,uint64 max_nonce_lifetime)` on the gateway: the
+    /// gas of methods its messages call without declaring it, the fee token of its messages
+    /// without a fee address, and the longest lifetime of its salted nonces. Zero and empty values
+    /// leave it to the gateway, all of them remove the settings.
+    #[handle_result]
+    pub fn set_settings(&mut self, message: SignedMessage) -> Result<(), GatewayError> {
+        let args = self.parse_message(message)?;
+        let settings: SetSettingsArgs = gateway_call_args(&args, "set_settings")?;
+        if settings.gas.0 == 0
+            && settings.fee_token.is_empty()
+            && settings.max_nonce_lifetime.0 == 0
+        {
+            self.settings.remove(&args.sender.0);
+            return Ok(());
+        }
+        self.settings.insert(
+            &args.sender.0,
+            &Settings {
+                gas: settings.gas,
+                fee_token: Some(settings.fee_token).filter(|token| !token.is_empty()),
+                max_nonce_lifetime: settings.max_nonce_lifetime,
+            },
+        );
+        Ok(())
+    }
+
+    /// Settings of the hex encoded address, if it has any.
+    #[handle_result]
+    pub fn get_settings(&self, address: String) -> Result<Option<Settings>, GatewayError> {
+        Ok(self.settings.get(&parse_address(&address)?))
+    }
+
+    /// Spending limit of the hex encoded address, if it has one.
+    #[handle_result]
+    pub fn get_spending_limit(
+        &self,
+        address: String,
+    ) -> Result<Option<SpendingLimit>, GatewayError> {
+        Ok(self.spending_limits.get(&parse_address(&address)?))
+    }
+
+    /// Registers the address confirming the signer's messages over its spending limit, from the
+    /// message calling `set_confirmer(address confirmer)` on the gateway. It must be another key
+    /// than the signer's, e.g. on another device, so a leaked signing key alone can't confirm.
+    /// Replacing the confirmer needs a confirmation of this message by the current one.
+    #[handle_result]
+    pub fn set_confirmer(
+        &mut self,
+        message: SignedMessage,
+        confirmation: Option<SignedMessage>,
+    ) -> Result<(), GatewayError> {
+        self.count_submission()?;
+        let (args, digest) = self.verify_message(message)?;
+        self.use_nonce(&args)?;
+        let confirmer: SetConfirmerArgs = gateway_call_args(&args, "set_confirmer")?;
+        let account = self.account_of(&args.sender.0)?;
+        ensure(
+            confirmer.confirmer != args.sender.0
+                && confirmer.confirmer != account
+                && confirmer.confirmer != RawAddress::default(),
+            GatewayError::InvalidConfirmer,
+        )?;
+        if self.confirmers.contains_key(&account) {
+            let confirmation = confirmation.ok_or(GatewayError::ConfirmationRequired)?;
+            self.check_confirmed(&args, &digest, confirmation)?;
+        }
+        self.confirmers.insert(&account, &confirmer.confirmer);
+        Ok(())
+    }
+
+    /// Hex encoded confirmer of the proxy account of the hex encoded address, if it has one.
+    #[handle_result]
+    pub fn get_confirmer(&self, address: String) -> Result<Option<String>, GatewayError> {
+        Ok(self
+            .confirmers
+            .get(&parse_address(&address)?)
+            .map(|confirmer| checksum_address(&confirmer)))
+    }
+
+    /// Checks the confirmation is the message of the confirmer registered for the sender's
+    /// account calling `confirm(bytes digest)` on the gateway with the digest of the confirmed
+    /// message. Its nonce isn't checked, the confirmed message's nonce is used only once.
+    fn check_confirmed(
+        &self,
+        args: &InternalMetaCallArgs,
+        digest: &RawU256,
+        confirmation: SignedMessage,
+    ) -> Result<(), GatewayError> {
+        let confirmer = self
+            .confirmers
+            .get(&self.account_of(&args.sender.0)?)
+            .ok_or(GatewayError::NoConfirmer)?;
+        let (confirm, _) = self.verify_message(confirmation)?;
+        ensure(
+            confirm.sender.0 == confirmer,
+            GatewayError::InvalidConfirmation,
+        )?;
+        let confirm: ConfirmArgs = gateway_call_args(&confirm, "confirm")?;
+        ensure(
+            confirm.digest.0[..] == digest[..],
+            GatewayError::InvalidConfirmation,
+        )
+    }
+
+    /// Accounts the value sent from the proxy account of the address against the circuit breaker
+    /// and its spending limit. Fails unless it's within the limit or confirmed.
+    fn spend(
+        &mut self,
+        address: &RawAddress,
+        value: Balance,
+        confirmed: bool,
+    ) -> Result<(), GatewayError> {
+        self.add_epoch_value(value)?;
+        let mut limit = match self.spending_limits.get(address) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let now = block_seconds();
+        if now >= limit.period_start.0.saturating_add(limit.period.0) {
+            limit.period_start = U64(now);
+            limit.spent = U128(0);
+        }
+        limit.spent = U128(limit.spent.0.saturating_add(value));
+        ensure(
+            confirmed || limit.spent.0 <= limit.amount.0,
+            GatewayError::SpendingLimit,
+        )?;
+        self.spending_limits.insert(address, &limit);
+        Ok(())
+    }
+
+    /// Executes the signed message from the signer's proxy account. Messages whose value comes
+    /// from the relayer must have exactly the value attached. Messages to `SELF_RECEIVER` manage
+    /// the account itself instead, see `self_call`.
+    #[payable]
+    #[handle_result]
+    pub fn proxy(
+        &mut self,
+        message: SignedMessage,
+    ) -> Result<PromiseOrValue<MessageOutcome>, GatewayError> {
+        self.count_submission()?;
+        let (args, digest) = self.verify_message(message)?;
+        self.proxy_verified(args, digest)
+    }
+
+    /// Rest of `proxy` once the message with the digest is verified: uses its nonce, executes
+    /// it and records it in the history of the signer.
+    fn proxy_verified(
+        &mut self,
+        args: InternalMetaCallArgs,
+        digest: RawU256,
+    ) -> Result<PromiseOrValue<MessageOutcome>, GatewayError> {
+        self.use_nonce(&args)?;
+        if args.deposit_source == DepositSource::Account {
+            ensure(env::attached_deposit() == 0, GatewayError::RelayerDeposit)?;
+        }
+        let account = self.account_of(&args.sender.0)?;
+        let account_id = self.account_id_of(&account);
+        let sender = args.sender.0;
+        let promise = if args.contract_address == SELF_RECEIVER {
+            match self.self_call(&account, args)? {
+                PromiseOrValue::Promise(promise) => promise,
+                PromiseOrValue::Value(()) => {
+                    self.record_message(&sender, &digest, Some(true));
+                    return Ok(PromiseOrValue::Value(MessageOutcome {
+                        digest: Some(format!("0x{}", hex::encode(digest))),
+                        account_id,
+                        success: true,
+                    }));
+                }
+            }
+        } else {
+            self.spend(&account, spent(&args), false)?;
+            self.proxy_call(Promise::new(self.acting_account_id(&account)?), args)?
+        };
+        self.record_message(&sender, &digest, None);
+        let promise = promise.then(on_proxy_callback(&sender, &digest, &account_id));
+        trace_gas("promises");
+        Ok(promise.into())
+    }
+
+    /// `proxy` of the message given as human-readable JSON instead of borsh, so it can be
+    /// submitted from near-cli and simple scripts. See `MetaCallArgsJson` for the fields.
+    #[payable]
+    #[handle_result]
+    pub fn proxy_json(
+        &mut self,
+        args: MetaCallArgsJson,
+    ) -> Result<PromiseOrValue<MessageOutcome>, GatewayError> {
+        let message = MetaCallArgs::try_from(args)?;
+        self.proxy(SignedMessage(message.try_to_vec().unwrap()))
+    }
+
+    /// Adds the value a message spends to the epoch's total, failing like `check_circuit_breaker`.
+    /// Logs a NEP-297 `circuit_breaker` event when the total goes over the cap.
+    fn add_epoch_value(&mut self, value: Balance) -> Result<(), GatewayError> {
+        let (epoch, total) = match self.check_circuit_breaker(value)? {
+            Some(epoch_total) => epoch_total,
+            None => return Ok(()),
+        };
+        let cap = self
+            .circuit_breaker
+            .as_ref()
+            .map_or(0, |breaker| breaker.cap.0);
+        let new_total = total.saturating_add(value);
+        if new_total > cap && total <= cap {
+            GatewayEvent::CircuitBreaker(vec![CircuitBreakerData {
+                epoch: U64(epoch),
+                total: U128(new_total),
+            }])
+            .emit();
+        }
+        self.epoch_value = (epoch, new_total);
+        Ok(())
+    }
+
+    /// Fails with `ERR_CIRCUIT_BREAKER` if the value a message spends takes the epoch's total
+    /// over the circuit breaker's cap, unless the value is below its threshold. Returns the
+    /// epoch and its total so far, none without a circuit breaker.
+    fn check_circuit_breaker(
+        &self,
+        value: Balance,
+    ) -> Result<Option<(u64, Balance)>, GatewayError> {
+        let breaker = match &self.circuit_breaker {
+            Some(breaker) => breaker,
+            None => return Ok(None),
+        };
+        let epoch = env::epoch_height();
+        let total = match self.epoch_value {
+            (value_epoch, total) if value_epoch == epoch => total,
+            _ => 0,
+        };
+        if total.saturating_add(value) > breaker.cap.0 {
+            ensure(value < breaker.threshold.0, GatewayError::CircuitBreaker)?;
+        }
+        Ok(Some((epoch, total)))
+    }
+
+    /// Resolves `proxy` to the outcome of the address' message with the digest, executed from
+    /// the account, and records it in the address' history.
+    #[private]
+    #[handle_result]
+    pub fn on_proxy(
+        &mut self,
+        address: String,
+        digest: String,
+        account_id: AccountId,
+    ) -> Result<MessageOutcome, GatewayError> {
+        let success = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        self.record_outcome(&parse_address(&address)?, &digest, success);
+        Ok(MessageOutcome {
+            digest: Some(digest),
+            account_id,
+            success,
+        })
+    }
+
+    /// Adds the message with the digest, executed in this block, to the address' history,
+    /// forgetting the oldest beyond `MAX_HISTORY`.
+    fn record_message(&mut self, address: &RawAddress, digest: &RawU256, success: Option<bool>) {
+        let mut history = self.history.get(address).unwrap_or_default();
+        if history.len() == MAX_HISTORY {
+            history.remove(0);
+        }
+        history.push(ExecutedMessage {
+            digest: format!("0x{}", hex::encode(digest)),
+            block_height: U64(env::block_height()),
+            success,
+        });
+        self.history.insert(address, &history);
+    }
+
+    /// Sets the outcome of the message with the hex encoded digest in the address' history.
+    fn record_outcome(&mut self, address: &RawAddress, digest: &str, success: bool) {
+        let mut history = match self.history.get(address) {
+            Some(history) => history,
+            None => return,
+        };
+        if let Some(message) = history
+            .iter_mut()
+            .rev()
+            .find(|message| message.digest == digest)
+        {
+            message.success = Some(success);
+            self.history.insert(address, &history);
+        }
+    }
+
+    /// Last `MAX_HISTORY` messages `proxy`, `submit_eth_tx` and `create` executed for the hex
+    /// encoded address, oldest first, so users can check what their relayer submitted.
+    #[handle_result]
+    pub fn get_history(&self, address: String) -> Result<Vec<ExecutedMessage>, GatewayError> {
+        Ok(self
+            .history
+            .get(&parse_address(&address)?)
+            .unwrap_or_default())
+    }
+
+    /// Executes the signed call of a management method of the account, from a message to
+    /// `SELF_RECEIVER` without value or tip:
+    /// - `update(bytes32 code_hash)` deploys the proxy code or template with the sha256 hash on
+    ///   the account, only for proxy accounts the gateway created.
+    /// - `add_key(bytes32 public_key)` adds the ed25519 key to the account as a full access key.
+    /// - `lock()` stops the gateway acting for the account, e.g. when the key may be leaked.
+    /// - `unlock()` lifts the lock, once `get_rotation_delay` seconds passed since it was set.
+    /// - `set_controller(address new_controller)` starts rotating the controlling address like
+    ///   `rotate_owner`.
+    ///
+    /// Locked accounts only take `unlock` and `set_controller`. Logs a NEP-297 `self_call` event.
+    fn self_call(
+        &mut self,
+        account: &RawAddress,
+        args: InternalMetaCallArgs,
+    ) -> Result<PromiseOrValue<()>, GatewayError> {
+        ensure(args.value == 0 && args.tip == 0, GatewayError::SelfValue)?;
+        ensure(
+            !self.locks.contains_key(account)
+                || matches!(args.method_name.as_str(), "unlock" | "set_controller"),
+            GatewayError::AccountLocked,
+        )?;
+        GatewayEvent::SelfCall(vec![SelfCallData {
+            address: checksum_address(account),
+            method: args.method_name.clone(),
+        }])
+        .emit();
+        Ok(match args.method_name.as_str() {
+            "update" => {
+                let update: UpdateArgs = call_args(&args)?;
+                ensure(
+                    self.is_gateway_proxy(account),
+                    GatewayError::NotProxyAccount,
+                )?;
+                let code_hash = hex::encode(&update.code_hash.0);
+                let code = self
+                    .code_of_hash(&code_hash)
+                    .ok_or(GatewayError::UnknownCode)?;
+                Promise::new(proxy_account_id(account))
+                    .function_call(
+                        "update".to_string(),
+                        code,
+                        0,
+                        Gas(self.gas_schedule.update.0),
+                    )
+                    .then(on_migrate_callback(account, &code_hash))
+                    .into()
+            }
+            "add_key" => {
+                let add_key: AddKeyArgs = call_args(&args)?;
+                ensure(
+                    add_key.public_key.0.len() == 32,
+                    GatewayError::InvalidPublicKey,
+                )?;
+                // ed25519 key type, followed by the key.
+                let mut public_key = vec![0];
+                public_key.extend_from_slice(&add_key.public_key.0);
+                Promise::new(self.acting_account_id(account)?)
+                    .function_call("add_key".to_string(), public_key, 0, GAS_FOR_ADD_KEY)
+                    .into()
+            }
+            "lock" => {
+                self.locks.insert(account, &block_seconds());
+                PromiseOrValue::Value(())
+            }
+            "unlock" => {
+                let locked_at = self.locks.get(account).ok_or(GatewayError::NotLocked)?;
+                ensure(
+                    block_seconds() >= locked_at.saturating_add(self.rotation_delay),
+                    GatewayError::LockTimelock,
+                )?;
+                self.locks.remove(account);
+                PromiseOrValue::Value(())
+            }
+            "set_controller" => {
+                let set_controller: SetControllerArgs = call_args(&args)?;
+                self.schedule_rotation(account, &args.sender.0, set_controller.new_controller)?;
+                PromiseOrValue::Value(())
+            }
+            _ => return Err(GatewayError::UnexpectedMethod),
+        })
+    }
+
+    /// Whether the gateway stopped acting for the account of the hex encoded address, and
+    /// since when in seconds.
+    #[handle_result]
+    pub fn get_lock(&self, address: String) -> Result<Option<U64>, GatewayError> {
+        Ok(self.locks.get(&parse_address(&address)?).map(U64))
+    }
+
+    /// Like `proxy`, for messages over the signer's spending limit. The confirmation is the
+    /// signer's message calling `confirm(bytes digest)` on the gateway with the message's digest.
+    #[handle_result]
+    pub fn proxy_confirmed(
+        &mut self,
+        message: SignedMessage,
+        confirmation: SignedMessage,
+    ) -> Result<Promise, GatewayError> {
+        self.count_submission()?;
+        let (args, digest) = self.verify_message(message)?;
+        self.use_nonce(&args)?;
+        self.check_confirmed(&args, &digest, confirmation)?;
+        let account = self.account_of(&args.sender.0)?;
+        self.spend(&account, spent(&args), true)?;
+        self.proxy_call(Promise::new(self.acting_account_id(&account)?), args)
+    }
+
+    /// Lets the spender address send up to `amount` yoctoNEAR from the signer's proxy account
+    /// with `proxy_from` until the expiry timestamp in seconds, from the message calling
+    /// `approve(address spender,uint128 amount,uint64 expires_at)` on the gateway. Replaces the
+    /// previous allowance of the spender, amount 0 revokes it.
+    #[handle_result]
+    pub fn approve(&mut self, message: SignedMessage) -> Result<(), GatewayError> {
+        let args = self.parse_message(message)?;
+        let approve: ApproveArgs = gateway_call_args(&args, "approve")?;
+        let id = (self.account_of(&args.sender.0)?, approve.spender);
+        if approve.amount.0 == 0 {
+            self.allowances.remove(&id);
+        } else {
+            self.allowances.insert(
+                &id,
+                &Allowance {
+                    amount: approve.amount,
+                    expires_at: approve.expires_at,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Allowance of the spender from the owner, both hex encoded addresses.
+    #[handle_result]
+    pub fn get_allowance(
+        &self,
+        owner: String,
+        spender: String,
+    ) -> Result<Option<Allowance>, GatewayError> {
+        Ok(self
+            .allowances
+            .get(&(parse_address(&owner)?, parse_address(&spender)?)))
+    }
+
+    /// Lets the spender NEAR account transfer up to `amount` of the NEP-141 token from the
+    /// signer's proxy account with `ft_transfer_from` until the expiry timestamp in seconds, like
+    /// ERC-2612 permit, from the message calling
+    /// `permit(string token,string spender,uint128 amount,uint64 expires_at)` on the gateway.
+    /// The message's value, if any, registers the spender's storage on the token from the proxy
+    /// account, so a single signature gets the spender ready to receive. Replaces the previous
+    /// allowance, amount 0 revokes it.
+    #[handle_result]
+    pub fn permit(&mut self, message: SignedMessage) -> Result<Option<Promise>, GatewayError> {
+        let args = self.parse_message(message)?;
+        let permit: PermitArgs = gateway_call_args(&args, "permit")?;
+        let account = self.account_of(&args.sender.0)?;
+        let id = (account, permit.token.clone(), permit.spender.clone());
+        if permit.amount.0 == 0 {
+            self.token_allowances.remove(&id);
+        } else {
+            self.token_allowances.insert(
+                &id,
+                &Allowance {
+                    amount: permit.amount,
+                    expires_at: permit.expires_at,
+                },
+            );
+        }
+        if args.value == 0 {
+            return Ok(None);
+        }
+        self.spend(&account, args.value, false)?;
+        Ok(Some(account_call(
+            self.acting_account_id(&account)?,
+            CallArgs {
+                gas: self.gas_schedule.call.0,
+                amount: args.value,
+                receiver_id: permit.token.to_string(),
+                method_name: "storage_deposit".to_string(),
+                args: json!({ "account_id": permit.spender, "registration_only": true })
+                    .to_string()
+                    .into_bytes(),
+            },
+        )))
+    }
+
+    /// Allowance of the spender account for the token from the hex encoded owner address.
+    #[handle_result]
+    pub fn get_token_allowance(
+        &self,
+        owner: String,
+        token: AccountId,
+        spender: AccountId,
+    ) -> Result<Option<Allowance>, GatewayError> {
+        Ok(self
+            .token_allowances
+            .get(&(parse_address(&owner)?, token, spender)))
+    }
+
+    /// Transfers `amount` of the NEP-141 token from the proxy account of the hex encoded owner
+    /// address to `receiver_id` with `ft_transfer`, within the allowance the owner permitted the
+    /// calling account.
+    #[handle_result]
+    pub fn ft_transfer_from(
+        &mut self,
+        owner: String,
+        token: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> Result<Promise, GatewayError> {
+        let owner = parse_address(&owner)?;
+        let id = (owner, token.clone(), env::predecessor_account_id());
+        let mut allowance = self
+            .token_allowances
+            .get(&id)
+            .ok_or(GatewayError::NoAllowance)?;
+        ensure(
+            allowance.expires_at.0 > block_seconds(),
+            GatewayError::AllowanceExpired,
+        )?;
+        ensure(
+            amount.0 <= allowance.amount.0,
+            GatewayError::AllowanceExceeded,
+        )?;
+        allowance.amount = U128(allowance.amount.0 - amount.0);
+        self.token_allowances.insert(&id, &allowance);
+        Ok(account_call(
+            self.acting_account_id(&owner)?,
+            CallArgs {
+                gas: self.gas_schedule.call.0,
+                amount: 1,
+                receiver_id: token.to_string(),
+                method_name: "ft_transfer".to_string(),
+                args: json!({ "receiver_id": receiver_id, "amount": amount })
+                    .to_string()
+                    .into_bytes(),
+            },
+        ))
+    }
+
+    /// Executes the message signed by a spender from the proxy account of the hex encoded owner
+    /// address, within the allowance the owner gave it. The spender's own nonce is used, and the
+    /// value counts against the owner's spending limit as well. Allowances only cover plain
+    /// transfers: messages calling a method, or sending to the gateway, fail.
+    #[handle_result]
+    pub fn proxy_from(
+        &mut self,
+        owner: String,
+        message: SignedMessage,
+    ) -> Result<Promise, GatewayError> {
+        let args = self.parse_message(message)?;
+        ensure(
+            args.method_name.is_empty()
+                && args.contract_address != env::current_account_id().as_str(),
+            GatewayError::AllowanceCall,
+        )?;
+        let owner = parse_address(&owner)?;
+        let id = (owner, args.sender.0);
+        let mut allowance = self.allowances.get(&id).ok_or(GatewayError::NoAllowance)?;
+        ensure(
+            allowance.expires_at.0 > block_seconds(),
+            GatewayError::AllowanceExpired,
+        )?;
+        ensure(
+            spent(&args) <= allowance.amount.0,
+            GatewayError::AllowanceExceeded,
+        )?;
+        allowance.amount = U128(allowance.amount.0 - spent(&args));
+        self.allowances.insert(&id, &allowance);
+        self.spend(&owner, spent(&args), false)?;
+        self.proxy_call(Promise::new(self.acting_account_id(&owner)?), args)
+    }
+
+    /// Registers a session key, by the address of its secp256k1 or ed25519 key, that can act for
+    /// the signer through `proxy_session` within the scope given in the message calling
+    /// `grant_session(address session,string[] receivers,string[] methods,uint128 value_cap,uint64 expires_at)`
+    /// on the gateway. Replaces the previous scope of the session key.
+    #[handle_result]
+    pub fn grant_session(&mut self, message: SignedMessage) -> Result<(), GatewayError> {
+        let args = self.parse_message(message)?;
+        let grant: GrantSessionArgs = gateway_call_args(&args, "grant_session")?;
+        ensure(
+            grant.expires_at.0 > block_seconds(),
+            GatewayError::SessionExpired,
+        )?;
+        self.sessions.insert(
+            &(self.account_of(&args.sender.0)?, grant.session),
+            &Session {
+                receivers: grant.receivers,
+                methods: grant.methods,
+                value_cap: grant.value_cap,
+                expires_at: grant.expires_at,
+            },
+        );
+        Ok(())
+    }
+
+    /// Like `grant_session`, for signing in with SIWE: authorized by a SIWE message for the
+    /// gateway's domain and its `personal_sign` signature. The session key is the message's
+    /// `near:session:<address>` resource, the scope its `near:receiver:<account_id>`,
+    /// `near:method:<name>` and `near:value:<yoctoNEAR cap>` resources, and the session lasts
+    /// until the message's expiration time.
+    #[handle_result]
+    pub fn grant_session_siwe(
+        &mut self,
+        message: String,
+        signature: Base64VecU8,
+    ) -> Result<(), GatewayError> {
+        let (siwe, account) = self.use_siwe(&message, &signature.0)?;
+        let sessions = siwe_resources(&siwe, "session");
+        let value_caps = siwe_resources(&siwe, "value");
+        ensure(
+            sessions.len() == 1 && value_caps.len() <= 1,
+            GatewayError::SiweResources,
+        )?;
+        let value_cap = value_caps
+            .first()
+            .map_or(Some(0), |value_cap| value_cap.parse().ok())
+            .ok_or(GatewayError::SiweResources)?;
+        let to_strings = |values: Vec<&str>| values.into_iter().map(String::from).collect();
+        self.sessions.insert(
+            &(account, parse_address(sessions[0])?),
+            &Session {
+                receivers: to_strings(siwe_resources(&siwe, "receiver")),
+                methods: to_strings(siwe_resources(&siwe, "method")),
+                value_cap: U128(value_cap),
+                expires_at: U64(siwe.expiration_time.unwrap_or_default()),
+            },
+        );
+        Ok(())
+    }
+
+    /// Revokes the session key, from the message calling `revoke_session(address session)` on
+    /// the gateway.
+    #[handle_result]
+    pub fn revoke_session(&mut self, message: SignedMessage) -> Result<(), GatewayError> {
+        let args = self.parse_message(message)?;
+        let revoke: RevokeSessionArgs = gateway_call_args(&args, "revoke_session")?;
+        let owner = self.account_of(&args.sender.0)?;
+        self.sessions.remove(&(owner, revoke.session));
+        Ok(())
+    }
+
+    /// Scope of the session key of the owner, both hex encoded addresses.
+    #[handle_result]
+    pub fn get_session(
+        &self,
+        owner: String,
+        session: String,
+    ) -> Result<Option<Session>, GatewayError> {
+        Ok(self
+            .sessions
+            .get(&(parse_address(&owner)?, parse_address(&session)?)))
+    }
+
+    /// Executes the message signed by a session key from the proxy account of the hex encoded
+    /// owner address, if it's within the session's scope. The session key's own nonce is used,
+    /// and the value counts against the owner's spending limit.
+    #[handle_result]
+    pub fn proxy_session(
+        &mut self,
+        owner: String,
+        message: SignedMessage,
+    ) -> Result<Promise, GatewayError> {
+        let args = self.parse_message(message)?;
+        let owner = parse_address(&owner)?;
+        let session = self
+            .sessions
+            .get(&(owner, args.sender.0))
+            .ok_or(GatewayError::NoSession)?;
+        ensure(
+            session.expires_at.0 > block_seconds(),
+            GatewayError::SessionExpired,
+        )?;
+        ensure(
+            session.receivers.contains(&args.contract_address),
+            GatewayError::SessionReceiver,
+        )?;
+        ensure(
+            session.methods.is_empty() || session.methods.contains(&args.method_name),
+            GatewayError::SessionMethod,
+        )?;
+        ensure(
+            spent(&args) <= session.value_cap.0,
+            GatewayError::SessionValue,
+        )?;
+        self.spend(&owner, spent(&args), false)?;
+        self.proxy_call(Promise::new(self.acting_account_id(&owner)?), args)
+    }
+
+    /// Creates the signer's proxy account funded with the attached deposit and executes the
+    /// signed message from it, all in one batch, so new users onboard with a single transaction.
+    /// The deposit must cover `required_create_deposit`, like for `create`. Fails as a whole,
+    /// refunding the deposit, if the account already exists.
+    #[payable]
+    #[handle_result]
+    pub fn create_and_proxy(&mut self, message: SignedMessage) -> Result<Promise, GatewayError> {
+        let args = self.parse_message(message)?;
+        ensure(
+            env::attached_deposit() >= self.required_create_deposit().0,
+            GatewayError::NotEnoughDeposit,
+        )?;
+        // The attached deposit funds the account instead.
+        ensure(
+            args.deposit_source == DepositSource::Account,
+            GatewayError::RelayerDeposit,
+        )?;
+        self.spend(&args.sender.0, spent(&args), false)?;
+        let sender = args.sender.0;
+        let (promise, escrowed) =
+            self.create_proxy_account(&sender, env::attached_deposit(), None)?;
+        let callback = on_create_callback(
+            &sender,
+            None,
+            env::attached_deposit(),
+            0,
+            escrowed,
+            Gas(self.gas_schedule.create.0),
+        );
+        if self.factory.is_none() {
+            return Ok(self.proxy_call(promise, args)?.then(callback));
+        }
+        // The factory creates the account in its own receipt, the call follows it.
+        let account = Promise::new(self.account_id_of(&sender));
+        self.proxy_call(promise.then(callback).then(account), args)
+    }
+
+    /// Credits the attached deposit to the ledger balance of the hex encoded address, which its
+    /// messages spend through `proxy_ledger` or `withdraw` without any account being created
+    /// for it. Anyone can deposit, e.g. exchanges crediting users by their Ethereum address.
+    #[payable]
+    #[handle_result]
+    pub fn deposit_to(&mut self, address: String) -> Result<(), GatewayError> {
+        let address = parse_address(&address)?;
+        let balance = self.ledger.get(&address).unwrap_or_default();
+        self.ledger
+            .insert(&address, &(balance + env::attached_deposit()));
+        GatewayEvent::Deposit(vec![DepositData {
+            address: checksum_address(&address),
+            amount: U128(env::attached_deposit()),
+            sender: env::predecessor_account_id(),
+        }])
+        .emit();
+        Ok(())
+    }
+
+    /// Ledger balance of the hex encoded address.
+    #[handle_result]
+    pub fn get_ledger_balance(&self, address: String) -> Result<U128, GatewayError> {
+        Ok(U128(
+            self.ledger
+                .get(&parse_address(&address)?)
+                .unwrap_or_default(),
+        ))
+    }
+
+    /// Ledger balances of the hex encoded addresses, in order.
+    #[handle_result]
+    pub fn get_ledger_balances(&self, addresses: Vec<String>) -> Result<Vec<U128>, GatewayError> {
+        addresses
+            .into_iter()
+            .map(|address| self.get_ledger_balance(address))
+            .collect()
+    }
+
+    /// Like `proxy`, but in ledger mode: the value comes from the signer's ledger balance and
+    /// the transfer or call is made by the gateway itself, so the signer needs no proxy account.
+    /// Receivers see the gateway as the caller for every ledger address, so it can't call the
+    /// gateway itself. The value returns to the balance if the transfer or call fails.
+    #[handle_result]
+    pub fn proxy_ledger(&mut self, message: SignedMessage) -> Result<Promise, GatewayError> {
+        let args = self.parse_message(message)?;
+        ensure(
+            args.contract_address != env::current_account_id().as_str(),
+            GatewayError::LedgerReceiver,
+        )?;
+        ensure(
+            args.contract_address != SELF_RECEIVER,
+            GatewayError::SelfReceiver,
+        )?;
+        self.check_allowed(&args)?;
+        ensure(
+            args.deposit_source == DepositSource::Account,
+            GatewayError::RelayerDeposit,
+        )?;
+        let account = self.account_of(&args.sender.0)?;
+        let balance = self.ledger.get(&account).unwrap_or_default();
+        ensure(balance >= args.value, GatewayError::NotEnoughBalance)?;
+        self.spend(&account, args.value, false)?;
+        self.ledger.insert(&account, &(balance - args.value));
+        let promise = Promise::new(receiver_account_id(&args.contract_address)?);
+        let promise = if args.method_name.is_empty() {
+            promise.transfer(args.value)
+        } else {
+            let gas = method_gas(
+                &args,
+                Gas(self.gas_schedule.call.0),
+                remaining_gas() - GAS_FOR_ON_LEDGER_CALL,
+            )?;
+            promise.function_call(args.method_name, args.args, args.value, gas)
+        };
+        Ok(promise.then(on_ledger_callback(&account, args.value)))
+    }
+
+    /// Withdraws from the signer's ledger balance to a NEAR account, from the message calling
+    /// `withdraw(uint128 amount,string receiver)` on the gateway.
+    #[handle_result]
+    pub fn withdraw(&mut self, message: SignedMessage) -> Result<Promise, GatewayError> {
+        let args = self.parse_message(message)?;
+        let withdraw: WithdrawArgs = gateway_call_args(&args, "withdraw")?;
+        let account = self.account_of(&args.sender.0)?;
+        let balance = self.ledger.get(&account).unwrap_or_default();
+        ensure(balance >= withdraw.amount.0, GatewayError::NotEnoughBalance)?;
+        self.spend(&account, withdraw.amount.0, false)?;
+        self.ledger.insert(&account, &(balance - withdraw.amount.0));
+        GatewayEvent::Withdraw(vec![WithdrawData {
+            address: checksum_address(&account),
+            amount: withdraw.amount,
+            receiver: withdraw.receiver.clone(),
+        }])
+        .emit();
+        Ok(Promise::new(withdraw.receiver)
+            .transfer(withdraw.amount.0)
+            .then(on_ledger_callback(&account, withdraw.amount.0)))
+    }
+
+    /// Returns the value of a failed `proxy_ledger` transfer or call, or withdrawal, to the
+    /// address' ledger balance, as the refund goes to the gateway.
+    #[private]
+    #[handle_result]
+    pub fn on_ledger_call(&mut self, address: String, amount: U128) -> Result<(), GatewayError> {
+        if amount.0 == 0 {
+            return Ok(());
+        }
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            return Ok(());
+        }
+        let address = parse_address(&address)?;
+        let balance = self.ledger.get(&address).unwrap_or_default();
+        self.ledger.insert(&address, &(balance + amount.0));
+        Ok(())
+    }
+
+    /// Registers the NEP-141 token for `balances_of`. Only the gateway account can call it.
+    #[handle_result]
+    pub fn register_token(&mut self, token_id: AccountId) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        ensure(
+            !self.tokens.iter().any(|token| token == token_id),
+            GatewayError::TokenRegistered,
+        )?;
+        self.tokens.push(&token_id);
+        Ok(())
+    }
+
+    /// Tokens registered for `balances_of`.
+    pub fn get_tokens(&self) -> Vec<AccountId> {
+        self.tokens.to_vec()
+    }
+
+    /// NEAR account acting for the hex encoded address: the linked account or its proxy account,
+    /// named after the factory that created it or the gateway.
+    #[handle_result]
+    pub fn get_account_id(&self, address: String) -> Result<AccountId, GatewayError> {
+        Ok(self.account_id_of(&parse_address(&address)?))
+    }
+
+    /// Balances of the registered tokens held by the account acting for the hex encoded address,
+    /// along with its ledger balance, so wallets get the address' portfolio in one call.
+    /// Views can't call other contracts, so it's a call, and its NEAR balance is read off-chain.
+    #[handle_result]
+    pub fn balances_of(&self, address: String) -> Result<PromiseOrValue<Balances>, GatewayError> {
+        let raw_address = parse_address(&address)?;
+        let account_id = self.account_id_of(&raw_address);
+        let tokens = self.tokens.to_vec();
+        let promise = tokens
+            .iter()
+            .map(|token_id| {
+                Promise::new(token_id.clone()).function_call(
+                    "ft_balance_of".to_string(),
+                    json!({ "account_id": account_id }).to_string().into_bytes(),
+                    0,
+                    GAS_FOR_FT_BALANCE_OF,
+                )
+            })
+            .reduce(|all, promise| all.and(promise));
+        let promise = match promise {
+            Some(promise) => promise,
+            None => {
+                return Ok(PromiseOrValue::Value(Balances {
+                    account_id,
+                    ledger: self.get_ledger_balance(address)?,
+                    tokens: vec![],
+                }))
+            }
+        };
+        Ok(promise
+            .then(
+                Promise::new(env::current_account_id()).function_call(
+                    "on_balances".to_string(),
+                    json!({ "address": address, "account_id": account_id, "tokens": tokens })
+                        .to_string()
+                        .into_bytes(),
+                    0,
+                    GAS_FOR_ON_BALANCES,
+                ),
+            )
+            .into())
+    }
+
+    /// Collects the `ft_balance_of` results of `balances_of`, one per token.
+    #[private]
+    #[handle_result]
+    pub fn on_balances(
+        &self,
+        address: String,
+        account_id: AccountId,
+        tokens: Vec<AccountId>,
+    ) -> Result<Balances, GatewayError> {
+        let tokens = tokens
+            .into_iter()
+            .enumerate()
+            .map(|(index, token_id)| {
+                let balance = match env::promise_result(index as u64) {
+                    PromiseResult::Successful(value) => serde_json::from_slice(&value).ok(),
+                    _ => None,
+                };
+                TokenBalance { token_id, balance }
+            })
+            .collect();
+        Ok(Balances {
+            account_id,
+            ledger: self.get_ledger_balance(address)?,
+            tokens,
+        })
+    }
+
+    /// Adds the attached deposit to the caller's paymaster balance.
+    #[payable]
+    pub fn paymaster_deposit(&mut self) {
+        let account_id = env::predecessor_account_id();
+        let mut paymaster = self.paymasters.get(&account_id).unwrap_or(Paymaster {
+            balance: U128(0),
+            receivers: vec![],
+            subsidy: U128(0),
+            max_per_user: U128(0),
+        });
+        paymaster.balance = U128(paymaster.balance.0 + env::attached_deposit());
+        self.paymasters.insert(&account_id, &paymaster);
+    }
+
+    /// Sets the caller's paymaster policy: meta calls to the receivers get `subsidy` paid to
+    /// their relayer, up to `max_per_user` for each address.
+    #[handle_result]
+    pub fn set_paymaster_policy(
+        &mut self,
+        receivers: Vec<String>,
+        subsidy: U128,
+        max_per_user: U128,
+    ) -> Result<(), GatewayError> {
+        let account_id = env::predecessor_account_id();
+        let mut paymaster = self
+            .paymasters
+            .get(&account_id)
+            .ok_or(GatewayError::NoPaymaster)?;
+        paymaster.receivers = receivers;
+        paymaster.subsidy = subsidy;
+        paymaster.max_per_user = max_per_user;
+        self.paymasters.insert(&account_id, &paymaster);
+        Ok(())
+    }
+
+    /// Withdraws from the caller's paymaster balance.
+    #[handle_result]
+    pub fn paymaster_withdraw(&mut self, amount: U128) -> Result<Promise, GatewayError> {
+        let account_id = env::predecessor_account_id();
+        let mut paymaster = self
+            .paymasters
+            .get(&account_id)
+            .ok_or(GatewayError::NoPaymaster)?;
+        ensure(
+            amount.0 <= paymaster.balance.0,
+            GatewayError::NotEnoughBalance,
+        )?;
+        paymaster.balance = U128(paymaster.balance.0 - amount.0);
+        self.paymasters.insert(&account_id, &paymaster);
+        Ok(Promise::new(account_id).transfer(amount.0))
+    }
+
+    /// Balance and policy of the paymaster account.
+    pub fn get_paymaster(&self, account_id: AccountId) -> Option<Paymaster> {
+        self.paymasters.get(&account_id)
+    }
+
+    /// Subsidies the paymaster paid for the hex encoded address so far.
+    #[handle_result]
+    pub fn get_subsidy(&self, paymaster: AccountId, address: String) -> Result<U128, GatewayError> {
+        Ok(U128(
+            self.subsidies
+                .get(&(paymaster, parse_address(&address)?))
+                .unwrap_or_default(),
+        ))
+    }
+
+    /// Like `proxy`, sponsored by the paymaster: if the message calls one of its receivers, the
+    /// paymaster pays the relayer its subsidy, so users without funds for fees can still act.
+    /// The subsidy is set aside until the message executes, and returned to the paymaster if it
+    /// fails, see `on_sponsored`.
+    #[handle_result]
+    pub fn proxy_sponsored(
+        &mut self,
+        paymaster: AccountId,
+        message: SignedMessage,
+    ) -> Result<Promise, GatewayError> {
+        let args = self.parse_message(message)?;
+        let mut policy = self
+            .paymasters
+            .get(&paymaster)
+            .ok_or(GatewayError::NoPaymaster)?;
+        ensure(
+            policy.receivers.contains(&args.contract_address),
+            GatewayError::NotSponsored,
+        )?;
+        let id = (paymaster.clone(), args.sender.0);
+        let subsidized = self.subsidies.get(&id).unwrap_or_default() + policy.subsidy.0;
+        ensure(
+            subsidized <= policy.max_per_user.0,
+            GatewayError::SubsidyExceeded,
+        )?;
+        ensure(
+            policy.subsidy.0 <= policy.balance.0,
+            GatewayError::NotEnoughBalance,
+        )?;
+        policy.balance = U128(policy.balance.0 - policy.subsidy.0);
+        self.paymasters.insert(&paymaster, &policy);
+        self.subsidies.insert(&id, &subsidized);
+        let sender = args.sender.0;
+        let account = self.account_of(&sender)?;
+        self.spend(&account, spent(&args), false)?;
+        let promise = self.proxy_call(Promise::new(self.acting_account_id(&account)?), args)?;
+        Ok(promise.then(on_sponsored_callback(&paymaster, &sender, policy.subsidy.0)))
+    }
+
+    /// Pays the relayer the subsidy of the address' message sponsored by the paymaster once it
+    /// executed, logging a NEP-297 `sponsor` event. If it failed, the subsidy goes back to the
+    /// paymaster's balance and no longer counts towards the address' `max_per_user`.
+    #[private]
+    #[handle_result]
+    pub fn on_sponsored(
+        &mut self,
+        paymaster: AccountId,
+        address: String,
+        relayer: AccountId,
+        subsidy: U128,
+    ) -> Result<(), GatewayError> {
+        let address = parse_address(&address)?;
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            GatewayEvent::Sponsor(vec![SponsorData {
+                paymaster,
+                address: checksum_address(&address),
+                relayer: relayer.clone(),
+                amount: subsidy,
+            }])
+            .emit();
+            if subsidy.0 > 0 {
+                Promise::new(relayer).transfer(subsidy.0);
+            }
+            return Ok(());
+        }
+        if let Some(mut policy) = self.paymasters.get(&paymaster) {
+            policy.balance = U128(policy.balance.0 + subsidy.0);
+            self.paymasters.insert(&paymaster, &policy);
+        }
+        let id = (paymaster, address);
+        let subsidized = self.subsidies.get(&id).unwrap_or_default();
+        self.subsidies
+            .insert(&id, &subsidized.saturating_sub(subsidy.0));
+        Ok(())
+    }
+
+    /// Sets the price of the NEP-141 token for paying relayer fees: `token_amount` of it is worth
+    /// `near_amount` yoctoNEAR. Zero `near_amount` stops accepting it. Only the gateway account
+    /// can call it, e.g. from a price oracle.
+    #[handle_result]
+    pub fn set_fee_token(
+        &mut self,
+        token: AccountId,
+        token_amount: U128,
+        near_amount: U128,
+    ) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        if near_amount.0 == 0 {
+            self.fee_tokens.remove(&token);
+        } else {
+            ensure(token_amount.0 > 0, GatewayError::InvalidPrice)?;
+            self.fee_tokens.insert(
+                &token,
+                &FeeToken {
+                    token_amount,
+                    near_amount,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Price of the fee token, if it's accepted.
+    pub fn get_fee_token(&self, token: AccountId) -> Option<FeeToken> {
+        self.fee_tokens.get(&token)
+    }
+
+    /// Adds the attached deposit to the NEAR relayers are paid from for fees in tokens.
+    #[payable]
+    pub fn gas_station_deposit(&mut self) {
+        self.gas_station += env::attached_deposit();
+    }
+
+    /// NEAR left for paying relayers for fees in tokens.
+    pub fn get_gas_station(&self) -> U128 {
+        U128(self.gas_station)
+    }
+
+    /// Like `proxy`, for messages whose `fee_address` is a fee token: the proxy account sends
+    /// `fee_amount` of it to the gateway with `ft_transfer_call`, and once it arrives the calling
+    /// relayer is paid its worth in NEAR from the gas station. Users without NEAR can pay for
+    /// relaying this way. The gateway keeps the tokens, see `withdraw_fee_tokens`.
+    #[handle_result]
+    pub fn proxy_token_fee(&mut self, message: SignedMessage) -> Result<Promise, GatewayError> {
+        let args = self.parse_message(message)?;
+        let token =
+            AccountId::try_from(args.fee_address.clone()).map_err(|_| GatewayError::NotFeeToken)?;
+        let price = self
+            .fee_tokens
+            .get(&token)
+            .ok_or(GatewayError::NotFeeToken)?;
+        let near = U256::from(args.fee_amount) * U256::from(price.near_amount.0)
+            / U256::from(price.token_amount.0);
+        // Compared before converting, so worth above any balance fails the same way.
+        ensure(
+            near <= U256::from(self.gas_station),
+            GatewayError::NotEnoughBalance,
+        )?;
+        let near = near.as_u128();
+        let account = self.account_of(&args.sender.0)?;
+        let account_id = self.acting_account_id(&account)?;
+        let fee_id = self.next_fee_id;
+        self.next_fee_id += 1;
+        self.pending_fees.insert(
+            &fee_id,
+            &PendingFee {
+                account_id: account_id.clone(),
+                token,
+                amount: args.fee_amount,
+                relayer: env::predecessor_account_id(),
+                near,
+            },
+        );
+        let fee_args = CallArgs {
+            gas: GAS_FOR_TRANSFER_CALL.0,
+            amount: 1,
+            receiver_id: args.fee_address.clone(),
+            method_name: "ft_transfer_call".to_string(),
+            args: json!({
+                "receiver_id": env::current_account_id(),
+                "amount": U128(args.fee_amount),
+                "msg": fee_id.to_string(),
+            })
+            .to_string()
+            .into_bytes(),
+        };
+        let fee_gas = GAS_FOR_TRANSFER_CALL + GAS_FOR_PROXY;
+        // Same batch as the message's call, so the message doesn't execute without its fee.
+        let promise = Promise::new(account_id).function_call(
+            "call".to_string(),
+            fee_args.try_to_vec().unwrap(),
+            0,
+            fee_gas,
+        );
+        self.spend(&account, spent(&args), false)?;
+        self.proxy_call_reserving(promise, args, fee_gas)
+    }
+
+    /// NEP-141 receiver of the token fees of `proxy_token_fee`: pays the relayer once the fee
+    /// with the id in `msg` arrives in full. Any other transfer is refunded, as is the fee if
+    /// the gas station ran out in the meantime.
+    pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> U128 {
+        let fee_id = match msg.parse() {
+            Ok(fee_id) => fee_id,
+            Err(_) => return amount,
+        };
+        let fee = match self.pending_fees.get(&fee_id) {
+            Some(fee) => fee,
+            None => return amount,
+        };
+        // Other transfers naming the fee leave it pending for the real one.
+        let matches = fee.token == env::predecessor_account_id()
+            && fee.account_id == sender_id
+            && fee.amount == amount.0;
+        if !matches || fee.near > self.gas_station {
+            return amount;
+        }
+        self.pending_fees.remove(&fee_id);
+        self.gas_station -= fee.near;
+        if fee.near > 0 {
+            Promise::new(fee.relayer).transfer(fee.near);
+        }
+        U128(0)
+    }
+
+    /// Sends the fee tokens the gateway collected, e.g. to swap them for NEAR to refill the gas
+    /// station. Only the gateway account can call it.
+    #[handle_result]
+    pub fn withdraw_fee_tokens(
+        &mut self,
+        token: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> Result<Promise, GatewayError> {
+        check_gateway_caller()?;
+        Ok(Promise::new(token).function_call(
+            "ft_transfer".to_string(),
+            json!({ "receiver_id": receiver_id, "amount": amount })
+                .to_string()
+                .into_bytes(),
+            1,
+            Gas(self.gas_schedule.call.0),
+        ))
+    }
+
+    /// Like `proxy`, but authenticated by signed legacy or EIP-1559 Ethereum transaction, for wallets that can't sign
+    /// typed data. Transaction without data transfers its value to the proxy account of `to`,
+    /// otherwise its data is `rlp([receiver, method_def, args])` of the call. It must be signed
+    /// for `get_eth_chain_id()`. The history records it under the transaction hash.
+    /// See `parse_eth_transaction` for the mapping.
+    #[handle_result]
+    pub fn submit_eth_tx(
+        &mut self,
+        rlp: Base64VecU8,
+    ) -> Result<PromiseOrValue<MessageOutcome>, GatewayError> {
+        self.count_submission()?;
+        let (args, digest) = self.verify_eth_tx(&rlp.0)?;
+        self.proxy_verified(args, digest)
+    }
+
+    /// Sets the guardians of the signer's proxy account, from the message calling
+    /// `set_guardians(address[] guardians,uint32 threshold,uint64 delay)` on the gateway.
+    /// `threshold` of the guardians can rotate the controlling address with `recover`, executed
+    /// `delay` seconds later unless cancelled. No guardians remove them.
+    #[handle_result]
+    pub fn set_guardians(&mut self, message: SignedMessage) -> Result<(), GatewayError> {
+        let args = self.parse_message(message)?;
+        let guardians: Guardians = gateway_call_args(&args, "set_guardians")?;
+        let account = self.account_of(&args.sender.0)?;
+        if guardians.guardians.is_empty() {
+            self.guardians.remove(&account);
+            return Ok(());
+        }
+        ensure(
+            guardians.threshold > 0 && guardians.threshold as usize <= guardians.guardians.len(),
+            GatewayError::InvalidGuardians,
+        )?;
+        self.guardians.insert(&account, &guardians);
+        Ok(())
+    }
+
+    /// Guardians of the proxy account of the hex encoded address, if it has any.
+    #[handle_result]
+    pub fn get_guardians(&self, address: String) -> Result<Option<Guardians>, GatewayError> {
+        Ok(self.guardians.get(&parse_address(&address)?))
+    }
+
+    /// Makes the signer's proxy account multi-signature, from the message calling
+    /// `set_signers(address[] signers,uint32 threshold)` on the gateway: from then on its
+    /// messages carry signatures of `threshold` of the distinct signers, with the account, or
+    /// its controller after a rotation, as sender. The signers change or remove (no signers) the
+    /// set with a message of their own.
+    #[handle_result]
+    pub fn set_signers(&mut self, message: SignedMessage) -> Result<(), GatewayError> {
+        let args = self.parse_message(message)?;
+        let signer_set: SignerSet = gateway_call_args(&args, "set_signers")?;
+        let account = self.account_of(&args.sender.0)?;
+        if signer_set.signers.is_empty() {
+            self.signer_sets.remove(&account);
+            return Ok(());
+        }
+        ensure(
+            signer_set.threshold > 0 && signer_set.threshold as usize <= signer_set.signers.len(),
+            GatewayError::InvalidSignerSet,
+        )?;
+        for (i, signer) in signer_set.signers.iter().enumerate() {
+            ensure(
+                !signer_set.signers[..i].contains(signer),
+                GatewayError::DuplicateSigner,
+            )?;
+        }
+        self.signer_sets.insert(&account, &signer_set);
+        Ok(())
+    }
+
+    /// Signer set of the multi-signature proxy account of the hex encoded address, if it is one.
+    #[handle_result]
+    pub fn get_signers(&self, address: String) -> Result<Option<SignerSet>, GatewayError> {
+        Ok(self.signer_sets.get(&parse_address(&address)?))
+    }
+
+    /// Links the calling NEAR account to the signer, from the message calling
+    /// `link_account(string account_id)` on the gateway with the caller's account id, so both
+    /// sides authorize it. The signer's messages then act from the linked account instead of a
+    /// proxy account, which it can't have: the linked account must accept `call` and `transfer`
+    /// from the gateway as the proxy contract does. Funds escrowed for the address go to it.
+    #[handle_result]
+    pub fn link_account(
+        &mut self,
+        message: SignedMessage,
+    ) -> Result<Option<Promise>, GatewayError> {
+        let args = self.parse_message(message)?;
+        let link: LinkAccountArgs = gateway_call_args(&args, "link_account")?;
+        let account = self.account_of(&args.sender.0)?;
+        self.link(&account, link.account_id)
+    }
+
+    /// Like `link_account`, authorized by a SIWE message for the gateway's domain with a
+    /// `near:link:<account_id>` resource of the caller's account id, and its `personal_sign`
+    /// signature.
+    #[handle_result]
+    pub fn link_account_siwe(
+        &mut self,
+        message: String,
+        signature: Base64VecU8,
+    ) -> Result<Option<Promise>, GatewayError> {
+        let (siwe, account) = self.use_siwe(&message, &signature.0)?;
+        let account_ids = siwe_resources(&siwe, "link");
+        ensure(account_ids.len() == 1, GatewayError::SiweResources)?;
+        let account_id = AccountId::try_from(account_ids[0].to_string())
+            .map_err(|_| GatewayError::LinkMismatch)?;
+        self.link(&account, account_id)
+    }
+
+    /// Links the calling NEAR account, which must be `account_id`, to the address.
+    fn link(
+        &mut self,
+        address: &RawAddress,
+        account_id: AccountId,
+    ) -> Result<Option<Promise>, GatewayError> {
+        ensure(
+            account_id == env::predecessor_account_id(),
+            GatewayError::LinkMismatch,
+        )?;
+        ensure(
+            !self.created.contains(address),
+            GatewayError::AlreadyCreated,
+        )?;
+        ensure(
+            self.linked_accounts.insert(address, &account_id).is_none(),
+            GatewayError::AlreadyLinked,
+        )?;
+        Ok(self
+            .escrow
+            .remove(address)
+            .map(|escrowed| Promise::new(account_id).transfer(escrowed)))
+    }
+
+    /// Unlinks the calling NEAR account from the hex encoded address, ending the address'
+    /// access to it.
+    #[handle_result]
+    pub fn unlink_account(&mut self, address: String) -> Result<(), GatewayError> {
+        let address = parse_address(&address)?;
+        ensure(
+            self.linked_accounts.get(&address) == Some(env::predecessor_account_id()),
+            GatewayError::NotLinked,
+        )?;
+        self.linked_accounts.remove(&address);
+        Ok(())
+    }
+
+    /// NEAR account linked to the hex encoded address, if any.
+    #[handle_result]
+    pub fn get_linked_account(&self, address: String) -> Result<Option<AccountId>, GatewayError> {
+        Ok(self.linked_accounts.get(&parse_address(&address)?))
+    }
+
+    /// Starts rotating the controlling address of a proxy account to a new one, from messages of
+    /// at least `threshold` of its guardians calling `recover(address account,address new_controller)`
+    /// on the gateway. Executable with `execute_rotation` after the guardians' delay, until then
+    /// the current controller can cancel it with `cancel_rotation`.
+    #[handle_result]
+    pub fn recover(&mut self, messages: Vec<SignedMessage>) -> Result<(), GatewayError> {
+        let mut target: Option<(RawAddress, RawAddress)> = None;
+        let mut approvals: Vec<RawAddress> = vec![];
+        for message in messages {
+            let args = self.parse_message(message)?;
+            let recover: RecoverArgs = gateway_call_args(&args, "recover")?;
+            let recovery = (recover.account, recover.new_controller);
+            ensure(
+                target.get_or_insert(recovery) == &recovery,
+                GatewayError::RecoveryMismatch,
+            )?;
+            ensure(
+                !approvals.contains(&args.sender.0),
+                GatewayError::DuplicateGuardian,
+            )?;
+            approvals.push(args.sender.0);
+        }
+        let (account, new_controller) = target.ok_or(GatewayError::NotEnoughGuardians)?;
+        let guardians = self
+            .guardians
+            .get(&account)
+            .ok_or(GatewayError::NoGuardians)?;
+        ensure(
+            approvals
+                .iter()
+                .all(|guardian| guardians.guardians.contains(guardian)),
+            GatewayError::NotGuardian,
+        )?;
+        ensure(
+            approvals.len() >= guardians.threshold as usize,
+            GatewayError::NotEnoughGuardians,
+        )?;
+        self.pending_rotations.insert(
+            &account,
+            &PendingRotation {
+                new_controller,
+                executable_at: U64(block_seconds().saturating_add(guardians.delay.0)),
+            },
+        );
+        Ok(())
+    }
+
+    /// Starts rotating the controlling address of the signer's proxy account to a new address,
+    /// from the message calling `rotate_owner(address new_address)` on the gateway. Executable
+    /// with `execute_rotation` after `get_rotation_delay` seconds, until then the signer can
+    /// cancel it with `cancel_rotation`.
+    #[handle_result]
+    pub fn rotate_owner(&mut self, message: SignedMessage) -> Result<(), GatewayError> {
+        let args = self.parse_message(message)?;
+        let rotate: RotateOwnerArgs = gateway_call_args(&args, "rotate_owner")?;
+        let account = self.account_of(&args.sender.0)?;
+        self.schedule_rotation(&account, &args.sender.0, rotate.new_address)?;
+        Ok(())
+    }
+
+    /// Starts rotating the account's controlling address from the sender to the new one,
+    /// executable after `rotation_delay` seconds.
+    fn schedule_rotation(
+        &mut self,
+        account: &RawAddress,
+        sender: &RawAddress,
+        new_controller: RawAddress,
+    ) -> Result<(), GatewayError> {
+        ensure(&new_controller != sender, GatewayError::AddressInUse)?;
+        self.pending_rotations.insert(
+            account,
+            &PendingRotation {
+                new_controller,
+                executable_at: U64(block_seconds().saturating_add(self.rotation_delay)),
+            },
+        );
+        Ok(())
+    }
+
+    /// Delay in seconds between `rotate_owner` and when the rotation can be executed.
+    pub fn get_rotation_delay(&self) -> U64 {
+        U64(self.rotation_delay)
+    }
+
+    /// Sets the delay of rotations started with `rotate_owner`. Only the gateway account can
+    /// call it.
+    #[handle_result]
+    pub fn set_rotation_delay(&mut self, delay: U64) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        self.rotation_delay = delay.0;
+        Ok(())
+    }
+
+    /// Sets how many messages the relayer may submit per epoch, or every relayer without its own
+    /// quota if none is given. No quota lifts the limit, or for a relayer falls back to the
+    /// default. Only the gateway account can call it.
+    #[handle_result]
+    pub fn set_relayer_quota(
+        &mut self,
+        relayer: Option<AccountId>,
+        quota: Option<U64>,
+    ) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        match (relayer, quota) {
+            (Some(relayer), Some(quota)) => {
+                self.relayer_quotas.insert(&relayer, &quota.0);
+            }
+            (Some(relayer), None) => {
+                self.relayer_quotas.remove(&relayer);
+            }
+            (None, quota) => self.relayer_quota = quota.map(|quota| quota.0),
+        }
+        Ok(())
+    }
+
+    /// Messages the relayer may submit per epoch, none if it isn't limited.
+    pub fn get_relayer_quota(&self, relayer: AccountId) -> Option<U64> {
+        self.relayer_quotas
+            .get(&relayer)
+            .or(self.relayer_quota)
+            .map(U64)
+    }
+
+    /// Messages the relayer submitted in this epoch, counted while it has a quota.
+    pub fn get_relayer_usage(&self, relayer: AccountId) -> U64 {
+        U64(match self.relayer_usage.get(&relayer) {
+            Some((epoch, submitted)) if epoch == env::epoch_height() => submitted,
+            _ => 0,
+        })
+    }
+
+    /// Sets the circuit breaker capping the value `proxy` executes per epoch, none removes it.
+    /// Only the gateway account can call it.
+    #[handle_result]
+    pub fn set_circuit_breaker(
+        &mut self,
+        circuit_breaker: Option<CircuitBreaker>,
+    ) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        self.circuit_breaker = circuit_breaker;
+        Ok(())
+    }
+
+    /// Lifts a tripped circuit breaker before the epoch ends, counting the epoch's value from
+    /// zero again. Only the gateway account can call it.
+    #[handle_result]
+    pub fn lift_circuit_breaker(&mut self) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        self.epoch_value = (env::epoch_height(), 0);
+        Ok(())
+    }
+
+    /// Circuit breaker capping the value `proxy` executes per epoch, if any.
+    pub fn get_circuit_breaker(&self) -> Option<CircuitBreaker> {
+        self.circuit_breaker.clone()
+    }
+
+    /// Value of the messages `proxy` executed in this epoch, counted while there's a circuit
+    /// breaker.
+    pub fn get_epoch_value(&self) -> U128 {
+        U128(match self.epoch_value {
+            (epoch, total) if epoch == env::epoch_height() => total,
+            _ => 0,
+        })
+    }
+
+    /// Enables or disables logging each executed meta call as an Ethereum log besides the NEP-297 events.
+    /// Only the gateway account can call it.
+    #[handle_result]
+    pub fn set_eth_logs(&mut self, enabled: bool) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        self.eth_logs = enabled;
+        Ok(())
+    }
+
+    /// Whether each meta call is also logged as an Ethereum log.
+    pub fn get_eth_logs(&self) -> bool {
+        self.eth_logs
+    }
+
+    /// Sets the size limits of messages. Only the gateway account can call it.
+    #[handle_result]
+    pub fn set_message_limits(&mut self, limits: MessageLimits) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        self.message_limits = limits;
+        Ok(())
+    }
+
+    /// Longest method definition and arguments messages may carry, in bytes.
+    pub fn get_message_limits(&self) -> MessageLimits {
+        self.message_limits.clone()
+    }
+
+    /// Account creating new proxy accounts, if not the gateway.
+    pub fn get_factory(&self) -> Option<AccountId> {
+        self.factory.clone()
+    }
+
+    /// Sets the account creating new proxy accounts as `<hex address>.<factory>`, or the
+    /// gateway itself if none. Accounts created before keep their names. The factory's
+    /// `create_proxy(name)` must create the named sub-account with the attached deposit and a
+    /// proxy contract accepting `call` and `transfer` from the gateway, returning the promise.
+    /// Only the gateway account can call it.
+    #[handle_result]
+    pub fn set_factory(&mut self, factory: Option<AccountId>) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        self.factory = factory;
+        Ok(())
+    }
+
+    /// Receivers and methods the gateway doesn't forward calls to.
+    pub fn get_denylist(&self) -> Denylist {
+        self.denylist.clone()
+    }
+
+    /// Sets the receivers and methods the gateway refuses to forward calls to. Messages calling
+    /// them fail with `ERR_POLICY_VIOLATION`, their nonce stays unused. Only the gateway account
+    /// can call it.
+    #[handle_result]
+    pub fn set_denylist(&mut self, denylist: Denylist) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        self.denylist = denylist;
+        Ok(())
+    }
+
+    /// Gas budgets of the actions taken for messages.
+    pub fn get_gas_schedule(&self) -> GasSchedule {
+        self.gas_schedule.clone()
+    }
+
+    /// Sets the gas budgets of the actions taken for messages. Only the gateway account can
+    /// call it.
+    #[handle_result]
+    pub fn set_gas_schedule(&mut self, gas_schedule: GasSchedule) -> Result<(), GatewayError> {
+        check_gateway_caller()?;
+        self.gas_schedule = gas_schedule;
+        Ok(())
+    }
+
+    /// Cancels the pending rotation of the signer's proxy account, from the message calling
+    /// `cancel_rotation()` on the gateway.
+    #[handle_result]
+    pub fn cancel_rotation(&mut self, message: SignedMessage) -> Result<(), GatewayError> {
+        let args = self.parse_message(message)?;
+        check_gateway_call(&args, "cancel_rotation")?;
+        let account = self.account_of(&args.sender.0)?;
+        ensure(
+            self.pending_rotations.remove(&account).is_some(),
+            GatewayError::NoRotation,
+        )?;
+        Ok(())
+    }
+
+    /// Pending rotation of the controlling address of the hex encoded address' proxy account.
+    #[handle_result]
+    pub fn get_pending_rotation(
+        &self,
+        address: String,
+    ) -> Result<Option<PendingRotation>, GatewayError> {
+        Ok(self.pending_rotations.get(&parse_address(&address)?))
+    }
+
+    /// Executes the pending rotation of the hex encoded address' proxy account once its
+    /// timelock passed. Anyone can call it.
+    #[handle_result]
+    pub fn execute_rotation(&mut self, address: String) -> Result<(), GatewayError> {
+        let account = parse_address(&address)?;
+        let rotation = self
+            .pending_rotations
+            .remove(&account)
+            .ok_or(GatewayError::NoRotation)?;
+        ensure(
+            block_seconds() >= rotation.executable_at.0,
+            GatewayError::RotationTimelock,
+        )?;
+        self.rotate_controller(&account, &rotation.new_controller)?;
+        Ok(())
+    }
+
+    /// Hex encoded address controlling the proxy account of the hex encoded address.
+    #[handle_result]
+    pub fn get_controller(&self, address: String) -> Result<String, GatewayError> {
+        let account = parse_address(&address)?;
+        let controller = self.controllers.get(&account).unwrap_or(account);
+        Ok(checksum_address(&controller))
+    }
+
+    /// Makes the new address control the proxy account instead of the current controller. The
+    /// new address continues from the higher of the two sequential nonces, so neither its own
+    /// nor the account's earlier messages can be replayed.
+    fn rotate_controller(
+        &mut self,
+        account: &RawAddress,
+        new_controller: &RawAddress,
+    ) -> Result<(), GatewayError> {
+        let old_controller = self.controllers.get(account).unwrap_or(*account);
+        ensure(
+            new_controller == account
+                || !(self.created.contains(new_controller)
+                    || self.controlled.contains_key(new_controller)),
+            GatewayError::AddressInUse,
+        )?;
+        let nonce = std::cmp::max(
+            self.current_nonce(&old_controller, U256::zero()),
+            self.current_nonce(new_controller, U256::zero()),
+        );
+        self.set_sequential_nonce(new_controller, nonce)?;
+        if old_controller != *account {
+            self.controlled.remove(&old_controller);
+        }
+        if new_controller == account {
+            self.controllers.remove(account);
+        } else {
+            self.controllers.insert(account, new_controller);
+            self.controlled.insert(new_controller, account);
+        }
+        Ok(())
+    }
+
+    /// Chain id Ethereum transactions submitted to `submit_eth_tx` are signed for, specific to
+    /// the gateway, see `gateway_chain_id`.
+    pub fn get_eth_chain_id(&self) -> U64 {
+        U64(gateway_core::meta_parsing::gateway_chain_id(
+            env::current_account_id().as_bytes(),
+        ))
+    }
+
+    /// Refuses messages to receivers or methods on the denylist, logging a NEP-297
+    /// `policy_violation` event before failing, so operators see the attempts.
+    fn check_allowed(&self, args: &InternalMetaCallArgs) -> Result<(), GatewayError> {
+        let receiver_denied = self.denylist.receivers.contains(&args.contract_address);
+        let method_denied =
+            !args.method_name.is_empty() && self.denylist.methods.contains(&args.method_name);
+        if !receiver_denied && !method_denied {
+            return Ok(());
+        }
+        GatewayEvent::PolicyViolation(vec![PolicyViolationData {
+            address: checksum_address(&args.sender.0),
+            receiver: args.contract_address.clone(),
+            method: args.method_name.clone(),
+        }])
+        .emit();
+        Err(GatewayError::PolicyViolation)
+    }
+
+    /// Adds the transfer or call from the sender's proxy account the verified meta call says
+    /// to the promise on that account.
+    fn proxy_call(
+        &self,
+        promise: Promise,
+        args: InternalMetaCallArgs,
+    ) -> Result<Promise, GatewayError> {
+        self.proxy_call_reserving(promise, args, Gas(0))
+    }
+
+    /// `proxy_call` leaving out `reserved` gas of what's left, for actions already on the
+    /// promise, which aren't counted as used until the promise is scheduled.
+    fn proxy_call_reserving(
+        &self,
+        promise: Promise,
+        args: InternalMetaCallArgs,
+        reserved: Gas,
+    ) -> Result<Promise, GatewayError> {
+        // Only `proxy` manages accounts.
+        ensure(
+            args.contract_address != SELF_RECEIVER,
+            GatewayError::SelfReceiver,
+        )?;
+        self.check_allowed(&args)?;
+        if self.eth_logs {
+            emit_eth_log(&args);
+        }
+        let schedule = &self.gas_schedule;
+        let tip = args.tip;
+        let gas_for_tip = if tip > 0 {
+            Gas(schedule.transfer.0)
+        } else {
+            Gas(0)
+        };
+        // Keeps enough for a callback following the call, like `proxy` resolving to its outcome.
+        let gas = remaining_gas() - reserved - gas_for_tip - GAS_FOR_ON_PROXY;
+        // The relayer's deposit goes along with the call, and the proxy account spends exactly it.
+        let deposit = match args.deposit_source {
+            DepositSource::Account => 0,
+            DepositSource::Relayer => {
+                ensure(
+                    env::attached_deposit() == args.value,
+                    GatewayError::RelayerDeposit,
+                )?;
+                GatewayEvent::RelayerDeposit(vec![RelayerDepositData {
+                    address: checksum_address(&args.sender.0),
+                    relayer: env::predecessor_account_id(),
+                    amount: U128(args.value),
+                }])
+                .emit();
+                args.value
+            }
+        };
+        let promise = if args.method_name.is_empty() {
+            promise.function_call(
+                "transfer".to_string(),
+                transfer_args(args.value, &args.contract_address),
+                deposit,
+                gas.min(Gas(schedule.transfer.0)),
+            )
+        } else {
+            // The proxy account gets just enough for itself on top of the method's gas, the rest
+            // of the prepaid gas isn't spent.
+            let method_gas = method_gas(
+                &args,
+                Gas(schedule.call.0),
+                Gas(gas.0.saturating_sub(GAS_FOR_PROXY.0)),
+            )?;
+            let call_args = CallArgs {
+                gas: method_gas.0,
+                amount: args.value,
+                receiver_id: args.contract_address,
+                method_name: args.method_name,
+                args: args.args,
+            };
+            let call_args_bytes = call_args.try_to_vec().unwrap();
+            promise.function_call(
+                "call".to_string(),
+                call_args_bytes,
+                deposit,
+                method_gas + GAS_FOR_PROXY,
+            )
+        };
+        if tip == 0 {
+            return Ok(promise);
+        }
+        // Same batch as the call, so the tip is only paid if the proxy account executes it.
+        Ok(promise.function_call(
+            "transfer".to_string(),
+            transfer_args(tip, env::predecessor_account_id().as_str()),
+            0,
+            gas_for_tip,
+        ))
+    }
+
+    /// Semantic version of the gateway build.
+    pub fn version(&self) -> String {
+        env!("CARGO_PKG_VERSION").to_string()
+    }
+
+    /// NEP-330 source metadata: the version of the build, its repository and the standards the
+    /// gateway implements.
+    pub fn contract_source_metadata(&self) -> ContractSourceMetadata {
+        let standard = |standard: &str, version: &str| Standard {
+            standard: standard.to_string(),
+            version: version.to_string(),
+        };
+        ContractSourceMetadata {
+            version: Some(self.version()),
+            link: Some(env!("CARGO_PKG_REPOSITORY").to_string()),
+            standards: vec![standard("nep330", "1.1.0"), standard("nep297", "1.0.0")],
+        }
+    }
+
+    /// Hex encoded EIP-712 domain separator messages are signed under.
+    pub fn domain_separator(&self) -> String {
+        format!("0x{}", hex::encode(self.domain_separator))
+    }
+
+    /// Chain id of the EIP-712 domain.
+    pub fn get_chain_id(&self) -> U64 {
+        U64(CHAIN_ID)
+    }
+
+    /// EIP-712 domain fields and message types, so wallet SDKs build typed data from the gateway
+    /// instead of hardcoding it.
+    pub fn get_typed_data_info(&self) -> TypedDataInfo {
+        TypedDataInfo {
+            domain_type: meta_parsing::EIP712_DOMAIN_TYPE.to_string(),
+            name: meta_parsing::NEAR_DOMAIN_NAME.to_string(),
+            version: meta_parsing::NEAR_DOMAIN_VERSION.to_string(),
+            chain_id: U64(CHAIN_ID),
+            domain_separator: self.domain_separator(),
+            near_tx_type: meta_parsing::NEAR_TX_TYPE.to_string(),
+            near_tx_flat_type: meta_parsing::NEAR_TX_FLAT_TYPE.to_string(),
+        }
+    }
+
+    /// Computes EIP-712 encoding of the message without checking signature and nonce.
+    /// Allows wallets to find mismatches with their local hashing without sending transactions.
+    #[handle_result]
+    pub fn hash_meta_call(&self, message: SignedMessage) -> Result<MetaCallHash, GatewayError> {
+        let encoding = gateway_core::meta_parsing::encode_unsigned_meta_call(
+            &self.domain_separator,
+            env::current_account_id().as_bytes(),
+            message.0,
+            |method_id| self.method_defs.get(u64::from(method_id)),
+        )?;
+        Ok(MetaCallHash {
+            digest: format!("0x{}", hex::encode(encoding.digest)),
+            type_string: encoding.type_string,
+            type_hash: format!("0x{}", hex::encode(encoding.type_hash)),
+            encoded_struct: format!("0x{}", hex::encode(encoding.encoded_struct)),
+            arguments_hash: format!("0x{}", hex::encode(encoding.arguments_hash)),
+        })
+    }
+
+    /// Parses and verifies given message like `proxy` does, but without consuming the nonce.
+    /// Returns the address of the signing key, the signed digest and whether the message nonce is the expected one.
+    #[handle_result]
+    pub fn recover_signer(&self, message: SignedMessage) -> Result<RecoveredSigner, GatewayError> {
+        Ok(gateway_core::meta_parsing::recover_signer(
+            &self.domain_separator,
+            CHAIN_ID,
+            env::current_account_id().as_bytes(),
+            message.0,
+            |method_id| self.method_defs.get(u64::from(method_id)),
+            |sender, nonce| self.expected_nonce(&sender.0, nonce),
+        )?)
+    }
+
+    /// Checks the message like `proxy` would, without executing it: parsing, signatures, nonce,
+    /// the acting account, the denylist, circuit breaker and spending limit, and for messages
+    /// paying a fee token whether the gas station covers its worth for `proxy_token_fee`. With
+    /// the relayer that would submit it, its quota too. Lets relayers skip messages that would
+    /// fail before submitting them.
+    pub fn dry_run(&self, message: SignedMessage, relayer: Option<AccountId>) -> DryRunVerdict {
+        let (args, digest) = match self.verify_message(message) {
+            Ok(verified) => verified,
+            Err(error) => {
+                return DryRunVerdict {
+                    address: None,
+                    digest: None,
+                    account_id: None,
+                    deposit: U128(0),
+                    error: Some(error.to_string()),
+                }
+            }
+        };
+        let account = self.controlled.get(&args.sender.0).unwrap_or(args.sender.0);
+        DryRunVerdict {
+            address: Some(checksum_address(&args.sender.0)),
+            digest: Some(format!("0x{}", hex::encode(digest))),
+            account_id: Some(self.account_id_of(&account)),
+            deposit: U128(match args.deposit_source {
+                DepositSource::Account => 0,
+                DepositSource::Relayer => args.value,
+            }),
+            error: self
+                .dry_run_error(relayer.as_ref(), &account, &args)
+                .map(|error| error.to_string()),
+        }
+    }
+
+    /// First check of `dry_run` the verified message fails, submitted by the relayer and acting
+    /// for the account.
+    fn dry_run_error(
+        &self,
+        relayer: Option<&AccountId>,
+        account: &RawAddress,
+        args: &InternalMetaCallArgs,
+    ) -> Option<GatewayError> {
+        let rate_limited = relayer
+            .and_then(|relayer| self.relayer_submissions(relayer))
+            .is_some_and(|(submitted, quota)| submitted >= quota);
+        if rate_limited {
+            return Some(GatewayError::RateLimited);
+        }
+        if self.expected_nonce(&args.sender.0, args.nonce) != args.nonce {
+            return Some(GatewayError::IncorrectNonce);
+        }
+        if *account == args.sender.0 && self.controllers.contains_key(account) {
+            return Some(GatewayError::NotController);
+        }
+        let locked = self.locks.contains_key(account);
+        if args.contract_address == SELF_RECEIVER {
+            if args.value > 0 || args.tip > 0 {
+                return Some(GatewayError::SelfValue);
+            }
+            if locked && !matches!(args.method_name.as_str(), "unlock" | "set_controller") {
+                return Some(GatewayError::AccountLocked);
+            }
+            return None;
+        }
+        if self.implicit_accounts.contains_key(account) {
+            return Some(GatewayError::ImplicitAccount);
+        }
+        if self.standalone_accounts.contains(account) {
+            return Some(GatewayError::StandaloneAccount);
+        }
+        if locked {
+            return Some(GatewayError::AccountLocked);
+        }
+        if self.denylist.receivers.contains(&args.contract_address)
+            || (!args.method_name.is_empty() && self.denylist.methods.contains(&args.method_name))
+        {
+            return Some(GatewayError::PolicyViolation);
+        }
+        if let Err(error) = self.check_circuit_breaker(spent(args)) {
+            return Some(error);
+        }
+        if let Some(limit) = self.spending_limits.get(account) {
+            let period_over =
+                block_seconds() >= limit.period_start.0.saturating_add(limit.period.0);
+            let already_spent = if period_over { 0 } else { limit.spent.0 };
+            if already_spent.saturating_add(spent(args)) > limit.amount.0 {
+                return Some(GatewayError::SpendingLimit);
+            }
+        }
+        let fee_token = AccountId::try_from(args.fee_address.clone())
+            .ok()
+            .and_then(|token| self.fee_tokens.get(&token));
+        if let Some(price) = fee_token {
+            let near = U256::from(args.fee_amount) * U256::from(price.near_amount.0)
+                / U256::from(price.token_amount.0);
+            if near > U256::from(self.gas_station) {
+                return Some(GatewayError::NotEnoughBalance);
+            }
+        }
+        None
+    }
+
+    /// `recover_signer` for each of the messages, so relayers can filter out invalid ones in one call.
+    /// Invalid messages give the parsing error instead of panicking.
+    pub fn recover_signers(
+        &self,
+        messages: Vec<SignedMessage>,
+    ) -> Vec<Result<RecoveredSigner, String>> {
+        gateway_core::meta_parsing::recover_signers(
+            &self.domain_separator,
+            CHAIN_ID,
+            env::current_account_id().as_bytes(),
+            messages.into_iter().map(|message| message.0).collect(),
+            |method_id| self.method_defs.get(u64::from(method_id)),
+            |sender, nonce| self.expected_nonce(&sender.0, nonce),
+        )
+        .into_iter()
+        .map(|result| result.map_err(|error| GatewayError::from(error).to_string()))
+        .collect()
+    }
+
+    // pub fn update(&self, message: SignedMessage) -> Promise {
+    //     Promise::new(account_id).function_call("update", )
+    // }
+
+    // TODO: just for test purposes
+    pub fn test_call(&self, x: U64, y: String) -> U64 {
+        U64(x.0 + y.len() as u64)
+    }
+}
+
+/// The gateway's near-abi, abi.json as `generate_abi` returns it. near-sdk only embeds the ABI
+/// when built with cargo-near.
+#[cfg(feature = "abi")]
+#[near_bindgen]
+impl Contract {
+    /// near-abi of the gateway: its methods with their arguments and results, and its events, so
+    /// clients generate their bindings from it.
+    pub fn contract_abi(&self) -> serde_json::Value {
+        serde_json::from_str(include_str!("../abi.json")).unwrap()
+    }
+}
+
+/// near-abi of the gateway as near-sdk generates it from the contract's methods, with the schema
+/// of `GatewayEvent` added to its definitions, as near-abi has no place for events. The events
+/// are logged wrapped with their `standard` and `version`. abi.json must match it, see
+/// `test_abi_generated`.
+#[cfg(all(feature = "abi-generate", not(target_arch = "wasm32")))]
+#[allow(improper_ctypes, improper_ctypes_definitions)]
+pub fn generate_abi() -> serde_json::Value {
+    use near_sdk::__private::ChunkedAbiEntry;
+
+    type AbiChunk = unsafe extern "C" fn() -> (*const u8, usize);
+    // Generated by `near_bindgen` for each impl block of the contract, named after its first
+    // method.
+    extern "C" {
+        fn __near_abi_new() -> (*const u8, usize);
+        fn __near_abi_contract_abi() -> (*const u8, usize);
     }
+    let chunks: [AbiChunk; 2] = [__near_abi_new, __near_abi_contract_abi];
+    let entries = chunks.iter().map(|chunk| {
+        // The generated functions leak the JSON they return.
+        let json = unsafe {
+            let (data, len) = chunk();
+            std::slice::from_raw_parts(data, len)
+        };
+        serde_json::from_slice::<ChunkedAbiEntry>(json).unwrap()
+    });
+    let mut entry = ChunkedAbiEntry::combine(entries).unwrap();
+    let mut gen = schemars::gen::SchemaGenerator::default();
+    gen.subschema_for::<GatewayEvent>();
+    entry
+        .body
+        .root_schema
+        .definitions
+        .extend(gen.take_definitions());
+    json!({
+        "schema_version": entry.schema_version,
+        "metadata": {
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "authors": env!("CARGO_PKG_AUTHORS").split(':').collect::<Vec<_>>(),
+        },
+        "body": entry.body,
+    })
 }
@@ -0,0 +1,180 @@
+//! Helpers for end-to-end tests of the gateway in a near-workspaces sandbox, built with the
+//! `testing` feature: wallets signing messages the way Ethereum wallets do, and deploying the
+//! gateway. Integrators can use them to test their own deployments the way the gateway's
+//! tests do. `TestWallet` signs with Ethereum keys instead of NEAR ones, without the sandbox.
+
+use gateway_core::client::{public_key_to_address, MetaCallBuilder};
+pub use gateway_core::testing::{signing_key_from_seed, TestWallet};
+use gateway_core::types::Address;
+use near_crypto::{InMemorySigner, KeyType, PublicKey, Signature, Signer};
+use near_sdk::Balance;
+use near_workspaces::result::ExecutionFinalResult;
+use near_workspaces::types::NearToken;
+use near_workspaces::{Account, Contract};
+use primitive_types::U256;
+
+use crate::{ArgsEncoding, MetaCallSignature, SignedMessage};
+
+/// Balance of gateways created by `deploy_gateway`, covering their storage.
+const GATEWAY_BALANCE: u128 = 50;
+
+/// Signs the message with the NEAR key, as the wallet holding it would.
+pub fn sign_meta_call(signer: &dyn Signer, builder: &MetaCallBuilder) -> Vec<u8> {
+    builder.build(meta_call_signature(signer, builder))
+}
+
+/// Signature of the message by the NEAR key, to build the message with.
+pub fn meta_call_signature(signer: &dyn Signer, builder: &MetaCallBuilder) -> MetaCallSignature {
+    let digest = match builder.digest() {
+        Ok(digest) => digest,
+        Err(err) => panic!("Failed to prepare: {:?}", err),
+    };
+    match (signer.sign(&digest), signer.public_key()) {
+        (Signature::ED25519(sig), PublicKey::ED25519(public_key)) => MetaCallSignature::Ed25519 {
+            public_key: public_key.0,
+            signature: sig.to_bytes(),
+        },
+        (Signature::SECP256K1(sig), _) => {
+            let array = <[u8; 65]>::from(sig);
+            let mut signature = [0u8; 64];
+            signature.copy_from_slice(&array[..64]);
+            MetaCallSignature::Secp256k1 {
+                signature,
+                // Add 27 to align eth-sig-util signature format
+                v: array[64] + 27,
+            }
+        }
+        _ => panic!("Wrong Signer"),
+    }
+}
+
+/// Address of the NEAR key, as the gateway derives it from the signature.
+pub fn near_public_key_to_address(public_key: &PublicKey) -> Address {
+    public_key_to_address(public_key.key_data())
+}
+
+/// Wallet signing messages for the gateway at `gateway_id`, keeping track of its nonce.
+pub struct Wallet {
+    pub signer: InMemorySigner,
+    pub gateway_id: String,
+    pub nonce: U256,
+    pub chain_id: u64,
+    pub public_key: Address,
+}
+
+impl Wallet {
+    pub fn new(gateway_id: &str) -> Self {
+        Self::with_key_type(gateway_id, KeyType::SECP256K1)
+    }
+
+    pub fn with_key_type(gateway_id: &str, key_type: KeyType) -> Self {
+        Self::from_seed(gateway_id, key_type, "a")
+    }
+
+    /// Wallet with the key derived from `seed`, the same for the same seed.
+    pub fn from_seed(gateway_id: &str, key_type: KeyType, seed: &str) -> Self {
+        let signer = InMemorySigner::from_seed("doesnt".parse().unwrap(), key_type, seed);
+        Self {
+            public_key: near_public_key_to_address(&signer.public_key),
+            signer,
+            gateway_id: gateway_id.to_string(),
+            nonce: U256::zero(),
+            chain_id: 1,
+        }
+    }
+
+    /// Account the gateway creates for the wallet's address.
+    pub fn account_id(&self) -> String {
+        format!("{}.{}", hex::encode(self.public_key), self.gateway_id)
+    }
+
+    pub fn message(
+        &mut self,
+        receiver_id: &str,
+        value: Balance,
+        method_def: &str,
+        args: &[Vec<u8>],
+    ) -> SignedMessage {
+        self.registered_message(receiver_id, value, method_def, None, args)
+    }
+
+    /// Message calling method definition registered at `method_id`.
+    pub fn registered_message(
+        &mut self,
+        receiver_id: &str,
+        value: Balance,
+        method_def: &str,
+        method_id: Option<u32>,
+        args: &[Vec<u8>],
+    ) -> SignedMessage {
+        let args = if args.is_empty() {
+            vec![]
+        } else {
+            rlp::encode_list::<Vec<u8>, _>(args).to_vec()
+        };
+        self.encoded_message(receiver_id, value, method_def, method_id, args)
+    }
+
+    /// Message with RLP encoded arguments, for nested ones.
+    pub fn encoded_message(
+        &mut self,
+        receiver_id: &str,
+        value: Balance,
+        method_def: &str,
+        method_id: Option<u32>,
+        args: Vec<u8>,
+    ) -> SignedMessage {
+        let builder = MetaCallBuilder::new(&self.gateway_id, self.chain_id)
+            .nonce(self.nonce)
+            .fee(5, "token")
+            .receiver(receiver_id)
+            .value(value)
+            .args(ArgsEncoding::Rlp, args);
+        let builder = match method_id {
+            Some(method_id) => builder.registered_method(method_id, method_def),
+            None => builder.method(method_def),
+        };
+        let result = sign_meta_call(&self.signer, &builder);
+        self.nonce += U256::one();
+        SignedMessage(result)
+    }
+}
+
+/// Prints the gas, status and logs of the transaction and its receipts, and panics with the
+/// failure if it failed.
+pub fn assert_success(result: ExecutionFinalResult) {
+    for outcome in result.outcomes() {
+        println!(
+            "{}: {}Tg, {:?} {:?}",
+            outcome.executor_id,
+            outcome.gas_burnt.as_tgas(),
+            outcome.clone().into_result(),
+            outcome.logs
+        );
+    }
+    if let Err(failure) = result.into_result() {
+        panic!("{}", failure);
+    }
+}
+
+/// Deploys and initializes the gateway `wasm` at the `name` sub-account of `root`.
+pub async fn deploy_gateway(
+    root: &Account,
+    name: &str,
+    wasm: &[u8],
+) -> near_workspaces::Result<Contract> {
+    let account = root
+        .create_subaccount(name)
+        .initial_balance(NearToken::from_near(GATEWAY_BALANCE))
+        .transact()
+        .await?
+        .into_result()?;
+    let gateway = account.deploy(wasm).await?.into_result()?;
+    gateway
+        .call("new")
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(gateway)
+}
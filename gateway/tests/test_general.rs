@@ -1,188 +1,5419 @@
-use near_sdk_sim::{call, deploy, init_simulator, to_yocto, ExecutionResult};
+use near_workspaces::network::Sandbox;
+use near_workspaces::operations::Function;
+use near_workspaces::result::ExecutionFinalResult;
+use near_workspaces::types::{Gas, NearToken};
+use near_workspaces::{Account, Contract, Worker};
 
 use ethabi::Address;
+use gateway::testing::{
+    assert_success, deploy_gateway, meta_call_signature, sign_meta_call, TestWallet, Wallet,
+};
 use gateway::{
-    near_erc712_domain, prepare_meta_call_args, u256_to_arr, ContractContract as Contract,
-    InternalMetaCallArgs, MetaCallArgs,
+    make_nonce, near_erc712_domain, prepare_meta_call_args, salted_nonce, AccountMode,
+    ArgsEncoding, CallArgsFormat, CircuitBreaker, Denylist, DepositSource, DryRunVerdict,
+    ExecutedMessage, GasSchedule, InternalMetaCallArgs, MessageLimits, MessageOutcome,
+    MetaCallArgsJson, MetaCallHash, MetaCallSignature, MultisigSignatures, ProxyTemplate,
+    RecoveredSigner, SignedMessage, StateSnapshot, TypedDataSchema,
 };
-use near_sdk::json_types::Base64VecU8;
-use near_sdk::{Balance, Gas};
-use near_sdk_sim::borsh::BorshSerialize;
-use near_sdk_sim::near_crypto::{InMemorySigner, KeyType, PublicKey, Signature, Signer};
-use primitive_types::{H256, U256};
+use gateway_core::client::MetaCallBuilder;
+use gateway_core::siwe::personal_message_hash;
+use gateway_core::types::{checksum_address, ValueUnit, FT_TRANSFER_CALL_DEF, NFT_TRANSFER_DEF};
+use near_crypto::{KeyType, Signature, Signer};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::serde_json::{json, Value};
+use near_sdk::Balance;
+use primitive_types::U256;
 use sha3::Digest;
 
-near_sdk_sim::lazy_static_include::lazy_static_include_bytes! {
-    GATEWAY_WASM => "../res/gateway.wasm"
+const GATEWAY_WASM: &[u8] = include_bytes!("../../res/gateway.wasm");
+
+/// First gateway, built with near-sdk 3, kept to test migrating its state.
+const LEGACY_GATEWAY_WASM: &[u8] = include_bytes!("../../res/legacy_gateway.wasm");
+
+/// Proxy built by the gateway's `build.rs`, which `res/gateway.wasm` must embed.
+const PROXY_WASM: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/proxy.wasm"));
+
+/// NEP-141 token built by the gateway's `build.rs` from `test-token`.
+const TEST_TOKEN_WASM: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/test_token.wasm"));
+
+const TGAS: u64 = 1_000_000_000_000;
+
+/// yoctoNEAR in the decimal amount of NEAR.
+fn to_yocto(near: &str) -> Balance {
+    let (whole, fraction) = near.split_once('.').unwrap_or((near, ""));
+    format!("{}{:0<24}", whole, fraction).parse().unwrap()
 }
 
-const TGAS: Gas = 1_000_000_000_000;
+/// Sub-account `name` of the root account with `balance` yoctoNEAR.
+async fn create_user(root: &Account, name: &str, balance: Balance) -> anyhow::Result<Account> {
+    Ok(root
+        .create_subaccount(name)
+        .initial_balance(NearToken::from_yoctonear(balance))
+        .transact()
+        .await?
+        .into_result()?)
+}
 
-pub fn encode_meta_call_function_args(
-    signer: &dyn Signer,
-    chain_id: u64,
-    nonce: U256,
-    fee_amount: Balance,
-    fee_address: String,
-    contract_address: String,
+/// Token balance of the account.
+async fn ft_balance(token: &Contract, account_id: &str) -> anyhow::Result<Balance> {
+    let balance: U128 = token
+        .view("ft_balance_of")
+        .args_json(json!({ "account_id": account_id }))
+        .await?
+        .json()?;
+    Ok(balance.0)
+}
+
+/// Balance of the account in yoctoNEAR.
+async fn balance(worker: &Worker<Sandbox>, account_id: &str) -> anyhow::Result<Balance> {
+    let account = worker.view_account(&account_id.parse()?).await?;
+    Ok(account.balance.as_yoctonear())
+}
+
+async fn exists(worker: &Worker<Sandbox>, account_id: &str) -> bool {
+    match account_id.parse() {
+        Ok(account_id) => worker.view_account(&account_id).await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Timestamp of the latest block in seconds, what the gateway checks expiration times against.
+async fn now(worker: &Worker<Sandbox>) -> anyhow::Result<u64> {
+    Ok(worker.view_block().await?.timestamp() / 1_000_000_000)
+}
+
+/// Balance of the account of the address at the gateway.
+async fn account_balance(
+    worker: &Worker<Sandbox>,
+    gateway: &Contract,
+    address: &str,
+) -> anyhow::Result<Balance> {
+    let account_id: String = gateway
+        .view("get_account_id")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    balance(worker, &account_id).await
+}
+
+/// Confirmation of the message signed by the confirmer's wallet, for messages over the spending
+/// limit.
+async fn confirm_message(
+    gateway: &Contract,
+    wallet: &mut Wallet,
+    message: &SignedMessage,
+) -> anyhow::Result<SignedMessage> {
+    let hash: MetaCallHash = gateway
+        .view("hash_meta_call")
+        .args_json(json!({ "message": message.clone() }))
+        .await?
+        .json()?;
+    let digest = hex::decode(&hash.digest[2..]).unwrap();
+    Ok(wallet.message(gateway.id(), 0, "confirm(bytes digest)", &[digest]))
+}
+
+/// Message of the wallet making its account multi-signature with the signers and threshold.
+fn set_signers_message(wallet: &mut Wallet, signers: &[&Wallet], threshold: u32) -> SignedMessage {
+    let mut args = rlp::RlpStream::new_list(2);
+    args.append_list::<Vec<u8>, _>(
+        &signers
+            .iter()
+            .map(|signer| signer.public_key.as_bytes().to_vec())
+            .collect::<Vec<_>>(),
+    );
+    args.append(&threshold.to_be_bytes().to_vec());
+    let gateway_id = wallet.gateway_id.clone();
+    wallet.encoded_message(
+        &gateway_id,
+        0,
+        "set_signers(address[] signers,uint32 threshold)",
+        None,
+        args.out().to_vec(),
+    )
+}
+
+/// Sends the message signed by the session key for the owner's account, keeping the nonce of
+/// failed messages, which don't use it.
+async fn proxy_session(
+    root: &Account,
+    gateway: &Contract,
+    owner_address: &str,
+    session: &mut Wallet,
+    receiver: &str,
     value: Balance,
     method_def: &str,
-    args: Vec<u8>,
-) -> Vec<u8> {
-    let domain_separator = near_erc712_domain(U256::from(chain_id));
-    let (msg, _, _) = match prepare_meta_call_args(
-        &domain_separator,
-        "gateway".as_bytes(),
-        &InternalMetaCallArgs {
-            sender: Address::zero(),
-            nonce,
-            fee_amount,
-            fee_address: fee_address.clone(),
-            contract_address: contract_address.clone(),
-            method_name: method_def.to_string(),
-            value,
-            args: args.clone(),
-        },
-    ) {
-        Ok(x) => x,
-        Err(err) => panic!("Failed to prepare: {:?}", err),
-    };
-    match signer.sign(&msg) {
-        Signature::ED25519(_) => panic!("Wrong Signer"),
-        Signature::SECP256K1(sig) => {
-            let array = Into::<[u8; 65]>::into(sig.clone()).to_vec();
-            let mut signature = [0u8; 64];
-            signature.copy_from_slice(&array[..64]);
-            MetaCallArgs {
-                signature,
-                // Add 27 to align eth-sig-util signature format
-                v: array[64] + 27,
-                nonce: u256_to_arr(&nonce),
-                fee_amount: u256_to_arr(&U256::from(fee_amount)),
-                fee_address,
-                contract_address,
-                value: u256_to_arr(&U256::from(value)),
-                method: method_def.to_string(),
-                args,
-            }
-            .try_to_vec()
-            .expect("Failed to serialize")
-        }
+) -> anyhow::Result<ExecutionFinalResult> {
+    let nonce = session.nonce;
+    let message = session.message(receiver, value, method_def, &[vec![1], b"a".to_vec()]);
+    let result = root
+        .call(gateway.id(), "proxy_session")
+        .args_json(json!({ "owner": owner_address, "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    if !result.is_success() {
+        session.nonce = nonce;
     }
+    Ok(result)
 }
 
-pub fn public_key_to_address(public_key: PublicKey) -> Address {
-    match public_key {
-        PublicKey::ED25519(_) => panic!("Wrong PublicKey"),
-        PublicKey::SECP256K1(pubkey) => {
-            let pk: [u8; 64] = pubkey.into();
-            let bytes = H256::from_slice(sha3::Keccak256::digest(&pk.to_vec()).as_slice());
-            let mut result = Address::zero();
-            result.as_bytes_mut().copy_from_slice(&bytes[12..]);
-            result
-        }
-    }
+/// Sub-account `name` of the gateway with the proxy code and 10 NEAR.
+async fn create_proxy(gateway: &Contract, name: &str) -> anyhow::Result<Account> {
+    let account = gateway
+        .as_account()
+        .create_subaccount(name)
+        .initial_balance(NearToken::from_yoctonear(to_yocto("10")))
+        .transact()
+        .await?
+        .into_result()?;
+    account.deploy(PROXY_WASM).await?.into_result()?;
+    Ok(account)
 }
 
-struct Wallet {
-    signer: InMemorySigner,
-    nonce: U256,
-    chain_id: u64,
-    pub public_key: Address,
+/// Signed EIP-155 legacy Ethereum transaction, as a wallet would send it.
+fn sign_eth_transaction(signer: &dyn Signer, chain_id: u64, nonce: U256, data: &[u8]) -> Vec<u8> {
+    let fields = |stream: &mut rlp::RlpStream| {
+        stream
+            .append(&nonce)
+            .append(&1u8)
+            .append(&21000u32)
+            .append(&vec![0u8; 20])
+            .append(&0u8)
+            .append(&data.to_vec());
+    };
+    let mut stream = rlp::RlpStream::new_list(9);
+    fields(&mut stream);
+    stream.append(&chain_id).append(&0u8).append(&0u8);
+    let hash = sha3::Keccak256::digest(&stream.out());
+    let signature = match signer.sign(&hash) {
+        Signature::SECP256K1(sig) => <[u8; 65]>::from(sig),
+        _ => panic!("Wrong Signer"),
+    };
+    let mut stream = rlp::RlpStream::new_list(9);
+    fields(&mut stream);
+    stream
+        .append(&(chain_id * 2 + 35 + u64::from(signature[64])))
+        .append(&U256::from_big_endian(&signature[..32]))
+        .append(&U256::from_big_endian(&signature[32..64]));
+    stream.out().to_vec()
 }
 
-impl Wallet {
-    pub fn new() -> Self {
-        let signer = InMemorySigner::from_seed("doesnt", KeyType::SECP256K1, "a");
-        Self {
-            public_key: public_key_to_address(signer.public_key.clone()),
-            signer,
-            nonce: U256::zero(),
-            chain_id: 1,
-        }
+/// Transfer message for the first gateway: its borsh layout, without the fields added since,
+/// signed over the digest it computes, which only covers the domain.
+fn legacy_message(wallet: &Wallet, nonce: u64, receiver_id: &str) -> Base64VecU8 {
+    let mut bytes = vec![0x19, 0x01];
+    bytes.extend_from_slice(&near_erc712_domain(U256::from(wallet.chain_id)));
+    let inner = sha3::Keccak256::digest(&bytes);
+    bytes.extend_from_slice(&inner);
+    let hash = sha3::Keccak256::digest(&bytes);
+    let signature = match wallet.signer.sign(&hash) {
+        Signature::SECP256K1(sig) => <[u8; 65]>::from(sig),
+        _ => panic!("Wrong Signer"),
+    };
+    let mut message = signature[..64].to_vec();
+    message.push(signature[64] + 27);
+    let mut nonce_bytes = [0u8; 32];
+    U256::from(nonce).to_big_endian(&mut nonce_bytes);
+    message.extend_from_slice(&nonce_bytes);
+    message.extend_from_slice(&[0; 32]);
+    for field in ["", receiver_id] {
+        message.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        message.extend_from_slice(field.as_bytes());
     }
+    message.extend_from_slice(&[0; 32]);
+    // Empty method and arguments.
+    message.extend_from_slice(&[0; 8]);
+    Base64VecU8(message)
+}
 
-    pub fn message(
-        &mut self,
-        receiver_id: &str,
-        value: Balance,
-        method_def: &str,
-        args: Vec<u8>,
-    ) -> Base64VecU8 {
-        let result = encode_meta_call_function_args(
-            &self.signer,
-            self.chain_id,
-            self.nonce,
-            5,
-            "token".to_string(),
-            receiver_id.to_string(),
-            value,
-            method_def,
-            if args.is_empty() {
-                vec![]
-            } else {
-                rlp::encode_list::<Vec<u8>, _>(&[args]).to_vec()
-            },
-        );
-        self.nonce += U256::one();
-        Base64VecU8(result)
-    }
+/// UTC date and time of the timestamp in seconds, e.g. `1970-01-01T00:00:00Z`.
+fn format_timestamp(timestamp: u64) -> String {
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html.
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let seconds = timestamp % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
 }
 
-fn assert_success(result: ExecutionResult) {
-    for promise in result.promise_results() {
-        let p = promise.unwrap();
-        println!("{:?}", p);
-        println!(
-            "{}Tg, {:?} {:?}",
-            p.gas_burnt() / 1_000_000_000_000,
-            p.status(),
-            p.logs()
-        );
-    }
-    match result.is_ok() {
-        true => {}
-        false => {
-            result.assert_success();
-        }
+/// SIWE message for the gateway signed by the secp256k1 key with `personal_sign`, issued at
+/// `issued_at` and valid for an hour.
+fn sign_siwe(
+    signer: &dyn Signer,
+    address: &Address,
+    gateway_id: &str,
+    issued_at: u64,
+    nonce: &str,
+    resources: &[&str],
+) -> (String, Base64VecU8) {
+    let mut message = format!(
+        "{} wants you to sign in with your Ethereum account:
+{}
+
+
+URI: https://app.example
+Version: 1
+Chain ID: 1
+Nonce: {}
+Issued At: {}
+Expiration Time: {}
+Resources:",
+        gateway_id,
+        checksum_address(&address.0),
+        nonce,
+        format_timestamp(issued_at),
+        format_timestamp(issued_at + 3600)
+    );
+    for resource in resources {
+        message.push_str(&format!("\n- {}", resource));
     }
+    let signature = match signer.sign(personal_message_hash(message.as_bytes()).as_bytes()) {
+        Signature::SECP256K1(sig) => <[u8; 65]>::from(sig).to_vec(),
+        _ => panic!("Wrong Signer"),
+    };
+    (message, Base64VecU8(signature))
 }
 
-#[test]
-fn test_basics() {
-    let root = init_simulator(None);
-    let _user2 = root.create_user("user2".to_string(), to_yocto("100"));
-    let gateway = deploy!(contract: Contract, contract_id: "test".to_string(), bytes: &GATEWAY_WASM, signer_account: root, init_method: new());
+#[tokio::test]
+async fn test_basics() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
 
-    let mut wallet = Wallet::new();
-    let message = wallet.message("", 0, "create()", vec![]);
+    let mut wallet = Wallet::new(gateway.id());
+    let message = wallet.message("", 0, "create()", &[]);
 
-    call!(
-        root,
-        gateway.create(message),
-        deposit = to_yocto("5") // 165630000000000000000000
-    )
-    .assert_success();
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0 + to_yocto("1")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
 
-    let new_account = format!("{}.test", hex::encode(&wallet.public_key));
-    root.transfer(new_account.clone(), to_yocto("2"));
+    // The account gets exactly the required deposit, the excess is refunded.
+    let new_account = wallet.account_id();
+    assert_eq!(balance(&worker, &new_account).await?, deposit.0);
+    root.transfer_near(
+        &new_account.parse()?,
+        NearToken::from_yoctonear(to_yocto("2")),
+    )
+    .await?
+    .into_result()?;
 
     // check that new account exists.
-    let acc = root.borrow_runtime().view_account(&new_account).unwrap();
+    let acc = worker.view_account(&new_account.parse()?).await?;
     println!("{:?}", acc);
 
-    let message = wallet.message("user2", to_yocto("1"), "", vec![]);
-    assert_success(call!(root, gateway.proxy(message), gas = 100 * TGAS));
-    assert_eq!(
-        root.borrow_runtime().view_account("user2").unwrap().amount,
-        to_yocto("101")
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
     );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
 
     let message = wallet.message(
-        "test",
+        gateway.id(),
         to_yocto("1"),
-        "test_call(bytes args)",
-        "{\"x\": 1, \"y\": \"test\"}".as_bytes().to_vec(),
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"test".to_vec()],
+    );
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    Ok(())
+}
+
+/// Messages signed by the Ethereum key of `TestWallet` are accepted in sequence.
+#[tokio::test]
+async fn test_test_wallet() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = TestWallet::from_seed(gateway.id(), "alice");
+    let message = wallet.message("", 0, "create()", vec![]);
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": SignedMessage(message) }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    root.transfer_near(
+        &wallet.account_id().parse()?,
+        NearToken::from_yoctonear(to_yocto("2")),
+    )
+    .await?
+    .into_result()?;
+
+    for _ in 0..2 {
+        let message = wallet.message(user2.id(), to_yocto("0.5"), "", vec![]);
+        assert_success(
+            root.call(gateway.id(), "proxy")
+                .args_json(json!({ "message": SignedMessage(message) }))
+                .gas(Gas::from_tgas(100))
+                .transact()
+                .await?,
+        );
+    }
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_hash_meta_call() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let message = wallet.message(
+        gateway.id(),
+        0,
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"test".to_vec()],
+    );
+    let result: MetaCallHash = gateway
+        .view("hash_meta_call")
+        .args_json(json!({ "message": message }))
+        .await?
+        .json()?;
+
+    let (digest, _, _) = prepare_meta_call_args(
+        &near_erc712_domain(U256::from(1)),
+        gateway.id().as_bytes(),
+        &InternalMetaCallArgs {
+            sender: Address::zero(),
+            nonce: U256::zero(),
+            fee_amount: 5,
+            fee_address: "token".to_string(),
+            contract_address: gateway.id().to_string(),
+            method_name: "test_call(uint64 x,string y)".to_string(),
+            value: 0,
+            args_encoding: ArgsEncoding::Rlp,
+            call_args_format: CallArgsFormat::Json,
+            args: rlp::encode_list::<Vec<u8>, _>(&[vec![1], b"test".to_vec()]).to_vec(),
+            signers: vec![],
+            schema: TypedDataSchema::Nested,
+            tip: 0,
+            gas: 0,
+            deposit_source: DepositSource::Account,
+            value_unit: ValueUnit::Yocto,
+            multisig_account: None,
+        },
+    )
+    .unwrap();
+    assert_eq!(result.digest, format!("0x{}", hex::encode(digest)));
+    assert!(result.type_string.starts_with("NearTx(string gatewayId,"));
+    assert!(result.type_string.ends_with("Arguments(uint64 x,string y)"));
+    assert_eq!(
+        result.type_hash,
+        format!(
+            "0x{}",
+            hex::encode(sha3::Keccak256::digest(result.type_string.as_bytes()))
+        )
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_flat_schema() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .fee(5, "token")
+        .receiver(gateway.id())
+        .method("test_call(uint64 x,string y)")
+        .args(
+            ArgsEncoding::Rlp,
+            rlp::encode_list::<Vec<u8>, _>(&[vec![1], b"test".to_vec()]).to_vec(),
+        )
+        .schema(TypedDataSchema::Flat);
+    let message = SignedMessage(sign_meta_call(&wallet.signer, &builder));
+
+    let result: MetaCallHash = gateway
+        .view("hash_meta_call")
+        .args_json(json!({ "message": message.clone() }))
+        .await?
+        .json()?;
+    assert!(result.type_string.starts_with("NearTxFlat("));
+    assert!(result.encoded_struct.ends_with(&result.arguments_hash[2..]));
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert_success(result);
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address, "key": null }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "1");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_recover_signer() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let message = wallet.message("user2", to_yocto("1"), "", &[]);
+    let result: RecoveredSigner = gateway
+        .view("recover_signer")
+        .args_json(json!({ "message": message.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(
+        result.address,
+        format!("0x{}", hex::encode(wallet.public_key))
+    );
+    assert_eq!(result.expected_nonce, "0");
+    assert!(result.nonce_matches);
+
+    // Viewing doesn't consume the nonce, so the same message can be checked again.
+    let result: RecoveredSigner = gateway
+        .view("recover_signer")
+        .args_json(json!({ "message": message }))
+        .await?
+        .json()?;
+    assert!(result.nonce_matches);
+
+    let message = wallet.message("user2", to_yocto("1"), "", &[]);
+    let result: RecoveredSigner = gateway
+        .view("recover_signer")
+        .args_json(json!({ "message": message }))
+        .await?
+        .json()?;
+    assert_eq!(
+        result.address,
+        format!("0x{}", hex::encode(wallet.public_key))
+    );
+    assert!(!result.nonce_matches);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_recover_signers() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let valid = wallet.message("user2", to_yocto("1"), "", &[]);
+    let future_nonce = wallet.message("user2", to_yocto("1"), "", &[]);
+    let broken = wallet.message(gateway.id(), 0, "test_call(uint64)", &[vec![1]]);
+    let messages = vec![
+        valid.clone(),
+        future_nonce,
+        broken,
+        SignedMessage(b"garbage".to_vec()),
+    ];
+    let results: Vec<Result<RecoveredSigner, String>> = gateway
+        .view("recover_signers")
+        .args_json(json!({ "messages": messages }))
+        .await?
+        .json()?;
+
+    assert_eq!(results.len(), 4);
+    let single: RecoveredSigner = gateway
+        .view("recover_signer")
+        .args_json(json!({ "message": valid }))
+        .await?
+        .json()?;
+    let first = results[0].as_ref().unwrap();
+    assert_eq!(first.address, single.address);
+    assert_eq!(first.digest, single.digest);
+    assert!(first.nonce_matches);
+    let second = results[1].as_ref().unwrap();
+    assert_eq!(second.address, first.address);
+    assert!(!second.nonce_matches);
+    assert!(results[2]
+        .as_ref()
+        .unwrap_err()
+        .starts_with("ERR_META_TX_PARSE: expected ' ' followed by argument name"));
+    assert_eq!(
+        results[3].as_ref().unwrap_err(),
+        "ERR_META_TX_PARSE: ArgumentParseError"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_parse_error_details() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let message = wallet.message(gateway.id(), 0, "test_call(uint64)", &[vec![1]]);
+    let result = gateway
+        .view("hash_meta_call")
+        .args_json(json!({ "message": message }))
+        .await;
+    assert!(format!("{:?}", result.unwrap_err()).contains(
+        "ERR_META_TX_PARSE: expected ' ' followed by argument name at offset 16, found \")\""
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_registered_method_def() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let message = wallet.message("", 0, "create()", &[]);
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let method_def = "test_call(uint64 x,string y)";
+    let method_id: u32 = root
+        .call(gateway.id(), "register_method_def")
+        .args_json(json!({ "method_def": method_def.to_string() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("1")))
+        .max_gas()
+        .transact()
+        .await?
+        .json()?;
+    // Registering the same definition again gives the same id.
+    let same_method_id: u32 = root
+        .call(gateway.id(), "register_method_def")
+        .args_json(json!({ "method_def": method_def.to_string() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("1")))
+        .max_gas()
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(method_id, same_method_id);
+    let registered: Option<String> = gateway
+        .view("get_method_def")
+        .args_json(json!({ "method_id": method_id }))
+        .await?
+        .json()?;
+    assert_eq!(registered.as_deref(), Some(method_def));
+
+    let message = wallet.registered_message(
+        gateway.id(),
+        0,
+        method_def,
+        Some(method_id),
+        &[vec![1], b"test".to_vec()],
+    );
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+
+    // Unknown id.
+    let message = wallet.registered_message(
+        gateway.id(),
+        0,
+        method_def,
+        Some(method_id + 1),
+        &[vec![1], b"test".to_vec()],
+    );
+    assert!(!root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .is_success());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ed25519_signer() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::with_key_type(gateway.id(), KeyType::ED25519);
+    let message = wallet.message("", 0, "create()", &[]);
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let new_account = wallet.account_id();
+    assert!(exists(&worker, &new_account).await);
+    root.transfer_near(
+        &new_account.parse()?,
+        NearToken::from_yoctonear(to_yocto("2")),
+    )
+    .await?
+    .into_result()?;
+
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    let result: RecoveredSigner = gateway
+        .view("recover_signer")
+        .args_json(json!({ "message": message.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(
+        result.address,
+        format!("0x{}", hex::encode(wallet.public_key))
+    );
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_submit_eth_tx() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let message = wallet.message("", 0, "create()", &[]);
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let mut data = rlp::RlpStream::new_list(3);
+    data.append(&gateway.id().as_str())
+        .append(&"test_call(uint64 x,string y)")
+        .append(&rlp::encode_list::<Vec<u8>, _>(&[vec![1], b"test".to_vec()]).to_vec());
+    let data = data.out();
+    let chain_id: U64 = gateway.view("get_eth_chain_id").await?.json()?;
+    assert!(chain_id.0 > 1);
+
+    // Transactions signed for Ethereum mainnet, or any other chain, aren't accepted.
+    let mainnet = sign_eth_transaction(&wallet.signer, 1, wallet.nonce, &data);
+    let result = root
+        .call(gateway.id(), "submit_eth_tx")
+        .args_json(json!({ "rlp": Base64VecU8(mainnet) }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ChainIdMismatch"));
+
+    let tx = sign_eth_transaction(&wallet.signer, chain_id.0, wallet.nonce, &data);
+    let digest = format!("0x{}", hex::encode(sha3::Keccak256::digest(&tx)));
+    let outcome: MessageOutcome = root
+        .call(gateway.id(), "submit_eth_tx")
+        .args_json(json!({ "rlp": Base64VecU8(tx.clone()) }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(outcome.digest, Some(digest.clone()));
+    assert!(outcome.success);
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address.clone(), "key": null }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "2");
+    // Recorded in the history under the transaction hash, like messages under their digest.
+    let history: Vec<ExecutedMessage> = gateway
+        .view("get_history")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert_eq!(history[1].digest, digest);
+    assert_eq!(history[1].success, Some(true));
+
+    // Replaying the transaction fails on the used nonce.
+    assert!(!root
+        .call(gateway.id(), "submit_eth_tx")
+        .args_json(json!({ "rlp": Base64VecU8(tx) }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .is_success());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_for() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    // Underfunded creation fails right away, leaving the address to its owner.
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let result = root
+        .call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address.clone() }))
+        .deposit(NearToken::from_yoctonear(deposit.0 - 1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ENOUGH_DEPOSIT"));
+    let created: bool = gateway
+        .view("is_created")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert!(!created);
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("5")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let new_account = wallet.account_id();
+    assert!(exists(&worker, &new_account).await);
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address, "key": null }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "0");
+
+    // The owner claims the account with its first message.
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+
+    assert!(!root
+        .call(gateway.id(), "create_for")
+        .args_json(json!({ "address": "0x1234".to_string() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("5")))
+        .max_gas()
+        .transact()
+        .await?
+        .is_success());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_and_proxy() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    // Underfunded creation fails before anything is scheduled.
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    let result = root
+        .call(gateway.id(), "create_and_proxy")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0 - 1))
+        .gas(Gas::from_tgas(200))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ENOUGH_DEPOSIT"));
+    assert!(!exists(&worker, &wallet.account_id()).await);
+    wallet.nonce -= U256::one();
+
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    assert_success(
+        root.call(gateway.id(), "create_and_proxy")
+            .args_json(json!({ "message": message }))
+            .deposit(NearToken::from_yoctonear(to_yocto("5")))
+            .gas(Gas::from_tgas(200))
+            .transact()
+            .await?,
+    );
+    let new_account = wallet.account_id();
+    assert!(exists(&worker, &new_account).await);
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+
+    // The account exists now, so the call fails and nothing is transferred.
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    let _ = root
+        .call(gateway.id(), "create_and_proxy")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(to_yocto("5")))
+        .gas(Gas::from_tgas(200))
+        .transact()
+        .await?;
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_duplicate_create() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let created: bool = gateway
+        .view("is_created")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert!(!created);
+
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let created: bool = gateway
+        .view("is_created")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert!(created);
+
+    let message = wallet.message("", 0, "create()", &[]);
+    let result = root
+        .call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(!result.is_success());
+    assert!(format!("{:?}", result.failures()).contains("ERR_ALREADY_CREATED"));
+    // The nonce of the failed message isn't consumed.
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address.clone(), "key": null }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "1");
+
+    assert!(!root
+        .call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .is_success());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deposit_before_create() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "deposit_for")
+        .args_json(json!({ "address": address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("3")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let escrow: U128 = gateway
+        .view("get_escrow")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(escrow.0, to_yocto("3"));
+
+    // Creation sweeps the escrowed funds into the account.
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let new_account = wallet.account_id();
+    assert_eq!(
+        balance(&worker, &new_account).await?,
+        deposit.0 + to_yocto("3")
+    );
+    let escrow: U128 = gateway
+        .view("get_escrow")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(escrow.0, 0);
+
+    // Afterwards deposits go straight to the account.
+    root.call(gateway.id(), "deposit_for")
+        .args_json(json!({ "address": address }))
+        .deposit(NearToken::from_yoctonear(to_yocto("1")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    assert_eq!(
+        balance(&worker, &new_account).await?,
+        deposit.0 + to_yocto("4")
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_keyed_nonces() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // Messages with different keys don't wait for each other.
+    let key = U256::from(7);
+    wallet.nonce = make_nonce(key, 0);
+    let first = wallet.message(
+        gateway.id(),
+        0,
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"a".to_vec()],
+    );
+    let second = wallet.message(
+        gateway.id(),
+        0,
+        "test_call(uint64 x,string y)",
+        &[vec![2], b"b".to_vec()],
+    );
+    wallet.nonce = U256::one();
+    let plain = wallet.message(
+        gateway.id(),
+        0,
+        "test_call(uint64 x,string y)",
+        &[vec![3], b"c".to_vec()],
+    );
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": plain }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    // Out of order within the key fails.
+    assert!(!root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": second.clone() }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .is_success());
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": first }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": second }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address.clone(), "key": key.to_string() }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, make_nonce(key, 2).to_string());
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address, "key": null }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "2");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_salted_nonces() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let now = now(&worker).await?;
+    let mut salted = |salt: u64, expires_at: u64| {
+        wallet.nonce = salted_nonce(U256::from(salt), expires_at);
+        wallet.message(
+            gateway.id(),
+            0,
+            "test_call(uint64 x,string y)",
+            &[vec![1], b"a".to_vec()],
+        )
+    };
+    let first = salted(1, now + 3600);
+    let second = salted(2, now + 3600);
+    let expired = salted(3, 0);
+    let too_far = salted(4, now + 30 * 24 * 3600);
+
+    // Salted messages execute in any order, but only once.
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": second.clone() }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": first }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": second }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NONCE_USED"));
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": expired }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NONCE_EXPIRED"));
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": too_far }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NONCE_EXPIRY_TOO_FAR"));
+
+    // The sequential nonce is untouched.
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address, "key": null }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "1");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cancel() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // Messages held by a relayer.
+    let first = wallet.message("user2", to_yocto("1"), "", &[]);
+    let second = wallet.message("user2", to_yocto("1"), "", &[]);
+
+    // Other messages can't be used to cancel.
+    let result = root
+        .call(gateway.id(), "cancel")
+        .args_json(json!({ "message": first.clone() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_UNEXPECTED_METHOD"));
+
+    // Cancel signed with the nonce of the last held message cancels both.
+    wallet.nonce = U256::from(2);
+    let cancel = wallet.message(gateway.id(), 0, "cancel()", &[]);
+    root.call(gateway.id(), "cancel")
+        .args_json(json!({ "message": cancel.clone() }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address.clone(), "key": null }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "3");
+    let other = format!(
+        "0x{}",
+        hex::encode(Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "b").public_key)
+    );
+    let nonces: Vec<String> = gateway
+        .view("get_nonces")
+        .args_json(json!({ "addresses": vec![address, other] }))
+        .await?
+        .json()?;
+    assert_eq!(nonces, vec!["3", "0"]);
+    for message in [first, second, cancel] {
+        let result = root
+            .call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?;
+        assert!(format!("{:?}", result.failures()).contains("ERR_INCORRECT_NONCE"));
+    }
+
+    // Salted message is cancelled by cancel with the same salted nonce.
+    let expires_at = now(&worker).await? + 3600;
+    wallet.nonce = salted_nonce(U256::one(), expires_at);
+    let salted = wallet.message("user2", to_yocto("1"), "", &[]);
+    wallet.nonce = salted_nonce(U256::one(), expires_at);
+    let cancel = wallet.message(gateway.id(), 0, "cancel()", &[]);
+    root.call(gateway.id(), "cancel")
+        .args_json(json!({ "message": cancel }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": salted }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NONCE_USED"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spending_limit() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let limit_message = |wallet: &mut Wallet, amount: u128, period: u64| {
+        wallet.message(
+            gateway.id(),
+            0,
+            "set_spending_limit(uint128 amount,uint64 period)",
+            &[amount.to_be_bytes().to_vec(), period.to_be_bytes().to_vec()],
+        )
+    };
+
+    // Limits need a confirmer, another key than the one signing the messages.
+    let message = limit_message(&mut wallet, to_yocto("1"), 24 * 3600);
+    let result = root
+        .call(gateway.id(), "set_spending_limit")
+        .args_json(json!({ "message": message, "confirmation": null }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NO_CONFIRMER"));
+    wallet.nonce -= U256::one();
+    let confirmer_message = |wallet: &mut Wallet, confirmer: Address| {
+        wallet.message(
+            gateway.id(),
+            0,
+            "set_confirmer(address confirmer)",
+            &[confirmer.as_bytes().to_vec()],
+        )
+    };
+    let own_address = wallet.public_key;
+    let message = confirmer_message(&mut wallet, own_address);
+    let result = root
+        .call(gateway.id(), "set_confirmer")
+        .args_json(json!({ "message": message, "confirmation": null }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_INVALID_CONFIRMER"));
+    wallet.nonce -= U256::one();
+    let mut confirmer = Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "confirmer");
+    let message = confirmer_message(&mut wallet, confirmer.public_key);
+    root.call(gateway.id(), "set_confirmer")
+        .args_json(json!({ "message": message, "confirmation": null }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let registered: Option<String> = gateway
+        .view("get_confirmer")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(
+        registered.map(|confirmer| confirmer.to_lowercase()),
+        Some(format!("0x{}", hex::encode(confirmer.public_key)))
+    );
+
+    let message = limit_message(&mut wallet, to_yocto("1"), 24 * 3600);
+    root.call(gateway.id(), "set_spending_limit")
+        .args_json(json!({ "message": message, "confirmation": null }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message.clone() }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_SPENDING_LIMIT"));
+
+    // The signing key alone can't confirm.
+    let mut same_key = Wallet::new(gateway.id());
+    let confirmation = confirm_message(&gateway, &mut same_key, &message).await?;
+    let result = root
+        .call(gateway.id(), "proxy_confirmed")
+        .args_json(json!({ "message": message.clone(), "confirmation": confirmation }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_INVALID_CONFIRMATION"));
+
+    // Over the limit with a confirmation signed by the confirmer.
+    let confirmation = confirm_message(&gateway, &mut confirmer, &message).await?;
+    assert_success(
+        root.call(gateway.id(), "proxy_confirmed")
+            .args_json(json!({ "message": message, "confirmation": confirmation }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("102"));
+    let limit: Value = gateway
+        .view("get_spending_limit")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(limit["spent"], to_yocto("2").to_string());
+
+    // Raising the limit needs a confirmation too.
+    let message = limit_message(&mut wallet, to_yocto("5"), 24 * 3600);
+    let result = root
+        .call(gateway.id(), "set_spending_limit")
+        .args_json(json!({ "message": message.clone(), "confirmation": null }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_CONFIRMATION_REQUIRED"));
+    let confirmation = confirm_message(&gateway, &mut confirmer, &message).await?;
+    root.call(gateway.id(), "set_spending_limit")
+        .args_json(json!({ "message": message, "confirmation": confirmation }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let limit: Value = gateway
+        .view("get_spending_limit")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert_eq!(limit["amount"], to_yocto("5").to_string());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_allowance() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut owner = Wallet::new(gateway.id());
+    let mut spender = Wallet::with_key_type(gateway.id(), KeyType::ED25519);
+    let owner_address = format!("0x{}", hex::encode(owner.public_key));
+    let spender_address = format!("0x{}", hex::encode(spender.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let message = owner.message(
+        gateway.id(),
+        0,
+        "approve(address spender,uint128 amount,uint64 expires_at)",
+        &[
+            spender.public_key.as_bytes().to_vec(),
+            to_yocto("2").to_be_bytes().to_vec(),
+            (now(&worker).await? + 3600).to_be_bytes().to_vec(),
+        ],
+    );
+    root.call(gateway.id(), "approve")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // The spender's signature and nonce authorize sending from the owner's account.
+    let message = spender.message(user2.id(), to_yocto("1"), "", &[]);
+    assert_success(
+        root.call(gateway.id(), "proxy_from")
+            .args_json(json!({ "owner": owner_address.clone(), "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+    let allowance: Value = gateway
+        .view("get_allowance")
+        .args_json(json!({ "owner": owner_address.clone(), "spender": spender_address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(allowance["amount"], to_yocto("1").to_string());
+
+    let message = spender.message(user2.id(), to_yocto("2"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy_from")
+        .args_json(json!({ "owner": owner_address.clone(), "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_ALLOWANCE_EXCEEDED"));
+    spender.nonce -= U256::one();
+
+    // Allowances only cover transfers: the spender can't call methods from the owner's account,
+    // or send to the gateway from it.
+    let message = spender.message(
+        user2.id(),
+        0,
+        "test_call(uint64 x)",
+        &[5u64.to_be_bytes().to_vec()],
+    );
+    let result = root
+        .call(gateway.id(), "proxy_from")
+        .args_json(json!({ "owner": owner_address.clone(), "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_ALLOWANCE_CALL"));
+    spender.nonce -= U256::one();
+    let message = spender.message(gateway.id(), to_yocto("1"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy_from")
+        .args_json(json!({ "owner": owner_address.clone(), "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_ALLOWANCE_CALL"));
+    let allowance: Value = gateway
+        .view("get_allowance")
+        .args_json(json!({ "owner": owner_address.clone(), "spender": spender_address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(allowance["amount"], to_yocto("1").to_string());
+
+    // Without allowance the other way around.
+    let message = owner.message(user2.id(), to_yocto("1"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy_from")
+        .args_json(json!({ "owner": spender_address, "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NO_ALLOWANCE"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_keys() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut owner = Wallet::new(gateway.id());
+    let mut session = Wallet::with_key_type(gateway.id(), KeyType::ED25519);
+    let owner_address = format!("0x{}", hex::encode(owner.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let mut args = rlp::RlpStream::new_list(5);
+    args.append(&session.public_key.as_bytes().to_vec());
+    args.append_list::<Vec<u8>, _>(&[gateway.id().as_bytes().to_vec()]);
+    args.append_list::<Vec<u8>, _>(&[b"test_call".to_vec()]);
+    args.append(&to_yocto("1").to_be_bytes().to_vec());
+    args.append(&(now(&worker).await? + 3600).to_be_bytes().to_vec());
+    let message = owner.encoded_message(
+        gateway.id(),
+        0,
+        "grant_session(address session,string[] receivers,string[] methods,uint128 value_cap,uint64 expires_at)",
+        None,
+        args.out().to_vec(),
+    );
+    root.call(gateway.id(), "grant_session")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    assert_success(
+        proxy_session(
+            &root,
+            &gateway,
+            &owner_address,
+            &mut session,
+            gateway.id(),
+            to_yocto("1"),
+            "test_call(uint64 x,string y)",
+        )
+        .await?,
+    );
+    let result = proxy_session(
+        &root,
+        &gateway,
+        &owner_address,
+        &mut session,
+        user2.id(),
+        0,
+        "test_call(uint64 x,string y)",
+    )
+    .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_SESSION_RECEIVER"));
+    let result = proxy_session(
+        &root,
+        &gateway,
+        &owner_address,
+        &mut session,
+        gateway.id(),
+        0,
+        "other_call(uint64 x,string y)",
+    )
+    .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_SESSION_METHOD"));
+    let result = proxy_session(
+        &root,
+        &gateway,
+        &owner_address,
+        &mut session,
+        gateway.id(),
+        to_yocto("2"),
+        "test_call(uint64 x,string y)",
+    )
+    .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_SESSION_VALUE"));
+
+    let message = owner.message(
+        gateway.id(),
+        0,
+        "revoke_session(address session)",
+        &[session.public_key.as_bytes().to_vec()],
+    );
+    root.call(gateway.id(), "revoke_session")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let result = proxy_session(
+        &root,
+        &gateway,
+        &owner_address,
+        &mut session,
+        gateway.id(),
+        0,
+        "test_call(uint64 x,string y)",
+    )
+    .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NO_SESSION"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_social_recovery() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut owner = Wallet::new(gateway.id());
+    let mut first = Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "first guardian");
+    let mut second = Wallet::from_seed(gateway.id(), KeyType::ED25519, "second guardian");
+    let mut new_owner = Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "new owner");
+    let owner_address = format!("0x{}", hex::encode(owner.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let mut args = rlp::RlpStream::new_list(3);
+    args.append_list::<Vec<u8>, _>(&[
+        first.public_key.as_bytes().to_vec(),
+        second.public_key.as_bytes().to_vec(),
+    ]);
+    args.append(&2u32.to_be_bytes().to_vec());
+    args.append(&0u64.to_be_bytes().to_vec());
+    let message = owner.encoded_message(
+        gateway.id(),
+        0,
+        "set_guardians(address[] guardians,uint32 threshold,uint64 delay)",
+        None,
+        args.out().to_vec(),
+    );
+    root.call(gateway.id(), "set_guardians")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let recover_args = [
+        owner.public_key.as_bytes().to_vec(),
+        new_owner.public_key.as_bytes().to_vec(),
+    ];
+    let recover_def = "recover(address account,address new_controller)";
+    let first_approval = first.message(gateway.id(), 0, recover_def, &recover_args);
+    let second_approval = second.message(gateway.id(), 0, recover_def, &recover_args);
+    let result = root
+        .call(gateway.id(), "recover")
+        .args_json(json!({ "messages": vec![first_approval.clone()] }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ENOUGH_GUARDIANS"));
+    root.call(gateway.id(), "recover")
+        .args_json(json!({ "messages": vec![first_approval, second_approval] }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // The owner can cancel during the timelock.
+    let message = owner.message(gateway.id(), 0, "cancel_rotation()", &[]);
+    root.call(gateway.id(), "cancel_rotation")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let result = root
+        .call(gateway.id(), "execute_rotation")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NO_ROTATION"));
+
+    let first_approval = first.message(gateway.id(), 0, recover_def, &recover_args);
+    let second_approval = second.message(gateway.id(), 0, recover_def, &recover_args);
+    root.call(gateway.id(), "recover")
+        .args_json(json!({ "messages": vec![first_approval, second_approval] }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    root.call(gateway.id(), "execute_rotation")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let controller: String = gateway
+        .view("get_controller")
+        .args_json(json!({ "address": owner_address }))
+        .await?
+        .json()?;
+    assert_eq!(controller, checksum_address(&new_owner.public_key.0));
+
+    // The new address controls the account, continuing from its nonce.
+    new_owner.nonce = U256::from(2);
+    let message = new_owner.message(user2.id(), to_yocto("1"), "", &[]);
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+    let message = owner.message(user2.id(), to_yocto("1"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_CONTROLLER"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rotate_owner() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut owner = Wallet::new(gateway.id());
+    let mut new_owner = Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "new owner");
+    let owner_address = format!("0x{}", hex::encode(owner.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let rotate_def = "rotate_owner(address new_address)";
+    let new_address = new_owner.public_key.as_bytes().to_vec();
+    let message = owner.message(
+        gateway.id(),
+        0,
+        rotate_def,
+        std::slice::from_ref(&new_address),
+    );
+    root.call(gateway.id(), "rotate_owner")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let result = root
+        .call(gateway.id(), "execute_rotation")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_ROTATION_TIMELOCK"));
+
+    // Only the gateway account changes the delay.
+    let result = root
+        .call(gateway.id(), "set_rotation_delay")
+        .args_json(json!({ "delay": U64(0) }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    gateway
+        .call("set_rotation_delay")
+        .args_json(json!({ "delay": U64(0) }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let message = owner.message(gateway.id(), 0, rotate_def, &[new_address]);
+    root.call(gateway.id(), "rotate_owner")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    root.call(gateway.id(), "execute_rotation")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    new_owner.nonce = U256::from(2);
+    let message = new_owner.message(user2.id(), to_yocto("1"), "", &[]);
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
     );
-    assert_success(call!(root, gateway.proxy(message), gas = 100 * TGAS));
+    let message = owner.message(user2.id(), to_yocto("1"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_CONTROLLER"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_multisig() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut owner = Wallet::new(gateway.id());
+    let signers = [
+        Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "first signer"),
+        Wallet::from_seed(gateway.id(), KeyType::ED25519, "second signer"),
+        Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "third signer"),
+    ];
+    let owner_address = format!("0x{}", hex::encode(owner.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // Signers are listed once, a repeated one can't make up the threshold alone.
+    let message = set_signers_message(&mut owner, &[&signers[0], &signers[0]], 2);
+    let result = root
+        .call(gateway.id(), "set_signers")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_DUPLICATE_SIGNER"));
+    owner.nonce -= U256::one();
+
+    let message = set_signers_message(&mut owner, &[&signers[0], &signers[1], &signers[2]], 2);
+    root.call(gateway.id(), "set_signers")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let signer_set: Value = gateway
+        .view("get_signers")
+        .args_json(json!({ "address": owner_address }))
+        .await?
+        .json()?;
+    assert_eq!(signer_set["threshold"], 2);
+
+    // The owner's key alone no longer acts for the account.
+    let message = owner.message(user2.id(), to_yocto("1"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_MULTISIG_REQUIRED"));
+
+    let transfer = |nonce: u64, approvers: &[&Wallet]| {
+        let builder = MetaCallBuilder::new(gateway.id(), 1)
+            .nonce(U256::from(nonce))
+            .fee(5, "token")
+            .receiver(user2.id())
+            .value(to_yocto("1"))
+            .multisig_account(owner.public_key);
+        let signatures = approvers
+            .iter()
+            .map(|approver| meta_call_signature(&approver.signer, &builder))
+            .collect();
+        SignedMessage(builder.build(MetaCallSignature::Multisig {
+            account: owner.public_key.0,
+            signatures: MultisigSignatures(signatures),
+        }))
+    };
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": transfer(1, &[&signers[0], &signers[0]]) }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ENOUGH_SIGNERS"));
+    let outsider = Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "outsider");
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": transfer(1, &[&signers[0], &outsider]) }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_SIGNER"));
+
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": transfer(1, &[&signers[0], &signers[1]]) }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+    // The signatures can't be replayed.
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": transfer(1, &[&signers[1], &signers[2]]) }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_INCORRECT_NONCE"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_multisig_after_rotation() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut owner = Wallet::new(gateway.id());
+    let mut controller = Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "controller");
+    let signers = [
+        Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "first signer"),
+        Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "second signer"),
+    ];
+    let owner_address = format!("0x{}", hex::encode(owner.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    gateway
+        .call("set_rotation_delay")
+        .args_json(json!({ "delay": U64(0) }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let message = owner.message(
+        gateway.id(),
+        0,
+        "rotate_owner(address new_address)",
+        &[controller.public_key.as_bytes().to_vec()],
+    );
+    root.call(gateway.id(), "rotate_owner")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    root.call(gateway.id(), "execute_rotation")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // The new controller makes the account multi-signature.
+    controller.nonce = U256::one();
+    let message = set_signers_message(&mut controller, &[&signers[0], &signers[1]], 2);
+    root.call(gateway.id(), "set_signers")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let signer_set: Value = gateway
+        .view("get_signers")
+        .args_json(json!({ "address": owner_address }))
+        .await?
+        .json()?;
+    assert_eq!(signer_set["threshold"], 2);
+
+    // The controller's key alone no longer acts for the account either.
+    let message = controller.message(user2.id(), to_yocto("1"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_MULTISIG_REQUIRED"));
+
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .nonce(U256::from(2))
+        .fee(5, "token")
+        .receiver(user2.id())
+        .value(to_yocto("1"))
+        .multisig_account(controller.public_key);
+    let signatures = signers
+        .iter()
+        .map(|signer| meta_call_signature(&signer.signer, &builder))
+        .collect();
+    let message = SignedMessage(builder.build(MetaCallSignature::Multisig {
+        account: controller.public_key.0,
+        signatures: MultisigSignatures(signatures),
+    }));
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_link_account() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+    // Existing account taking calls from the gateway, as the proxy contract does.
+    let alice = create_proxy(&gateway, "alice").await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "deposit_for")
+        .args_json(json!({ "address": address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("1")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let link_def = "link_account(string account_id)";
+    let message = wallet.message(gateway.id(), 0, link_def, &[alice.id().as_bytes().to_vec()]);
+    let result = root
+        .call(gateway.id(), "link_account")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_LINK_MISMATCH"));
+    wallet.nonce = U256::zero();
+    let message = wallet.message(gateway.id(), 0, link_def, &[alice.id().as_bytes().to_vec()]);
+    alice
+        .call(gateway.id(), "link_account")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let linked: Option<String> = gateway
+        .view("get_linked_account")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(linked, Some(alice.id().to_string()));
+    // The escrowed funds went to the linked account.
+    let escrow: U128 = gateway
+        .view("get_escrow")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(escrow.0, 0);
+    assert!(balance(&worker, alice.id()).await? > to_yocto("10"));
+    let result = root
+        .call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_ALREADY_LINKED"));
+
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+
+    // Only the linked account unlinks itself.
+    let result = root
+        .call(gateway.id(), "unlink_account")
+        .args_json(json!({ "address": address.clone() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_LINKED"));
+    alice
+        .call(gateway.id(), "unlink_account")
+        .args_json(json!({ "address": address.clone() }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let linked: Option<String> = gateway
+        .view("get_linked_account")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert_eq!(linked, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_siwe() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let alice = create_proxy(&gateway, "alice").await?;
+
+    let owner = Wallet::new(gateway.id());
+    let mut session = Wallet::with_key_type(gateway.id(), KeyType::ED25519);
+    let owner_address = format!("0x{}", hex::encode(owner.public_key));
+
+    let issued_at = now(&worker).await?;
+    let (message, signature) = sign_siwe(
+        &owner.signer,
+        &owner.public_key,
+        gateway.id(),
+        issued_at,
+        "linknonce",
+        &[&format!("near:link:{}", alice.id())],
+    );
+    let result = root
+        .call(gateway.id(), "link_account_siwe")
+        .args_json(json!({ "message": message.clone(), "signature": signature.clone() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_LINK_MISMATCH"));
+    let tampered = message.replace(alice.id().as_str(), &format!("bob.{}", gateway.id()));
+    let result = alice
+        .call(gateway.id(), "link_account_siwe")
+        .args_json(json!({ "message": tampered, "signature": signature.clone() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("InvalidEcRecoverSignature"));
+    alice
+        .call(gateway.id(), "link_account_siwe")
+        .args_json(json!({ "message": message.clone(), "signature": signature.clone() }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let linked: Option<String> = gateway
+        .view("get_linked_account")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(linked, Some(alice.id().to_string()));
+    // The nonce of a SIWE message is used once.
+    alice
+        .call(gateway.id(), "unlink_account")
+        .args_json(json!({ "address": owner_address.clone() }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let result = alice
+        .call(gateway.id(), "link_account_siwe")
+        .args_json(json!({ "message": message, "signature": signature }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NONCE_USED"));
+
+    let session_key = format!("near:session:0x{}", hex::encode(session.public_key));
+    let receiver = format!("near:receiver:{}", gateway.id());
+    let (message, signature) = sign_siwe(
+        &owner.signer,
+        &owner.public_key,
+        gateway.id(),
+        issued_at,
+        "sessionnonce",
+        &[&session_key, &receiver, "near:value:0"],
+    );
+    root.call(gateway.id(), "grant_session_siwe")
+        .args_json(json!({ "message": message, "signature": signature }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let granted: Value = gateway
+        .view("get_session")
+        .args_json(json!({
+            "owner": owner_address.clone(),
+            "session": format!("0x{}", hex::encode(session.public_key)),
+        }))
+        .await?
+        .json()?;
+    assert_eq!(granted["expires_at"], (issued_at + 3600).to_string());
+
+    let message = session.message(
+        gateway.id(),
+        0,
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"a".to_vec()],
+    );
+    assert_success(
+        root.call(gateway.id(), "proxy_session")
+            .args_json(json!({ "owner": owner_address.clone(), "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    let message = session.message(
+        gateway.id(),
+        1,
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"a".to_vec()],
+    );
+    let result = root
+        .call(gateway.id(), "proxy_session")
+        .args_json(json!({ "owner": owner_address, "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_SESSION_VALUE"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ledger() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let other = deploy_gateway(&root, "other", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let result = root
+        .call(gateway.id(), "deposit_to")
+        .args_json(json!({ "address": address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("3")))
+        .max_gas()
+        .transact()
+        .await?;
+    let result = result.into_result()?;
+    assert!(result.logs()[0].starts_with("EVENT_JSON:"));
+    assert!(result.logs()[0].contains(r#""event":"deposit""#));
+
+    // Transfers come from the gateway, without creating an account.
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    assert_success(
+        root.call(gateway.id(), "proxy_ledger")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+    let ledger_balance: U128 = gateway
+        .view("get_ledger_balance")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(ledger_balance.0, to_yocto("2"));
+    let created: bool = gateway
+        .view("is_created")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert!(!created);
+
+    let message = wallet.message(
+        other.id(),
+        0,
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"test".to_vec()],
+    );
+    assert_success(
+        root.call(gateway.id(), "proxy_ledger")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+
+    // A failed transfer returns the value to the balance.
+    let message = wallet.message("nobody", to_yocto("1"), "", &[]);
+    let _ = root
+        .call(gateway.id(), "proxy_ledger")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    let ledger_balance: U128 = gateway
+        .view("get_ledger_balance")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(ledger_balance.0, to_yocto("2"));
+
+    let message = wallet.message(user2.id(), to_yocto("3"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy_ledger")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ENOUGH_BALANCE"));
+    wallet.nonce -= U256::one();
+
+    // The gateway can't be called as itself.
+    let message = wallet.message(
+        gateway.id(),
+        0,
+        "set_rotation_delay(uint64 delay)",
+        &[vec![0]],
+    );
+    let result = root
+        .call(gateway.id(), "proxy_ledger")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_LEDGER_RECEIVER"));
+
+    let withdraw = |wallet: &mut Wallet, amount: Balance| {
+        wallet.message(
+            gateway.id(),
+            0,
+            "withdraw(uint128 amount,string receiver)",
+            &[
+                amount.to_be_bytes().to_vec(),
+                user2.id().as_bytes().to_vec(),
+            ],
+        )
+    };
+    let message = withdraw(&mut wallet, to_yocto("3"));
+    let result = root
+        .call(gateway.id(), "withdraw")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ENOUGH_BALANCE"));
+    wallet.nonce -= U256::one();
+    let message = withdraw(&mut wallet, to_yocto("2"));
+    assert_success(
+        root.call(gateway.id(), "withdraw")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("103"));
+    let balances: Vec<U128> = gateway
+        .view("get_ledger_balances")
+        .args_json(json!({ "addresses": vec![address] }))
+        .await?
+        .json()?;
+    assert_eq!(balances[0].0, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_balances_of() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let other = deploy_gateway(&root, "other", GATEWAY_WASM).await?;
+
+    let wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "deposit_to")
+        .args_json(json!({ "address": address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("1")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let account_id: String = gateway
+        .view("get_account_id")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(account_id, wallet.account_id());
+
+    // Without tokens the balances come right away.
+    let balances: Value = root
+        .call(gateway.id(), "balances_of")
+        .args_json(json!({ "address": address.clone() }))
+        .max_gas()
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(balances["account_id"], account_id.as_str());
+    assert_eq!(balances["ledger"], to_yocto("1").to_string());
+    assert_eq!(balances["tokens"], Value::Array(vec![]));
+
+    let result = root
+        .call(gateway.id(), "register_token")
+        .args_json(json!({ "token_id": other.id().to_string() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    gateway
+        .call("register_token")
+        .args_json(json!({ "token_id": other.id().to_string() }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let result = gateway
+        .call("register_token")
+        .args_json(json!({ "token_id": other.id().to_string() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_TOKEN_REGISTERED"));
+    let tokens: Vec<String> = gateway.view("get_tokens").await?.json()?;
+    assert_eq!(tokens, vec![other.id().to_string()]);
+
+    // Tokens failing `ft_balance_of` have no balance.
+    let result = root
+        .call(gateway.id(), "balances_of")
+        .args_json(json!({ "address": address }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    let result = result.into_result()?;
+    let balances: Value = result.json()?;
+    assert_eq!(balances["tokens"][0]["token_id"], other.id().as_str());
+    assert_eq!(balances["tokens"][0]["balance"], Value::Null);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_token_standards() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // The call must attach exactly 1 yoctoNEAR.
+    let message = wallet.message(
+        "token",
+        0,
+        FT_TRANSFER_CALL_DEF,
+        &[b"pool".to_vec(), vec![10], b"swap".to_vec()],
+    );
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(200))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_ONE_YOCTO_DEPOSIT"));
+    wallet.nonce -= U256::one();
+
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .nonce(wallet.nonce)
+        .ft_transfer_call("token", "pool", 10, "swap");
+    let message = SignedMessage(sign_meta_call(&wallet.signer, &builder));
+    // The gateway accepts it, there's no token to call here.
+    let _ = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(200))
+        .transact()
+        .await?;
+    wallet.nonce += U256::one();
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address.clone(), "key": null }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "2");
+
+    // NFT transfers need 1 yoctoNEAR as well, approvals a storage deposit.
+    let message = wallet.message(
+        "nft",
+        2,
+        NFT_TRANSFER_DEF,
+        &[b"bob".to_vec(), b"1".to_vec()],
+    );
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(200))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_ONE_YOCTO_DEPOSIT"));
+    wallet.nonce -= U256::one();
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .nonce(wallet.nonce)
+        .nft_approve("nft", "1", "market", None, 0);
+    let message = SignedMessage(sign_meta_call(&wallet.signer, &builder));
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(200))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_APPROVE_DEPOSIT"));
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .nonce(wallet.nonce)
+        .nft_transfer("nft", "market", "1", Some("list"));
+    let message = SignedMessage(sign_meta_call(&wallet.signer, &builder));
+    let _ = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(200))
+        .transact()
+        .await?;
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address, "key": null }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "3");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_permit() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let alice = create_user(&root, "alice", to_yocto("100")).await?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let owner = format!("0x{}", hex::encode(wallet.public_key));
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // The value registers alice's storage on the token along with the allowance.
+    let message = wallet.message(
+        gateway.id(),
+        to_yocto("0.01"),
+        "permit(string token,string spender,uint128 amount,uint64 expires_at)",
+        &[
+            b"token".to_vec(),
+            alice.id().as_bytes().to_vec(),
+            100u128.to_be_bytes().to_vec(),
+            (now(&worker).await? + 3600).to_be_bytes().to_vec(),
+        ],
+    );
+    assert_success(
+        root.call(gateway.id(), "permit")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    let allowance: Value = gateway
+        .view("get_token_allowance")
+        .args_json(
+            json!({ "owner": owner.clone(), "token": "token".to_string(), "spender": alice.id() }),
+        )
+        .await?
+        .json()?;
+    assert_eq!(allowance["amount"], "100");
+
+    let result = root
+        .call(gateway.id(), "ft_transfer_from")
+        .args_json(json!({
+            "owner": owner.clone(),
+            "token": "token".to_string(),
+            "receiver_id": root.id(),
+            "amount": U128(10),
+        }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NO_ALLOWANCE"));
+    let result = alice
+        .call(gateway.id(), "ft_transfer_from")
+        .args_json(json!({
+            "owner": owner.clone(),
+            "token": "token".to_string(),
+            "receiver_id": alice.id(),
+            "amount": U128(101),
+        }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_ALLOWANCE_EXCEEDED"));
+    let _ = alice
+        .call(gateway.id(), "ft_transfer_from")
+        .args_json(json!({
+            "owner": owner.clone(),
+            "token": "token".to_string(),
+            "receiver_id": alice.id(),
+            "amount": U128(60),
+        }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    let allowance: Value = gateway
+        .view("get_token_allowance")
+        .args_json(json!({ "owner": owner, "token": "token".to_string(), "spender": alice.id() }))
+        .await?
+        .json()?;
+    assert_eq!(allowance["amount"], "40");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_paymaster() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let dapp = create_user(&root, "dapp", to_yocto("100")).await?;
+    let relayer = create_user(&root, "relayer", to_yocto("100")).await?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = dapp
+        .call(gateway.id(), "set_paymaster_policy")
+        .args_json(json!({
+            "receivers": Vec::<String>::new(),
+            "subsidy": U128(0),
+            "max_per_user": U128(0),
+        }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NO_PAYMASTER"));
+    dapp.call(gateway.id(), "paymaster_deposit")
+        .deposit(NearToken::from_yoctonear(to_yocto("1")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    dapp.call(gateway.id(), "set_paymaster_policy")
+        .args_json(json!({
+            "receivers": vec![gateway.id().to_string()],
+            "subsidy": U128(to_yocto("0.1")),
+            "max_per_user": U128(to_yocto("0.15")),
+        }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let call_message = |wallet: &mut Wallet, receiver: &str| {
+        wallet.message(
+            receiver,
+            0,
+            "test_call(uint64 x,string y)",
+            &[vec![1], b"test".to_vec()],
+        )
+    };
+    let message = call_message(&mut wallet, dapp.id());
+    let result = relayer
+        .call(gateway.id(), "proxy_sponsored")
+        .args_json(json!({ "paymaster": dapp.id(), "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_SPONSORED"));
+    wallet.nonce -= U256::one();
+
+    // A message the account can't execute, here sending more than it holds, isn't subsidized:
+    // the paymaster keeps its balance and the address its allowance.
+    let before = balance(&worker, relayer.id()).await?;
+    let message = wallet.message(
+        gateway.id(),
+        to_yocto("1000"),
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"test".to_vec()],
+    );
+    let result = relayer
+        .call(gateway.id(), "proxy_sponsored")
+        .args_json(json!({ "paymaster": dapp.id(), "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(!result
+        .logs()
+        .iter()
+        .any(|log| log.contains(r#""event":"sponsor""#)));
+    assert!(balance(&worker, relayer.id()).await? < before);
+    let paymaster: Value = gateway
+        .view("get_paymaster")
+        .args_json(json!({ "account_id": dapp.id() }))
+        .await?
+        .json()?;
+    assert_eq!(paymaster["balance"], to_yocto("1").to_string());
+    let subsidy: U128 = gateway
+        .view("get_subsidy")
+        .args_json(json!({ "paymaster": dapp.id(), "address": format!("0x{}", hex::encode(wallet.public_key)) }))
+        .await?
+        .json()?;
+    assert_eq!(subsidy.0, 0);
+
+    // The relayer gets the subsidy once the message executed, and the event reports it.
+    let before = balance(&worker, relayer.id()).await?;
+    let message = call_message(&mut wallet, gateway.id());
+    let result = relayer
+        .call(gateway.id(), "proxy_sponsored")
+        .args_json(json!({ "paymaster": dapp.id(), "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(result
+        .logs()
+        .iter()
+        .any(|log| log.contains(r#""event":"sponsor""#)));
+    assert_success(result);
+    assert!(balance(&worker, relayer.id()).await? > before + to_yocto("0.09"));
+    let paymaster: Value = gateway
+        .view("get_paymaster")
+        .args_json(json!({ "account_id": dapp.id() }))
+        .await?
+        .json()?;
+    assert_eq!(paymaster["balance"], to_yocto("0.9").to_string());
+
+    // Each address gets at most `max_per_user`.
+    let message = call_message(&mut wallet, gateway.id());
+    let result = relayer
+        .call(gateway.id(), "proxy_sponsored")
+        .args_json(json!({ "paymaster": dapp.id(), "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_SUBSIDY_EXCEEDED"));
+
+    dapp.call(gateway.id(), "paymaster_withdraw")
+        .args_json(json!({ "amount": U128(to_yocto("0.9")) }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let paymaster: Value = gateway
+        .view("get_paymaster")
+        .args_json(json!({ "account_id": dapp.id() }))
+        .await?
+        .json()?;
+    assert_eq!(paymaster["balance"], "0");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_token_fees() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let relayer = create_user(&root, "relayer", to_yocto("100")).await?;
+    // Stands in for the token contract calling `ft_on_transfer`.
+    let token = create_user(&root, "token", to_yocto("100")).await?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let account_id = wallet.account_id();
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = root
+        .call(gateway.id(), "set_fee_token")
+        .args_json(json!({
+            "token": token.id(),
+            "token_amount": U128(100),
+            "near_amount": U128(to_yocto("1")),
+        }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    gateway
+        .call("set_fee_token")
+        .args_json(json!({
+            "token": token.id(),
+            "token_amount": U128(100),
+            "near_amount": U128(to_yocto("1")),
+        }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // Fee of 10 tokens is worth 0.1 NEAR, which the gas station must have.
+    let fee_message = |wallet: &mut Wallet, token: &str| {
+        let builder = MetaCallBuilder::new(gateway.id(), 1)
+            .nonce(wallet.nonce)
+            .fee(10, token)
+            .receiver(gateway.id())
+            .method("test_call(uint64 x,string y)")
+            .args(
+                ArgsEncoding::Rlp,
+                rlp::encode_list::<Vec<u8>, _>(&[vec![1], b"a".to_vec()]).to_vec(),
+            );
+        wallet.nonce += U256::one();
+        SignedMessage(sign_meta_call(&wallet.signer, &builder))
+    };
+    let message = fee_message(&mut wallet, token.id().as_str());
+    let result = relayer
+        .call(gateway.id(), "proxy_token_fee")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(200))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ENOUGH_BALANCE"));
+    wallet.nonce -= U256::one();
+    root.call(gateway.id(), "gas_station_deposit")
+        .deposit(NearToken::from_yoctonear(to_yocto("1")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    // The stand-in token has no contract, so fee 0 stays pending.
+    let message = fee_message(&mut wallet, token.id().as_str());
+    relayer
+        .call(gateway.id(), "proxy_token_fee")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(200))
+        .transact()
+        .await?
+        .into_result()?;
+
+    // Transfers that aren't a pending fee in full are refunded, and leave the fee pending.
+    let refund: U128 = token
+        .call(gateway.id(), "ft_on_transfer")
+        .args_json(
+            json!({ "sender_id": account_id.clone(), "amount": U128(9), "msg": "0".to_string() }),
+        )
+        .max_gas()
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(refund.0, 9);
+    let refund: U128 = root
+        .call(gateway.id(), "ft_on_transfer")
+        .args_json(
+            json!({ "sender_id": account_id.clone(), "amount": U128(10), "msg": "0".to_string() }),
+        )
+        .max_gas()
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(refund.0, 10);
+    let refund: U128 = token
+        .call(gateway.id(), "ft_on_transfer")
+        .args_json(
+            json!({ "sender_id": account_id.clone(), "amount": U128(10), "msg": "7".to_string() }),
+        )
+        .max_gas()
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(refund.0, 10);
+    let before = balance(&worker, relayer.id()).await?;
+    let refund: U128 = token
+        .call(gateway.id(), "ft_on_transfer")
+        .args_json(
+            json!({ "sender_id": account_id.clone(), "amount": U128(10), "msg": "0".to_string() }),
+        )
+        .max_gas()
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(refund.0, 0);
+    assert_eq!(
+        balance(&worker, relayer.id()).await?,
+        before + to_yocto("0.1")
+    );
+    let gas_station: U128 = gateway.view("get_gas_station").await?.json()?;
+    assert_eq!(gas_station.0, to_yocto("0.9"));
+
+    // With a real token the fee arrives in the same transaction as the message.
+    let ft = create_user(&root, "ft", to_yocto("10"))
+        .await?
+        .deploy(TEST_TOKEN_WASM)
+        .await?
+        .into_result()?;
+    ft.call("new")
+        .args_json(json!({ "owner_id": root.id(), "total_supply": U128(1000) }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    root.call(ft.id(), "ft_transfer")
+        .args_json(json!({ "receiver_id": account_id.clone(), "amount": U128(100) }))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    gateway
+        .call("set_fee_token")
+        .args_json(json!({
+            "token": ft.id(),
+            "token_amount": U128(100),
+            "near_amount": U128(to_yocto("1")),
+        }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let before = balance(&worker, relayer.id()).await?;
+    let message = fee_message(&mut wallet, ft.id().as_str());
+    relayer
+        .call(gateway.id(), "proxy_token_fee")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?
+        .into_result()?;
+    // The fee is worth more than the gas the relayer spent.
+    assert!(balance(&worker, relayer.id()).await? > before);
+    let gas_station: U128 = gateway.view("get_gas_station").await?.json()?;
+    assert_eq!(gas_station.0, to_yocto("0.8"));
+    assert_eq!(ft_balance(&ft, &account_id).await?, 90);
+    assert_eq!(ft_balance(&ft, gateway.id()).await?, 10);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tip() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let relayer = create_user(&root, "relayer", to_yocto("100")).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    root.transfer_near(
+        &wallet.account_id().parse()?,
+        NearToken::from_yoctonear(to_yocto("2")),
+    )
+    .await?
+    .into_result()?;
+
+    // The tip is signed, so the relayer can't change it.
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .nonce(wallet.nonce)
+        .receiver(user2.id())
+        .value(to_yocto("1"))
+        .tip(to_yocto("0.5"));
+    let message = SignedMessage(sign_meta_call(&wallet.signer, &builder));
+    let hash: MetaCallHash = gateway
+        .view("hash_meta_call")
+        .args_json(json!({ "message": message.clone() }))
+        .await?
+        .json()?;
+    assert!(hash.type_string.ends_with(",uint256 tip)"));
+
+    let before = balance(&worker, relayer.id()).await?;
+    assert_success(
+        relayer
+            .call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+    assert!(balance(&worker, relayer.id()).await? > before + to_yocto("0.45"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gas_schedule() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let mut schedule: GasSchedule = gateway.view("get_gas_schedule").await?.json()?;
+    assert_eq!(schedule.call.0, 20 * TGAS);
+    schedule.call = U64(100 * TGAS);
+    let result = root
+        .call(gateway.id(), "set_gas_schedule")
+        .args_json(json!({ "gas_schedule": schedule.clone() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    gateway
+        .call("set_gas_schedule")
+        .args_json(json!({ "gas_schedule": schedule }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let schedule: GasSchedule = gateway.view("get_gas_schedule").await?.json()?;
+    assert_eq!(schedule.call.0, 100 * TGAS);
+
+    // Transactions with less gas than the budget still go through, with what's left.
+    let message = wallet.message(
+        gateway.id(),
+        0,
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"test".to_vec()],
+    );
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(60))
+            .transact()
+            .await?,
+    );
+
+    // Gas declared in the message is signed, and clamped the same way.
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .nonce(wallet.nonce)
+        .receiver(gateway.id())
+        .method("test_call(uint64 x,string y)")
+        .args(
+            ArgsEncoding::Rlp,
+            rlp::encode_list::<Vec<u8>, _>(&[vec![1], b"test".to_vec()]).to_vec(),
+        )
+        .gas(250 * TGAS);
+    let message = SignedMessage(sign_meta_call(&wallet.signer, &builder));
+    let hash: MetaCallHash = gateway
+        .view("hash_meta_call")
+        .args_json(json!({ "message": message.clone() }))
+        .await?
+        .json()?;
+    assert!(hash.type_string.ends_with(",uint64 gas)"));
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relayer_deposit() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let relayer = create_user(&root, "relayer", to_yocto("100")).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // The account itself couldn't afford the transfer, the relayer attaches it.
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .nonce(wallet.nonce)
+        .receiver(user2.id())
+        .value(to_yocto("5"))
+        .deposit_source(DepositSource::Relayer);
+    let message = SignedMessage(sign_meta_call(&wallet.signer, &builder));
+    let result = relayer
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("4")))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_RELAYER_DEPOSIT"));
+    let result = relayer
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(to_yocto("5")))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(result.logs()[0].contains(r#""event":"relayer_deposit""#));
+    assert_success(result);
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("105"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_factory() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    // No `create_proxy` deployed, so creations through it fail.
+    let factory = create_user(&root, "factory", to_yocto("10")).await?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = root
+        .call(gateway.id(), "set_factory")
+        .args_json(json!({ "factory": factory.id() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    gateway
+        .call("set_factory")
+        .args_json(json!({ "factory": factory.id() }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let factory_id: Option<String> = gateway.view("get_factory").await?.json()?;
+    assert_eq!(factory_id, Some(factory.id().to_string()));
+
+    // Existing accounts keep their names.
+    let account_id: String = gateway
+        .view("get_account_id")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert_eq!(account_id, wallet.account_id());
+
+    // New ones are created by the factory, released if it fails.
+    let mut wallet = Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "b");
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let message = wallet.message("", 0, "create()", &[]);
+    let _ = root
+        .call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?;
+    let created: bool = gateway
+        .view("is_created")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert!(!created);
+    let account_id: String = gateway
+        .view("get_account_id")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert_eq!(account_id, wallet.account_id());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_implicit_account() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let public_key = [0xab; 32];
+    let implicit_account = hex::encode(public_key);
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .nonce(wallet.nonce)
+        .create_implicit(&public_key);
+    wallet.nonce += U256::one();
+    let message = SignedMessage(sign_meta_call(&wallet.signer, &builder));
+    root.call(gateway.id(), "create_implicit")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(to_yocto("0.01")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    assert_eq!(balance(&worker, &implicit_account).await?, to_yocto("0.01"));
+    let account_id: String = gateway
+        .view("get_account_id")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(account_id, implicit_account);
+    let mode: Option<AccountMode> = gateway
+        .view("get_account_mode")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert_eq!(mode, Some(AccountMode::Implicit));
+
+    // The key controls the account, not the gateway.
+    let message = wallet.message(gateway.id(), 0, "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_IMPLICIT_ACCOUNT"));
+    wallet.nonce -= U256::one();
+    let message = wallet.message("", 0, "create()", &[]);
+    let result = root
+        .call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(to_yocto("5")))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_ALREADY_CREATED"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_proxy_code() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    // The gateway starts with the proxy built along with it, a mismatch means `res/gateway.wasm`
+    // is stale.
+    let hash: Option<String> = gateway.view("proxy_code_hash").await?.json()?;
+    assert_eq!(hash, Some(hex::encode(sha2::Sha256::digest(PROXY_WASM))));
+
+    let result = root
+        .call(gateway.id(), "set_proxy_code")
+        .args_json(json!({ "code": Base64VecU8(PROXY_WASM.to_vec()) }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    gateway
+        .call("set_proxy_code")
+        .args_json(json!({ "code": Base64VecU8(PROXY_WASM.to_vec()) }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let hash: Option<String> = gateway.view("proxy_code_hash").await?.json()?;
+    assert_eq!(hash, Some(hex::encode(sha2::Sha256::digest(PROXY_WASM))));
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    assert!(deposit.0 > PROXY_WASM.len() as Balance * 10u128.pow(19));
+
+    let mut wallet = Wallet::new(gateway.id());
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_proxy_templates() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let result = root
+        .call(gateway.id(), "set_proxy_template")
+        .args_json(
+            json!({ "name": "minimal".to_string(), "code": Base64VecU8(PROXY_WASM.to_vec()) }),
+        )
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    gateway
+        .call("set_proxy_template")
+        .args_json(
+            json!({ "name": "minimal".to_string(), "code": Base64VecU8(PROXY_WASM.to_vec()) }),
+        )
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let templates: Vec<ProxyTemplate> = gateway.view("get_proxy_templates").await?.json()?;
+    assert_eq!(templates.len(), 1);
+    assert_eq!(templates[0].name, "minimal");
+    assert_eq!(
+        templates[0].code_hash,
+        hex::encode(sha2::Sha256::digest(PROXY_WASM))
+    );
+    assert_eq!(templates[0].code_len.0, PROXY_WASM.len() as u64);
+
+    let mut wallet = Wallet::new(gateway.id());
+    let account_id = wallet.account_id();
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .nonce(wallet.nonce)
+        .create("full");
+    let message = SignedMessage(sign_meta_call(&wallet.signer, &builder));
+    let result = root
+        .call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(to_yocto("5")))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_UNKNOWN_TEMPLATE"));
+
+    let deposit: U128 = gateway
+        .view("required_template_deposit")
+        .args_json(json!({ "template": "minimal".to_string() }))
+        .await?
+        .json()?;
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .nonce(wallet.nonce)
+        .create("minimal");
+    wallet.nonce += U256::one();
+    let message = SignedMessage(sign_meta_call(&wallet.signer, &builder));
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    assert!(exists(&worker, &account_id).await);
+
+    // The account acts on messages like any other.
+    let message = wallet.message(gateway.id(), 0, "", &[]);
+    root.call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_migrate_proxies() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let from_hash = hex::encode(sha2::Sha256::digest(PROXY_WASM));
+    // The proxy code with an empty custom section, a new version in all but behavior.
+    let mut code = PROXY_WASM.to_vec();
+    code.extend_from_slice(&[0, 3, 2, b'v', b'2']);
+    let to_hash = hex::encode(sha2::Sha256::digest(&code));
+    gateway
+        .call("set_proxy_template")
+        .args_json(json!({ "name": "v2".to_string(), "code": Base64VecU8(code) }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let mut opted_out = Wallet::from_seed(gateway.id(), KeyType::SECP256K1, "b");
+    let mut addresses = vec![];
+    for wallet in [&mut wallet, &mut opted_out] {
+        let address = format!("0x{}", hex::encode(wallet.public_key));
+        root.call(gateway.id(), "create_for")
+            .args_json(json!({ "address": address.clone() }))
+            .deposit(NearToken::from_yoctonear(to_yocto("10")))
+            .max_gas()
+            .transact()
+            .await?
+            .into_result()?;
+        let code_hash: Option<String> = gateway
+            .view("get_account_code_hash")
+            .args_json(json!({ "address": address.clone() }))
+            .await?
+            .json()?;
+        assert_eq!(code_hash, Some(from_hash.clone()));
+        addresses.push(address);
+    }
+    let message = opted_out.message(
+        gateway.id(),
+        0,
+        "set_migration_opt_out(bool opt_out)",
+        &[vec![1]],
+    );
+    root.call(gateway.id(), "set_migration_opt_out")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let opt_out: bool = gateway
+        .view("is_migration_opt_out")
+        .args_json(json!({ "address": addresses[1].clone() }))
+        .await?
+        .json()?;
+    assert!(opt_out);
+    // Not created yet.
+    addresses.push(format!("0x{}", hex::encode([0x11; 20])));
+
+    let result = root
+        .call(gateway.id(), "migrate_proxies")
+        .args_json(json!({
+            "from_hash": from_hash.clone(),
+            "to_hash": to_hash.clone(),
+            "accounts": addresses.clone(),
+        }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    gateway
+        .call("set_migrator")
+        .args_json(json!({ "migrator": root.id() }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let result = root
+        .call(gateway.id(), "migrate_proxies")
+        .args_json(json!({
+            "from_hash": from_hash.clone(),
+            "to_hash": to_hash.clone(),
+            "accounts": addresses.clone(),
+        }))
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?
+        .into_result()?;
+    let handled: U64 = result.json()?;
+    assert_eq!(handled.0, 3);
+    assert!(result
+        .logs()
+        .iter()
+        .any(|log| log.contains("migrate_proxies")));
+    assert!(result
+        .logs()
+        .iter()
+        .any(|log| log.contains("proxy_migrated")));
+
+    let account_id = wallet.account_id();
+    let account = worker.view_account(&account_id.parse()?).await?;
+    assert_eq!(hex::encode(account.code_hash.0), to_hash);
+    let code_hash: Option<String> = gateway
+        .view("get_account_code_hash")
+        .args_json(json!({ "address": addresses[0].clone() }))
+        .await?
+        .json()?;
+    assert_eq!(code_hash, Some(to_hash.clone()));
+    let code_hash: Option<String> = gateway
+        .view("get_account_code_hash")
+        .args_json(json!({ "address": addresses[1].clone() }))
+        .await?
+        .json()?;
+    assert_eq!(code_hash, Some(from_hash));
+
+    // The migrated account still acts on messages.
+    let message = wallet.message(gateway.id(), 0, "", &[]);
+    root.call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_airdrop() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let existing = format!("0x{}", hex::encode([0xab; 20]));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": existing.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("5")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let new = format!("0x{}", hex::encode([0xcd; 20]));
+    let addresses = vec![existing.clone(), new.clone()];
+    let required: U128 = gateway.view("required_create_deposit").await?.json()?;
+
+    let result = root
+        .call(gateway.id(), "airdrop")
+        .args_json(json!({ "addresses": addresses.clone(), "amount_each": U128(to_yocto("1")) }))
+        .deposit(NearToken::from_yoctonear(to_yocto("2")))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ENOUGH_DEPOSIT"));
+
+    let before = root.view_account().await?.balance.as_yoctonear();
+    let result = root
+        .call(gateway.id(), "airdrop")
+        .args_json(json!({ "addresses": addresses, "amount_each": U128(to_yocto("1")) }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?
+        .into_result()?;
+    let handled: U64 = result.json()?;
+    assert_eq!(handled.0, 2);
+    // The event names the addresses checksummed, whatever case they were passed in.
+    let log = result
+        .logs()
+        .iter()
+        .find(|log| log.contains("airdrop"))
+        .unwrap()
+        .to_string();
+    assert!(log.contains(&checksum_address(&[0xab; 20])));
+    assert!(log.contains(&checksum_address(&[0xcd; 20])));
+    // The rest of the deposit is refunded.
+    let spent = before - root.view_account().await?.balance.as_yoctonear();
+    assert!(spent >= to_yocto("2") + required.0 && spent < to_yocto("3") + required.0);
+
+    assert!(account_balance(&worker, &gateway, &existing).await? > to_yocto("5"));
+    assert!(account_balance(&worker, &gateway, &new).await? >= to_yocto("1"));
+    let created: bool = gateway
+        .view("is_created")
+        .args_json(json!({ "address": new }))
+        .await?
+        .json()?;
+    assert!(created);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_drop() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    // The sandbox signs the gateway's transactions with its only key, so the drop is keyed by it
+    // instead of a fresh one.
+    let public_key = gateway.as_account().secret_key().public_key().to_string();
+    let public_key: near_sdk::PublicKey = public_key.parse().unwrap();
+    let result = root
+        .call(gateway.id(), "send_drop")
+        .args_json(json!({ "public_key": public_key.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("0.1")))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ENOUGH_DEPOSIT"));
+    // Adding the key fails, as the gateway has it already, but the drop is recorded.
+    let _ = root
+        .call(gateway.id(), "send_drop")
+        .args_json(json!({ "public_key": public_key.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("5")))
+        .max_gas()
+        .transact()
+        .await?;
+    let amount: U128 = gateway
+        .view("get_drop")
+        .args_json(json!({ "public_key": public_key.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(amount.0, to_yocto("4.9"));
+    let result = root
+        .call(gateway.id(), "send_drop")
+        .args_json(json!({ "public_key": public_key.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("5")))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_DROP_EXISTS"));
+
+    let address = format!("0x{}", hex::encode([0x11; 20]));
+    let result = root
+        .call(gateway.id(), "claim_drop")
+        .args_json(json!({ "address": address.clone() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    let result = gateway
+        .call("claim_drop")
+        .args_json(json!({ "address": address.clone() }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(result.logs().iter().any(|log| log.contains("claim_drop")));
+    let created: bool = gateway
+        .view("is_created")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert!(created);
+    let account_id: String = gateway
+        .view("get_account_id")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert_eq!(balance(&worker, &account_id).await?, to_yocto("4.9"));
+    let amount: U128 = gateway
+        .view("get_drop")
+        .args_json(json!({ "public_key": public_key }))
+        .await?
+        .json()?;
+    assert_eq!(amount.0, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_standalone_account() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+
+    let message = wallet.message(gateway.id(), 0, "create_standalone()", &[]);
+    let result = root
+        .call(gateway.id(), "create_standalone")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(to_yocto("5")))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NO_STANDALONE_CODE"));
+    wallet.nonce -= U256::one();
+
+    let result = root
+        .call(gateway.id(), "set_standalone_code")
+        .args_json(json!({ "code": Base64VecU8(PROXY_WASM.to_vec()) }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    // The plain proxy code has no `new`, so creating the account with it fails as a whole and
+    // releases the address.
+    gateway
+        .call("set_standalone_code")
+        .args_json(json!({ "code": Base64VecU8(PROXY_WASM.to_vec()) }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let deposit: U128 = gateway.view("required_standalone_deposit").await?.json()?;
+    let message = wallet.message(gateway.id(), 0, "create_standalone()", &[]);
+    let _ = root
+        .call(gateway.id(), "create_standalone")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?;
+    let created: bool = gateway
+        .view("is_created")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert!(!created);
+    let mode: Option<AccountMode> = gateway
+        .view("get_account_mode")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert_eq!(mode, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_self_calls() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // Management calls move no value.
+    let message = wallet.message("self", 1, "lock()", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_SELF_VALUE"));
+    wallet.nonce -= U256::one();
+
+    // Locked accounts don't act until unlocked, which takes the rotation delay.
+    let message = wallet.message("self", 0, "lock()", &[]);
+    root.call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let lock: Option<U64> = gateway
+        .view("get_lock")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert!(lock.is_some());
+    let message = wallet.message(gateway.id(), 0, "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_ACCOUNT_LOCKED"));
+    wallet.nonce -= U256::one();
+    let message = wallet.message("self", 0, "unlock()", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_LOCK_TIMELOCK"));
+    wallet.nonce -= U256::one();
+    gateway
+        .call("set_rotation_delay")
+        .args_json(json!({ "delay": U64(0) }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let message = wallet.message("self", 0, "unlock()", &[]);
+    root.call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let lock: Option<U64> = gateway
+        .view("get_lock")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(lock, None);
+
+    // Updates only deploy code the gateway knows.
+    let mut code = PROXY_WASM.to_vec();
+    code.extend_from_slice(&[0, 3, 2, b'v', b'2']);
+    let code_hash = sha2::Sha256::digest(&code).to_vec();
+    let message = wallet.message(
+        "self",
+        0,
+        "update(bytes32 code_hash)",
+        std::slice::from_ref(&code_hash),
+    );
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_UNKNOWN_CODE"));
+    wallet.nonce -= U256::one();
+    gateway
+        .call("set_proxy_template")
+        .args_json(json!({ "name": "v2".to_string(), "code": Base64VecU8(code) }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let message = wallet.message(
+        "self",
+        0,
+        "update(bytes32 code_hash)",
+        std::slice::from_ref(&code_hash),
+    );
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    let result = result.into_result()?;
+    assert!(result.logs().iter().any(|log| log.contains("self_call")));
+    let account_code_hash: Option<String> = gateway
+        .view("get_account_code_hash")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(account_code_hash, Some(hex::encode(code_hash)));
+
+    let new_controller = [0x33; 20];
+    let message = wallet.message(
+        "self",
+        0,
+        "set_controller(address new_controller)",
+        &[new_controller.to_vec()],
+    );
+    root.call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let rotation: Value = gateway
+        .view("get_pending_rotation")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert_eq!(
+        rotation["new_controller"],
+        format!("0x{}", hex::encode(new_controller))
+    );
+
+    // Other entry points don't take management calls.
+    let message = wallet.message("self", 0, "lock()", &[]);
+    let result = root
+        .call(gateway.id(), "proxy_ledger")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_SELF_RECEIVER"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_denylist() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let denylist = Denylist {
+        receivers: vec!["sanctioned".to_string()],
+        methods: vec!["test_call".to_string()],
+    };
+    let result = root
+        .call(gateway.id(), "set_denylist")
+        .args_json(json!({ "denylist": denylist.clone() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    gateway
+        .call("set_denylist")
+        .args_json(json!({ "denylist": denylist }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let denylist: Denylist = gateway.view("get_denylist").await?.json()?;
+    assert_eq!(denylist.receivers, vec!["sanctioned".to_string()]);
+
+    let message = wallet.message("sanctioned", to_yocto("1"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_POLICY_VIOLATION"));
+    wallet.nonce -= U256::one();
+    let message = wallet.message(
+        gateway.id(),
+        0,
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"a".to_vec()],
+    );
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_POLICY_VIOLATION"));
+    wallet.nonce -= U256::one();
+
+    // Others go through.
+    let message = wallet.message(gateway.id(), to_yocto("1"), "", &[]);
+    root.call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_settings() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let settings_message = |wallet: &mut Wallet, gas: u64, fee_token: &str, lifetime: u64| {
+        wallet.message(
+            gateway.id(),
+            0,
+            "set_settings(uint64 gas,string fee_token,uint64 max_nonce_lifetime)",
+            &[
+                gas.to_be_bytes().to_vec(),
+                fee_token.as_bytes().to_vec(),
+                lifetime.to_be_bytes().to_vec(),
+            ],
+        )
+    };
+    let message = settings_message(&mut wallet, 20 * TGAS, "token", 3600);
+    root.call(gateway.id(), "set_settings")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let settings: Value = gateway
+        .view("get_settings")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(settings["gas"], (20 * TGAS).to_string());
+    assert_eq!(settings["fee_token"], "token");
+    assert_eq!(settings["max_nonce_lifetime"], "3600");
+
+    // Salted nonces can't outlive the signer's lifetime.
+    let nonce = wallet.nonce;
+    let now = now(&worker).await?;
+    wallet.nonce = salted_nonce(U256::one(), now + 2 * 3600);
+    let message = wallet.message(
+        gateway.id(),
+        0,
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"a".to_vec()],
+    );
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NONCE_EXPIRY_TOO_FAR"));
+    wallet.nonce = salted_nonce(U256::one(), now + 1800);
+    let message = wallet.message(
+        gateway.id(),
+        0,
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"a".to_vec()],
+    );
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+
+    // Zero and empty values remove the settings.
+    wallet.nonce = nonce;
+    let message = settings_message(&mut wallet, 0, "", 0);
+    root.call(gateway.id(), "set_settings")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let settings: Option<Value> = gateway
+        .view("get_settings")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert!(settings.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_accounts() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let mut addresses = vec![];
+    for seed in &["a", "b", "c"] {
+        let mut wallet = Wallet::from_seed(gateway.id(), KeyType::SECP256K1, seed);
+        let message = wallet.message("", 0, "create()", &[]);
+        root.call(gateway.id(), "create")
+            .args_json(json!({ "message": message }))
+            .deposit(NearToken::from_yoctonear(deposit.0))
+            .max_gas()
+            .transact()
+            .await?
+            .into_result()?;
+        addresses.push(checksum_address(&wallet.public_key.0));
+    }
+
+    let num: U64 = gateway.view("get_num_accounts").await?.json()?;
+    assert_eq!(num.0, 3);
+    let accounts: Vec<String> = gateway
+        .view("get_accounts")
+        .args_json(json!({ "from_index": 0, "limit": 2 }))
+        .await?
+        .json()?;
+    assert_eq!(accounts, addresses[..2].to_vec());
+    let accounts: Vec<String> = gateway
+        .view("get_accounts")
+        .args_json(json!({ "from_index": 2, "limit": 10 }))
+        .await?
+        .json()?;
+    assert_eq!(accounts, addresses[2..].to_vec());
+    let accounts: Vec<String> = gateway
+        .view("get_accounts")
+        .args_json(json!({ "from_index": 5, "limit": 10 }))
+        .await?
+        .json()?;
+    assert!(accounts.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_legacy_nonce() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    // Key 0 nonce as the first gateway stored it: 32 bytes under `n` and the address.
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let key = [b"n".as_ref(), wallet.public_key.as_bytes()].concat();
+    let mut value = [0u8; 32];
+    U256::from(3).to_big_endian(&mut value);
+    worker.patch_state(gateway.id(), &key, &value).await?;
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "3");
+
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    let result = root
+        .call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_INCORRECT_NONCE"));
+
+    // Using the nonce moves it to the registry and frees the old entry.
+    wallet.nonce = U256::from(3);
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "4");
+    let state = worker.view_state(gateway.id()).await?;
+    assert!(!state.contains_key(&key));
+    let accounts: Vec<String> = gateway
+        .view("get_accounts")
+        .args_json(json!({ "from_index": 0, "limit": 10 }))
+        .await?
+        .json()?;
+    assert_eq!(accounts, vec![checksum_address(&wallet.public_key.0)]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_state_snapshot() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let next = deploy_gateway(&root, "next", GATEWAY_WASM).await?;
+
+    let mut owner = Wallet::new(gateway.id());
+    let spender = Wallet::with_key_type(gateway.id(), KeyType::ED25519);
+    let owner_address = format!("0x{}", hex::encode(owner.public_key));
+    let spender_address = format!("0x{}", hex::encode(spender.public_key));
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = owner.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let message = owner.message(
+        gateway.id(),
+        0,
+        "approve(address spender,uint128 amount,uint64 expires_at)",
+        &[
+            spender.public_key.as_bytes().to_vec(),
+            to_yocto("2").to_be_bytes().to_vec(),
+            (now(&worker).await? + 3600).to_be_bytes().to_vec(),
+        ],
+    );
+    root.call(gateway.id(), "approve")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let message = owner.message(
+        gateway.id(),
+        0,
+        "set_settings(uint64 gas,string fee_token,uint64 max_nonce_lifetime)",
+        &[
+            (20 * TGAS).to_be_bytes().to_vec(),
+            vec![],
+            0u64.to_be_bytes().to_vec(),
+        ],
+    );
+    root.call(gateway.id(), "set_settings")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let snapshot: StateSnapshot = gateway
+        .view("export_state")
+        .args_json(json!({ "from_index": 0, "limit": 10 }))
+        .await?
+        .json()?;
+    assert_eq!(snapshot.accounts.len(), 1);
+    assert_eq!(snapshot.allowances.len(), 1);
+    let empty: StateSnapshot = gateway
+        .view("export_state")
+        .args_json(json!({ "from_index": 10, "limit": 10 }))
+        .await?
+        .json()?;
+    assert!(empty.accounts.is_empty() && empty.allowances.is_empty());
+
+    // Only the new gateway itself can import.
+    let result = root
+        .call(next.id(), "import_state")
+        .args_json(json!({ "snapshot": snapshot }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    let snapshot: StateSnapshot = gateway
+        .view("export_state")
+        .args_json(json!({ "from_index": 0, "limit": 10 }))
+        .await?
+        .json()?;
+    next.call("import_state")
+        .args_json(json!({ "snapshot": snapshot }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let nonce: String = next
+        .view("get_nonce")
+        .args_json(json!({ "address": owner_address.clone(), "key": null }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "3");
+    let allowance: Value = next
+        .view("get_allowance")
+        .args_json(json!({ "owner": owner_address.clone(), "spender": spender_address }))
+        .await?
+        .json()?;
+    assert_eq!(allowance["amount"], to_yocto("2").to_string());
+    let settings: Value = next
+        .view("get_settings")
+        .args_json(json!({ "address": owner_address }))
+        .await?
+        .json()?;
+    assert_eq!(settings["gas"], (20 * TGAS).to_string());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_migrate() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", LEGACY_GATEWAY_WASM).await?;
+
+    // The first gateway creates the account, using nonce 0.
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": legacy_message(&wallet, 0, "") }))
+        .deposit(NearToken::from_yoctonear(to_yocto("1")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // Only the gateway itself can migrate.
+    let result = root
+        .call(gateway.id(), "migrate")
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("Method migrate is private"));
+    gateway
+        .as_account()
+        .batch(gateway.id())
+        .deploy(GATEWAY_WASM)
+        .call(Function::new("migrate").gas(Gas::from_tgas(200)))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address.clone(), "key": null }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "1");
+    let account_id: String = gateway
+        .view("get_account_id")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(account_id, wallet.account_id());
+
+    // Messages executed by the first gateway can't be replayed.
+    let message = wallet.message(root.id(), 0, "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_INCORRECT_NONCE"));
+    // The account created by the first gateway keeps acting on messages.
+    let message = wallet.message(root.id(), 0, "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert_success(result);
+    let nonce: String = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": address, "key": null }))
+        .await?
+        .json()?;
+    assert_eq!(nonce, "2");
+    let num: U64 = gateway.view("get_num_accounts").await?.json()?;
+    assert_eq!(num.0, 1);
+
+    // The state is no longer the first gateway's.
+    let result = gateway
+        .as_account()
+        .batch(gateway.id())
+        .deploy(GATEWAY_WASM)
+        .call(Function::new("migrate").gas(Gas::from_tgas(200)))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NO_STATE"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_message_outcome() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    let hash: MetaCallHash = gateway
+        .view("hash_meta_call")
+        .args_json(json!({ "message": message.clone() }))
+        .await?
+        .json()?;
+    let outcome: MessageOutcome = root
+        .call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0 + to_yocto("1")))
+        .max_gas()
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(outcome.digest, Some(hash.digest));
+    assert_eq!(outcome.account_id.as_str(), wallet.account_id());
+    assert!(outcome.success);
+
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    let hash: MetaCallHash = gateway
+        .view("hash_meta_call")
+        .args_json(json!({ "message": message.clone() }))
+        .await?
+        .json()?;
+    let outcome: MessageOutcome = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(outcome.digest, Some(hash.digest));
+    assert!(outcome.success);
+
+    // The nonce is used even though the proxy account can't send that much.
+    let message = wallet.message(user2.id(), to_yocto("1000"), "", &[]);
+    let outcome: MessageOutcome = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .json()?;
+    assert!(!outcome.success);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dry_run() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = checksum_address(&wallet.public_key.0);
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let message = wallet.message("user2", to_yocto("1"), "", &[]);
+    let verdict: DryRunVerdict = gateway
+        .view("dry_run")
+        .args_json(json!({ "message": message.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(verdict.address, Some(address.clone()));
+    assert_eq!(verdict.account_id, Some(wallet.account_id().parse()?));
+    assert!(verdict.error.is_none());
+
+    // The nonce isn't used, the next message has the same one.
+    wallet.nonce -= U256::one();
+    let next = wallet.message("user2", to_yocto("2"), "", &[]);
+    let verdict: DryRunVerdict = gateway
+        .view("dry_run")
+        .args_json(json!({ "message": next }))
+        .await?
+        .json()?;
+    assert!(verdict.error.is_none());
+    let verdict: DryRunVerdict = gateway
+        .view("dry_run")
+        .args_json(json!({ "message": wallet.message("user2", 0, "", &[]) }))
+        .await?
+        .json()?;
+    assert_eq!(verdict.error.as_deref(), Some("ERR_INCORRECT_NONCE"));
+
+    let verdict: DryRunVerdict = gateway
+        .view("dry_run")
+        .args_json(json!({ "message": SignedMessage(vec![1, 2, 3]) }))
+        .await?
+        .json()?;
+    assert!(verdict.address.is_none());
+    assert!(verdict.error.unwrap().starts_with("ERR_META_TX_PARSE"));
+
+    let denylist = Denylist {
+        receivers: vec!["user2".to_string()],
+        methods: vec![],
+    };
+    gateway
+        .call("set_denylist")
+        .args_json(json!({ "denylist": denylist }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let verdict: DryRunVerdict = gateway
+        .view("dry_run")
+        .args_json(json!({ "message": message }))
+        .await?
+        .json()?;
+    assert_eq!(verdict.error.as_deref(), Some("ERR_POLICY_VIOLATION"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_typed_data_info() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let separator: String = gateway.view("domain_separator").await?.json()?;
+    assert_eq!(
+        separator,
+        format!("0x{}", hex::encode(near_erc712_domain(U256::from(1))))
+    );
+    let chain_id: U64 = gateway.view("get_chain_id").await?.json()?;
+    assert_eq!(chain_id.0, 1);
+    let info: Value = gateway.view("get_typed_data_info").await?.json()?;
+    assert_eq!(info["name"], "NEAR");
+    assert_eq!(info["version"], "1");
+    assert_eq!(info["chain_id"], "1");
+    assert_eq!(info["domain_separator"], separator);
+    assert!(info["near_tx_type"]
+        .as_str()
+        .unwrap()
+        .starts_with("NearTx(string gatewayId,"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_source_metadata() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let version: String = gateway.view("version").await?.json()?;
+    assert_eq!(version, env!("CARGO_PKG_VERSION"));
+    let metadata: Value = gateway.view("contract_source_metadata").await?.json()?;
+    assert_eq!(metadata["version"], version);
+    assert_eq!(metadata["link"], env!("CARGO_PKG_REPOSITORY"));
+    assert_eq!(metadata["standards"][0]["standard"], "nep330");
+    Ok(())
+}
+
+#[test]
+fn test_abi_up_to_date() {
+    let abi: Value = near_sdk::serde_json::from_str(include_str!("../abi.json")).unwrap();
+    let mut functions: Vec<&str> = abi["body"]["functions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|function| function["name"].as_str().unwrap())
+        .collect();
+    let mut methods: Vec<&str> = include_str!("../src/lib.rs")
+        .lines()
+        .filter_map(|line| line.strip_prefix("    pub fn "))
+        .map(|line| &line[..line.find('(').unwrap()])
+        .collect();
+    functions.sort_unstable();
+    methods.sort_unstable();
+    assert_eq!(
+        functions, methods,
+        "abi.json is out of date, see test_abi_generated"
+    );
+}
+
+/// Checks abi.json is the near-abi near-sdk generates, `UPDATE_ABI=1 cargo test -p gateway
+/// --features testing,abi-generate test_abi_generated` writes it.
+#[cfg(feature = "abi-generate")]
+#[test]
+fn test_abi_generated() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/abi.json");
+    let abi = gateway::generate_abi();
+    if std::env::var_os("UPDATE_ABI").is_some() {
+        let json = near_sdk::serde_json::to_string_pretty(&abi).unwrap();
+        std::fs::write(path, json + "\n").unwrap();
+    }
+    let expected: Value =
+        near_sdk::serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert!(
+        abi == expected,
+        "abi.json is out of date, run with UPDATE_ABI=1"
+    );
+}
+
+#[tokio::test]
+async fn test_history() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0 + to_yocto("1")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    root.call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .into_result()?;
+    let message = wallet.message(user2.id(), to_yocto("1000"), "", &[]);
+    let hash: MetaCallHash = gateway
+        .view("hash_meta_call")
+        .args_json(json!({ "message": message.clone() }))
+        .await?
+        .json()?;
+    root.call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let history: Vec<ExecutedMessage> = gateway
+        .view("get_history")
+        .args_json(json!({ "address": address.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(
+        history
+            .iter()
+            .map(|message| message.success)
+            .collect::<Vec<_>>(),
+        vec![Some(true), Some(true), Some(false)]
+    );
+    assert_eq!(history[2].digest, hash.digest);
+
+    // Only the last messages are kept.
+    for _ in 0..10 {
+        let message = wallet.message(user2.id(), 1, "", &[]);
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+    let history: Vec<ExecutedMessage> = gateway
+        .view("get_history")
+        .args_json(json!({ "address": address }))
+        .await?
+        .json()?;
+    assert_eq!(history.len(), 10);
+    assert!(history.iter().all(|message| message.digest != hash.digest));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relayer_quota() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let relayer = create_user(&root, "relayer", to_yocto("100")).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address }))
+        .deposit(NearToken::from_yoctonear(to_yocto("10")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = root
+        .call(gateway.id(), "set_relayer_quota")
+        .args_json(json!({ "relayer": null, "quota": U64(2) }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    gateway
+        .call("set_relayer_quota")
+        .args_json(json!({ "relayer": null, "quota": U64(2) }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    gateway
+        .call("set_relayer_quota")
+        .args_json(json!({ "relayer": relayer.id(), "quota": U64(5) }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let quota: Option<U64> = gateway
+        .view("get_relayer_quota")
+        .args_json(json!({ "relayer": root.id() }))
+        .await?
+        .json()?;
+    assert_eq!(quota.map(|quota| quota.0), Some(2));
+
+    for _ in 0..2 {
+        let message = wallet.message(
+            gateway.id(),
+            0,
+            "test_call(uint64 x,string y)",
+            &[vec![1], b"a".to_vec()],
+        );
+        assert_success(
+            root.call(gateway.id(), "proxy")
+                .args_json(json!({ "message": message }))
+                .gas(Gas::from_tgas(100))
+                .transact()
+                .await?,
+        );
+    }
+    let message = wallet.message(
+        gateway.id(),
+        0,
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"a".to_vec()],
+    );
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message.clone() }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_RATE_LIMITED"));
+    let verdict: DryRunVerdict = gateway
+        .view("dry_run")
+        .args_json(json!({ "message": message.clone(), "relayer": root.id() }))
+        .await?
+        .json()?;
+    assert_eq!(verdict.error.as_deref(), Some("ERR_RATE_LIMITED"));
+    let verdict: DryRunVerdict = gateway
+        .view("dry_run")
+        .args_json(json!({ "message": message.clone(), "relayer": relayer.id() }))
+        .await?
+        .json()?;
+    assert_eq!(verdict.error, None);
+    let usage: U64 = gateway
+        .view("get_relayer_usage")
+        .args_json(json!({ "relayer": root.id() }))
+        .await?
+        .json()?;
+    assert_eq!(usage.0, 2);
+
+    // Another relayer with its own quota submits the same message.
+    assert_success(
+        relayer
+            .call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+
+    // Lifting the default quota lifts the limit.
+    gateway
+        .call("set_relayer_quota")
+        .args_json(json!({ "relayer": null, "quota": null }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let message = wallet.message(
+        gateway.id(),
+        0,
+        "test_call(uint64 x,string y)",
+        &[vec![1], b"a".to_vec()],
+    );
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_circuit_breaker() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address }))
+        .deposit(NearToken::from_yoctonear(to_yocto("20")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let circuit_breaker = CircuitBreaker {
+        cap: U128(to_yocto("3")),
+        threshold: U128(to_yocto("1")),
+    };
+    gateway
+        .call("set_circuit_breaker")
+        .args_json(json!({ "circuit_breaker": circuit_breaker }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let message = wallet.message(user2.id(), to_yocto("3"), "", &[]);
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    let value: U128 = gateway.view("get_epoch_value").await?.json()?;
+    assert_eq!(value.0, to_yocto("3"));
+
+    // Over the cap only values below the threshold go through.
+    let message = wallet.message(user2.id(), to_yocto("2"), "", &[]);
+    let verdict: DryRunVerdict = gateway
+        .view("dry_run")
+        .args_json(json!({ "message": message.clone() }))
+        .await?
+        .json()?;
+    assert_eq!(verdict.error.as_deref(), Some("ERR_CIRCUIT_BREAKER"));
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_CIRCUIT_BREAKER"));
+    wallet.nonce -= U256::one();
+    let message = wallet.message(user2.id(), to_yocto("0.5"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert_success(result);
+
+    // Lifting the breaker counts from zero again.
+    gateway
+        .call("lift_circuit_breaker")
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let message = wallet.message(user2.id(), to_yocto("2"), "", &[]);
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_ledger() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "deposit_to")
+        .args_json(json!({ "address": address }))
+        .deposit(NearToken::from_yoctonear(to_yocto("5")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let circuit_breaker = CircuitBreaker {
+        cap: U128(to_yocto("3")),
+        threshold: U128(to_yocto("1")),
+    };
+    gateway
+        .call("set_circuit_breaker")
+        .args_json(json!({ "circuit_breaker": circuit_breaker }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // Values leaving through other entry points than `proxy` count towards the cap too.
+    let message = wallet.message(user2.id(), to_yocto("3"), "", &[]);
+    assert_success(
+        root.call(gateway.id(), "proxy_ledger")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    let value: U128 = gateway.view("get_epoch_value").await?.json()?;
+    assert_eq!(value.0, to_yocto("3"));
+    let message = wallet.message(
+        gateway.id(),
+        0,
+        "withdraw(uint128 amount,string receiver)",
+        &[
+            to_yocto("2").to_be_bytes().to_vec(),
+            user2.id().as_bytes().to_vec(),
+        ],
+    );
+    let result = root
+        .call(gateway.id(), "withdraw")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_CIRCUIT_BREAKER"));
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("103"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_eth_logs() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address.clone() }))
+        .deposit(NearToken::from_yoctonear(to_yocto("20")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(!result.logs().iter().any(|log| log.starts_with("EVM_LOG:")));
+
+    gateway
+        .call("set_eth_logs")
+        .args_json(json!({ "enabled": true }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    assert!(gateway.view("get_eth_logs").await?.json::<bool>()?);
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message.clone() }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    // Replays fail the nonce check before anything is logged.
+    let replay = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", replay.failures()).contains("ERR_INCORRECT_NONCE"));
+    assert!(!replay.logs().iter().any(|log| log.starts_with("EVM_LOG:")));
+    let log = result
+        .logs()
+        .iter()
+        .find_map(|log| log.strip_prefix("EVM_LOG:"))
+        .map(|log| near_sdk::serde_json::from_str::<Value>(log).unwrap())
+        .unwrap();
+    let topics = log["topics"].as_array().unwrap();
+    assert_eq!(
+        topics[0],
+        format!(
+            "0x{}",
+            hex::encode(sha3::Keccak256::digest(
+                b"MetaCall(address,uint256,string,string,uint256)"
+            ))
+        )
+    );
+    assert_eq!(
+        topics[1],
+        format!("0x{:0>64}", hex::encode(wallet.public_key))
+    );
+    assert_eq!(topics[2], format!("0x{:064x}", 1));
+    // Data is `abi.encode(receiver, method, value)`.
+    let data = hex::decode(log["data"].as_str().unwrap().trim_start_matches("0x")).unwrap();
+    let tokens = ethabi::decode(
+        &[
+            ethabi::ParamType::String,
+            ethabi::ParamType::String,
+            ethabi::ParamType::Uint(256),
+        ],
+        &data,
+    )
+    .unwrap();
+    assert_eq!(tokens[0], ethabi::Token::String(user2.id().to_string()));
+    assert_eq!(tokens[1], ethabi::Token::String("".to_string()));
+    assert_eq!(tokens[2], ethabi::Token::Uint(to_yocto("1").into()));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_checksummed_addresses() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = checksum_address(&wallet.public_key.0);
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    root.call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let accounts: Vec<String> = gateway
+        .view("get_accounts")
+        .args_json(json!({ "from_index": 0, "limit": 10 }))
+        .await?
+        .json()?;
+    assert_eq!(accounts, vec![address.clone()]);
+
+    // Checksummed, lowercase and uppercase addresses are accepted, miscased ones aren't.
+    for accepted in &[
+        address.clone(),
+        address.to_lowercase(),
+        format!("0x{}", address[2..].to_uppercase()),
+    ] {
+        let nonce: String = gateway
+            .view("get_nonce")
+            .args_json(json!({ "address": accepted.clone(), "key": null }))
+            .await?
+            .json()?;
+        assert_eq!(nonce, "1");
+    }
+    // Flipping the case of one letter breaks the checksum.
+    let letter = address[2..].find(char::is_alphabetic).unwrap() + 2;
+    let mut miscased = address.clone();
+    let flipped = if address[letter..].starts_with(char::is_lowercase) {
+        address[letter..letter + 1].to_uppercase()
+    } else {
+        address[letter..letter + 1].to_lowercase()
+    };
+    miscased.replace_range(letter..letter + 1, &flipped);
+    let result = gateway
+        .view("get_nonce")
+        .args_json(json!({ "address": miscased.clone(), "key": null }))
+        .await;
+    assert!(format!("{:?}", result.unwrap_err()).contains("ERR_ADDRESS_CHECKSUM"));
+
+    // Messages to a miscased address are likely copy errors.
+    let message = wallet.message(&miscased, 0, "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_ADDRESS_CHECKSUM"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_hex_message() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address }))
+        .deposit(NearToken::from_yoctonear(to_yocto("20")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // Signed messages can be passed as `0x` prefixed hex instead of base64.
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    let args = json!({ "message": format!("0x{}", hex::encode(&message.0)) });
+    assert_success(
+        root.call(gateway.id(), "proxy")
+            .args_json(args)
+            .gas(Gas::from_tgas(100))
+            .transact()
+            .await?,
+    );
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_proxy_json() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address }))
+        .deposit(NearToken::from_yoctonear(to_yocto("20")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // The message's fields as plain JSON, signed as usual.
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .fee(5, "token")
+        .receiver(user2.id())
+        .value(to_yocto("1"));
+    let signature = match meta_call_signature(&wallet.signer, &builder) {
+        MetaCallSignature::Secp256k1 { signature, v } => {
+            format!("0x{}{:02x}", hex::encode(&signature[..]), v)
+        }
+        _ => unreachable!(),
+    };
+    let args = MetaCallArgsJson {
+        signature,
+        nonce: "0".to_string(),
+        fee_amount: "5".to_string(),
+        fee_address: "token".to_string(),
+        contract_address: user2.id().to_string(),
+        value: "1 NEAR".to_string(),
+        method: String::new(),
+        method_id: None,
+        args_encoding: None,
+        call_args_format: None,
+        args: None,
+        schema: None,
+        tip: None,
+        gas: None,
+        deposit_source: None,
+        value_unit: None,
+    };
+    let mut invalid = args.clone();
+    invalid.value = "1 ETH".to_string();
+    let result = root
+        .call(gateway.id(), "proxy_json")
+        .args_json(json!({ "args": invalid }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("amount like"));
+
+    let outcome: MessageOutcome = root
+        .call(gateway.id(), "proxy_json")
+        .args_json(json!({ "args": args }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .json()?;
+    assert!(outcome.success);
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_wei_value() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address }))
+        .deposit(NearToken::from_yoctonear(to_yocto("20")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // 1.5 * 10^18 wei-style units are 1.5 NEAR.
+    let builder = MetaCallBuilder::new(gateway.id(), 1)
+        .fee(5, "token")
+        .receiver(user2.id())
+        .value(1_500_000_000_000_000_000)
+        .value_unit(ValueUnit::Wei);
+    let message = SignedMessage(sign_meta_call(&wallet.signer, &builder));
+    let outcome: MessageOutcome = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .json()?;
+    assert!(outcome.success);
+    assert_eq!(balance(&worker, user2.id()).await?, to_yocto("101.5"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_message_limits() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+
+    let mut wallet = Wallet::new(gateway.id());
+    let address = format!("0x{}", hex::encode(wallet.public_key));
+    root.call(gateway.id(), "create_for")
+        .args_json(json!({ "address": address }))
+        .deposit(NearToken::from_yoctonear(to_yocto("20")))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let limits: MessageLimits = gateway.view("get_message_limits").await?.json()?;
+    assert_eq!((limits.method, limits.args), (1024, 16 * 1024));
+
+    let limits = MessageLimits {
+        method: 20,
+        args: 64,
+    };
+    let result = root
+        .call(gateway.id(), "set_message_limits")
+        .args_json(json!({ "limits": limits.clone() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_NOT_ALLOWED"));
+    gateway
+        .call("set_message_limits")
+        .args_json(json!({ "limits": limits }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let message = wallet.message(
+        user2.id(),
+        0,
+        "test_call(string long_name)",
+        &[b"x".to_vec()],
+    );
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_METHOD_TOO_LARGE"));
+    wallet.nonce -= U256::one();
+
+    let message = wallet.message(user2.id(), 0, "test_call(string y)", &[vec![b'x'; 100]]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_ARGS_TOO_LARGE"));
+    wallet.nonce -= U256::one();
+
+    // Oversized messages fail before they are decoded, and the ones that don't decode fail.
+    let mut message = wallet.message(user2.id(), 0, "", &[]);
+    wallet.nonce -= U256::one();
+    message.0.extend(vec![0; 5 * 1024]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message.clone() }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_MESSAGE_TOO_LARGE"));
+    message.0.truncate(message.0.len() - 5 * 1024 + 1);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_META_TX_PARSE"));
+
+    // Ethereum transactions are held to the same limits.
+    let mut data = rlp::RlpStream::new_list(3);
+    data.append(&user2.id().as_str())
+        .append(&"test_call(string long_name)")
+        .append(&rlp::encode_list::<Vec<u8>, _>(&[b"x".to_vec()]).to_vec());
+    let chain_id: U64 = gateway.view("get_eth_chain_id").await?.json()?;
+    let tx = sign_eth_transaction(&wallet.signer, chain_id.0, wallet.nonce, &data.out());
+    let result = root
+        .call(gateway.id(), "submit_eth_tx")
+        .args_json(json!({ "rlp": Base64VecU8(tx) }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(format!("{:?}", result.failures()).contains("ERR_METHOD_TOO_LARGE"));
+
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    root.call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(())
+}
+
+/// Gas and code sizes may grow by this percentage over `gas_baseline.json` before
+/// `test_gas_regressions` fails.
+const REGRESSION_THRESHOLD_PERCENT: u64 = 10;
+
+/// Measures gas of `create`, `proxy` transfers and calls across argument sizes, and the wasm
+/// sizes, against `tests/gas_baseline.json`. Run with `UPDATE_GAS_BASELINE=1` to record the
+/// current numbers instead, e.g. after an intended change, and commit the file.
+#[tokio::test]
+async fn test_gas_regressions() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let gateway = deploy_gateway(&root, "gateway", GATEWAY_WASM).await?;
+    let user2 = create_user(&root, "user2", to_yocto("100")).await?;
+    let other = deploy_gateway(&root, "other", GATEWAY_WASM).await?;
+    let mut measured = vec![
+        ("gateway_wasm_bytes".to_string(), GATEWAY_WASM.len() as u64),
+        ("proxy_wasm_bytes".to_string(), PROXY_WASM.len() as u64),
+    ];
+
+    let mut wallet = Wallet::new(gateway.id());
+    let deposit: U128 = gateway.view("required_create_deposit").await?.json()?;
+    let message = wallet.message("", 0, "create()", &[]);
+    let result = root
+        .call(gateway.id(), "create")
+        .args_json(json!({ "message": message }))
+        .deposit(NearToken::from_yoctonear(deposit.0))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    measured.push(("create".to_string(), result.total_gas_burnt.as_gas()));
+
+    let message = wallet.message(user2.id(), to_yocto("1"), "", &[]);
+    let result = root
+        .call(gateway.id(), "proxy")
+        .args_json(json!({ "message": message }))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .into_result()?;
+    assert!(result.json::<MessageOutcome>()?.success);
+    measured.push((
+        "proxy_transfer".to_string(),
+        result.total_gas_burnt.as_gas(),
+    ));
+
+    // The called view takes no arguments, so only the gateway and the proxy handle them.
+    for size in &[0, 1024, 4096] {
+        let message = wallet.message(
+            other.id(),
+            0,
+            "get_eth_logs(string data)",
+            &[vec![b'x'; *size]],
+        );
+        let result = root
+            .call(gateway.id(), "proxy")
+            .args_json(json!({ "message": message }))
+            .gas(Gas::from_tgas(200))
+            .transact()
+            .await?
+            .into_result()?;
+        assert!(result.json::<MessageOutcome>()?.success);
+        measured.push((
+            format!("proxy_call_{}", size),
+            result.total_gas_burnt.as_gas(),
+        ));
+    }
+
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/gas_baseline.json");
+    let baseline: Option<Value> = std::fs::read_to_string(path)
+        .ok()
+        .map(|text| near_sdk::serde_json::from_str(&text).unwrap());
+    let baseline = match baseline {
+        Some(baseline) if std::env::var("UPDATE_GAS_BASELINE").is_err() => baseline,
+        _ => {
+            let record: near_sdk::serde_json::Map<String, Value> = measured
+                .into_iter()
+                .map(|(name, value)| (name, json!(value)))
+                .collect();
+            let text = near_sdk::serde_json::to_string_pretty(&record).unwrap();
+            std::fs::write(path, text + "\n").unwrap();
+            println!("Recorded the gas baseline in {}", path);
+            return Ok(());
+        }
+    };
+    let regressions: Vec<String> = measured
+        .iter()
+        .filter_map(|(name, value)| {
+            let expected = baseline[name].as_u64()?;
+            println!("{}: {} (baseline {})", name, value, expected);
+            if *value * 100 > expected * (100 + REGRESSION_THRESHOLD_PERCENT) {
+                Some(format!(
+                    "{} is {}, over {} by more than {}%",
+                    name, value, expected, REGRESSION_THRESHOLD_PERCENT
+                ))
+            } else {
+                None
+            }
+        })
+        .collect();
+    assert!(regressions.is_empty(), "Regressions: {:?}", regressions);
+    Ok(())
 }
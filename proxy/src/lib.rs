@@ -1,49 +1,43 @@
-#![no_std]
-#![feature(core_intrinsics)]
-#![feature(alloc_error_handler)]
+// Only the contract is `no_std`, on the host the crate builds with std for fuzzing and the
+// unit tests against the `mock` feature's host functions. Exported methods are
+// `extern "C-unwind"` so that the mock's `panic` fails unit tests instead of aborting them; the
+// contract is built with `panic = "abort"`, where this changes nothing.
+#![cfg_attr(target_arch = "wasm32", no_std)]
+#![cfg_attr(target_arch = "wasm32", feature(core_intrinsics))]
+#![cfg_attr(target_arch = "wasm32", feature(alloc_error_handler))]
 
 extern crate alloc;
 
+#[cfg(any(target_arch = "wasm32", feature = "mock"))]
 use alloc::vec;
 
+#[cfg(target_arch = "wasm32")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+#[cfg(target_arch = "wasm32")]
 #[panic_handler]
 #[no_mangle]
 pub unsafe fn on_panic(_info: &::core::panic::PanicInfo) -> ! {
     ::core::intrinsics::abort();
 }
 
+#[cfg(target_arch = "wasm32")]
 #[alloc_error_handler]
 #[no_mangle]
 pub unsafe fn on_alloc_error(_: core::alloc::Layout) -> ! {
     ::core::intrinsics::abort();
 }
 
-#[allow(dead_code)]
-extern "C" {
-    fn read_register(register_id: u64, ptr: u64);
-    fn register_len(register_id: u64) -> u64;
-    fn current_account_id(register_id: u64);
-    fn predecessor_account_id(register_id: u64);
-    fn input(register_id: u64);
-    fn panic();
-    fn log_utf8(len: u64, ptr: u64);
-    fn promise_batch_create(account_id_len: u64, account_id_ptr: u64) -> u64;
-    fn promise_batch_action_function_call(
-        promise_index: u64,
-        method_name_len: u64,
-        method_name_ptr: u64,
-        arguments_len: u64,
-        arguments_ptr: u64,
-        amount_ptr: u64,
-        gas: u64,
-    );
-    fn promise_batch_action_deploy_contract(promise_index: u64, code_len: u64, code_ptr: u64);
-    fn promise_batch_action_transfer(promise_index: u64, amount_ptr: u64);
-}
+pub mod sys;
+#[cfg(any(target_arch = "wasm32", feature = "mock"))]
+use sys::*;
+
+/// Version of the proxy code, as a JSON string.
+#[cfg(any(target_arch = "wasm32", feature = "mock"))]
+const VERSION: &str = concat!("\"", env!("CARGO_PKG_VERSION"), "\"");
 
+#[cfg(any(target_arch = "wasm32", feature = "mock"))]
 #[allow(dead_code)]
 fn log(message: &str) {
     unsafe {
@@ -52,6 +46,7 @@ fn log(message: &str) {
 }
 
 /// Check that predecessor of given account if suffix of given account.
+#[cfg(any(target_arch = "wasm32", feature = "mock"))]
 fn assert_predecessor() {
     unsafe {
         current_account_id(0);
@@ -67,57 +62,114 @@ fn assert_predecessor() {
     }
 }
 
-fn slice_to_u64(s: &[u8]) -> u64 {
-    let mut word = [0u8; 8];
-    word.copy_from_slice(s);
-    u64::from_le_bytes(word)
+/// Check that deposit attached by the gateway is either none or exactly the given amount,
+/// so deposits forwarded from the relayer are spent as a whole and never mixed with the balance.
+#[cfg(any(target_arch = "wasm32", feature = "mock"))]
+fn assert_deposit(amount: &[u8]) {
+    unsafe {
+        let deposit = [0u8; 16];
+        attached_deposit(deposit.as_ptr() as _);
+        if deposit != [0u8; 16] && deposit[..] != amount[..] {
+            panic();
+        }
+    }
+}
+
+/// Input of `call`, borrowed from the input buffer.
+#[derive(Debug, PartialEq)]
+pub struct CallInput<'a> {
+    pub gas: u64,
+    /// Little-endian u128 deposit attached to the call.
+    pub amount: &'a [u8],
+    pub receiver_id: &'a [u8],
+    pub method_name: &'a [u8],
+    pub args: &'a [u8],
 }
 
-fn slice_to_u32(s: &[u8]) -> u32 {
+/// Splits `len` bytes off the front of the input, None if it's shorter.
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if data.len() < len {
+        return None;
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Some(head)
+}
+
+fn take_u32(data: &mut &[u8]) -> Option<u32> {
     let mut word = [0u8; 4];
-    word.copy_from_slice(s);
-    u32::from_le_bytes(word)
+    word.copy_from_slice(take(data, 4)?);
+    Some(u32::from_le_bytes(word))
 }
 
-/// This proxies passed call.
-/// Checks that predecessor is suffix of the given account.
+/// Decodes the input of `call`:
 /// <gas:64><amount:u128><receiver_len:u32><receiver_id:bytes><method_name_len:u32><method_name:bytes><args_len:u32><args:bytes>
-#[no_mangle]
-pub extern "C" fn call() {
+/// None unless the lengths add up to exactly the input, so the host never reads past it.
+pub fn decode_call(mut data: &[u8]) -> Option<CallInput<'_>> {
+    let mut gas = [0u8; 8];
+    gas.copy_from_slice(take(&mut data, 8)?);
+    let amount = take(&mut data, 16)?;
+    let receiver_len = take_u32(&mut data)? as usize;
+    let receiver_id = take(&mut data, receiver_len)?;
+    let method_name_len = take_u32(&mut data)? as usize;
+    let method_name = take(&mut data, method_name_len)?;
+    let args_len = take_u32(&mut data)? as usize;
+    let args = take(&mut data, args_len)?;
+    if !data.is_empty() {
+        return None;
+    }
+    Some(CallInput {
+        gas: u64::from_le_bytes(gas),
+        amount,
+        receiver_id,
+        method_name,
+        args,
+    })
+}
+
+/// This proxies passed call.
+/// Checks that predecessor is suffix of the given account, and that the attached deposit, if any, is the amount.
+/// Input is decoded by `decode_call`.
+#[cfg(any(target_arch = "wasm32", feature = "mock"))]
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C-unwind" fn call() {
     assert_predecessor();
     unsafe {
         input(2);
         let data = vec![0u8; register_len(2) as usize];
         read_register(2, data.as_ptr() as *const u64 as u64);
-        let gas = slice_to_u64(&data[..8]);
-        let amount = &data[8..24]; // as u128;
-        let receiver_len = slice_to_u32(&data[24..28]) as usize;
-        let method_name_len = slice_to_u32(&data[28 + receiver_len..32 + receiver_len]) as usize;
-        let args_len = slice_to_u32(
-            &data[32 + receiver_len + method_name_len..36 + receiver_len + method_name_len],
-        ) as usize;
-        let id = promise_batch_create(receiver_len as _, data.as_ptr() as u64 + 28);
+        let call = match decode_call(&data) {
+            Some(call) => call,
+            None => {
+                panic();
+                return;
+            }
+        };
+        assert_deposit(call.amount);
+        let id = promise_batch_create(call.receiver_id.len() as _, call.receiver_id.as_ptr() as _);
         promise_batch_action_function_call(
             id,
-            method_name_len as _,
-            data.as_ptr() as u64 + 32 + receiver_len as u64,
-            args_len as _,
-            data.as_ptr() as u64 + 36 + (receiver_len + method_name_len) as u64,
-            amount.as_ptr() as _,
-            gas,
+            call.method_name.len() as _,
+            call.method_name.as_ptr() as _,
+            call.args.len() as _,
+            call.args.as_ptr() as _,
+            call.amount.as_ptr() as _,
+            call.gas,
         );
     }
 }
 
 /// Transfers given amount of $NEAR to given account.
 /// Input format <amount:u128><receiver_id:bytes>
-#[no_mangle]
-pub extern "C" fn transfer() {
+#[cfg(any(target_arch = "wasm32", feature = "mock"))]
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C-unwind" fn transfer() {
     assert_predecessor();
     unsafe {
         input(2);
         let data = vec![0u8; register_len(2) as usize];
         read_register(2, data.as_ptr() as *const u64 as u64);
+        assert_deposit(&data[..16]);
         let id = promise_batch_create((data.len() - 16) as _, data.as_ptr() as u64 + 16);
         promise_batch_action_transfer(id, data.as_ptr() as _);
     }
@@ -125,8 +177,9 @@ pub extern "C" fn transfer() {
 
 /// This allows to update the contract on this account.
 /// Checks that predecessor is suffix of the given account.
-#[no_mangle]
-pub extern "C" fn update() {
+#[cfg(any(target_arch = "wasm32", feature = "mock"))]
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C-unwind" fn update() {
     assert_predecessor();
     unsafe {
         let id = promise_batch_create(u64::MAX as _, 0 as _);
@@ -134,3 +187,191 @@ pub extern "C" fn update() {
         promise_batch_action_deploy_contract(id, u64::MAX as _, 2 as _);
     }
 }
+
+/// Adds a full access key to this account, so its holder controls the account directly too.
+/// Checks that predecessor is suffix of the given account.
+/// Input format <public_key:bytes>, the key type byte followed by the key.
+#[cfg(any(target_arch = "wasm32", feature = "mock"))]
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C-unwind" fn add_key() {
+    assert_predecessor();
+    unsafe {
+        input(2);
+        let data = vec![0u8; register_len(2) as usize];
+        read_register(2, data.as_ptr() as *const u64 as u64);
+        let id = promise_batch_create(u64::MAX as _, 0 as _);
+        promise_batch_action_add_key_with_full_access(id, data.len() as _, data.as_ptr() as _, 0);
+    }
+}
+
+/// Returns the version of the proxy code, so explorers and the relayer can tell which build an
+/// account runs.
+#[cfg(any(target_arch = "wasm32", feature = "mock"))]
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C-unwind" fn version() {
+    unsafe {
+        value_return(VERSION.len() as _, VERSION.as_ptr() as _);
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::sys::mock::{set_host, with_host, Action, Host, Promise};
+
+    fn setup(predecessor: &str, input: &[u8], deposit: u128) {
+        set_host(Host {
+            current_account_id: b"abc.gateway".to_vec(),
+            predecessor_account_id: predecessor.as_bytes().to_vec(),
+            input: input.to_vec(),
+            attached_deposit: deposit,
+            ..Host::default()
+        });
+    }
+
+    fn promises() -> Vec<Promise> {
+        with_host(|host| host.promises.clone())
+    }
+
+    fn call_input(amount: u128, receiver_id: &str, method_name: &str, args: &[u8]) -> Vec<u8> {
+        let mut data = 30_000_000_000_000u64.to_le_bytes().to_vec();
+        data.extend_from_slice(&amount.to_le_bytes());
+        for field in &[receiver_id.as_bytes(), method_name.as_bytes(), args] {
+            data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            data.extend_from_slice(field);
+        }
+        data
+    }
+
+    #[test]
+    fn test_decode_call() {
+        let data = call_input(5, "token", "ft_transfer", b"{}");
+        let call = super::decode_call(&data).unwrap();
+        assert_eq!(call.gas, 30_000_000_000_000);
+        assert_eq!(call.amount, &5u128.to_le_bytes()[..]);
+        assert_eq!(call.receiver_id, b"token");
+        assert_eq!(call.method_name, b"ft_transfer");
+        assert_eq!(call.args, b"{}");
+
+        // Lengths must add up to exactly the input.
+        assert_eq!(super::decode_call(&data[..data.len() - 1]), None);
+        let mut longer = data.clone();
+        longer.push(0);
+        assert_eq!(super::decode_call(&longer), None);
+        let mut overflowing = data;
+        overflowing[24..28].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(super::decode_call(&overflowing), None);
+    }
+
+    #[test]
+    fn test_call() {
+        setup("gateway", &call_input(5, "token", "ft_transfer", b"{}"), 0);
+        super::call();
+        assert_eq!(
+            promises(),
+            vec![Promise {
+                receiver_id: b"token".to_vec(),
+                actions: vec![Action::FunctionCall {
+                    method_name: b"ft_transfer".to_vec(),
+                    args: b"{}".to_vec(),
+                    amount: 5,
+                    gas: 30_000_000_000_000,
+                }],
+            }]
+        );
+
+        // Deposit forwarded by the gateway is exactly the amount.
+        setup("gateway", &call_input(5, "token", "ft_transfer", b"{}"), 5);
+        super::call();
+        assert_eq!(promises().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Proxy panicked")]
+    fn test_call_deposit_mismatch() {
+        setup("gateway", &call_input(5, "token", "ft_transfer", b"{}"), 4);
+        super::call();
+    }
+
+    #[test]
+    #[should_panic(expected = "Proxy panicked")]
+    fn test_call_malformed() {
+        let mut data = call_input(5, "token", "ft_transfer", b"{}");
+        data.push(0);
+        setup("gateway", &data, 0);
+        super::call();
+    }
+
+    #[test]
+    fn test_assert_predecessor() {
+        setup("gateway", b"", 0);
+        super::assert_predecessor();
+        for predecessor in &["way", "other", "abc.gateway", "gateway.abc"] {
+            setup(predecessor, b"", 0);
+            assert!(std::panic::catch_unwind(super::assert_predecessor).is_err());
+        }
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mut data = 7u128.to_le_bytes().to_vec();
+        data.extend_from_slice(b"user.near");
+        setup("gateway", &data, 0);
+        super::transfer();
+        assert_eq!(
+            promises(),
+            vec![Promise {
+                receiver_id: b"user.near".to_vec(),
+                actions: vec![Action::Transfer { amount: 7 }],
+            }]
+        );
+
+        setup("other", &data, 0);
+        assert!(std::panic::catch_unwind(|| super::transfer()).is_err());
+        assert!(promises().is_empty());
+    }
+
+    #[test]
+    fn test_update() {
+        setup("gateway", b"\0asm code", 0);
+        super::update();
+        assert_eq!(
+            promises(),
+            vec![Promise {
+                receiver_id: b"abc.gateway".to_vec(),
+                actions: vec![Action::DeployContract {
+                    code: b"\0asm code".to_vec(),
+                }],
+            }]
+        );
+
+        setup("abc", b"\0asm code", 0);
+        assert!(std::panic::catch_unwind(|| super::update()).is_err());
+    }
+
+    #[test]
+    fn test_add_key() {
+        let public_key = [vec![1], vec![2; 64]].concat();
+        setup("gateway", &public_key, 0);
+        super::add_key();
+        assert_eq!(
+            promises(),
+            vec![Promise {
+                receiver_id: b"abc.gateway".to_vec(),
+                actions: vec![Action::AddKeyWithFullAccess {
+                    public_key,
+                    nonce: 0,
+                }],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_version() {
+        setup("anyone", b"", 0);
+        super::version();
+        assert_eq!(
+            with_host(|host| host.return_value.clone()),
+            Some(format!("\"{}\"", env!("CARGO_PKG_VERSION")).into_bytes())
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Host functions the proxy calls: the NEAR runtime's in the contract, and with the `mock`
+//! feature native stand-ins recording what they are asked to do, for unit tests on the host.
+
+#[cfg(target_arch = "wasm32")]
+#[allow(dead_code)]
+extern "C" {
+    pub fn read_register(register_id: u64, ptr: u64);
+    pub fn register_len(register_id: u64) -> u64;
+    pub fn current_account_id(register_id: u64);
+    pub fn predecessor_account_id(register_id: u64);
+    pub fn input(register_id: u64);
+    pub fn attached_deposit(balance_ptr: u64);
+    pub fn panic();
+    pub fn value_return(value_len: u64, value_ptr: u64);
+    pub fn log_utf8(len: u64, ptr: u64);
+    pub fn promise_batch_create(account_id_len: u64, account_id_ptr: u64) -> u64;
+    pub fn promise_batch_action_function_call(
+        promise_index: u64,
+        method_name_len: u64,
+        method_name_ptr: u64,
+        arguments_len: u64,
+        arguments_ptr: u64,
+        amount_ptr: u64,
+        gas: u64,
+    );
+    pub fn promise_batch_action_deploy_contract(promise_index: u64, code_len: u64, code_ptr: u64);
+    pub fn promise_batch_action_transfer(promise_index: u64, amount_ptr: u64);
+    pub fn promise_batch_action_add_key_with_full_access(
+        promise_index: u64,
+        public_key_len: u64,
+        public_key_ptr: u64,
+        nonce: u64,
+    );
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "mock"))]
+pub use mock::*;
+
+/// Host functions over the state of the current thread's `Host`, which tests set up with
+/// `mock::set_host` and inspect with `mock::with_host`. Pointers are plain host addresses, and
+/// like in the runtime a length of `u64::MAX` reads the register the pointer names instead.
+/// They are unsafe like the runtime's: pointers must be valid for the lengths given.
+#[cfg(all(not(target_arch = "wasm32"), feature = "mock"))]
+#[allow(clippy::missing_safety_doc)]
+pub mod mock {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    /// Action added to a promise.
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Action {
+        FunctionCall {
+            method_name: Vec<u8>,
+            args: Vec<u8>,
+            amount: u128,
+            gas: u64,
+        },
+        Transfer {
+            amount: u128,
+        },
+        DeployContract {
+            code: Vec<u8>,
+        },
+        AddKeyWithFullAccess {
+            public_key: Vec<u8>,
+            nonce: u64,
+        },
+    }
+
+    /// Promise created by the contract, with the actions added to it.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Promise {
+        pub receiver_id: Vec<u8>,
+        pub actions: Vec<Action>,
+    }
+
+    /// Context the contract runs in and what it did.
+    #[derive(Debug, Default)]
+    pub struct Host {
+        pub current_account_id: Vec<u8>,
+        pub predecessor_account_id: Vec<u8>,
+        pub input: Vec<u8>,
+        pub attached_deposit: u128,
+        pub registers: HashMap<u64, Vec<u8>>,
+        pub promises: Vec<Promise>,
+        pub logs: Vec<String>,
+        pub return_value: Option<Vec<u8>>,
+    }
+
+    thread_local! {
+        static HOST: RefCell<Host> = RefCell::new(Host::default());
+    }
+
+    /// Replaces the host of the current thread.
+    pub fn set_host(host: Host) {
+        HOST.with(|current| *current.borrow_mut() = host);
+    }
+
+    /// Runs `f` on the host of the current thread, e.g. to check the promises the contract made.
+    pub fn with_host<T>(f: impl FnOnce(&mut Host) -> T) -> T {
+        HOST.with(|host| f(&mut host.borrow_mut()))
+    }
+
+    unsafe fn read_memory(len: u64, ptr: u64) -> Vec<u8> {
+        if len == u64::MAX {
+            return with_host(|host| host.registers[&ptr].clone());
+        }
+        std::slice::from_raw_parts(ptr as *const u8, len as usize).to_vec()
+    }
+
+    unsafe fn read_u128(ptr: u64) -> u128 {
+        let mut amount = [0u8; 16];
+        amount.copy_from_slice(&read_memory(16, ptr));
+        u128::from_le_bytes(amount)
+    }
+
+    fn set_register(register_id: u64, value: Vec<u8>) {
+        with_host(|host| host.registers.insert(register_id, value));
+    }
+
+    fn add_action(promise_index: u64, action: Action) {
+        with_host(|host| host.promises[promise_index as usize].actions.push(action));
+    }
+
+    pub unsafe fn read_register(register_id: u64, ptr: u64) {
+        let value = with_host(|host| host.registers[&register_id].clone());
+        std::ptr::copy_nonoverlapping(value.as_ptr(), ptr as *mut u8, value.len());
+    }
+
+    pub unsafe fn register_len(register_id: u64) -> u64 {
+        with_host(|host| {
+            host.registers
+                .get(&register_id)
+                .map_or(u64::MAX, |value| value.len() as u64)
+        })
+    }
+
+    pub unsafe fn current_account_id(register_id: u64) {
+        set_register(
+            register_id,
+            with_host(|host| host.current_account_id.clone()),
+        );
+    }
+
+    pub unsafe fn predecessor_account_id(register_id: u64) {
+        set_register(
+            register_id,
+            with_host(|host| host.predecessor_account_id.clone()),
+        );
+    }
+
+    pub unsafe fn input(register_id: u64) {
+        set_register(register_id, with_host(|host| host.input.clone()));
+    }
+
+    pub unsafe fn attached_deposit(balance_ptr: u64) {
+        let deposit = with_host(|host| host.attached_deposit).to_le_bytes();
+        std::ptr::copy_nonoverlapping(deposit.as_ptr(), balance_ptr as *mut u8, 16);
+    }
+
+    pub unsafe fn panic() {
+        std::panic!("Proxy panicked");
+    }
+
+    pub unsafe fn value_return(value_len: u64, value_ptr: u64) {
+        let value = read_memory(value_len, value_ptr);
+        with_host(|host| host.return_value = Some(value));
+    }
+
+    pub unsafe fn log_utf8(len: u64, ptr: u64) {
+        let message = String::from_utf8(read_memory(len, ptr)).unwrap();
+        with_host(|host| host.logs.push(message));
+    }
+
+    pub unsafe fn promise_batch_create(account_id_len: u64, account_id_ptr: u64) -> u64 {
+        let receiver_id = read_memory(account_id_len, account_id_ptr);
+        with_host(|host| {
+            host.promises.push(Promise {
+                receiver_id,
+                actions: vec![],
+            });
+            host.promises.len() as u64 - 1
+        })
+    }
+
+    pub unsafe fn promise_batch_action_function_call(
+        promise_index: u64,
+        method_name_len: u64,
+        method_name_ptr: u64,
+        arguments_len: u64,
+        arguments_ptr: u64,
+        amount_ptr: u64,
+        gas: u64,
+    ) {
+        let action = Action::FunctionCall {
+            method_name: read_memory(method_name_len, method_name_ptr),
+            args: read_memory(arguments_len, arguments_ptr),
+            amount: read_u128(amount_ptr),
+            gas,
+        };
+        add_action(promise_index, action);
+    }
+
+    pub unsafe fn promise_batch_action_deploy_contract(
+        promise_index: u64,
+        code_len: u64,
+        code_ptr: u64,
+    ) {
+        let code = read_memory(code_len, code_ptr);
+        add_action(promise_index, Action::DeployContract { code });
+    }
+
+    pub unsafe fn promise_batch_action_transfer(promise_index: u64, amount_ptr: u64) {
+        let amount = read_u128(amount_ptr);
+        add_action(promise_index, Action::Transfer { amount });
+    }
+
+    pub unsafe fn promise_batch_action_add_key_with_full_access(
+        promise_index: u64,
+        public_key_len: u64,
+        public_key_ptr: u64,
+        nonce: u64,
+    ) {
+        let public_key = read_memory(public_key_len, public_key_ptr);
+        add_action(
+            promise_index,
+            Action::AddKeyWithFullAccess { public_key, nonce },
+        );
+    }
+}
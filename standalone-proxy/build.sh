@@ -2,4 +2,4 @@
 set -e
 
 RUSTFLAGS='-C link-arg=-s' cargo +nightly build --target wasm32-unknown-unknown --release
-cp ../target/wasm32-unknown-unknown/release/proxy.wasm ../res/
+cp ../target/wasm32-unknown-unknown/release/standalone_proxy.wasm ../res/
@@ -0,0 +1,139 @@
+//! Proxy account that verifies the messages of its owner's Ethereum address itself, instead of
+//! trusting the gateway to. The gateway only creates it, after that relayers submit the owner's
+//! messages straight to the account's `execute`.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen, AccountId, Gas, PanicOnDefault, Promise, PromiseResult};
+use primitive_types::U256;
+
+use gateway_core::meta_parsing::{near_erc712_domain, parse_meta_call, ParsingError};
+use gateway_core::types::{checksum_address, DepositSource, RawAddress, RawU256};
+
+const CHAIN_ID: u64 = 1;
+
+const TGAS: u64 = 1_000_000_000_000;
+/// Gas kept for `execute` itself, the rest goes to the call unless the message declares less.
+const GAS_FOR_EXECUTE: Gas = Gas(10 * TGAS);
+/// Gas of `on_execute`, paying the tip once the call succeeded.
+const GAS_FOR_ON_EXECUTE: Gas = Gas(5 * TGAS);
+
+#[near_bindgen]
+#[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
+pub struct Contract {
+    /// Address whose signed messages the account executes.
+    owner: RawAddress,
+    /// EIP-712 domain separator, computed once at init.
+    domain_separator: RawU256,
+    /// Sequential nonce the owner's next message must have.
+    nonce: u64,
+}
+
+fn parse_address(address: &str) -> RawAddress {
+    let bytes = hex::decode(address.trim_start_matches("0x")).expect("ERR_INVALID_ADDRESS");
+    assert_eq!(bytes.len(), 20, "ERR_INVALID_ADDRESS");
+    let mut result = RawAddress::default();
+    result.copy_from_slice(&bytes);
+    result
+}
+
+/// Panics with details of the parsing error, so the wallet can tell what's wrong with the message.
+fn panic_parsing_error<T>(error: ParsingError) -> T {
+    env::panic_str(&format!("ERR_META_TX_PARSE: {}", error))
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Initializes the account for the hex encoded address, done by the gateway creating it.
+    #[init]
+    pub fn new(owner: String) -> Self {
+        Self {
+            owner: parse_address(&owner),
+            domain_separator: near_erc712_domain(U256::from(CHAIN_ID)),
+            nonce: 0,
+        }
+    }
+
+    /// Executes the owner's message signed for this account as the gateway: transfers the value
+    /// to the receiver or calls its method with it, and pays the tip to the caller once that
+    /// succeeded. Only sequential nonces under key 0 are accepted, and the value comes from the
+    /// account.
+    pub fn execute(&mut self, message: Base64VecU8) -> Promise {
+        let args = parse_meta_call(
+            &self.domain_separator,
+            CHAIN_ID,
+            env::current_account_id().as_bytes(),
+            message.0,
+            |_| None,
+        )
+        .unwrap_or_else(panic_parsing_error);
+        assert!(
+            args.sender.0 == self.owner && args.signers.is_empty(),
+            "ERR_NOT_OWNER"
+        );
+        assert_eq!(args.nonce, U256::from(self.nonce), "ERR_INCORRECT_NONCE");
+        assert_eq!(
+            args.deposit_source,
+            DepositSource::Account,
+            "ERR_RELAYER_DEPOSIT"
+        );
+        self.nonce += 1;
+        let receiver_id: AccountId = args
+            .contract_address
+            .parse()
+            .unwrap_or_else(|_| env::panic_str("ERR_INVALID_ACCOUNT_ID"));
+        let gas_for_tip = if args.tip > 0 {
+            GAS_FOR_ON_EXECUTE
+        } else {
+            Gas(0)
+        };
+        let left = env::prepaid_gas().0.saturating_sub(env::used_gas().0);
+        let reserved = GAS_FOR_EXECUTE.0 + gas_for_tip.0;
+        assert!(left > reserved, "ERR_NOT_ENOUGH_GAS");
+        let available = Gas(left - reserved);
+        let promise = Promise::new(receiver_id);
+        let promise = if args.method_name.is_empty() {
+            promise.transfer(args.value)
+        } else {
+            let gas = if args.gas > 0 {
+                Gas(args.gas).min(available)
+            } else {
+                available
+            };
+            promise.function_call(args.method_name, args.args, args.value, gas)
+        };
+        if args.tip == 0 {
+            return promise;
+        }
+        // Like the gateway's proxy accounts, tips are only paid for executed messages.
+        promise.then(
+            Promise::new(env::current_account_id()).function_call(
+                "on_execute".to_string(),
+                json!({ "relayer": env::predecessor_account_id(), "tip": U128(args.tip) })
+                    .to_string()
+                    .into_bytes(),
+                0,
+                GAS_FOR_ON_EXECUTE,
+            ),
+        )
+    }
+
+    /// Pays the tip of `execute` to the relayer if the message's transfer or call succeeded.
+    #[private]
+    pub fn on_execute(&self, relayer: AccountId, tip: U128) {
+        if matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            Promise::new(relayer).transfer(tip.0);
+        }
+    }
+
+    /// EIP-55 checksummed address whose messages the account executes.
+    pub fn get_owner(&self) -> String {
+        checksum_address(&self.owner)
+    }
+
+    /// Nonce the owner's next message must have.
+    pub fn get_nonce(&self) -> U64 {
+        U64(self.nonce)
+    }
+}
@@ -0,0 +1,115 @@
+//! Minimal NEP-141 fungible token for the gateway's sandbox tests, so fees paid in tokens go
+//! through a real `ft_transfer_call`. Accounts are registered on their first transfer, without
+//! storage deposits.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::{self, json};
+use near_sdk::{
+    assert_one_yocto, env, near_bindgen, AccountId, Balance, Gas, PanicOnDefault, Promise,
+    PromiseResult,
+};
+
+const TGAS: u64 = 1_000_000_000_000;
+/// Gas kept for `ft_transfer_call` itself, the rest goes to the receiver's `ft_on_transfer`.
+const GAS_FOR_FT_TRANSFER_CALL: Gas = Gas(10 * TGAS);
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas(10 * TGAS);
+
+#[near_bindgen]
+#[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
+pub struct Contract {
+    balances: LookupMap<AccountId, Balance>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Mints the whole supply to the owner.
+    #[init]
+    pub fn new(owner_id: AccountId, total_supply: U128) -> Self {
+        let mut balances = LookupMap::new(b"b".to_vec());
+        balances.insert(&owner_id, &total_supply.0);
+        Self { balances }
+    }
+
+    pub fn ft_balance_of(&self, account_id: AccountId) -> U128 {
+        U128(self.balances.get(&account_id).unwrap_or_default())
+    }
+
+    #[payable]
+    pub fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>) {
+        assert_one_yocto();
+        let _ = memo;
+        self.transfer(&env::predecessor_account_id(), &receiver_id, amount.0);
+    }
+
+    /// Transfers the amount and calls the receiver's `ft_on_transfer`, refunding what it returns
+    /// as unused in `ft_resolve_transfer`.
+    #[payable]
+    pub fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> Promise {
+        assert_one_yocto();
+        let _ = memo;
+        let sender_id = env::predecessor_account_id();
+        self.transfer(&sender_id, &receiver_id, amount.0);
+        let gas = env::prepaid_gas()
+            - env::used_gas()
+            - GAS_FOR_FT_TRANSFER_CALL
+            - GAS_FOR_RESOLVE_TRANSFER;
+        Promise::new(receiver_id.clone())
+            .function_call(
+                "ft_on_transfer".to_string(),
+                json!({ "sender_id": sender_id, "amount": amount, "msg": msg })
+                    .to_string()
+                    .into_bytes(),
+                0,
+                gas,
+            )
+            .then(
+                Promise::new(env::current_account_id()).function_call(
+                    "ft_resolve_transfer".to_string(),
+                    json!({ "sender_id": sender_id, "receiver_id": receiver_id, "amount": amount })
+                        .to_string()
+                        .into_bytes(),
+                    0,
+                    GAS_FOR_RESOLVE_TRANSFER,
+                ),
+            )
+    }
+
+    /// Returns the unused amount to the sender, all of it if `ft_on_transfer` failed. Resolves to
+    /// the amount used.
+    #[private]
+    pub fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        let unused = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value)
+                .map_or(amount.0, |unused| unused.0.min(amount.0)),
+            _ => amount.0,
+        };
+        let refund = unused.min(self.ft_balance_of(receiver_id.clone()).0);
+        if refund > 0 {
+            self.transfer(&receiver_id, &sender_id, refund);
+        }
+        U128(amount.0 - refund)
+    }
+}
+
+impl Contract {
+    fn transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: Balance) {
+        let balance = self.balances.get(sender_id).unwrap_or_default();
+        assert!(balance >= amount, "Not enough balance");
+        self.balances.insert(sender_id, &(balance - amount));
+        let received = self.balances.get(receiver_id).unwrap_or_default();
+        self.balances.insert(receiver_id, &(received + amount));
+    }
+}